@@ -0,0 +1,23 @@
+fn main() {
+    // Declared by hand rather than compiled from proto/denoise.proto, so no protoc is needed
+    #[cfg(feature = "grpc")]
+    {
+        let denoise = tonic_build::manual::Method::builder()
+            .name("denoise")
+            .route_name("Denoise")
+            .input_type("crate::grpc::AudioChunk")
+            .output_type("crate::grpc::AudioChunk")
+            .codec_path("tonic_prost::ProstCodec")
+            .client_streaming()
+            .server_streaming()
+            .build();
+        let service = tonic_build::manual::Service::builder()
+            .name("DenoiseService")
+            .package("denoise.v1")
+            .method(denoise)
+            .build();
+        tonic_build::manual::Builder::new()
+            .build_client(false)
+            .compile(&[service]);
+    }
+}
@@ -0,0 +1,172 @@
+//! Zip and tar archives as input or output locations.
+//!
+//! An input archive is unpacked (matching entries only) into a staging
+//! directory and processed like a directory tree; an output archive is packed
+//! from the staged results once the run finishes.
+
+use crate::walk;
+use anyhow::{Context, Result, bail};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+/// Recognizes archives by file name: `.zip`, `.tar`, `.tar.gz` and `.tgz`.
+pub fn kind(path: &Path) -> Option<Kind> {
+    let name = path.file_name()?.to_str()?.to_lowercase();
+    if name.ends_with(".zip") {
+        Some(Kind::Zip)
+    } else if name.ends_with(".tar") {
+        Some(Kind::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(Kind::TarGz)
+    } else {
+        None
+    }
+}
+
+/// Rejects absolute paths and `..` so entries cannot escape the staging directory.
+pub fn is_safe(name: &Path) -> bool {
+    name.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Unpacks entries with an accepted extension into `dir`; returns how many.
+pub fn extract(archive: &Path, dir: &Path, extensions: &[String]) -> Result<usize> {
+    let kind = kind(archive).context("Unsupported archive type")?;
+    let file = File::open(archive)
+        .with_context(|| format!("Failed to open archive: {}", archive.display()))?;
+    let mut count = 0;
+
+    let mut write_entry = |name: &Path, reader: &mut dyn std::io::Read| -> Result<()> {
+        if !is_safe(name) {
+            bail!(
+                "Refusing to extract entry outside the archive root: {}",
+                name.display()
+            );
+        }
+        let dest = dir.join(name);
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        let mut out = BufWriter::new(
+            File::create(&dest)
+                .with_context(|| format!("Failed to create file: {}", dest.display()))?,
+        );
+        std::io::copy(reader, &mut out)
+            .with_context(|| format!("Failed to extract: {}", name.display()))?;
+        count += 1;
+        Ok(())
+    };
+
+    match kind {
+        Kind::Zip => {
+            let mut zip = zip::ZipArchive::new(BufReader::new(file))
+                .with_context(|| format!("Malformed zip archive: {}", archive.display()))?;
+            for i in 0..zip.len() {
+                let mut entry = zip.by_index(i)?;
+                if entry.is_dir() {
+                    continue;
+                }
+                let name = entry.enclosed_name().with_context(|| {
+                    format!(
+                        "Unsafe entry name in archive: {}",
+                        String::from_utf8_lossy(entry.name_raw())
+                    )
+                })?;
+                if walk::has_extension(&name, extensions) {
+                    write_entry(&name, &mut entry)?;
+                }
+            }
+        }
+        Kind::Tar | Kind::TarGz => {
+            let reader: Box<dyn std::io::Read> = if kind == Kind::TarGz {
+                Box::new(flate2::read::GzDecoder::new(BufReader::new(file)))
+            } else {
+                Box::new(BufReader::new(file))
+            };
+            let mut tar = tar::Archive::new(reader);
+            for entry in tar
+                .entries()
+                .with_context(|| format!("Malformed tar archive: {}", archive.display()))?
+            {
+                let mut entry = entry?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let name = entry.path()?.into_owned();
+                if walk::has_extension(&name, extensions) {
+                    write_entry(&name, &mut entry)?;
+                }
+            }
+        }
+    }
+    Ok(count)
+}
+
+/// Packs every file under `dir` into a new archive at `archive`; returns how many.
+pub fn pack(dir: &Path, archive: &Path) -> Result<usize> {
+    let kind = kind(archive).context("Unsupported archive type")?;
+    if let Some(parent) = archive.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    let file = BufWriter::new(
+        File::create(archive)
+            .with_context(|| format!("Failed to create archive: {}", archive.display()))?,
+    );
+
+    let files: Vec<_> = WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect();
+
+    match kind {
+        Kind::Zip => {
+            let mut zip = zip::ZipWriter::new(file);
+            for path in &files {
+                let name = path.strip_prefix(dir)?.to_string_lossy().replace('\\', "/");
+                let size = std::fs::metadata(path)?.len();
+                let options = zip::write::SimpleFileOptions::default()
+                    .compression_method(zip::CompressionMethod::Deflated)
+                    .large_file(size >= u32::MAX as u64);
+                zip.start_file(name, options)?;
+                std::io::copy(&mut BufReader::new(File::open(path)?), &mut zip)?;
+            }
+            zip.finish()
+                .with_context(|| format!("Failed to write archive: {}", archive.display()))?;
+        }
+        Kind::Tar => {
+            write_tar(dir, &files, file)?
+                .into_inner()
+                .map_err(|e| e.into_error())
+                .with_context(|| format!("Failed to write archive: {}", archive.display()))?;
+        }
+        Kind::TarGz => {
+            let gz = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            write_tar(dir, &files, gz)?
+                .finish()
+                .with_context(|| format!("Failed to write archive: {}", archive.display()))?;
+        }
+    }
+    Ok(files.len())
+}
+
+fn write_tar<W: Write>(dir: &Path, files: &[PathBuf], writer: W) -> Result<W> {
+    let mut tar = tar::Builder::new(writer);
+    for path in files {
+        tar.append_path_with_name(path, path.strip_prefix(dir)?)?;
+    }
+    Ok(tar.into_inner()?)
+}
@@ -0,0 +1,230 @@
+//! WAV sample I/O shared by the pipeline, the corpus generator and the review outputs.
+
+use crate::rng::Rng;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+/// Frames read or written at a time by the streaming readers and writers,
+/// which keep memory bounded however long a file is.
+pub const BLOCK_FRAMES: usize = 16384;
+
+/// Decoded WAV contents: interleaved samples in `[-1, 1]` and the original spec.
+pub struct Audio {
+    pub samples: Vec<f32>,
+    pub spec: hound::WavSpec,
+}
+
+impl Audio {
+    /// Samples of one channel; for mono files that is the whole signal.
+    pub fn channel(&self, index: usize) -> Vec<f32> {
+        let channels = usize::from(self.spec.channels.max(1));
+        self.samples
+            .iter()
+            .skip(index)
+            .step_by(channels)
+            .copied()
+            .collect()
+    }
+}
+
+/// How several channels are mixed into one.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Downmix {
+    /// The mean of the channels
+    Average,
+    /// The channel with the most energy over the whole file
+    MaxEnergy,
+}
+
+impl Downmix {
+    /// Mixes the `channels` of `audio` into mono samples.
+    pub fn mix(self, audio: &Audio, channels: &[usize]) -> Vec<f32> {
+        let signals: Vec<Vec<f32>> = channels.iter().map(|&c| audio.channel(c)).collect();
+        match self {
+            Downmix::Average => {
+                let frames = signals.first().map_or(0, Vec::len);
+                (0..frames)
+                    .map(|i| {
+                        let sum: f64 = signals.iter().map(|s| f64::from(s[i])).sum();
+                        (sum / signals.len() as f64) as f32
+                    })
+                    .collect()
+            }
+            Downmix::MaxEnergy => {
+                let energy = |s: &[f32]| s.iter().map(|&x| f64::from(x).powi(2)).sum::<f64>();
+                signals
+                    .into_iter()
+                    .max_by(|a, b| energy(a).total_cmp(&energy(b)))
+                    .unwrap_or_default()
+            }
+        }
+    }
+}
+
+/// Reads any PCM or float WAV file, normalizing samples to `[-1, 1]`.
+pub fn read_wav(path: &Path) -> Result<Audio> {
+    let mut reader = hound::WavReader::open(path)
+        .with_context(|| format!("Failed to open WAV file: {}", path.display()))?;
+    let spec = reader.spec();
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>(),
+        hound::SampleFormat::Int => {
+            let full_scale = f64::from(1u32 << (spec.bits_per_sample.min(32) - 1));
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| (f64::from(s) / full_scale) as f32))
+                .collect()
+        }
+    }
+    .with_context(|| format!("Failed to read samples: {}", path.display()))?;
+    Ok(Audio { samples, spec })
+}
+
+/// Reads a WAV file a block of frames at a time, normalizing samples to `[-1, 1]`.
+pub struct FrameReader {
+    reader: hound::WavReader<BufReader<File>>,
+    path: std::path::PathBuf,
+    full_scale: f64,
+}
+
+impl FrameReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let reader = hound::WavReader::open(path)
+            .with_context(|| format!("Failed to open WAV file: {}", path.display()))?;
+        let full_scale = f64::from(1u32 << (reader.spec().bits_per_sample.min(32) - 1));
+        Ok(FrameReader {
+            reader,
+            path: path.to_path_buf(),
+            full_scale,
+        })
+    }
+
+    pub fn spec(&self) -> hound::WavSpec {
+        self.reader.spec()
+    }
+
+    /// Length of the file in frames.
+    pub fn frames(&self) -> usize {
+        self.reader.duration() as usize
+    }
+
+    /// The next interleaved block of up to [`BLOCK_FRAMES`] frames, or `None` at the end.
+    pub fn next_block(&mut self) -> Result<Option<Vec<f32>>> {
+        let take = BLOCK_FRAMES * usize::from(self.spec().channels.max(1));
+        let full_scale = self.full_scale;
+        let block = match self.spec().sample_format {
+            hound::SampleFormat::Float => self
+                .reader
+                .samples::<f32>()
+                .take(take)
+                .collect::<Result<Vec<_>, _>>(),
+            hound::SampleFormat::Int => self
+                .reader
+                .samples::<i32>()
+                .take(take)
+                .map(|s| s.map(|s| (f64::from(s) / full_scale) as f32))
+                .collect(),
+        }
+        .with_context(|| format!("Failed to read samples: {}", self.path.display()))?;
+        Ok((!block.is_empty()).then_some(block))
+    }
+}
+
+/// Writes samples in `[-1, 1]` to a WAV file as they come, optionally adding
+/// TPDF dither (triangular, ±1 LSB) before quantizing to integer PCM.
+pub struct FrameWriter {
+    writer: hound::WavWriter<BufWriter<File>>,
+    path: std::path::PathBuf,
+    max: f64,
+    dither: Option<Rng>,
+}
+
+impl FrameWriter {
+    pub fn create(path: &Path, spec: hound::WavSpec, dither: bool) -> Result<Self> {
+        let writer = hound::WavWriter::create(path, spec)
+            .with_context(|| format!("Failed to create WAV file: {}", path.display()))?;
+        Ok(FrameWriter {
+            writer,
+            path: path.to_path_buf(),
+            max: f64::from(1u32 << (spec.bits_per_sample.min(32) - 1)) - 1.0,
+            // Fixed seed keeps conversions reproducible
+            dither: dither.then(|| Rng::new(0x5eed)),
+        })
+    }
+
+    /// Appends interleaved samples.
+    pub fn write(&mut self, samples: &[f32]) -> Result<()> {
+        let FrameWriter {
+            writer,
+            path,
+            max,
+            dither,
+        } = self;
+        let failed = || format!("Failed to write WAV file: {}", path.display());
+        match writer.spec().sample_format {
+            hound::SampleFormat::Float => {
+                for &s in samples {
+                    writer.write_sample(s).with_context(failed)?;
+                }
+            }
+            hound::SampleFormat::Int => {
+                let max = *max;
+                for &s in samples {
+                    let mut v = f64::from(s.clamp(-1.0, 1.0)) * max;
+                    if let Some(rng) = dither {
+                        v += rng.next_f64() - rng.next_f64();
+                    }
+                    writer
+                        .write_sample(v.round().clamp(-max - 1.0, max) as i32)
+                        .with_context(failed)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Completes the header; the file is unusable without this.
+    pub fn finish(self) -> Result<()> {
+        self.writer
+            .finalize()
+            .with_context(|| format!("Failed to write WAV file: {}", self.path.display()))
+    }
+}
+
+/// Writes interleaved samples in `[-1, 1]` at the given bit depth.
+pub fn write_wav(path: &Path, samples: &[f32], rate: u32, bits: u16, channels: u16) -> Result<()> {
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate: rate,
+        bits_per_sample: bits,
+        sample_format: if bits == 32 {
+            hound::SampleFormat::Float
+        } else {
+            hound::SampleFormat::Int
+        },
+    };
+    write_spec(path, samples, spec, false)
+}
+
+/// Writes interleaved samples in `[-1, 1]` with `spec`, optionally adding TPDF
+/// dither (triangular, ±1 LSB) before quantizing to integer PCM.
+pub fn write_spec(path: &Path, samples: &[f32], spec: hound::WavSpec, dither: bool) -> Result<()> {
+    let mut writer = FrameWriter::create(path, spec, dither)?;
+    writer.write(samples)?;
+    writer.finish()
+}
+
+/// Re-encodes `src` into `dest` with `spec`, keeping the sample values.
+pub fn convert(src: &Path, dest: &Path, spec: hound::WavSpec, dither: bool) -> Result<()> {
+    let mut reader = FrameReader::open(src)?;
+    let mut writer = FrameWriter::create(dest, spec, dither)?;
+    while let Some(block) = reader.next_block()? {
+        writer.write(&block)?;
+    }
+    writer.finish()
+}
@@ -0,0 +1,278 @@
+//! Bearer-token authentication and per-token rate limits for the server mode.
+//!
+//! Tokens are either listed statically (`--token`, or one per line in
+//! `--tokens-file`, optionally followed by that token's requests-per-minute
+//! limit) or signed with `--token-secret` as `SUBJECT.EXPIRY.SIGNATURE`, where
+//! `EXPIRY` is a Unix timestamp and `SIGNATURE` is the hex HMAC-SHA256 of
+//! `SUBJECT.EXPIRY`. Signed tokens share their subject's rate limit.
+//!
+//! Without tokens every request is allowed, and rate limits are kept per
+//! remote address instead.
+
+use crate::checksum;
+use anyhow::{Context, Result, bail};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(clap::Args, Debug)]
+pub struct AuthArgs {
+    /// Accepted bearer token (repeatable or comma-separated)
+    #[arg(long = "token", value_delimiter = ',')]
+    tokens: Vec<String>,
+
+    /// File listing one accepted token per line, optionally followed by its requests-per-minute limit
+    #[arg(long)]
+    tokens_file: Option<PathBuf>,
+
+    /// Secret for HMAC-signed tokens of the form SUBJECT.EXPIRY.SIGNATURE
+    #[arg(long)]
+    token_secret: Option<String>,
+
+    /// Requests per minute allowed per token (or per address without tokens) unless the tokens file sets one (0 = unlimited)
+    #[arg(long, default_value_t = 0)]
+    rate_limit: u32,
+}
+
+/// Why a request was refused.
+pub enum Denied {
+    Unauthorized,
+    /// The token's limit is used up; retry after this many seconds.
+    RateLimited(u64),
+}
+
+pub struct Auth {
+    /// Static tokens and their per-minute limits.
+    tokens: Vec<(String, u32)>,
+    secret: Option<Vec<u8>>,
+    default_limit: u32,
+    /// Start and request count of the current window, per token or subject.
+    windows: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl Auth {
+    pub fn new(args: &AuthArgs) -> Result<Self> {
+        let mut tokens: Vec<(String, u32)> = args
+            .tokens
+            .iter()
+            .filter(|t| !t.is_empty())
+            .map(|t| (t.clone(), args.rate_limit))
+            .collect();
+
+        if let Some(path) = &args.tokens_file {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read tokens file: {}", path.display()))?;
+            for (number, line) in text.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let mut fields = line.split_whitespace();
+                let token = fields.next().unwrap_or_default().to_string();
+                let limit = match fields.next() {
+                    Some(limit) => limit.parse().with_context(|| {
+                        format!(
+                            "Invalid rate limit on line {} of {}",
+                            number + 1,
+                            path.display()
+                        )
+                    })?,
+                    None => args.rate_limit,
+                };
+                tokens.push((token, limit));
+            }
+        }
+
+        let secret = args.token_secret.as_ref().map(|s| s.as_bytes().to_vec());
+        if secret.as_ref().is_some_and(|s| s.is_empty()) {
+            bail!("--token-secret must not be empty");
+        }
+
+        Ok(Auth {
+            tokens,
+            secret,
+            default_limit: args.rate_limit,
+            windows: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Whether any tokens are configured; without them every request is allowed.
+    pub fn enabled(&self) -> bool {
+        !self.tokens.is_empty() || self.secret.is_some()
+    }
+
+    /// Checks an `Authorization` header value and counts the request against its
+    /// limit. Returns who is asking: `token:` and a truncated SHA-256 of a
+    /// static token, `sub:` and the subject of a signed one, or `remote` (the
+    /// client's address) when no tokens are configured.
+    pub fn check(&self, authorization: Option<&str>, remote: &str) -> Result<String, Denied> {
+        if !self.enabled() {
+            let client = format!("address {remote}");
+            self.count(client.clone(), self.default_limit)?;
+            return Ok(client);
+        }
+        let token = authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or(Denied::Unauthorized)?;
+
+        let (key, limit) = match self
+            .tokens
+            .iter()
+            .find(|(known, _)| constant_time_eq(known.as_bytes(), token.as_bytes()))
+        {
+            Some((known, limit)) => (format!("token:{}", fingerprint(known)), *limit),
+            None => (
+                format!("sub:{}", self.verify_signed(token)?),
+                self.default_limit,
+            ),
+        };
+        self.count(key.clone(), limit)?;
+        Ok(key)
+    }
+
+    /// Returns the subject of a valid, unexpired signed token.
+    fn verify_signed(&self, token: &str) -> Result<String, Denied> {
+        let secret = self.secret.as_ref().ok_or(Denied::Unauthorized)?;
+        let (payload, signature) = token.rsplit_once('.').ok_or(Denied::Unauthorized)?;
+        let (subject, expiry) = payload.rsplit_once('.').ok_or(Denied::Unauthorized)?;
+        let expected: String = checksum::hmac_sha256(secret, payload.as_bytes())
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        if !constant_time_eq(expected.as_bytes(), signature.to_lowercase().as_bytes()) {
+            return Err(Denied::Unauthorized);
+        }
+        let expiry: u64 = expiry.parse().map_err(|_| Denied::Unauthorized)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        if now >= expiry {
+            return Err(Denied::Unauthorized);
+        }
+        Ok(subject.to_string())
+    }
+
+    fn count(&self, key: String, limit: u32) -> Result<(), Denied> {
+        if limit == 0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        let (start, count) = windows.entry(key).or_insert((now, 0));
+        if now.duration_since(*start) >= WINDOW {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= limit {
+            let retry = WINDOW.saturating_sub(now.duration_since(*start));
+            return Err(Denied::RateLimited(retry.as_secs().max(1)));
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+/// The first 16 hex digits of a token's SHA-256, so it is never kept in the clear.
+fn fingerprint(token: &str) -> String {
+    let mut hash = checksum::sha256_reader(token.as_bytes()).unwrap_or_default();
+    hash.truncate(16);
+    hash
+}
+
+/// Compares without an early exit so response timing doesn't reveal matching prefixes.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth(tokens: &[&str], secret: Option<&str>, rate_limit: u32) -> Auth {
+        Auth::new(&AuthArgs {
+            tokens: tokens.iter().map(|t| t.to_string()).collect(),
+            tokens_file: None,
+            token_secret: secret.map(str::to_string),
+            rate_limit,
+        })
+        .unwrap()
+    }
+
+    fn signed(secret: &str, subject: &str, expiry: u64) -> String {
+        let payload = format!("{subject}.{expiry}");
+        let signature: String = checksum::hmac_sha256(secret.as_bytes(), payload.as_bytes())
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        format!("{payload}.{signature}")
+    }
+
+    fn bearer(token: &str) -> String {
+        format!("Bearer {token}")
+    }
+
+    #[test]
+    fn without_tokens_clients_are_their_addresses() {
+        let auth = auth(&[], None, 0);
+        assert!(!auth.enabled());
+        assert_eq!(
+            auth.check(None, "10.0.0.1").ok().unwrap(),
+            "address 10.0.0.1"
+        );
+    }
+
+    #[test]
+    fn static_tokens_are_known_by_their_hash() {
+        let auth = auth(&["hunter2"], None, 0);
+        let client = auth
+            .check(Some(&bearer("hunter2")), "10.0.0.1")
+            .ok()
+            .unwrap();
+        // SHA-256 of "hunter2"
+        assert_eq!(client, "token:f52fbd32b2b3b86f");
+        assert!(matches!(
+            auth.check(Some(&bearer("hunter3")), "10.0.0.1"),
+            Err(Denied::Unauthorized)
+        ));
+        assert!(matches!(
+            auth.check(None, "10.0.0.1"),
+            Err(Denied::Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn signed_tokens_are_known_by_their_subject_until_they_expire() {
+        let auth = auth(&[], Some("s3cret"), 0);
+        let token = signed("s3cret", "alice", u64::MAX);
+        assert_eq!(
+            auth.check(Some(&bearer(&token)), "10.0.0.1").ok().unwrap(),
+            "sub:alice"
+        );
+        for token in [
+            signed("s3cret", "alice", 1),
+            signed("other", "alice", u64::MAX),
+            token.replace("alice", "mallory"),
+        ] {
+            assert!(matches!(
+                auth.check(Some(&bearer(&token)), "10.0.0.1"),
+                Err(Denied::Unauthorized)
+            ));
+        }
+    }
+
+    #[test]
+    fn a_subject_named_like_a_token_has_its_own_limit() {
+        let auth = auth(&["alice"], Some("s3cret"), 1);
+        let signed = signed("s3cret", "alice", u64::MAX);
+        assert!(auth.check(Some(&bearer("alice")), "10.0.0.1").is_ok());
+        assert!(auth.check(Some(&bearer(&signed)), "10.0.0.1").is_ok());
+        assert!(matches!(
+            auth.check(Some(&bearer("alice")), "10.0.0.1"),
+            Err(Denied::RateLimited(retry)) if (1..=60).contains(&retry)
+        ));
+    }
+}
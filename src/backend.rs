@@ -0,0 +1,946 @@
+//! Denoiser backends, selected with `--backend`.
+//!
+//! Every backend turns a WAV file in the pipeline's target format (mono 16kHz
+//! 16-bit PCM unless `--expect-*` says otherwise) into a denoised file in the
+//! same format; the pipeline takes care of converting other inputs.
+
+use crate::audio;
+use crate::capture;
+use crate::dsp;
+use crate::plugin::{Plugin, Staged};
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// A denoiser the pipeline can hand files to.
+pub trait Backend: Send + Sync {
+    /// Denoises `input` into `output`, passing `model` on where it applies.
+    fn denoise(&self, input: &Path, output: &Path, model: Option<&str>) -> Result<()>;
+
+    /// Denoises `input` into `output` `passes` times over without files in
+    /// between, for backends that can; `None` leaves chaining to the pipeline.
+    fn denoise_passes(
+        &self,
+        _input: &Path,
+        _output: &Path,
+        _model: Option<&str>,
+        _passes: usize,
+    ) -> Option<Result<()>> {
+        None
+    }
+
+    /// Number of files worth processing at once when the user doesn't say.
+    fn default_jobs(&self) -> usize {
+        std::thread::available_parallelism().map_or(1, |n| n.get())
+    }
+}
+
+/// The backends `--backend` can select.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// POST each file to the --addr-api servers
+    Api,
+    /// The registered `--plugin backend=...` plugin
+    Plugin,
+    /// An external program run per file from --backend-command
+    Command,
+    /// Long-lived --stream-command workers fed raw PCM, without a process per file
+    Stream,
+    /// Built-in Rust port of RNNoise
+    Nnnoiseless,
+    /// The RNNoise C demo program (`rnnoise_demo`) on PATH
+    Rnnoise,
+    /// DeepFilterNet's `deep-filter` program on PATH
+    Deepfilternet,
+    /// An ONNX --model run in-process by ONNX Runtime, on a GPU where available (builds with --features onnx)
+    Onnx,
+    /// The first that works of: a backend plugin, a reachable --addr-api
+    /// server, --stream-command or --backend-command, onnx for an .onnx
+    /// model, nnnoiseless, then a program on PATH
+    Auto,
+}
+
+/// What `--backend auto` has to go on.
+pub struct Probe<'a> {
+    pub plugin: bool,
+    pub addresses: &'a [String],
+    pub model: Option<&'a str>,
+    pub command: bool,
+    pub stream: bool,
+    /// `--backend-program`, for rnnoise and deepfilternet.
+    pub program: Option<&'a str>,
+    pub args: Option<&'a ArgsTemplate>,
+}
+
+impl Probe<'_> {
+    /// Picks the backend for `--backend auto` and says why, logging why each
+    /// candidate before it was passed over. Accelerated denoisers (GPU
+    /// inference behind a plugin or the API) come first, then the programs
+    /// set with `--stream-command` or `--backend-command`, then ONNX Runtime
+    /// for an `.onnx` model, then the in-process port, which takes any model
+    /// in nnnoiseless' format, then the programs on PATH for models it
+    /// cannot load.
+    pub fn select(&self) -> Result<(BackendKind, String)> {
+        let passed = |name: &str, reason: &str| {
+            eprintln!("--backend auto: passed over {name}: {reason}");
+        };
+        if self.plugin {
+            return Ok((
+                BackendKind::Plugin,
+                "a backend plugin is registered".to_string(),
+            ));
+        }
+        passed("plugin", "no backend plugin is registered");
+        if self.addresses.is_empty() {
+            passed("api", "no --addr-api is given");
+        }
+        for address in self.addresses {
+            if reachable(address) {
+                return Ok((BackendKind::Api, format!("{address} is reachable")));
+            }
+            eprintln!("Warning: --backend auto: {address} is not reachable");
+        }
+        if self.stream {
+            return Ok((BackendKind::Stream, "--stream-command is set".to_string()));
+        }
+        passed("stream", "--stream-command is not set");
+        if self.command {
+            return Ok((BackendKind::Command, "--backend-command is set".to_string()));
+        }
+        passed("command", "--backend-command is not set");
+        if self.model.is_some_and(|m| m.ends_with(".onnx")) {
+            #[cfg(feature = "onnx")]
+            match crate::onnx::runtime() {
+                Ok(()) => {
+                    return Ok((BackendKind::Onnx, "the model is ONNX".to_string()));
+                }
+                Err(e) => passed("onnx", &format!("{e:#}")),
+            }
+            #[cfg(not(feature = "onnx"))]
+            passed("onnx", "this build has no --features onnx");
+        } else {
+            passed("onnx", "the model is not ONNX");
+        }
+        match self.model.map(weights).transpose() {
+            Ok(_) => {
+                return Ok((
+                    BackendKind::Nnnoiseless,
+                    format!("in-process, {}", cpu_features()),
+                ));
+            }
+            Err(e) => passed("nnnoiseless", &format!("it cannot use the model: {e:#}")),
+        }
+        let deep_filter = self.program.unwrap_or("deep-filter");
+        if on_path(deep_filter) {
+            return Ok((
+                BackendKind::Deepfilternet,
+                format!("{deep_filter} is on PATH"),
+            ));
+        }
+        passed("deepfilternet", &format!("{deep_filter} is not on PATH"));
+        // Only a model the nnnoiseless port cannot load gets this far
+        let rnnoise = self.program.unwrap_or("rnnoise_demo");
+        if !on_path(rnnoise) {
+            passed("rnnoise", &format!("{rnnoise} is not on PATH"));
+        } else if !self.args.is_some_and(ArgsTemplate::passes_model) {
+            passed(
+                "rnnoise",
+                &format!("{rnnoise} takes no model unless --backend-args passes {{model}}"),
+            );
+        } else {
+            return Ok((BackendKind::Rnnoise, format!("{rnnoise} is on PATH")));
+        }
+        bail!("--backend auto found no backend that can use the model")
+    }
+}
+
+/// Whether a TCP connection to the host of the URL `address` succeeds within a second.
+fn reachable(address: &str) -> bool {
+    let (default_port, rest) = match address.split_once("://") {
+        Some(("https", rest)) => (443, rest),
+        Some((_, rest)) => (80, rest),
+        None => (80, address),
+    };
+    let authority = rest.split(['/', '?']).next().unwrap_or_default();
+    let authority = authority.rsplit('@').next().unwrap_or_default();
+    let with_port = if authority
+        .rsplit_once(':')
+        .is_some_and(|(_, p)| p.parse::<u16>().is_ok())
+    {
+        authority.to_string()
+    } else {
+        format!("{authority}:{default_port}")
+    };
+    use std::net::ToSocketAddrs;
+    with_port.to_socket_addrs().is_ok_and(|mut addrs| {
+        addrs.any(|addr| {
+            std::net::TcpStream::connect_timeout(&addr, std::time::Duration::from_secs(1)).is_ok()
+        })
+    })
+}
+
+/// The SIMD extensions of this CPU that the in-process denoiser's code can use.
+fn cpu_features() -> String {
+    #[allow(unused_mut)]
+    let mut found: Vec<&str> = Vec::new();
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        for (name, present) in [
+            ("sse4.1", std::arch::is_x86_feature_detected!("sse4.1")),
+            ("avx", std::arch::is_x86_feature_detected!("avx")),
+            ("avx2", std::arch::is_x86_feature_detected!("avx2")),
+            ("fma", std::arch::is_x86_feature_detected!("fma")),
+        ] {
+            if present {
+                found.push(name);
+            }
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    found.push("neon");
+    if found.is_empty() {
+        "no SIMD extensions detected".to_string()
+    } else {
+        format!("CPU with {}", found.join(", "))
+    }
+}
+
+/// Whether `program` is an existing file in a directory on PATH, or at the
+/// path it names.
+fn on_path(program: &str) -> bool {
+    if Path::new(program).components().count() > 1 {
+        return Path::new(program).is_file();
+    }
+    let names: Vec<String> = if cfg!(windows) {
+        vec![format!("{program}.exe"), program.to_string()]
+    } else {
+        vec![program.to_string()]
+    };
+    std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path).any(|dir| names.iter().any(|name| dir.join(name).is_file()))
+    })
+}
+
+#[derive(Serialize)]
+struct DenoiseRequestBody {
+    filename: String,
+    filename_denoised: String,
+    model: Option<String>,
+}
+
+/// The HTTP denoising API, spreading files over the given addresses in turn.
+pub struct Api {
+    endpoints: Vec<String>,
+    next: AtomicUsize,
+}
+
+impl Api {
+    pub fn new(addresses: &[String]) -> Result<Self> {
+        if addresses.is_empty() {
+            bail!("At least one API address must be provided via --addr-api");
+        }
+        Ok(Api {
+            endpoints: addresses.to_vec(),
+            next: AtomicUsize::new(0),
+        })
+    }
+}
+
+impl Backend for Api {
+    fn denoise(&self, input: &Path, output: &Path, model: Option<&str>) -> Result<()> {
+        let body = DenoiseRequestBody {
+            filename: input.to_string_lossy().to_string(),
+            filename_denoised: output.to_string_lossy().to_string(),
+            model: model.map(str::to_string),
+        };
+        let turn = self.next.fetch_add(1, Ordering::Relaxed);
+        let api_addr = &self.endpoints[turn % self.endpoints.len()];
+        let resp = ureq::post(api_addr).send_json(&body)?;
+        if resp.status() != 200 {
+            bail!("API returned status {}", resp.status());
+        }
+        Ok(())
+    }
+
+    fn default_jobs(&self) -> usize {
+        self.endpoints.len()
+    }
+}
+
+impl Backend for Plugin {
+    fn denoise(&self, input: &Path, output: &Path, model: Option<&str>) -> Result<()> {
+        self.run(input, output, model)
+    }
+}
+
+/// An external program run once per file, e.g. `sox {in} {out} highpass 100`.
+///
+/// `{in}` and `{out}` are replaced by the file paths and `{model}` by the model
+/// (or an empty string) in each word of the command.
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandTemplate(Vec<String>);
+
+impl FromStr for CommandTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        template(s, "command").map(CommandTemplate)
+    }
+}
+
+impl Backend for CommandTemplate {
+    fn denoise(&self, input: &Path, output: &Path, model: Option<&str>) -> Result<()> {
+        let words = fill(&self.0, input, output, model);
+        run(&words[0], &words[1..])
+    }
+}
+
+/// The arguments the `rnnoise` and `deepfilternet` backends pass their
+/// program, for versions and forks that take them in another order or under
+/// other flags, e.g. `--model={model} {in} {out}`. Placeholders work as in a
+/// [`CommandTemplate`].
+#[derive(Debug, Clone, Serialize)]
+pub struct ArgsTemplate(Vec<String>);
+
+impl ArgsTemplate {
+    /// Whether the arguments hand the program `{model}`.
+    pub fn passes_model(&self) -> bool {
+        self.0.iter().any(|arg| arg.contains("{model}"))
+    }
+}
+
+impl FromStr for ArgsTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        template(s, "arguments").map(ArgsTemplate)
+    }
+}
+
+/// Splits `s` into words, which must use the `{in}` and `{out}` placeholders.
+fn template(s: &str, what: &str) -> Result<Vec<String>, String> {
+    let words = shlex::split(s)
+        .filter(|w| !w.is_empty())
+        .ok_or_else(|| format!("invalid {what}: '{s}'"))?;
+    for placeholder in ["{in}", "{out}"] {
+        if !words.iter().any(|w| w.contains(placeholder)) {
+            return Err(format!("no {placeholder} placeholder in {what} '{s}'"));
+        }
+    }
+    Ok(words)
+}
+
+/// `words` with the placeholders replaced.
+fn fill(words: &[String], input: &Path, output: &Path, model: Option<&str>) -> Vec<String> {
+    words
+        .iter()
+        .map(|w| {
+            w.replace("{in}", &input.to_string_lossy())
+                .replace("{out}", &output.to_string_lossy())
+                .replace("{model}", model.unwrap_or_default())
+        })
+        .collect()
+}
+
+/// The program an external backend runs, as set by `--backend-program` and
+/// `--backend-args`.
+pub struct Program {
+    name: String,
+    args: Option<ArgsTemplate>,
+}
+
+impl Program {
+    fn new(default: &str, name: Option<&str>, args: Option<ArgsTemplate>) -> Self {
+        Program {
+            name: name.unwrap_or(default).to_string(),
+            args,
+        }
+    }
+
+    /// Runs the program on `input` and `output`, with the `--backend-args`
+    /// or else the `default` arguments.
+    fn run(
+        &self,
+        input: &Path,
+        output: &Path,
+        model: Option<&str>,
+        default: impl FnOnce() -> Vec<String>,
+    ) -> Result<()> {
+        let args = match &self.args {
+            Some(args) => fill(&args.0, input, output, model),
+            None => default(),
+        };
+        run(&self.name, &args)
+    }
+}
+
+/// Runs `program` to completion, failing on a non-zero exit status.
+fn run(program: &str, args: &[String]) -> Result<()> {
+    let status = capture::status(Command::new(program).args(args))
+        .with_context(|| format!("Failed to start backend command: {program}"))?;
+    if !status.success() {
+        bail!("Backend command {program} failed ({status})");
+    }
+    Ok(())
+}
+
+/// A pool of long-lived worker processes started from `--stream-command`,
+/// each denoising one file at a time streamed over its stdin and stdout.
+///
+/// For every file the worker reads a little-endian `u32` sample count followed
+/// by that many little-endian 16-bit samples (interleaved, at the target rate)
+/// and answers in the same framing. Workers are started on demand, so the pool grows to the
+/// number of concurrent jobs, and one that fails is replaced by a fresh one.
+pub struct Stream {
+    program: String,
+    args: Vec<String>,
+    idle: Mutex<Vec<Worker>>,
+}
+
+struct Worker {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Stream {
+    pub fn new(command: &str, spec: hound::WavSpec) -> Result<Self> {
+        if spec.sample_format != hound::SampleFormat::Int || spec.bits_per_sample != 16 {
+            bail!("--backend stream exchanges 16-bit samples; use --expect-bits 16");
+        }
+        let mut words = shlex::split(command)
+            .filter(|w| !w.is_empty())
+            .with_context(|| format!("Invalid stream command: '{command}'"))?
+            .into_iter();
+        let program = words.next().context("Empty stream command")?;
+        Ok(Stream {
+            program,
+            args: words.collect(),
+            idle: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn spawn(&self) -> Result<Worker> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .with_context(|| format!("Failed to start stream worker: {}", self.program))?;
+        let stdin = child.stdin.take().context("Worker stdin unavailable")?;
+        let stdout = child.stdout.take().context("Worker stdout unavailable")?;
+        Ok(Worker {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout),
+        })
+    }
+}
+
+impl Worker {
+    /// Sends one file's samples and writes the denoised samples as they come back.
+    ///
+    /// The samples are sent from a thread of their own: a worker that answers
+    /// while it is still reading would otherwise fill its stdout pipe and wait
+    /// for us while we wait for it to take more of its stdin.
+    fn exchange(
+        &mut self,
+        input: &mut hound::WavReader<BufReader<File>>,
+        output: &mut hound::WavWriter<BufWriter<File>>,
+    ) -> Result<()> {
+        let Worker {
+            child,
+            stdin,
+            stdout,
+        } = self;
+        std::thread::scope(|scope| {
+            let sender = scope.spawn(move || -> Result<()> {
+                let mut stdin = BufWriter::new(stdin);
+                stdin.write_all(&input.len().to_le_bytes())?;
+                for sample in input.samples::<i16>() {
+                    stdin.write_all(&sample?.to_le_bytes())?;
+                }
+                stdin.flush()?;
+                Ok(())
+            });
+            let received = receive(stdout, output);
+            // A worker that stopped answering may never take the rest of its input
+            if received.is_err() {
+                let _ = child.kill();
+            }
+            let sent = sender.join().expect("stream sender panicked");
+            received.and(sent.context("Failed to send samples"))
+        })
+    }
+}
+
+/// Reads one file's denoised samples from a stream worker into `output`.
+fn receive(
+    stdout: &mut BufReader<ChildStdout>,
+    output: &mut hound::WavWriter<BufWriter<File>>,
+) -> Result<()> {
+    let mut count = [0; 4];
+    stdout.read_exact(&mut count)?;
+    let mut remaining = u32::from_le_bytes(count) as usize;
+    let mut pcm = vec![0; audio::BLOCK_FRAMES * 2];
+    while remaining > 0 {
+        let len = remaining.min(audio::BLOCK_FRAMES);
+        stdout.read_exact(&mut pcm[..len * 2])?;
+        for b in pcm[..len * 2].chunks_exact(2) {
+            output.write_sample(i16::from_le_bytes([b[0], b[1]]))?;
+        }
+        remaining -= len;
+    }
+    Ok(())
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Backend for Stream {
+    fn denoise(&self, input: &Path, output: &Path, _model: Option<&str>) -> Result<()> {
+        let mut reader = hound::WavReader::open(input)
+            .with_context(|| format!("Failed to open WAV file: {}", input.display()))?;
+        let mut writer = hound::WavWriter::create(output, reader.spec())
+            .with_context(|| format!("Failed to create file: {}", output.display()))?;
+
+        let idle = self.idle.lock().unwrap().pop();
+        let mut worker = match idle {
+            Some(worker) => worker,
+            None => self.spawn()?,
+        };
+        // A failed worker is dropped (and killed) rather than returned to the pool
+        worker
+            .exchange(&mut reader, &mut writer)
+            .with_context(|| format!("Stream worker {} failed", self.program))?;
+        self.idle.lock().unwrap().push(worker);
+        writer
+            .finalize()
+            .with_context(|| format!("Failed to write file: {}", output.display()))
+    }
+}
+
+/// Sample rate RNNoise and DeepFilterNet work at.
+pub const MODEL_RATE: u32 = 48000;
+
+/// The mono input resampled to the 48kHz the RNNoise-style models expect,
+/// a block at a time, with the input's format to write the result back in.
+pub struct Upsampled {
+    reader: audio::FrameReader,
+    /// Gone once the input has ended and its tail is out.
+    resampler: Option<dsp::Resampler>,
+}
+
+impl Upsampled {
+    pub fn open(input: &Path) -> Result<Self> {
+        let reader = audio::FrameReader::open(input)?;
+        if reader.spec().channels != 1 {
+            bail!(
+                "The built-in backends denoise mono audio; got {} channels",
+                reader.spec().channels
+            );
+        }
+        let resampler = dsp::Resampler::new(reader.spec().sample_rate, MODEL_RATE);
+        Ok(Upsampled {
+            reader,
+            resampler: Some(resampler),
+        })
+    }
+
+    pub fn spec(&self) -> hound::WavSpec {
+        self.reader.spec()
+    }
+
+    /// The next block of 48kHz samples, or `None` at the end.
+    pub fn next_block(&mut self) -> Result<Option<Vec<f32>>> {
+        while let Some(resampler) = &mut self.resampler {
+            let block = match self.reader.next_block()? {
+                Some(block) => resampler.push(&block),
+                None => self
+                    .resampler
+                    .take()
+                    .map(dsp::Resampler::finish)
+                    .unwrap_or_default(),
+            };
+            if !block.is_empty() {
+                return Ok(Some(block));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Model output at `rate` written back in the input's format, a block at a time.
+pub struct Downsampled {
+    writer: audio::FrameWriter,
+    resampler: dsp::Resampler,
+}
+
+impl Downsampled {
+    pub fn create(output: &Path, rate: u32, spec: hound::WavSpec) -> Result<Self> {
+        Ok(Downsampled {
+            writer: audio::FrameWriter::create(output, spec, false)?,
+            resampler: dsp::Resampler::new(rate, spec.sample_rate),
+        })
+    }
+
+    pub fn write(&mut self, samples: &[f32]) -> Result<()> {
+        self.writer.write(&self.resampler.push(samples))
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.writer.write(&self.resampler.finish())?;
+        self.writer.finish()
+    }
+}
+
+/// The built-in Rust port of RNNoise, optionally with custom weights.
+pub struct Nnnoiseless {
+    model: Option<Arc<nnnoiseless::RnnModel>>,
+    /// Denoisers run side by side, each starting its frames a fraction of a
+    /// frame after the one before; see `--overlap`.
+    lanes: usize,
+}
+
+impl Nnnoiseless {
+    /// Uses the weights file `model` (in nnnoiseless' format), or the bundled
+    /// ones, with frames overlapping by `overlap` percent.
+    pub fn new(model: Option<&str>, overlap: u32) -> Result<Self> {
+        if overlap > 90 || !100_u32.is_multiple_of(100 - overlap) {
+            bail!("--overlap must be 0, 50, 75, 80 or 90 percent");
+        }
+        let model = model.map(weights).transpose()?;
+        Ok(Nnnoiseless {
+            model,
+            lanes: (100 / (100 - overlap)) as usize,
+        })
+    }
+}
+
+/// Weights files loaded so far, by canonical path. Every pipeline of the
+/// process (the daemon's per-model ones, the server's and its `/stream`
+/// sessions) shares one copy of each, loaded the first time it is asked for.
+static WEIGHTS: OnceLock<Mutex<HashMap<PathBuf, Arc<nnnoiseless::RnnModel>>>> = OnceLock::new();
+
+/// The weights in the file at `path`, from [`WEIGHTS`] once loaded.
+fn weights(path: &str) -> Result<Arc<nnnoiseless::RnnModel>> {
+    let key = std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    // Held while loading, so threads asking for the same file wait for one read
+    let mut cache = WEIGHTS.get_or_init(Default::default).lock().unwrap();
+    if let Some(model) = cache.get(&key) {
+        return Ok(Arc::clone(model));
+    }
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read RNNoise weights: {path}"))?;
+    let model = nnnoiseless::RnnModel::from_bytes(&bytes)
+        .with_context(|| format!("Not an nnnoiseless weights file: {path}"))?;
+    let model = Arc::new(model);
+    cache.insert(key, Arc::clone(&model));
+    Ok(model)
+}
+
+impl Backend for Nnnoiseless {
+    fn denoise(&self, input: &Path, output: &Path, _model: Option<&str>) -> Result<()> {
+        self.chain(input, output, 1)
+    }
+
+    fn denoise_passes(
+        &self,
+        input: &Path,
+        output: &Path,
+        _model: Option<&str>,
+        passes: usize,
+    ) -> Option<Result<()>> {
+        Some(self.chain(input, output, passes))
+    }
+}
+
+impl Nnnoiseless {
+    /// Feeds every block through `passes` denoisers in a row, resampling to
+    /// the model's rate and back only once.
+    fn chain(&self, input: &Path, output: &Path, passes: usize) -> Result<()> {
+        let mut input = Upsampled::open(input)?;
+        let mut output = Downsampled::create(output, MODEL_RATE, input.spec())?;
+        let mut lives: Vec<Live> = (0..passes.max(1)).map(|_| self.live()).collect();
+        // The model works on 16-bit sample values held in floats
+        let to_model = |block: Vec<f32>| block.iter().map(|s| s * 32768.0).collect::<Vec<_>>();
+        let from_model = |block: Vec<f32>| block.iter().map(|s| s / 32768.0).collect::<Vec<_>>();
+        while let Some(block) = input.next_block()? {
+            let mut samples = to_model(block);
+            for live in &mut lives {
+                samples = live.push_float(&samples);
+            }
+            output.write(&from_model(samples))?;
+        }
+        // Each pass's tail still has to go through the passes after it
+        while !lives.is_empty() {
+            let mut samples = lives.remove(0).finish_float();
+            for live in &mut lives {
+                samples = live.push_float(&samples);
+            }
+            output.write(&from_model(samples))?;
+        }
+        output.finish()
+    }
+
+    /// A denoiser for audio that arrives a piece at a time.
+    pub fn live(&self) -> Live<'_> {
+        let hop = Live::FRAME / self.lanes;
+        Live {
+            lanes: (0..self.lanes)
+                .map(|i| Lane {
+                    state: match &self.model {
+                        Some(model) => nnnoiseless::DenoiseState::with_model(model),
+                        None => nnnoiseless::DenoiseState::new(),
+                    },
+                    // Each lane's frames start `hop` samples after the previous lane's
+                    pending: vec![0.0; i * hop],
+                    started: false,
+                    skip: i * hop,
+                    ready: VecDeque::new(),
+                })
+                .collect(),
+            pending: 0,
+        }
+    }
+}
+
+/// Denoises 48kHz 16-bit mono samples as they arrive, a 10ms frame at a time.
+/// The `_float` methods take and return 16-bit sample values held in floats.
+/// Output keeps pace with input, less the samples of an unfinished frame
+/// (and, with `--overlap`, of the frames of the lanes that start later).
+pub struct Live<'a> {
+    /// The staggered denoisers, whose outputs are averaged.
+    lanes: Vec<Lane<'a>>,
+    /// Samples taken but not yet returned.
+    pending: usize,
+}
+
+/// One denoiser of a [`Live`], fed the input a fixed number of samples late.
+struct Lane<'a> {
+    state: Box<nnnoiseless::DenoiseState<'a>>,
+    /// Samples of the frame still being filled.
+    pending: Vec<f32>,
+    /// Whether the first frame, whose output is the model's one frame of lag, is behind us.
+    started: bool,
+    /// Output samples still to drop for the silence this lane started with.
+    skip: usize,
+    /// Output lined up with the input, not yet returned.
+    ready: VecDeque<f32>,
+}
+
+impl Live<'_> {
+    const FRAME: usize = nnnoiseless::DenoiseState::FRAME_SIZE;
+
+    /// Takes more samples and returns whatever output they complete.
+    pub fn push(&mut self, samples: &[i16]) -> Vec<i16> {
+        let samples: Vec<f32> = samples.iter().map(|&s| f32::from(s)).collect();
+        to_i16(self.push_float(&samples))
+    }
+
+    /// Returns the rest of the output once the input has ended.
+    pub fn finish(self) -> Vec<i16> {
+        to_i16(self.finish_float())
+    }
+
+    pub fn push_float(&mut self, samples: &[f32]) -> Vec<f32> {
+        self.pending += samples.len();
+        for lane in &mut self.lanes {
+            lane.push(samples);
+        }
+        self.take()
+    }
+
+    pub fn finish_float(mut self) -> Vec<f32> {
+        let owed = self.pending;
+        // Frames of silence flush the unfinished frames and the lag
+        for lane in &mut self.lanes {
+            while lane.ready.len() < owed {
+                lane.push(&[0.0; Self::FRAME]);
+            }
+        }
+        self.take()
+    }
+
+    /// The average of the lanes where all of them have output, at most what is owed.
+    fn take(&mut self) -> Vec<f32> {
+        let len = self
+            .lanes
+            .iter()
+            .map(|lane| lane.ready.len())
+            .min()
+            .unwrap_or_default()
+            .min(self.pending);
+        self.pending -= len;
+        let scale = 1.0 / self.lanes.len() as f32;
+        let mut denoised = vec![0.0; len];
+        for lane in &mut self.lanes {
+            for (out, sample) in denoised.iter_mut().zip(lane.ready.drain(..len)) {
+                *out += sample * scale;
+            }
+        }
+        denoised
+    }
+}
+
+impl Lane<'_> {
+    fn push(&mut self, samples: &[f32]) {
+        self.pending.extend(samples);
+        let whole = self.pending.len() - self.pending.len() % Live::FRAME;
+        let frames: Vec<f32> = self.pending.drain(..whole).collect();
+        for frame in frames.chunks_exact(Live::FRAME) {
+            let mut out = [0.0; Live::FRAME];
+            self.state.process_frame(&mut out, frame);
+            if self.started {
+                let skipped = self.skip.min(Live::FRAME);
+                self.skip -= skipped;
+                self.ready.extend(&out[skipped..]);
+            }
+            self.started = true;
+        }
+    }
+}
+
+fn to_i16(samples: Vec<f32>) -> Vec<i16> {
+    samples
+        .iter()
+        .map(|s| s.round().clamp(-32768.0, 32767.0) as i16)
+        .collect()
+}
+
+/// The reference RNNoise C implementation through its `rnnoise_demo` program,
+/// which reads and writes raw 48kHz 16-bit PCM.
+pub struct Rnnoise(Program);
+
+impl Rnnoise {
+    pub fn new(program: Option<&str>, args: Option<ArgsTemplate>) -> Self {
+        Rnnoise(Program::new("rnnoise_demo", program, args))
+    }
+}
+
+impl Backend for Rnnoise {
+    fn denoise(&self, input: &Path, output: &Path, model: Option<&str>) -> Result<()> {
+        let mut staged = Staged::default();
+        let raw_in = staged.path(output, "rnnoise.in");
+        let raw_out = staged.path(output, "rnnoise.out");
+
+        let mut input = Upsampled::open(input)?;
+        let spec = input.spec();
+        let file = File::create(&raw_in)
+            .with_context(|| format!("Failed to create file: {}", raw_in.display()))?;
+        let mut pcm = BufWriter::new(file);
+        while let Some(block) = input.next_block()? {
+            for s in block {
+                pcm.write_all(&((s.clamp(-1.0, 1.0) * 32767.0).round() as i16).to_le_bytes())
+                    .with_context(|| format!("Failed to write file: {}", raw_in.display()))?;
+            }
+        }
+        pcm.flush()
+            .with_context(|| format!("Failed to write file: {}", raw_in.display()))?;
+        drop(pcm);
+        self.0.run(&raw_in, &raw_out, model, || {
+            vec![
+                raw_in.to_string_lossy().to_string(),
+                raw_out.to_string_lossy().to_string(),
+            ]
+        })?;
+
+        let file = File::open(&raw_out)
+            .with_context(|| format!("Failed to read file: {}", raw_out.display()))?;
+        let mut pcm = BufReader::new(file);
+        let mut output = Downsampled::create(output, MODEL_RATE, spec)?;
+        let mut bytes = Vec::with_capacity(audio::BLOCK_FRAMES * 2);
+        loop {
+            bytes.clear();
+            (&mut pcm)
+                .take(audio::BLOCK_FRAMES as u64 * 2)
+                .read_to_end(&mut bytes)
+                .with_context(|| format!("Failed to read file: {}", raw_out.display()))?;
+            if bytes.is_empty() {
+                break;
+            }
+            let samples: Vec<f32> = bytes
+                .chunks_exact(2)
+                .map(|b| f32::from(i16::from_le_bytes([b[0], b[1]])) / 32768.0)
+                .collect();
+            output.write(&samples)?;
+        }
+        output.finish()
+    }
+}
+
+/// DeepFilterNet through its `deep-filter` program, which writes the enhanced
+/// file under the same name into an output directory, the `{out}` of its
+/// `--backend-args`.
+pub struct DeepFilterNet(Program);
+
+impl DeepFilterNet {
+    pub fn new(program: Option<&str>, args: Option<ArgsTemplate>) -> Self {
+        DeepFilterNet(Program::new("deep-filter", program, args))
+    }
+}
+
+impl Backend for DeepFilterNet {
+    fn denoise(&self, input: &Path, output: &Path, model: Option<&str>) -> Result<()> {
+        let mut staged = Staged::default();
+        let upsampled = staged.path(output, "dfn");
+        let mut input = Upsampled::open(input)?;
+        let spec = input.spec();
+        let model_spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: MODEL_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = audio::FrameWriter::create(&upsampled, model_spec, false)?;
+        while let Some(block) = input.next_block()? {
+            writer.write(&block)?;
+        }
+        writer.finish()?;
+        let out_dir = output.with_file_name(format!(
+            ".{}.dfn.tmp",
+            output.file_name().unwrap_or_default().to_string_lossy()
+        ));
+
+        let result = self
+            .0
+            .run(&upsampled, &out_dir, model, || {
+                let mut args = vec![
+                    "--output-dir".to_string(),
+                    out_dir.to_string_lossy().to_string(),
+                ];
+                if let Some(model) = model {
+                    args.extend(["--model".to_string(), model.to_string()]);
+                }
+                args.push(upsampled.to_string_lossy().to_string());
+                args
+            })
+            .and_then(|()| {
+                let mut enhanced =
+                    audio::FrameReader::open(&out_dir.join(upsampled.file_name().unwrap()))?;
+                let channels = usize::from(enhanced.spec().channels.max(1));
+                let mut output = Downsampled::create(output, enhanced.spec().sample_rate, spec)?;
+                while let Some(block) = enhanced.next_block()? {
+                    let first: Vec<f32> = block.iter().step_by(channels).copied().collect();
+                    output.write(&first)?;
+                }
+                output.finish()
+            });
+        let _ = std::fs::remove_dir_all(&out_dir);
+        result
+    }
+}
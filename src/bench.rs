@@ -0,0 +1,109 @@
+//! The `bench` subcommand: times the pipeline at increasing worker counts so
+//! `--jobs` and backends can be compared on the local hardware.
+
+use crate::audio::write_wav;
+use crate::corpus::noisy_speech;
+use crate::pipeline::{Outcome, Pipeline, PipelineArgs};
+use crate::rng::Rng;
+use anyhow::{Context, Result, bail};
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    /// WAV file to denoise [default: a generated 10 s noisy test tone]
+    file: Option<PathBuf>,
+
+    /// Times the file is denoised at each worker count
+    #[arg(long, default_value_t = 8)]
+    runs: usize,
+
+    /// Largest worker count to try [default: number of CPUs]
+    #[arg(long)]
+    max_jobs: Option<usize>,
+
+    #[command(flatten)]
+    pipeline: PipelineArgs,
+}
+
+/// Runs the benchmark and prints one row per worker count.
+pub fn run(args: &BenchArgs, models_dir: Option<&Path>) -> Result<()> {
+    if args.runs == 0 {
+        bail!("--runs must be at least 1");
+    }
+    let pipeline = Pipeline::new(&args.pipeline, models_dir)?;
+    let scratch = std::env::temp_dir().join(format!(
+        "{}-bench-{}",
+        env!("CARGO_PKG_NAME"),
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&scratch)
+        .with_context(|| format!("Failed to create directory: {}", scratch.display()))?;
+    let result = bench(args, &pipeline, &scratch);
+    let _ = std::fs::remove_dir_all(&scratch);
+    result
+}
+
+fn bench(args: &BenchArgs, pipeline: &Pipeline, scratch: &Path) -> Result<()> {
+    let input = match &args.file {
+        Some(file) => file.clone(),
+        None => {
+            let path = scratch.join("tone.wav");
+            let target = args.pipeline.target;
+            let samples = noisy_speech(target.expect_rate, 10.0, 10.0, &mut Rng::new(1));
+            write_wav(&path, &samples, target.expect_rate, 16, 1)?;
+            path
+        }
+    };
+    let seconds = crate::walk::wav_duration(&input)
+        .with_context(|| format!("Not a readable WAV file: {}", input.display()))?;
+    let megabytes = std::fs::metadata(&input)?.len() as f64 / 1e6;
+
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let max_jobs = args.max_jobs.unwrap_or(cpus).max(1);
+    // Powers of two up to the limit, and the limit itself
+    let mut counts: Vec<usize> = std::iter::successors(Some(1), |n| Some(n * 2))
+        .take_while(|&n| n < max_jobs)
+        .collect();
+    counts.push(max_jobs);
+
+    println!(
+        "Denoising {} ({seconds:.1} s) {} times per worker count",
+        input.display(),
+        args.runs
+    );
+    println!(
+        "{:>7} {:>9} {:>9} {:>8} {:>7}",
+        "workers", "wall (s)", "realtime", "files/s", "MB/s"
+    );
+    for jobs in counts {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build()
+            .context("Failed to create thread pool")?;
+        let started = Instant::now();
+        pool.install(|| {
+            (0..args.runs).into_par_iter().try_for_each(|i| {
+                let output = scratch.join(format!("out{i}.wav"));
+                match pipeline.process(&input, &output)? {
+                    Outcome::Processed => Ok(()),
+                    Outcome::Skipped(reason)
+                    | Outcome::Invalid(reason)
+                    | Outcome::Failed(_, reason) => {
+                        bail!("Cannot benchmark {}: {reason}", input.display())
+                    }
+                }
+            })
+        })?;
+        let wall = started.elapsed().as_secs_f64().max(1e-9);
+        let runs = args.runs as f64;
+        println!(
+            "{jobs:>7} {wall:>9.2} {:>8.1}x {:>8.2} {:>7.2}",
+            seconds * runs / wall,
+            runs / wall,
+            megabytes * runs / wall
+        );
+    }
+    Ok(())
+}
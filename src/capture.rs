@@ -0,0 +1,97 @@
+//! `--capture-backend-output`: what backend programs and plugins print is
+//! collected per file, for the `--report` and `--backend-log` files, instead
+//! of interleaving on the console across parallel workers.
+
+use anyhow::{Context, Result};
+use std::cell::RefCell;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// The output collected for one file.
+pub type Log = Arc<Mutex<Vec<u8>>>;
+
+thread_local! {
+    static CURRENT: RefCell<Option<Log>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with the output of processes it starts on this thread going to `log`.
+pub fn collect<T>(log: Option<Log>, f: impl FnOnce() -> T) -> T {
+    let previous = CURRENT.with(|current| current.replace(log));
+    let result = f();
+    CURRENT.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// Where process output on this thread goes, to hand on to other threads.
+pub fn current() -> Option<Log> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// The collected output as text, or `None` when nothing was printed.
+pub fn text(log: &Log) -> Option<String> {
+    let bytes = log.lock().unwrap();
+    let text = String::from_utf8_lossy(&bytes).trim_end().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+/// Runs `command` to completion like [`Command::status`], collecting its
+/// stdout and stderr while output is collected on this thread.
+pub fn status(command: &mut Command) -> io::Result<ExitStatus> {
+    let Some(log) = current() else {
+        return command.status();
+    };
+    let output = command.stdin(Stdio::null()).output()?;
+    let mut bytes = log.lock().unwrap();
+    bytes.extend(&output.stdout);
+    bytes.extend(&output.stderr);
+    Ok(output.status)
+}
+
+/// Pipes `command`'s stderr while output is collected on this thread, for a
+/// process whose stdin and stdout are taken; see [`Stderr`].
+pub fn pipe_stderr(command: &mut Command) -> &mut Command {
+    let stderr = if current().is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::inherit()
+    };
+    command.stderr(stderr)
+}
+
+/// Reads the piped stderr of a process in the background, so it cannot fill
+/// up while the process is talked to, until [`Stderr::finish`].
+pub struct Stderr(Option<(Log, JoinHandle<Vec<u8>>)>);
+
+impl Stderr {
+    pub fn start(child: &mut Child) -> Self {
+        Stderr(current().zip(child.stderr.take()).map(|(log, mut stderr)| {
+            let reader = std::thread::spawn(move || {
+                let mut bytes = Vec::new();
+                let _ = stderr.read_to_end(&mut bytes);
+                bytes
+            });
+            (log, reader)
+        }))
+    }
+
+    /// Adds what the process wrote to the log; call once it has exited.
+    pub fn finish(self) {
+        if let Some((log, reader)) = self.0
+            && let Ok(bytes) = reader.join()
+        {
+            log.lock().unwrap().extend(bytes);
+        }
+    }
+}
+
+/// Writes `text` to `OUTPUT.log` beside `output`, e.g. `clip.wav.log`.
+pub fn write_log(output: &Path, text: &str) -> Result<()> {
+    let mut name = output.as_os_str().to_owned();
+    name.push(".log");
+    let path = PathBuf::from(name);
+    std::fs::write(&path, format!("{text}\n"))
+        .with_context(|| format!("Failed to write backend log: {}", path.display()))
+}
@@ -0,0 +1,159 @@
+//! `--chunk-secs`: long files are cut into overlapping chunks that the backend
+//! denoises in parallel, and the results are stitched back together by
+//! crossfading across each overlap, so the chunk weights always sum to one.
+
+use crate::audio::{BLOCK_FRAMES, FrameReader, FrameWriter};
+use anyhow::{Result, bail};
+use serde::Serialize;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Args, Debug, Clone, Copy, Serialize)]
+pub struct ChunkArgs {
+    /// Cut files longer than this into chunks that are denoised in parallel and stitched by overlap-add
+    #[arg(long, value_name = "SECS")]
+    pub chunk_secs: Option<f64>,
+
+    /// How long neighbouring --chunk-secs chunks overlap and crossfade
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 500,
+        requires = "chunk_secs"
+    )]
+    pub chunk_overlap_ms: u64,
+}
+
+impl ChunkArgs {
+    /// The chunker, when `--chunk-secs` is given.
+    pub fn chunker(&self) -> Result<Option<Chunker>> {
+        let Some(chunk_secs) = self.chunk_secs else {
+            return Ok(None);
+        };
+        if !chunk_secs.is_finite() || chunk_secs <= 0.0 {
+            bail!("--chunk-secs must be positive");
+        }
+        let overlap_secs = self.chunk_overlap_ms as f64 / 1000.0;
+        // Each overlap must end before the next one starts
+        if overlap_secs * 2.0 > chunk_secs {
+            bail!("--chunk-overlap-ms must be at most half of --chunk-secs");
+        }
+        Ok(Some(Chunker {
+            chunk_secs,
+            overlap_secs,
+        }))
+    }
+}
+
+pub struct Chunker {
+    chunk_secs: f64,
+    overlap_secs: f64,
+}
+
+impl Chunker {
+    /// The frame ranges `path` is cut into, each overlapping the next; a
+    /// single range when the file is no longer than one chunk.
+    pub fn plan(&self, path: &Path) -> Result<Vec<Range<usize>>> {
+        let reader = FrameReader::open(path)?;
+        let frames = reader.frames();
+        let rate = f64::from(reader.spec().sample_rate);
+        let chunk = ((self.chunk_secs * rate).round() as usize).max(2);
+        let overlap = ((self.overlap_secs * rate).round() as usize).min(chunk / 2);
+        let mut ranges = Vec::new();
+        ranges.push(0..chunk.min(frames));
+        while let Some(last) = ranges.last()
+            && last.end < frames
+        {
+            let start = last.end - overlap;
+            ranges.push(start..(start + chunk).min(frames));
+        }
+        Ok(ranges)
+    }
+}
+
+/// Writes each of `ranges` of `source` to the matching path of `pieces`,
+/// keeping at most the chunks under the current block open.
+pub fn cut(source: &Path, ranges: &[Range<usize>], pieces: &[PathBuf]) -> Result<()> {
+    let mut reader = FrameReader::open(source)?;
+    let spec = reader.spec();
+    let channels = usize::from(spec.channels.max(1));
+    let mut open: Vec<(usize, FrameWriter)> = Vec::new();
+    let (mut next, mut pos) = (0, 0);
+    while let Some(block) = reader.next_block()? {
+        let end = pos + block.len() / channels;
+        while next < ranges.len() && ranges[next].start < end {
+            open.push((next, FrameWriter::create(&pieces[next], spec, false)?));
+            next += 1;
+        }
+        for (i, writer) in &mut open {
+            let (from, to) = (ranges[*i].start.max(pos), ranges[*i].end.min(end));
+            if from < to {
+                writer.write(&block[(from - pos) * channels..(to - pos) * channels])?;
+            }
+        }
+        let (done, rest): (Vec<_>, Vec<_>) =
+            open.into_iter().partition(|(i, _)| ranges[*i].end <= end);
+        for (_, writer) in done {
+            writer.finish()?;
+        }
+        open = rest;
+        pos = end;
+    }
+    for (_, writer) in open {
+        writer.finish()?;
+    }
+    Ok(())
+}
+
+/// Joins the denoised chunks `outputs` of `ranges` into `dest`, fading each
+/// overlap linearly from one chunk into the next. Chunks the backend returned
+/// short are padded with silence, and long ones are truncated.
+pub fn stitch(outputs: &[PathBuf], ranges: &[Range<usize>], dest: &Path) -> Result<()> {
+    let spec = FrameReader::open(&outputs[0])?.spec();
+    let channels = usize::from(spec.channels.max(1));
+    let mut writer = FrameWriter::create(dest, spec, false)?;
+    // The previous chunk's samples over the current chunk's overlap
+    let mut tail: Vec<f32> = Vec::new();
+    for (i, (output, range)) in outputs.iter().zip(ranges).enumerate() {
+        let mut reader = FrameReader::open(output)?;
+        if reader.spec().channels != spec.channels {
+            bail!(
+                "The denoiser returned {} channels for chunk {} and {} for the first",
+                reader.spec().channels,
+                i + 1,
+                spec.channels
+            );
+        }
+        let head = tail.len() / channels;
+        let keep_from = ranges
+            .get(i + 1)
+            .map_or(range.len(), |next| next.start - range.start);
+        let mut next_tail = Vec::with_capacity((range.len() - keep_from) * channels);
+        let mut frame = 0;
+        while frame < range.len() {
+            let mut block = reader.next_block()?.unwrap_or_default();
+            if block.is_empty() {
+                block = vec![0.0; (range.len() - frame).min(BLOCK_FRAMES) * channels];
+            }
+            block.truncate((range.len() - frame) * channels);
+            let mut out = Vec::with_capacity(block.len());
+            for samples in block.chunks(channels) {
+                if frame < head {
+                    let weight = (frame as f32 + 0.5) / head as f32;
+                    for (c, &s) in samples.iter().enumerate() {
+                        let before = tail[frame * channels + c];
+                        out.push(before + (s - before) * weight);
+                    }
+                } else if frame >= keep_from {
+                    next_tail.extend_from_slice(samples);
+                } else {
+                    out.extend_from_slice(samples);
+                }
+                frame += 1;
+            }
+            writer.write(&out)?;
+        }
+        tail = next_tail;
+    }
+    writer.finish()
+}
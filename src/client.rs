@@ -0,0 +1,254 @@
+//! The `client` subcommand: hands files to a `serve` instance elsewhere and
+//! fetches the results, so small machines can use a central server.
+
+use crate::queue::Priority;
+use anyhow::{Context, Result, bail};
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+use ureq::Agent;
+
+#[derive(clap::Args, Debug)]
+pub struct ClientArgs {
+    /// Base URL of the server, e.g. http://denoise.internal:8080
+    #[arg(long, value_name = "URL")]
+    server: String,
+
+    /// Bearer token to send with every request
+    #[arg(long)]
+    token: Option<String>,
+
+    #[command(subcommand)]
+    action: ClientCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum ClientCommand {
+    /// Upload files, wait for their jobs and download the denoised results
+    Denoise {
+        /// WAV files to denoise
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Directory to write the results to, under the input file names [default: beside each input as STEM_denoised.wav]
+        #[arg(long)]
+        output_dir: Option<PathBuf>,
+
+        /// Files in flight at once
+        #[arg(long, default_value_t = 4)]
+        jobs: usize,
+
+        /// Seconds between status checks of a job, and before retrying a full queue
+        #[arg(long, default_value_t = 1.0, value_name = "SECS")]
+        poll_interval: f64,
+
+        /// Leave finished jobs on the server instead of deleting them
+        #[arg(long)]
+        keep_jobs: bool,
+
+        /// Queue priority of the jobs; bulk runs should use low so interactive uploads go first
+        #[arg(long, value_enum, default_value_t = Priority::Normal)]
+        priority: Priority,
+    },
+}
+
+#[derive(Deserialize)]
+struct Submitted {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct JobStatus {
+    status: String,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+struct Client<'a> {
+    agent: Agent,
+    server: &'a str,
+    token: Option<&'a str>,
+    poll_interval: Duration,
+    priority: Priority,
+}
+
+/// Runs the client; exits with 1 when any file could not be denoised.
+pub fn run(args: &ClientArgs) -> Result<ExitCode> {
+    let ClientCommand::Denoise {
+        files,
+        output_dir,
+        jobs,
+        poll_interval,
+        keep_jobs,
+        priority,
+    } = &args.action;
+    if !poll_interval.is_finite() || *poll_interval <= 0.0 {
+        bail!("--poll-interval must be positive");
+    }
+    if let Some(dir) = output_dir {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create output directory: {}", dir.display()))?;
+    }
+    let client = Client {
+        // Error statuses are answers to act on here, not failures
+        agent: Agent::config_builder()
+            .http_status_as_error(false)
+            .build()
+            .into(),
+        server: args.server.trim_end_matches('/'),
+        token: args.token.as_deref(),
+        poll_interval: Duration::from_secs_f64(*poll_interval),
+        priority: *priority,
+    };
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads((*jobs).max(1))
+        .build()
+        .context("Failed to create thread pool")?;
+    let failed = pool.install(|| {
+        files
+            .par_iter()
+            .filter(|input| {
+                let output = match output_dir {
+                    Some(dir) => dir.join(input.file_name().unwrap_or(input.as_os_str())),
+                    None => beside(input),
+                };
+                match client.denoise(input, &output, *keep_jobs) {
+                    Ok(()) => {
+                        println!("{} -> {}", input.display(), output.display());
+                        false
+                    }
+                    Err(e) => {
+                        eprintln!("Error denoising {}: {e:#}", input.display());
+                        true
+                    }
+                }
+            })
+            .count()
+    });
+    eprintln!(
+        "{} files denoised by {}, {failed} failed.",
+        files.len() - failed,
+        client.server
+    );
+    Ok(if failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// `DIR/STEM_denoised.wav` for `DIR/STEM.EXT`.
+fn beside(input: &Path) -> PathBuf {
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    input.with_file_name(format!("{stem}_denoised.wav"))
+}
+
+impl Client<'_> {
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.server)
+    }
+
+    fn authorize<T>(&self, request: ureq::RequestBuilder<T>) -> ureq::RequestBuilder<T> {
+        match self.token {
+            Some(token) => request.header("Authorization", format!("Bearer {token}")),
+            None => request,
+        }
+    }
+
+    fn denoise(&self, input: &Path, output: &Path, keep_job: bool) -> Result<()> {
+        let id = self.submit(input)?;
+        let result = self.wait(&id).and_then(|()| self.download(&id, output));
+        if !keep_job {
+            let _ = self
+                .authorize(self.agent.delete(self.url(&format!("/jobs/{id}"))))
+                .call();
+        }
+        result
+    }
+
+    /// Uploads `input`, waiting while the server's queue is full or the token is rate limited.
+    fn submit(&self, input: &Path) -> Result<String> {
+        loop {
+            let file = std::fs::File::open(input)
+                .with_context(|| format!("Failed to open file: {}", input.display()))?;
+            let mut response = self
+                .authorize(
+                    self.agent
+                        .post(self.url(&format!("/jobs?priority={}", self.priority.name()))),
+                )
+                .header("Content-Type", "audio/wav")
+                .send(file)
+                .with_context(|| format!("Failed to upload to {}", self.server))?;
+            match response.status().as_u16() {
+                202 => {
+                    let submitted: Submitted = response
+                        .body_mut()
+                        .read_json()
+                        .context("Malformed response to the upload")?;
+                    return Ok(submitted.id);
+                }
+                // A full queue or spool drains as jobs finish
+                503 | 507 => std::thread::sleep(self.poll_interval),
+                429 => {
+                    let retry = response
+                        .headers()
+                        .get("Retry-After")
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.parse().ok())
+                        .map_or(self.poll_interval, Duration::from_secs);
+                    std::thread::sleep(retry);
+                }
+                401 => bail!("The server refused the token (401)"),
+                status => bail!("The server refused the upload ({status})"),
+            }
+        }
+    }
+
+    fn wait(&self, id: &str) -> Result<()> {
+        loop {
+            let mut response = self
+                .authorize(self.agent.get(self.url(&format!("/jobs/{id}"))))
+                .call()
+                .with_context(|| format!("Failed to check job {id}"))?;
+            if response.status() != 200 {
+                bail!("Checking job {id} failed ({})", response.status());
+            }
+            let job: JobStatus = response
+                .body_mut()
+                .read_json()
+                .with_context(|| format!("Malformed status of job {id}"))?;
+            match job.status.as_str() {
+                "done" => return Ok(()),
+                "failed" => bail!(
+                    "The server failed job {id}: {}",
+                    job.error.as_deref().unwrap_or("no reason given")
+                ),
+                _ => std::thread::sleep(self.poll_interval),
+            }
+        }
+    }
+
+    fn download(&self, id: &str, output: &Path) -> Result<()> {
+        let response = self
+            .authorize(self.agent.get(self.url(&format!("/jobs/{id}/result"))))
+            .call()
+            .with_context(|| format!("Failed to download the result of job {id}"))?;
+        if response.status() != 200 {
+            bail!(
+                "Downloading the result of job {id} failed ({})",
+                response.status()
+            );
+        }
+        // Written aside and renamed, so an interrupted download leaves no partial output
+        let partial = output.with_extension("wav.part");
+        let mut file = std::fs::File::create(&partial)
+            .with_context(|| format!("Failed to create file: {}", partial.display()))?;
+        std::io::copy(&mut response.into_body().into_reader(), &mut file)
+            .with_context(|| format!("Failed to download the result of job {id}"))?;
+        std::fs::rename(&partial, output)
+            .with_context(|| format!("Failed to write file: {}", output.display()))
+    }
+}
@@ -0,0 +1,164 @@
+//! Wall-clock helpers that avoid pulling in a date/time crate.
+
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Current UTC date (`YYYYMMDD`) and time (`HHMMSS`).
+pub fn utc_now() -> (String, String) {
+    utc(SystemTime::now())
+}
+
+/// UTC date (`YYYYMMDD`) and time (`HHMMSS`) of `time`.
+fn utc(time: SystemTime) -> (String, String) {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86400, secs % 86400);
+    // Civil-from-days conversion (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (
+        format!("{year:04}{month:02}{day:02}"),
+        format!("{:02}{:02}{:02}", rem / 3600, rem % 3600 / 60, rem % 60),
+    )
+}
+
+/// Current UTC time in ISO 8601, e.g. `2024-05-01T12:30:00Z`.
+pub fn iso8601_now() -> String {
+    iso8601(SystemTime::now())
+}
+
+/// `time` in UTC in ISO 8601, e.g. `2024-05-01T12:30:00Z`.
+pub fn iso8601(time: SystemTime) -> String {
+    let (date, time) = utc(time);
+    format!(
+        "{}-{}-{}T{}:{}:{}Z",
+        &date[..4],
+        &date[4..6],
+        &date[6..],
+        &time[..2],
+        &time[2..4],
+        &time[4..]
+    )
+}
+
+/// A span of time back from now such as `24h`, `30m`, `7d` or `1.5w`; plain
+/// numbers are seconds.
+#[derive(Debug, Clone, Copy)]
+pub struct Age(pub Duration);
+
+impl FromStr for Age {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let multiplier: f64 = match unit.trim() {
+            "" | "s" => 1.0,
+            "m" | "min" => 60.0,
+            "h" => 3600.0,
+            "d" => 86400.0,
+            "w" => 7.0 * 86400.0,
+            other => return Err(format!("unknown time unit '{other}' (s, m, h, d, w)")),
+        };
+        match number.parse::<f64>() {
+            Ok(n) if n >= 0.0 => Ok(Age(Duration::from_secs_f64(n * multiplier))),
+            _ => Err(format!("expected a time span such as 24h or 7d, got '{s}'")),
+        }
+    }
+}
+
+impl Age {
+    /// The moment this long ago.
+    pub fn before_now(self) -> SystemTime {
+        SystemTime::now().checked_sub(self.0).unwrap_or(UNIX_EPOCH)
+    }
+}
+
+/// A moment given as an ISO 8601 date or date and time, e.g. `2024-05-01`,
+/// `2024-05-01T12:30:00Z` or `2024-05-01 12:30:00+02:00`, or as Unix seconds
+/// after an `@`, e.g. `@1714566600`. Times without an offset are UTC.
+#[derive(Debug, Clone, Copy)]
+pub struct Timestamp(pub SystemTime);
+
+impl FromStr for Timestamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!("expected a timestamp such as 2024-05-01T12:30:00Z or @1714566600, got '{s}'")
+        };
+        let s = s.trim();
+        if let Some(secs) = s.strip_prefix('@') {
+            let secs: u64 = secs.parse().map_err(|_| invalid())?;
+            return Ok(Timestamp(UNIX_EPOCH + Duration::from_secs(secs)));
+        }
+        let number = |part: Option<&str>, range: std::ops::RangeInclusive<i64>| {
+            part.and_then(|p| p.parse::<i64>().ok())
+                .filter(|n| range.contains(n))
+                .ok_or_else(invalid)
+        };
+        let (date, time) = match s.find(['T', 't', ' ']) {
+            Some(at) => (&s[..at], &s[at + 1..]),
+            None => (s, ""),
+        };
+        let mut fields = date.split('-');
+        let year = number(fields.next(), 1970..=9999)?;
+        let month = number(fields.next(), 1..=12)?;
+        let day = number(fields.next(), 1..=31)?;
+        if fields.next().is_some() {
+            return Err(invalid());
+        }
+
+        // The offset east of UTC, in seconds
+        let (clock, offset) = match time.find(['Z', 'z', '+', '-']) {
+            Some(at) => {
+                let zone = &time[at..];
+                let offset = match zone {
+                    "Z" | "z" => 0,
+                    _ => {
+                        let sign = if zone.starts_with('-') { -1 } else { 1 };
+                        let (hours, mins) = zone[1..].split_once(':').unwrap_or((&zone[1..], "0"));
+                        sign * (number(Some(hours), 0..=23)? * 3600
+                            + number(Some(mins), 0..=59)? * 60)
+                    }
+                };
+                (&time[..at], offset)
+            }
+            None => (time, 0),
+        };
+        let mut fields = clock.split(':');
+        let (hour, minute, second) = if clock.is_empty() {
+            (0, 0, 0)
+        } else {
+            (
+                number(fields.next(), 0..=23)?,
+                number(fields.next(), 0..=59)?,
+                number(Some(fields.next().unwrap_or("0")), 0..=60)?,
+            )
+        };
+        if fields.next().is_some() {
+            return Err(invalid());
+        }
+
+        // Days-from-civil conversion (Howard Hinnant's algorithm)
+        let y = if month <= 2 { year - 1 } else { year };
+        let era = y.div_euclid(400);
+        let yoe = y.rem_euclid(400);
+        let mp = (month + 9) % 12;
+        let doy = (153 * mp + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+        let secs = days * 86400 + hour * 3600 + minute * 60 + second - offset;
+        let secs = u64::try_from(secs).map_err(|_| invalid())?;
+        Ok(Timestamp(UNIX_EPOCH + Duration::from_secs(secs)))
+    }
+}
@@ -0,0 +1,283 @@
+//! The `compare` subcommand: denoises the same inputs with several models,
+//! each into a subdirectory of its own, and tabulates how each model did.
+
+use crate::manifest::field;
+use crate::noise;
+use crate::pipeline::{Outcome, Pipeline, PipelineArgs};
+use crate::quality::{self, Quality};
+use crate::walk::{self, SymlinkPolicy, WalkOptions};
+use anyhow::{Context, Result, bail};
+use rayon::prelude::*;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+#[derive(clap::Args, Debug)]
+pub struct CompareArgs {
+    /// Directory of inputs to denoise with every model
+    input_dir: PathBuf,
+
+    /// Directory to write OUTPUT_DIR/MODEL/... and the compare.csv and compare-files.csv tables to
+    output_dir: PathBuf,
+
+    /// Comma-separated models to compare, as given to --model
+    #[arg(long, value_delimiter = ',', required = true, value_name = "MODEL,...")]
+    models: Vec<String>,
+
+    /// Compare on only this many randomly chosen inputs
+    #[arg(long, value_name = "N")]
+    sample: Option<usize>,
+
+    /// Seed for --sample [default: from the clock, printed]
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Score outputs against the clean recordings at the same relative paths here
+    #[arg(long, value_name = "DIR")]
+    reference_dir: Option<PathBuf>,
+
+    /// Comma-separated file extensions to process, matched case-sensitively
+    #[arg(long, value_delimiter = ',', default_value = "wav")]
+    extensions: Vec<String>,
+
+    /// Files processed concurrently [default: one per API address]
+    #[arg(long)]
+    jobs: Option<usize>,
+
+    #[command(flatten)]
+    pipeline: PipelineArgs,
+}
+
+/// How one model did on one input.
+struct Run {
+    model: usize,
+    file: String,
+    /// `ok`, `skipped`, `invalid` or `failed`.
+    status: &'static str,
+    reason: String,
+    secs: f64,
+    audio_secs: f64,
+    /// Level of the noise left in the output.
+    residual_noise_dbfs: Option<f64>,
+    quality: Option<Quality>,
+}
+
+/// Runs every model over the inputs and writes the two tables.
+pub fn run(args: &CompareArgs, models_dir: Option<&Path>) -> Result<()> {
+    if args.pipeline.model.is_some() {
+        bail!("compare takes its models from --models, not --model");
+    }
+    // Each model's outputs go under its file stem, so the stems must differ
+    let names: Vec<String> = args
+        .models
+        .iter()
+        .map(|model| {
+            Path::new(model)
+                .file_stem()
+                .map_or_else(|| model.clone(), |s| s.to_string_lossy().to_string())
+        })
+        .collect();
+    if names.iter().collect::<BTreeSet<_>>().len() < names.len() {
+        bail!("The models' file names must differ, as they name the output subdirectories");
+    }
+    let pipelines = args
+        .models
+        .iter()
+        .map(|model| {
+            let mut pipeline_args = args.pipeline.clone();
+            pipeline_args.model = Some(model.clone());
+            Pipeline::new(&pipeline_args, models_dir)
+                .with_context(|| format!("Cannot use model {model}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let discovered = walk::discover(
+        &args.input_dir,
+        &WalkOptions {
+            follow_symlinks: false,
+            symlink_files: SymlinkPolicy::Process,
+            max_depth: None,
+            extensions: args.extensions.clone(),
+        },
+    );
+    let mut files = discovered.files;
+    if files.is_empty() {
+        bail!("No input files found in {}", args.input_dir.display());
+    }
+    if let Some(count) = args.sample
+        && count < files.len()
+    {
+        let seed = args.seed.unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64)
+        });
+        println!("Sampling {count} of {} files (--seed {seed}).", files.len());
+        walk::sample(&mut files, count, seed);
+    }
+    files.sort_by(|a, b| a.path().cmp(b.path()));
+
+    let jobs = args
+        .jobs
+        .unwrap_or_else(|| pipelines[0].default_jobs())
+        .max(1);
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .context("Failed to create thread pool")?;
+    println!(
+        "Denoising {} files with each of {} models",
+        files.len(),
+        pipelines.len()
+    );
+    let tasks: Vec<(usize, &Path)> = (0..pipelines.len())
+        .flat_map(|model| files.iter().map(move |e| (model, e.path())))
+        .collect();
+    let runs: Vec<Run> = pool.install(|| {
+        tasks
+            .par_iter()
+            .map(|&(model, input)| {
+                let relative = input.strip_prefix(&args.input_dir).unwrap_or(input);
+                let output = args.output_dir.join(&names[model]).join(relative);
+                let file = relative.to_string_lossy().to_string();
+                run_one(args, &pipelines[model], model, file, input, &output)
+            })
+            .collect()
+    });
+
+    std::fs::create_dir_all(&args.output_dir).with_context(|| {
+        format!(
+            "Failed to create output directory: {}",
+            args.output_dir.display()
+        )
+    })?;
+    let files_csv = args.output_dir.join("compare-files.csv");
+    write(&files_csv, &per_file(&runs, &names))?;
+    let models_csv = args.output_dir.join("compare.csv");
+    let table = per_model(&runs, &names);
+    write(&models_csv, &table)?;
+    print!("{}", table.replace(',', "\t"));
+    println!("Wrote {} and {}", models_csv.display(), files_csv.display());
+    Ok(())
+}
+
+fn run_one(
+    args: &CompareArgs,
+    pipeline: &Pipeline,
+    model: usize,
+    file: String,
+    input: &Path,
+    output: &Path,
+) -> Run {
+    let started = Instant::now();
+    let outcome = pipeline.process(input, output).unwrap_or_else(|e| {
+        eprintln!("Error processing {}: {:?}", input.display(), e);
+        Outcome::failed(&e)
+    });
+    let secs = started.elapsed().as_secs_f64();
+    let (status, reason) = match outcome {
+        Outcome::Processed => ("ok", String::new()),
+        Outcome::Skipped(reason) => ("skipped", reason),
+        Outcome::Invalid(reason) => ("invalid", reason),
+        Outcome::Failed(_, reason) => ("failed", reason),
+    };
+    let mut run = Run {
+        model,
+        file,
+        status,
+        reason,
+        secs,
+        audio_secs: 0.0,
+        residual_noise_dbfs: None,
+        quality: None,
+    };
+    if status != "ok" {
+        return run;
+    }
+    run.audio_secs = walk::wav_duration(output).unwrap_or(0.0);
+    match noise::profile_file(output) {
+        Ok(profile) => run.residual_noise_dbfs = Some(profile.level_dbfs),
+        Err(e) => eprintln!("Warning: no noise level for {}: {e:#}", output.display()),
+    }
+    if let Some(dir) = &args.reference_dir {
+        match quality::find_reference(dir, &run.file) {
+            Some(reference) => match quality::compare_files(&reference, output) {
+                Ok(quality) => run.quality = Some(quality),
+                Err(e) => {
+                    eprintln!("Warning: no quality scores for {}: {e:#}", output.display())
+                }
+            },
+            None => eprintln!(
+                "Warning: no reference for {} in {}",
+                run.file,
+                dir.display()
+            ),
+        }
+    }
+    run
+}
+
+fn write(path: &Path, csv: &str) -> Result<()> {
+    std::fs::write(path, csv).with_context(|| format!("Failed to write file: {}", path.display()))
+}
+
+/// A CSV cell of an optional figure: empty when missing.
+fn cell(value: Option<f64>, digits: usize) -> String {
+    value.map_or_else(String::new, |v| format!("{v:.digits$}"))
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    (count > 0).then(|| sum / f64::from(count))
+}
+
+fn per_file(runs: &[Run], names: &[String]) -> String {
+    let mut csv = String::from(
+        "model,file,status,reason,secs,residual_noise_dbfs,stoi,pesq_approx,si_sdr_db\n",
+    );
+    for run in runs {
+        csv.push_str(&format!(
+            "{},{},{},{},{:.3},{},{},{},{}\n",
+            field(&names[run.model]),
+            field(&run.file),
+            run.status,
+            field(&run.reason),
+            run.secs,
+            cell(run.residual_noise_dbfs, 1),
+            cell(run.quality.and_then(|q| q.stoi), 3),
+            cell(run.quality.and_then(|q| q.pesq_approx), 2),
+            cell(run.quality.map(|q| q.si_sdr_db), 2),
+        ));
+    }
+    csv
+}
+
+fn per_model(runs: &[Run], names: &[String]) -> String {
+    let mut csv = String::from(
+        "model,denoised,failed,realtime_factor,mean_residual_noise_dbfs,mean_stoi,mean_pesq_approx,mean_si_sdr_db\n",
+    );
+    for (model, name) in names.iter().enumerate() {
+        let ok: Vec<&Run> = runs
+            .iter()
+            .filter(|r| r.model == model && r.status == "ok")
+            .collect();
+        let failed = runs
+            .iter()
+            .filter(|r| r.model == model && r.status == "failed")
+            .count();
+        let busy: f64 = ok.iter().map(|r| r.secs).sum();
+        let audio: f64 = ok.iter().map(|r| r.audio_secs).sum();
+        let qualities = || ok.iter().filter_map(|r| r.quality);
+        csv.push_str(&format!(
+            "{},{},{failed},{},{},{},{},{}\n",
+            field(name),
+            ok.len(),
+            cell((busy > 0.0).then(|| audio / busy), 1),
+            cell(mean(ok.iter().filter_map(|r| r.residual_noise_dbfs)), 1),
+            cell(mean(qualities().filter_map(|q| q.stoi)), 3),
+            cell(mean(qualities().filter_map(|q| q.pesq_approx)), 2),
+            cell(mean(qualities().map(|q| q.si_sdr_db)), 2),
+        ));
+    }
+    csv
+}
@@ -0,0 +1,54 @@
+//! Shell completion scripts, generated by `clap_complete` from the clap
+//! command definition.
+
+use anyhow::Result;
+
+#[derive(clap::Args, Debug)]
+pub struct CompletionsArgs {
+    /// Shell to generate the script for
+    #[arg(value_enum)]
+    pub shell: clap_complete::Shell,
+}
+
+/// Prints the completion script for `args.shell` of the (unbuilt) command.
+pub fn run(args: &CompletionsArgs, mut cmd: clap::Command) -> Result<()> {
+    let bin = cmd.get_name().to_string();
+    clap_complete::generate(args.shell, &mut cmd, bin, &mut std::io::stdout());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+    use clap_complete::Shell;
+
+    fn script(shell: Shell) -> String {
+        let mut cmd = crate::Args::command();
+        let mut out = Vec::new();
+        clap_complete::generate(shell, &mut cmd, env!("CARGO_PKG_NAME"), &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn scripts_cover_subcommands_and_options() {
+        for shell in [
+            Shell::Bash,
+            Shell::Zsh,
+            Shell::Fish,
+            Shell::PowerShell,
+            Shell::Elvish,
+        ] {
+            let script = script(shell);
+            for word in ["serve", "addr-api", "token"] {
+                assert!(script.contains(word), "{shell}: no {word}");
+            }
+        }
+    }
+
+    #[test]
+    fn unix_shells_complete_enumerated_values() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            assert!(script(shell).contains("nnnoiseless"), "{shell}");
+        }
+    }
+}
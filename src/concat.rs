@@ -0,0 +1,96 @@
+//! `--concat-per-dir`: after a run, the WAV files of each output directory are
+//! joined in name order into one continuous file, `DIRNAME.concat.wav`.
+
+use crate::riff;
+use anyhow::{Context, Result, bail};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub const SUFFIX: &str = ".concat.wav";
+
+/// Joins the WAV files among `outputs` directory by directory, sorted by
+/// name, with `silence_secs` of silence between them; the joined file of a
+/// directory under `root` is named after it. Returns the number of joined
+/// files written.
+pub fn per_dir(root: &Path, outputs: &[&Path], silence_secs: f64) -> usize {
+    let mut dirs: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for &path in outputs {
+        if riff::is_wav(path)
+            && let Some(parent) = path.parent()
+        {
+            dirs.entry(parent.to_path_buf())
+                .or_default()
+                .push(path.to_path_buf());
+        }
+    }
+    for clips in dirs.values_mut() {
+        clips.sort();
+    }
+
+    let mut written = 0;
+    for (dir, clips) in &dirs {
+        let name = dir
+            .file_name()
+            .or(root.file_name())
+            .map_or_else(|| "output".into(), |n| n.to_string_lossy());
+        let joined = dir.join(format!("{name}{SUFFIX}"));
+        match join(clips, &joined, silence_secs) {
+            Ok(()) => written += 1,
+            Err(e) => {
+                let _ = std::fs::remove_file(&joined);
+                eprintln!(
+                    "Warning: could not join the files in {}: {e:#}",
+                    dir.display()
+                );
+            }
+        }
+    }
+    written
+}
+
+/// Writes `clips` one after another into `dest`, copying the samples as they are.
+fn join(clips: &[PathBuf], dest: &Path, silence_secs: f64) -> Result<()> {
+    let open = |path: &Path| {
+        hound::WavReader::open(path)
+            .with_context(|| format!("Failed to open WAV file: {}", path.display()))
+    };
+    let spec = open(&clips[0])?.spec();
+    let silence =
+        (silence_secs * f64::from(spec.sample_rate)).round() as usize * usize::from(spec.channels);
+    let mut writer = hound::WavWriter::create(dest, spec)
+        .with_context(|| format!("Failed to create WAV file: {}", dest.display()))?;
+    for (i, clip) in clips.iter().enumerate() {
+        let mut reader = open(clip)?;
+        if reader.spec() != spec {
+            bail!(
+                "{} is not in the format of {}",
+                clip.display(),
+                clips[0].display()
+            );
+        }
+        if i > 0 {
+            for _ in 0..silence {
+                match spec.sample_format {
+                    hound::SampleFormat::Float => writer.write_sample(0.0f32)?,
+                    hound::SampleFormat::Int => writer.write_sample(0i32)?,
+                }
+            }
+        }
+        let read = |e| anyhow::Error::new(e).context(format!("Failed to read {}", clip.display()));
+        match spec.sample_format {
+            hound::SampleFormat::Float => {
+                for sample in reader.samples::<f32>() {
+                    writer.write_sample(sample.map_err(read)?)?;
+                }
+            }
+            hound::SampleFormat::Int => {
+                for sample in reader.samples::<i32>() {
+                    writer.write_sample(sample.map_err(read)?)?;
+                }
+            }
+        }
+    }
+    writer
+        .finalize()
+        .with_context(|| format!("Failed to write WAV file: {}", dest.display()))
+}
@@ -0,0 +1,177 @@
+//! Worker concurrency control, including load-adaptive scaling for `--jobs auto`.
+
+use anyhow::Result;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// How many files are processed at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jobs {
+    Fixed(usize),
+    /// Scale between the configured bounds according to host load.
+    Auto,
+}
+
+impl FromStr for Jobs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Jobs::Auto);
+        }
+        match s.parse::<usize>() {
+            Ok(0) | Err(_) => Err(format!("expected a positive number or 'auto', got '{s}'")),
+            Ok(n) => Ok(Jobs::Fixed(n)),
+        }
+    }
+}
+
+/// A counting semaphore whose capacity can change while workers wait on it.
+pub struct Limiter {
+    state: Mutex<(usize, usize)>,
+    changed: Condvar,
+}
+
+/// Held by a worker while it processes a file.
+pub struct Permit<'a>(&'a Limiter);
+
+impl Limiter {
+    pub fn new(limit: usize) -> Self {
+        Limiter {
+            state: Mutex::new((0, limit.max(1))),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub fn acquire(&self) -> Permit<'_> {
+        let mut state = self.state.lock().unwrap();
+        while state.0 >= state.1 {
+            state = self.changed.wait(state).unwrap();
+        }
+        state.0 += 1;
+        Permit(self)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().1
+    }
+
+    pub fn set_limit(&self, limit: usize) {
+        self.state.lock().unwrap().1 = limit.max(1);
+        self.changed.notify_all();
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().0 -= 1;
+        self.0.changed.notify_one();
+    }
+}
+
+/// Spaces out work so that on average at most a given number of bytes per
+/// second is handed to the workers.
+pub struct Throttle {
+    bytes_per_sec: f64,
+    next: Mutex<Instant>,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: f64) -> Self {
+        Throttle {
+            bytes_per_sec,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Blocks until `bytes` more fit within the rate.
+    pub fn consume(&self, bytes: u64) {
+        let wait = {
+            let mut next = self.next.lock().unwrap();
+            let now = Instant::now();
+            let start = (*next).max(now);
+            *next = start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec);
+            start - now
+        };
+        std::thread::sleep(wait);
+    }
+}
+
+/// Sets the CPU niceness of the current thread, which threads and processes
+/// it starts later inherit; call it before spawning any workers.
+#[cfg(unix)]
+pub fn renice(niceness: i32) -> Result<()> {
+    // SAFETY: setpriority only reads its integer arguments
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, niceness) } != 0 {
+        let e = std::io::Error::last_os_error();
+        anyhow::bail!("Failed to set niceness {niceness}: {e}");
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn renice(_niceness: i32) -> Result<()> {
+    anyhow::bail!("--nice is only supported on Unix");
+}
+
+/// Host-wide CPU counters from `/proc/stat`: (total, idle incl. iowait, iowait).
+fn cpu_times() -> Option<(u64, u64, u64)> {
+    let stat = std::fs::read_to_string("/proc/stat").ok()?;
+    let fields: Vec<u64> = stat
+        .lines()
+        .next()?
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|f| f.parse().ok())
+        .collect();
+    let idle = *fields.get(3)?;
+    let iowait = fields.get(4).copied().unwrap_or(0);
+    Some((fields.iter().sum(), idle + iowait, iowait))
+}
+
+/// Periodically samples host load and raises or lowers the limiter's capacity
+/// within `min..=max` until `stop` is set.
+pub fn adapt(limiter: &Limiter, min: usize, max: usize, stop: &AtomicBool) {
+    const INTERVAL: Duration = Duration::from_secs(2);
+
+    let Some(mut last) = cpu_times() else {
+        eprintln!(
+            "Warning: cannot read /proc/stat; --jobs auto keeps {} workers",
+            limiter.limit()
+        );
+        return;
+    };
+    while !stop.load(Ordering::SeqCst) {
+        // Sleep in short steps so the run is not held up when it finishes
+        for _ in 0..20 {
+            if stop.load(Ordering::SeqCst) {
+                return;
+            }
+            std::thread::sleep(INTERVAL / 20);
+        }
+        let Some(now) = cpu_times() else { return };
+        let total = now.0.saturating_sub(last.0).max(1) as f64;
+        let busy = 1.0 - now.1.saturating_sub(last.1) as f64 / total;
+        let iowait = now.2.saturating_sub(last.2) as f64 / total;
+        last = now;
+
+        let current = limiter.limit();
+        let next = if busy > 0.9 || iowait > 0.25 {
+            current.saturating_sub(1).max(min)
+        } else if busy < 0.7 && iowait < 0.1 {
+            (current + 1).min(max)
+        } else {
+            current
+        };
+        if next != current {
+            eprintln!(
+                "Adjusting concurrency to {next} workers (cpu {:.0}%, iowait {:.0}%)",
+                busy * 100.0,
+                iowait * 100.0
+            );
+            limiter.set_limit(next);
+        }
+    }
+}
@@ -0,0 +1,515 @@
+//! Configuration file and environment variable overrides.
+//!
+//! Any long option can also come from a TOML file given with `--config` (or
+//! `WAVDENOISE_CONFIG`) or from an environment variable named
+//! `WAVDENOISE_<OPTION>` (e.g. `WAVDENOISE_ADDR_API`). Values are merged into the
+//! command line before clap parses it, with precedence CLI > env > config file.
+//! Config keys are option names with `-` or `_`, plus `input_dir`/`output_dir`
+//! for the positional arguments:
+//!
+//! ```toml
+//! input_dir = "/data/raw"
+//! output_dir = "/data/clean"
+//! addr_api = ["http://gpu1:3000/denoise", "http://gpu2:3000/denoise"]
+//! plugin = ["post=python3 tag.py"]
+//! ```
+//!
+//! `--profile NAME` fills in a preset of options below all of these. The
+//! built-in presets are in [`PROFILES`]; a config file can change them or add
+//! its own under `[profiles.NAME]` tables of the same form.
+
+use anyhow::{Context, Result, anyhow, bail};
+use clap::Command;
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+const ENV_PREFIX: &str = "WAVDENOISE_";
+
+/// The built-in `--profile` presets.
+const PROFILES: &str = r#"
+[podcast]
+downmix = true
+remove-dc = true
+post-filter = "highpass=80,deess"
+check-levels = true
+measure-loudness = true
+
+[telephony]
+downmix = true
+post-filter = "highpass=300,lowpass=3400"
+check-levels = true
+
+[field-recording]
+remove-dc = true
+post-filter = "highpass=40"
+chunk-secs = 300
+noise-report = true
+
+[asr-dataset]
+downmix = true
+split-on-silence = true
+min-silence-ms = 500
+min-duration = 0.5
+verify = true
+"#;
+
+type Options = BTreeMap<String, toml::Value>;
+
+/// Looks up an environment variable.
+type Env<'a> = dyn Fn(&str) -> Option<OsString> + 'a;
+
+/// Returns the process arguments with config file and environment values merged in.
+pub fn effective_args(cmd: Command) -> Result<Vec<OsString>> {
+    merge(cmd, std::env::args_os().collect(), &|name| {
+        std::env::var_os(name)
+    })
+}
+
+/// [`effective_args`] for the given arguments and environment.
+fn merge(mut cmd: Command, mut argv: Vec<OsString>, env: &Env) -> Result<Vec<OsString>> {
+    cmd.build();
+    let mut user = argv.split_off(1.min(argv.len()));
+    // `denoise` names the batch run as the stage after `prepare`; it takes the same arguments
+    if user.first().is_some_and(|first| first == "denoise") {
+        user.remove(0);
+    }
+
+    // Only the top-level run and each subcommand's own options are filled in
+    let sub = user
+        .first()
+        .and_then(|first| first.to_str())
+        .and_then(|first| cmd.find_subcommand(first))
+        .cloned();
+    let (target, prefix_len) = match &sub {
+        Some(sub) => (sub, 1),
+        None => (&cmd, 0),
+    };
+    let (given, positionals) = scan(target, &user[prefix_len..]);
+
+    let (config, profiles) = match config_path(&user, env) {
+        Some(path) => load(&path, &cmd)?,
+        None => Default::default(),
+    };
+    let profile = profile(&user, &config, profiles, env)?;
+
+    let mut injected = Vec::new();
+    for arg in target.get_arguments() {
+        let Some(long) = arg.get_long() else { continue };
+        if given.contains(long) || long == "config" || long == "help" || long == "version" {
+            continue;
+        }
+        let takes_value = arg.get_action().takes_values();
+        if let Some(value) = env(&env_name(long)) {
+            push_env(&mut injected, long, takes_value, value);
+        } else if let Some(value) = config.get(long).or_else(|| profile.get(long)) {
+            push_toml(&mut injected, long, takes_value, value)?;
+        }
+    }
+
+    let mut trailing = Vec::new();
+    if sub.is_none() {
+        // The positionals the user left out, in order up to the first one with no value
+        for arg in cmd.get_positionals().skip(positionals) {
+            let id = arg.get_id().as_str();
+            if let Some(value) = env(&env_name(id)) {
+                trailing.push(value);
+            } else if let Some(value) = config.get(&id.replace('_', "-")) {
+                trailing.push(scalar(id, value)?.into());
+            } else {
+                break;
+            }
+        }
+    }
+
+    argv.extend(user[..prefix_len].iter().cloned());
+    argv.extend(injected);
+    argv.extend(user[prefix_len..].iter().cloned());
+    argv.extend(trailing);
+    Ok(argv)
+}
+
+fn env_name(option: &str) -> String {
+    format!("{ENV_PREFIX}{}", option.to_uppercase().replace('-', "_"))
+}
+
+/// Finds the options the user typed, by their long names, and counts the
+/// positional arguments.
+fn scan(cmd: &Command, user: &[OsString]) -> (HashSet<String>, usize) {
+    let mut given = HashSet::new();
+    let mut positionals = 0;
+    let mut iter = user.iter();
+    while let Some(token) = iter.next() {
+        let token = token.to_string_lossy();
+        if token == "--" {
+            positionals += iter.len();
+            break;
+        }
+        let Some(option) = token.strip_prefix("--") else {
+            match token.strip_prefix('-') {
+                // `-` alone stands for stdin or stdout
+                None | Some("") => positionals += 1,
+                Some(shorts) => {
+                    if scan_shorts(cmd, shorts, &mut given) {
+                        iter.next();
+                    }
+                }
+            }
+            continue;
+        };
+        let (name, inline_value) = match option.split_once('=') {
+            Some((name, _)) => (name, true),
+            None => (option, false),
+        };
+        let takes_value = cmd
+            .get_arguments()
+            .find(|a| a.get_long() == Some(name))
+            .is_some_and(|a| a.get_action().takes_values());
+        if takes_value && !inline_value {
+            iter.next();
+        }
+        given.insert(name.to_string());
+    }
+    (given, positionals)
+}
+
+/// Records the options of a bundle of short flags such as `-yv` under their
+/// long names; returns whether the last one takes its value from the next
+/// token.
+fn scan_shorts(cmd: &Command, shorts: &str, given: &mut HashSet<String>) -> bool {
+    for (at, c) in shorts.char_indices() {
+        let Some(arg) = cmd.get_arguments().find(|a| a.get_short() == Some(c)) else {
+            return false;
+        };
+        given.insert(
+            arg.get_long()
+                .map_or_else(|| arg.get_id().to_string(), str::to_string),
+        );
+        if arg.get_action().takes_values() {
+            // The rest of the bundle, e.g. `-j4` or `-j=4`, is the value
+            return at + c.len_utf8() == shorts.len();
+        }
+    }
+    false
+}
+
+/// `--NAME VALUE` / `--NAME=VALUE` from the command line, else `WAVDENOISE_NAME`.
+fn early_option(user: &[OsString], name: &str, env: &Env) -> Option<OsString> {
+    let flag = format!("--{name}");
+    let mut iter = user.iter();
+    while let Some(token) = iter.next() {
+        let token = token.to_string_lossy();
+        if token == "--" {
+            break;
+        }
+        if token == flag {
+            return iter.next().cloned();
+        }
+        if let Some(value) = token.strip_prefix(&format!("{flag}=")) {
+            return Some(value.into());
+        }
+    }
+    env(&env_name(name))
+}
+
+fn config_path(user: &[OsString], env: &Env) -> Option<PathBuf> {
+    early_option(user, "config", env).map(PathBuf::from)
+}
+
+/// The options of the selected `--profile`, with the config file's changes to it.
+fn profile(
+    user: &[OsString],
+    config: &Options,
+    custom: BTreeMap<String, Options>,
+    env: &Env,
+) -> Result<Options> {
+    let name = match early_option(user, "profile", env) {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => match config.get("profile") {
+            Some(value) => scalar("profile", value)?,
+            None => return Ok(Options::new()),
+        },
+    };
+    let builtin: toml::Table = PROFILES.parse().expect("built-in profiles are valid TOML");
+    let mut profiles: BTreeMap<String, Options> = builtin
+        .into_iter()
+        .map(|(name, options)| match options {
+            toml::Value::Table(options) => (name, options.into_iter().collect()),
+            _ => unreachable!("built-in profiles are tables"),
+        })
+        .collect();
+    for (name, options) in custom {
+        profiles.entry(name).or_default().extend(options);
+    }
+    let names: Vec<&str> = profiles.keys().map(String::as_str).collect();
+    let names = names.join(", ");
+    profiles
+        .remove(&name)
+        .ok_or_else(|| anyhow!("Unknown profile '{name}'; expected one of {names}"))
+}
+
+/// Reads a config file, normalizing keys to option names and rejecting unknown
+/// ones. Returns its options and its `[profiles.NAME]` tables.
+fn load(path: &std::path::Path, cmd: &Command) -> Result<(Options, BTreeMap<String, Options>)> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+    let mut table: toml::Table = text
+        .parse()
+        .with_context(|| format!("Malformed config file: {}", path.display()))?;
+
+    let mut known: HashSet<String> = HashSet::new();
+    let mut collect = |c: &Command| {
+        for arg in c.get_arguments() {
+            match arg.get_long() {
+                Some(long) => known.insert(long.to_string()),
+                None => known.insert(arg.get_id().as_str().replace('_', "-")),
+            };
+        }
+    };
+    collect(cmd);
+    for sub in cmd.get_subcommands() {
+        collect(sub);
+    }
+
+    let options = |table: toml::Table| -> Result<Options> {
+        let mut options = Options::new();
+        for (key, value) in table {
+            let name = key.replace('_', "-");
+            if !known.contains(&name) {
+                bail!("Unknown option '{key}' in config file {}", path.display());
+            }
+            options.insert(name, value);
+        }
+        Ok(options)
+    };
+
+    let mut profiles = BTreeMap::new();
+    if let Some(value) = table.remove("profiles") {
+        let toml::Value::Table(tables) = value else {
+            bail!(
+                "'profiles' in config file {} must be a table",
+                path.display()
+            );
+        };
+        for (name, value) in tables {
+            let toml::Value::Table(profile) = value else {
+                bail!(
+                    "Profile '{name}' in config file {} must be a table",
+                    path.display()
+                );
+            };
+            profiles.insert(name, options(profile)?);
+        }
+    }
+    Ok((options(table)?, profiles))
+}
+
+fn push_env(out: &mut Vec<OsString>, long: &str, takes_value: bool, value: OsString) {
+    if takes_value {
+        let mut arg = OsString::from(format!("--{long}="));
+        arg.push(value);
+        out.push(arg);
+    } else if !matches!(
+        value.to_string_lossy().to_lowercase().as_str(),
+        "" | "0" | "false" | "no" | "off"
+    ) {
+        out.push(format!("--{long}").into());
+    }
+}
+
+fn push_toml(
+    out: &mut Vec<OsString>,
+    long: &str,
+    takes_value: bool,
+    value: &toml::Value,
+) -> Result<()> {
+    match value {
+        toml::Value::Boolean(flag) if !takes_value => {
+            if *flag {
+                out.push(format!("--{long}").into());
+            }
+        }
+        toml::Value::Array(items) => {
+            for item in items {
+                out.push(format!("--{long}={}", scalar(long, item)?).into());
+            }
+        }
+        other => out.push(format!("--{long}={}", scalar(long, other)?).into()),
+    }
+    Ok(())
+}
+
+fn scalar(key: &str, value: &toml::Value) -> Result<String> {
+    Ok(match value {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Integer(i) => i.to_string(),
+        toml::Value::Float(f) => f.to_string(),
+        toml::Value::Boolean(b) => b.to_string(),
+        toml::Value::Datetime(d) => d.to_string(),
+        _ => bail!("Config option '{key}' must be a string, number or boolean"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    /// A fresh directory for one test's files.
+    fn scratch(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "{}-config-{}-{test}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// The arguments after the program name that a run with `user` on the
+    /// command line and `vars` in its environment would be parsed from.
+    fn merged(user: &[&str], vars: &[(&str, &str)]) -> Result<Vec<String>> {
+        let argv = std::iter::once(env!("CARGO_PKG_NAME"))
+            .chain(user.iter().copied())
+            .map(OsString::from)
+            .collect();
+        let vars: BTreeMap<&str, &str> = vars.iter().copied().collect();
+        let args = merge(crate::Args::command(), argv, &|name| {
+            vars.get(name).map(OsString::from)
+        })?;
+        Ok(args
+            .into_iter()
+            .skip(1)
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect())
+    }
+
+    #[test]
+    fn the_command_line_beats_the_environment_which_beats_the_config_file() {
+        let dir = scratch("precedence");
+        let path = dir.join("run.toml");
+        std::fs::write(&path, "min_jobs = 2\nmax-jobs = 8\nestimate-speed = 1.5\n").unwrap();
+        let config = path.to_str().unwrap();
+        let args = merged(
+            &["--config", config, "--estimate-speed", "3"],
+            &[("WAVDENOISE_MAX_JOBS", "4")],
+        )
+        .unwrap();
+        assert!(args.contains(&"--min-jobs=2".to_string()));
+        assert!(args.contains(&"--max-jobs=4".to_string()));
+        assert!(!args.iter().any(|arg| arg.starts_with("--max-jobs=8")));
+        assert!(!args.iter().any(|arg| arg.starts_with("--estimate-speed=")));
+        assert!(args.ends_with(&["--estimate-speed".to_string(), "3".to_string()]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn environment_flags_are_off_when_false() {
+        let args = merged(
+            &[],
+            &[("WAVDENOISE_YES", "false"), ("WAVDENOISE_VERBOSE", "1")],
+        )
+        .unwrap();
+        assert_eq!(args, ["--verbose"]);
+    }
+
+    #[test]
+    fn bundled_short_flags_count_as_given() {
+        let vars = [("WAVDENOISE_YES", "1"), ("WAVDENOISE_VERBOSE", "1")];
+        let args = merged(&["-vy", "in"], &vars).unwrap();
+        assert_eq!(args, ["-vy", "in"]);
+        assert_eq!(merged(&["-y"], &vars).unwrap(), ["--verbose", "-y"]);
+    }
+
+    #[test]
+    fn fills_in_the_positionals_left_out() {
+        let vars = [
+            ("WAVDENOISE_INPUT_DIR", "/data/raw"),
+            ("WAVDENOISE_OUTPUT_DIR", "/data/clean"),
+        ];
+        assert_eq!(merged(&[], &vars).unwrap(), ["/data/raw", "/data/clean"]);
+        assert_eq!(merged(&["in"], &vars).unwrap(), ["in", "/data/clean"]);
+        assert_eq!(merged(&["in", "out"], &vars).unwrap(), ["in", "out"]);
+        // An output directory alone would be taken for the input
+        assert!(merged(&[], &vars[1..]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_config_keys() {
+        let dir = scratch("unknown");
+        let path = dir.join("run.toml");
+        std::fs::write(&path, "no_such_option = 1\n").unwrap();
+        let err = merged(&["--config", path.to_str().unwrap()], &[]).unwrap_err();
+        assert!(err.to_string().contains("Unknown option 'no_such_option'"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn built_in_profiles_only_set_real_options() {
+        let cmd = crate::Args::command();
+        let builtin: toml::Table = PROFILES.parse().unwrap();
+        for (name, options) in builtin {
+            for option in options.as_table().unwrap().keys() {
+                assert!(
+                    cmd.get_arguments().any(|a| a.get_long() == Some(option)),
+                    "profile {name} sets unknown option {option}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn a_profile_fills_in_what_nothing_else_sets() {
+        let args = merged(&["--profile", "podcast", "in"], &[]).unwrap();
+        for expected in [
+            "--downmix",
+            "--post-filter=highpass=80,deess",
+            "--measure-loudness",
+        ] {
+            assert!(args.contains(&expected.to_string()), "{args:?}");
+        }
+        let args = merged(
+            &["--profile=podcast", "--post-filter", "lowpass=8000", "in"],
+            &[("WAVDENOISE_MEASURE_LOUDNESS", "false")],
+        )
+        .unwrap();
+        assert!(!args.iter().any(|arg| arg.starts_with("--post-filter=")));
+        assert!(!args.contains(&"--measure-loudness".to_string()));
+        assert!(args.contains(&"--downmix".to_string()));
+        let args = merged(&["in"], &[("WAVDENOISE_PROFILE", "telephony")]).unwrap();
+        assert!(args.contains(&"--post-filter=highpass=300,lowpass=3400".to_string()));
+    }
+
+    #[test]
+    fn the_config_file_changes_and_adds_profiles() {
+        let dir = scratch("profiles");
+        let path = dir.join("run.toml");
+        std::fs::write(
+            &path,
+            "profile = \"voice\"\n\
+             [profiles.podcast]\npost_filter = \"highpass=100\"\n\
+             [profiles.voice]\nremove-dc = true\n",
+        )
+        .unwrap();
+        let config = path.to_str().unwrap();
+        let args = merged(&["--config", config, "in"], &[]).unwrap();
+        assert!(args.contains(&"--remove-dc".to_string()), "{args:?}");
+        assert!(!args.contains(&"--downmix".to_string()));
+
+        let args = merged(&["--config", config, "--profile", "podcast", "in"], &[]).unwrap();
+        assert!(args.contains(&"--post-filter=highpass=100".to_string()));
+        // The rest of the built-in preset still applies
+        assert!(args.contains(&"--downmix".to_string()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_unknown_profiles() {
+        let err = merged(&["--profile", "studio", "in"], &[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown profile 'studio'; expected one of asr-dataset, field-recording, podcast, telephony"
+        );
+    }
+}
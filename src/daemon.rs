@@ -0,0 +1,269 @@
+//! The `daemon` subcommand: a job queue kept in a directory, for systems that
+//! can only drop files.
+//!
+//! Each `*.json` file written to the queue directory is a job
+//! (`{"input": ..., "output": ..., "model": ...}`; relative paths are taken
+//! from the queue directory). A worker claims it by moving it to `running/`,
+//! denoises it and moves it on to `done/` or `failed/`. Its status is kept
+//! in `status/NAME.json` throughout.
+
+use crate::clock;
+use crate::error::DenoiseError;
+use crate::pipeline::{Outcome, Pipeline, PipelineArgs};
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(clap::Args, Debug)]
+pub struct DaemonArgs {
+    /// Directory to take job files from; running/, done/, failed/ and status/ are kept inside it
+    #[arg(long, value_name = "DIR")]
+    queue_dir: PathBuf,
+
+    /// Jobs denoised concurrently [default: one per API address]
+    #[arg(long)]
+    workers: Option<usize>,
+
+    /// Seconds between looks at the queue directory when it is empty
+    #[arg(long, default_value_t = 1.0, value_name = "SECS")]
+    poll_interval: f64,
+
+    /// Exit once the queue is empty instead of waiting for more jobs
+    #[arg(long)]
+    once: bool,
+
+    #[command(flatten)]
+    pipeline: PipelineArgs,
+}
+
+/// Contents of a job file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobFile {
+    input: PathBuf,
+    output: PathBuf,
+    /// Overrides --model for this job.
+    #[serde(default)]
+    model: Option<String>,
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Running,
+    Done,
+    Failed,
+}
+
+/// Contents of a status file.
+#[derive(Serialize)]
+struct JobStatus<'a> {
+    job: &'a str,
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    input: Option<&'a Path>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<&'a Path>,
+    /// The class of the failure of a failed job.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<DenoiseError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// UTC, ISO 8601.
+    updated_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secs: Option<f64>,
+}
+
+struct Daemon<'a> {
+    args: &'a DaemonArgs,
+    models_dir: Option<&'a Path>,
+    /// One pipeline per model asked for, built on first use.
+    pipelines: Mutex<HashMap<Option<String>, Arc<Pipeline>>>,
+}
+
+const SUBDIRS: [&str; 4] = ["running", "done", "failed", "status"];
+
+/// Processes job files until stopped, or until the queue is empty with `--once`.
+pub fn run(args: &DaemonArgs, models_dir: Option<&Path>) -> Result<()> {
+    if !args.poll_interval.is_finite() || args.poll_interval <= 0.0 {
+        anyhow::bail!("--poll-interval must be positive");
+    }
+    for dir in SUBDIRS {
+        let dir = args.queue_dir.join(dir);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    }
+    let daemon = Daemon {
+        args,
+        models_dir,
+        pipelines: Mutex::new(HashMap::new()),
+    };
+    let default = daemon.pipeline(args.pipeline.model.as_deref())?;
+    let workers = args
+        .workers
+        .unwrap_or_else(|| default.default_jobs())
+        .max(1);
+
+    // Jobs a stopped daemon left running start over
+    for job in daemon.job_files(&args.queue_dir.join("running"))? {
+        if let Some(name) = job.file_name() {
+            eprintln!("Requeuing interrupted job {}", name.to_string_lossy());
+            std::fs::rename(&job, args.queue_dir.join(name))
+                .with_context(|| format!("Failed to requeue job: {}", job.display()))?;
+        }
+    }
+
+    eprintln!(
+        "Watching {} for jobs with {workers} workers",
+        args.queue_dir.display()
+    );
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| scope.spawn(|| daemon.work()))
+            .collect();
+        handles
+            .into_iter()
+            .try_for_each(|h| h.join().map_err(|_| anyhow!("A worker panicked"))?)
+    })
+}
+
+impl Daemon<'_> {
+    fn pipeline(&self, model: Option<&str>) -> Result<Arc<Pipeline>> {
+        let model = model
+            .map(str::to_string)
+            .or_else(|| self.args.pipeline.model.clone());
+        let mut pipelines = self.pipelines.lock().unwrap();
+        if let Some(pipeline) = pipelines.get(&model) {
+            return Ok(Arc::clone(pipeline));
+        }
+        let mut pipeline_args = self.args.pipeline.clone();
+        pipeline_args.model = model.clone();
+        let pipeline = Arc::new(Pipeline::new(&pipeline_args, self.models_dir)?);
+        pipelines.insert(model, Arc::clone(&pipeline));
+        Ok(pipeline)
+    }
+
+    /// Job files in `dir`, oldest name first; dot files are writes in progress.
+    fn job_files(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut jobs: Vec<PathBuf> = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read directory: {}", dir.display()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                path.is_file()
+                    && path.extension().is_some_and(|e| e == "json")
+                    && !path
+                        .file_name()
+                        .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+            })
+            .collect();
+        jobs.sort();
+        Ok(jobs)
+    }
+
+    /// Claims jobs one at a time; the rename fails for all but one worker.
+    fn work(&self) -> Result<()> {
+        let queue = &self.args.queue_dir;
+        loop {
+            let claimed = self.job_files(queue)?.into_iter().find_map(|job| {
+                let name = job.file_name()?.to_os_string();
+                let running = queue.join("running").join(&name);
+                std::fs::rename(&job, &running).ok()?;
+                Some((name.to_string_lossy().to_string(), running))
+            });
+            match claimed {
+                Some((name, running)) => self.process(&name, &running),
+                None if self.args.once => return Ok(()),
+                None => std::thread::sleep(Duration::from_secs_f64(self.args.poll_interval)),
+            }
+        }
+    }
+
+    fn process(&self, name: &str, running: &Path) {
+        let queue = &self.args.queue_dir;
+        let started = Instant::now();
+        let job = std::fs::read(running)
+            .with_context(|| format!("Failed to read job: {}", running.display()))
+            .and_then(|bytes| {
+                serde_json::from_slice::<JobFile>(&bytes)
+                    .with_context(|| format!("Invalid job file: {}", running.display()))
+            })
+            .map(|job| JobFile {
+                input: queue.join(job.input),
+                output: queue.join(job.output),
+                model: job.model,
+            });
+        let (paths, result) = match job {
+            Ok(job) => {
+                self.write_status(name, Status::Running, Some(&job), None, None, None);
+                let outcome = self
+                    .pipeline(job.model.as_deref())
+                    .and_then(|pipeline| pipeline.process(&job.input, &job.output));
+                (Some(job), outcome)
+            }
+            Err(e) => (None, Err(e)),
+        };
+        let outcome = result.unwrap_or_else(|e| Outcome::failed(&e));
+        let (status, error) = match &outcome {
+            Outcome::Processed => (Status::Done, None),
+            Outcome::Skipped(reason) | Outcome::Invalid(reason) | Outcome::Failed(_, reason) => {
+                (Status::Failed, Some(reason.clone()))
+            }
+        };
+        match &error {
+            Some(error) => eprintln!("Job {name} failed: {error}"),
+            None => eprintln!("Job {name} done"),
+        }
+        self.write_status(
+            name,
+            status,
+            paths.as_ref(),
+            outcome.code(),
+            error,
+            Some(started.elapsed().as_secs_f64()),
+        );
+        let finished = queue
+            .join(match status {
+                Status::Done => "done",
+                _ => "failed",
+            })
+            .join(name);
+        if let Err(e) = std::fs::rename(running, &finished) {
+            eprintln!("Warning: failed to move job {name} out of running/: {e}");
+        }
+    }
+
+    fn write_status(
+        &self,
+        name: &str,
+        status: Status,
+        job: Option<&JobFile>,
+        code: Option<DenoiseError>,
+        error: Option<String>,
+        secs: Option<f64>,
+    ) {
+        let contents = JobStatus {
+            job: name,
+            status,
+            input: job.map(|j| j.input.as_path()),
+            output: job.map(|j| j.output.as_path()),
+            code,
+            error,
+            updated_at: clock::iso8601_now(),
+            secs,
+        };
+        let path = self.args.queue_dir.join("status").join(name);
+        // Renamed into place so readers never see a partial status
+        let partial = self.args.queue_dir.join("status").join(format!(".{name}"));
+        let written = serde_json::to_vec_pretty(&contents)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(std::fs::write(&partial, json)?))
+            .and_then(|()| Ok(std::fs::rename(&partial, &path)?));
+        if let Err(e) = written {
+            eprintln!("Warning: failed to write status {}: {e:#}", path.display());
+        }
+    }
+}
@@ -0,0 +1,298 @@
+//! The `--tui` dashboard: a live view of the batch drawn with `ratatui` on stderr.
+
+use crate::report::Totals;
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::{cursor, execute, terminal};
+use ratatui::Terminal;
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, Paragraph, Sparkline};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
+use std::time::{Duration, Instant};
+
+/// Time between redraws.
+const FRAME: Duration = Duration::from_millis(250);
+/// Seconds of throughput history shown in the graph (at most).
+const HISTORY: usize = 120;
+/// Failures listed, most recent first.
+const FAILURES_SHOWN: usize = 8;
+
+/// State the workers report into and the render loop draws from.
+pub struct Dashboard {
+    total: usize,
+    /// Files already counted in the totals when the dashboard was created.
+    baseline: usize,
+    started: Instant,
+    /// What each worker thread is doing: the file and when it began.
+    workers: Mutex<Vec<Option<(String, Instant)>>>,
+    failures: Mutex<Vec<(String, String)>>,
+}
+
+impl Dashboard {
+    /// A dashboard for `total` files to come, on top of what `totals` already counts.
+    pub fn new(total: usize, workers: usize, totals: &Totals) -> Self {
+        Dashboard {
+            total,
+            baseline: finished(totals),
+            started: Instant::now(),
+            workers: Mutex::new(vec![None; workers]),
+            failures: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Marks the calling worker thread as busy with `file`.
+    pub fn start(&self, file: &str) {
+        self.set(Some((file.to_string(), Instant::now())));
+    }
+
+    /// Marks the calling worker thread as idle, recording `failure` if there was one.
+    pub fn finish(&self, file: &str, failure: Option<&str>) {
+        self.set(None);
+        if let Some(reason) = failure {
+            let mut failures = self.failures.lock().unwrap();
+            failures.push((file.to_string(), reason.to_string()));
+        }
+    }
+
+    fn set(&self, slot: Option<(String, Instant)>) {
+        let Some(index) = rayon::current_thread_index() else {
+            return;
+        };
+        if let Some(worker) = self.workers.lock().unwrap().get_mut(index) {
+            *worker = slot;
+        }
+    }
+
+    /// Redraws on the alternate screen until `stop` is set, then restores the
+    /// terminal, as a panic also does. Raw mode turns Ctrl-C into a key press,
+    /// so it restores the terminal and ends the process itself.
+    pub fn run(&self, totals: &Totals, stop: &AtomicBool) {
+        if let Err(e) = self.show(totals, stop) {
+            restore();
+            eprintln!("Warning: the dashboard failed: {e}");
+        }
+    }
+
+    fn show(&self, totals: &Totals, stop: &AtomicBool) -> std::io::Result<()> {
+        static HOOK: Once = Once::new();
+        HOOK.call_once(|| {
+            let previous = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                restore();
+                previous(info);
+            }));
+        });
+        terminal::enable_raw_mode()?;
+        execute!(
+            std::io::stderr(),
+            terminal::EnterAlternateScreen,
+            cursor::Hide
+        )?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(std::io::stderr()))?;
+
+        let mut history: Vec<f64> = Vec::new();
+        let mut last_sample = (Instant::now(), 0);
+        loop {
+            let stopping = stop.load(Ordering::SeqCst);
+            let done = finished(totals) - self.baseline;
+            if last_sample.0.elapsed() >= Duration::from_secs(1) {
+                let rate = (done - last_sample.1) as f64 / last_sample.0.elapsed().as_secs_f64();
+                history.push(rate);
+                if history.len() > HISTORY {
+                    history.remove(0);
+                }
+                last_sample = (Instant::now(), done);
+            }
+            // Each draw fits the layout to the terminal's current size
+            terminal.draw(|frame| self.draw(frame, totals, &history))?;
+            if stopping {
+                break;
+            }
+            // Waits out the frame unless a key arrives first
+            if event::poll(FRAME)?
+                && let Event::Key(key) = event::read()?
+                && key.code == KeyCode::Char('c')
+                && key.modifiers.contains(KeyModifiers::CONTROL)
+            {
+                restore();
+                std::process::exit(130);
+            }
+        }
+        restore();
+        Ok(())
+    }
+
+    fn draw(&self, frame: &mut ratatui::Frame, totals: &Totals, history: &[f64]) {
+        let done = finished(totals) - self.baseline;
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let fraction = if self.total == 0 {
+            1.0
+        } else {
+            (done as f64 / self.total as f64).min(1.0)
+        };
+        let eta = if done == 0 || done >= self.total {
+            "--:--:--".to_string()
+        } else {
+            clock(elapsed / done as f64 * (self.total - done) as f64)
+        };
+        let workers = self.workers.lock().unwrap();
+        let failures = self.failures.lock().unwrap();
+
+        let [title, progress, counts, graph, busy, failed] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Length(2),
+            Constraint::Length(3),
+            Constraint::Length(workers.len() as u16 + 2),
+            Constraint::Min(0),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(
+            Line::from(format!(
+                " {}  {done}/{} files  elapsed {}  ETA {eta}",
+                env!("CARGO_PKG_NAME"),
+                self.total,
+                clock(elapsed)
+            )),
+            title,
+        );
+        frame.render_widget(
+            Gauge::default()
+                .ratio(fraction)
+                .label(format!("{:.1}%", fraction * 100.0)),
+            progress,
+        );
+        frame.render_widget(
+            Line::from(format!(
+                " Processed {}   Skipped {}   Failed {}   Now {:.1} files/s",
+                totals.processed.load(Ordering::SeqCst),
+                totals.skipped.load(Ordering::SeqCst),
+                totals.failed.load(Ordering::SeqCst),
+                history.last().copied().unwrap_or(0.0)
+            )),
+            counts,
+        );
+
+        // Tenths of a file per second, as the graph takes whole numbers
+        let shown = &history[history.len().saturating_sub(usize::from(graph.width))..];
+        let peak = shown.iter().copied().fold(0.0, f64::max);
+        let tenths: Vec<u64> = shown.iter().map(|&rate| (rate * 10.0) as u64).collect();
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::new().title(format!(" Throughput, files/s (peak {peak:.1})")))
+                .data(&tenths)
+                .max((peak * 10.0).ceil().max(1.0) as u64),
+            graph,
+        );
+
+        let lines: Vec<Line> = workers
+            .iter()
+            .enumerate()
+            .map(|(i, worker)| match worker {
+                Some((file, since)) => Line::from(format!(
+                    " {:>3}  {:>6.1}s  {file}",
+                    i + 1,
+                    since.elapsed().as_secs_f64()
+                )),
+                None => Line::from(format!(" {:>3}  idle", i + 1)),
+            })
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::new().title(" Workers")),
+            busy,
+        );
+
+        let lines: Vec<Line> = failures
+            .iter()
+            .rev()
+            .take(FAILURES_SHOWN)
+            .map(|(file, reason)| Line::from(format!("   {file}: {reason}")))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(Block::new().title(format!(" Failures ({})", failures.len()))),
+            failed,
+        );
+    }
+}
+
+/// Leaves raw mode and the alternate screen, and shows the cursor again.
+fn restore() {
+    let _ = terminal::disable_raw_mode();
+    let _ = execute!(
+        std::io::stderr(),
+        terminal::LeaveAlternateScreen,
+        cursor::Show
+    );
+}
+
+fn finished(totals: &Totals) -> usize {
+    totals.processed.load(Ordering::SeqCst)
+        + totals.skipped.load(Ordering::SeqCst)
+        + totals.failed.load(Ordering::SeqCst)
+}
+
+/// Formats seconds as `HH:MM:SS`.
+fn clock(secs: f64) -> String {
+    let secs = secs as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+
+    /// The dashboard drawn on a terminal of `width` by `height`, row by row.
+    fn screen(dashboard: &Dashboard, totals: &Totals, width: u16, height: u16) -> Vec<String> {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal
+            .draw(|frame| dashboard.draw(frame, totals, &[1.0, 2.5]))
+            .unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..height)
+            .map(|y| (0..width).map(|x| buffer[(x, y)].symbol()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn shows_progress_workers_and_failures() {
+        let totals = Totals::default();
+        totals.processed.store(1, Ordering::SeqCst);
+        let dashboard = Dashboard::new(4, 2, &totals);
+        totals.processed.store(2, Ordering::SeqCst);
+        totals.failed.store(1, Ordering::SeqCst);
+        dashboard.finish("bad.wav", Some("truncated data chunk"));
+
+        let screen = screen(&dashboard, &totals, 80, 24).join("\n");
+        for text in [
+            "2/4 files",
+            "50.0%",
+            "Processed 2   Skipped 0   Failed 1   Now 2.5 files/s",
+            "peak 2.5",
+            "  1  idle",
+            "  2  idle",
+            "Failures (1)",
+            "bad.wav: truncated data chunk",
+        ] {
+            assert!(screen.contains(text), "no {text:?} in\n{screen}");
+        }
+    }
+
+    #[test]
+    fn fits_small_terminals() {
+        let totals = Totals::default();
+        let dashboard = Dashboard::new(0, 16, &totals);
+        let screen = screen(&dashboard, &totals, 20, 5);
+        assert!(screen[0].starts_with(" wav-files-denoise"));
+        assert!(screen[1].contains("100.0%"));
+    }
+}
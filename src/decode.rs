@@ -0,0 +1,42 @@
+//! Decoding of non-WAV inputs for `--decoder ffmpeg`.
+
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// How inputs are read.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decoder {
+    /// Read WAV files only
+    Builtin,
+    /// Also decode anything `ffmpeg` reads (m4a, amr, wma, video containers)
+    Ffmpeg,
+}
+
+/// Decodes the first audio stream of `input` into a WAV file with `spec`.
+pub fn ffmpeg(input: &Path, output: &Path, spec: hound::WavSpec) -> Result<()> {
+    let codec = match (spec.sample_format, spec.bits_per_sample) {
+        (hound::SampleFormat::Float, _) => "pcm_f32le",
+        (_, 8) => "pcm_u8",
+        (_, 24) => "pcm_s24le",
+        _ => "pcm_s16le",
+    };
+    let status = Command::new("ffmpeg")
+        .args(["-nostdin", "-hide_banner", "-loglevel", "error", "-y", "-i"])
+        .arg(input)
+        .arg("-vn")
+        .args(["-ac", &spec.channels.to_string()])
+        .args(["-ar", &spec.sample_rate.to_string()])
+        .args(["-c:a", codec, "-f", "wav"])
+        .arg(output)
+        .stdin(Stdio::null())
+        .status()
+        .context("Failed to start ffmpeg (is it installed and on PATH?)")?;
+    if !status.success() {
+        bail!("ffmpeg could not decode {} ({status})", input.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,171 @@
+//! `--diff`: how an existing output tree stands against the input tree,
+//! without denoising anything. Each input's output is missing, stale (the
+//! input changed after it was written) or up to date, and files in the
+//! output tree that no input maps to are orphaned. `--prune` deletes the
+//! orphans.
+
+use crate::concat;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+#[derive(Debug, Default, Serialize)]
+pub struct Diff {
+    /// Inputs with no output, relative to the input directory.
+    pub missing: Vec<String>,
+    /// Inputs modified after their output was written.
+    pub stale: Vec<String>,
+    /// Outputs no input maps to, relative to the output directory.
+    pub orphaned: Vec<String>,
+    pub up_to_date: usize,
+}
+
+impl Diff {
+    /// Compares each input with its output, `(input, output)` as absolute
+    /// paths, and looks for orphans among the output tree's files with the
+    /// outputs' or `extensions`; the files of the input tree in `sources`
+    /// never are.
+    pub fn compare(
+        input_dir: &Path,
+        output_dir: &Path,
+        pairs: &[(PathBuf, PathBuf)],
+        sources: &[&Path],
+        extensions: &[String],
+    ) -> Self {
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut diff = Diff::default();
+        for (input, output) in pairs {
+            let key = input
+                .strip_prefix(input_dir)
+                .unwrap_or(input)
+                .to_string_lossy()
+                .to_string();
+            match (modified(input), modified(output)) {
+                (_, None) => diff.missing.push(key),
+                (Some(input_time), Some(output_time)) if input_time > output_time => {
+                    diff.stale.push(key)
+                }
+                _ => diff.up_to_date += 1,
+            }
+        }
+        let outputs: Vec<&Path> = pairs.iter().map(|(_, output)| output.as_path()).collect();
+        diff.orphaned = orphans(output_dir, &outputs, sources, extensions)
+            .iter()
+            .map(|path| {
+                path.strip_prefix(output_dir)
+                    .unwrap_or(path)
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect();
+        diff.missing.sort();
+        diff.stale.sort();
+        diff
+    }
+
+    /// One line per file that is not up to date, then the counts.
+    pub fn print(&self) {
+        for (label, files) in [
+            ("missing", &self.missing),
+            ("stale", &self.stale),
+            ("orphaned", &self.orphaned),
+        ] {
+            for file in files {
+                println!("{label:<9} {file}");
+            }
+        }
+        println!(
+            "{} up to date, {} missing, {} stale, {} orphaned.",
+            self.up_to_date,
+            self.missing.len(),
+            self.stale.len(),
+            self.orphaned.len()
+        );
+    }
+}
+
+/// The files under `output_dir` with one of the extensions of `outputs`, or
+/// of `extensions`, that are not among `outputs`, sorted.
+///
+/// The files of the input tree in `sources` (inputs, symlinks, passed-through
+/// files) are never orphans, for an output tree that is or holds the input
+/// tree. Hidden files (the journal, the lock, partial outputs) are never orphans,
+/// nor are the clips `--split-on-silence` and `--segments` write beside an
+/// output (`STEM_01.wav`, `STEM_01_label.wav`) or the files
+/// `--concat-per-dir` joins them into.
+pub fn orphans(
+    output_dir: &Path,
+    outputs: &[&Path],
+    sources: &[&Path],
+    extensions: &[String],
+) -> Vec<PathBuf> {
+    let expected: HashSet<&Path> = outputs.iter().chain(sources).copied().collect();
+    // Matched case-sensitively like inputs are, so passed-through files are left alone
+    let mut kinds: HashSet<String> = extensions.iter().cloned().collect();
+    kinds.extend(
+        outputs
+            .iter()
+            .filter_map(|o| o.extension())
+            .map(|e| e.to_string_lossy().to_string()),
+    );
+    // Clip names start with the stem of the output they were cut from
+    let stems: HashSet<(&Path, String)> = outputs
+        .iter()
+        .filter_map(|o| Some((o.parent()?, o.file_stem()?.to_string_lossy().to_string())))
+        .collect();
+    let is_clip = |path: &Path| {
+        let (Some(dir), Some(stem)) = (path.parent(), path.file_stem()) else {
+            return false;
+        };
+        let stem = stem.to_string_lossy();
+        stem.match_indices('_').any(|(at, _)| {
+            let number = stem[at + 1..].split('_').next().unwrap_or_default();
+            number.len() >= 2
+                && number.bytes().all(|b| b.is_ascii_digit())
+                && stems.contains(&(dir, stem[..at].to_string()))
+        })
+    };
+    let mut orphans: Vec<PathBuf> = WalkDir::new(output_dir)
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .map(walkdir::DirEntry::into_path)
+        .filter(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            path.extension()
+                .is_some_and(|e| kinds.contains(e.to_string_lossy().as_ref()))
+                && !name.ends_with(concat::SUFFIX)
+                && !expected.contains(path.as_path())
+                && !is_clip(path)
+        })
+        .collect();
+    orphans.sort();
+    orphans
+}
+
+/// Deletes the orphan `output` with the files written beside it (its JSON
+/// sidecar, backend log and the `companions` extensions), and then its
+/// directories up to `output_dir` as they become empty.
+pub fn prune(output_dir: &Path, output: &Path, companions: &[String]) -> Result<()> {
+    std::fs::remove_file(output)
+        .with_context(|| format!("Failed to delete orphaned output: {}", output.display()))?;
+    for suffix in [".json", ".log"] {
+        let mut beside = output.as_os_str().to_owned();
+        beside.push(suffix);
+        let _ = std::fs::remove_file(PathBuf::from(beside));
+    }
+    for ext in companions {
+        let _ = std::fs::remove_file(output.with_extension(ext));
+    }
+    let mut dir = output.parent();
+    while let Some(parent) = dir.filter(|d| *d != output_dir && d.starts_with(output_dir)) {
+        if std::fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+    Ok(())
+}
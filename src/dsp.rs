@@ -0,0 +1,333 @@
+//! Signal processing building blocks.
+
+use std::f64::consts::{PI, TAU};
+
+/// In-place iterative radix-2 FFT; `re.len()` must be a power of two.
+pub fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    debug_assert!(n.is_power_of_two() && im.len() == n);
+
+    // Bit-reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -TAU / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let tr = re[b] * cos - im[b] * sin;
+                let ti = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Magnitudes in dBFS of Hann-windowed frames of `size` samples taken every `hop`
+/// samples; each frame holds `size / 2` bins from DC upwards.
+pub fn spectrogram(samples: &[f32], size: usize, hop: usize) -> Vec<Vec<f32>> {
+    let window: Vec<f64> = (0..size)
+        .map(|i| 0.5 - 0.5 * (TAU * i as f64 / size as f64).cos())
+        .collect();
+    // A full-scale sine peaks at size/4 after the Hann window
+    let reference = size as f64 / 4.0;
+
+    let frames = samples.len().saturating_sub(size) / hop.max(1) + 1;
+    (0..frames)
+        .map(|f| {
+            let start = f * hop;
+            let mut re: Vec<f64> = (0..size)
+                .map(|i| f64::from(samples.get(start + i).copied().unwrap_or(0.0)) * window[i])
+                .collect();
+            let mut im = vec![0.0; size];
+            fft(&mut re, &mut im);
+            (0..size / 2)
+                .map(|k| {
+                    let magnitude = (re[k] * re[k] + im[k] * im[k]).sqrt() / reference;
+                    (20.0 * magnitude.max(1e-10).log10()) as f32
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Resamples `samples` from `from` Hz to `to` Hz with a Hann-windowed sinc
+/// interpolator (16 zero crossings per side), low-passing at the lower Nyquist
+/// frequency of the two rates.
+pub fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    let mut resampler = Resampler::new(from, to);
+    let mut resampled = resampler.push(samples);
+    resampled.extend(resampler.finish());
+    resampled
+}
+
+/// [`resample`] for audio that arrives a block at a time, keeping only the
+/// input the kernel still reaches; the output is the same.
+pub struct Resampler {
+    ratio: f64,
+    cutoff: f64,
+    /// Kernel half-width in input samples.
+    radius: f64,
+    /// Input from sample `base` on.
+    buffer: Vec<f32>,
+    base: usize,
+    /// Input samples taken so far.
+    taken: usize,
+    /// Index of the next output sample.
+    next: usize,
+}
+
+impl Resampler {
+    const ZERO_CROSSINGS: f64 = 16.0;
+
+    pub fn new(from: u32, to: u32) -> Self {
+        let ratio = f64::from(to) / f64::from(from);
+        let cutoff = ratio.min(1.0);
+        Resampler {
+            ratio,
+            cutoff,
+            radius: Self::ZERO_CROSSINGS / cutoff,
+            buffer: Vec::new(),
+            base: 0,
+            taken: 0,
+            next: 0,
+        }
+    }
+
+    /// Takes more input and returns the output samples it completes.
+    pub fn push(&mut self, samples: &[f32]) -> Vec<f32> {
+        if self.ratio == 1.0 {
+            return samples.to_vec();
+        }
+        self.buffer.extend(samples);
+        self.taken += samples.len();
+        let mut resampled = Vec::with_capacity((samples.len() as f64 * self.ratio) as usize + 1);
+        loop {
+            let t = self.next as f64 / self.ratio;
+            if (t + self.radius).floor() as usize >= self.taken {
+                break;
+            }
+            resampled.push(self.sample(t));
+            self.next += 1;
+        }
+        // Input before the next kernel's reach is no longer needed
+        let first = (self.next as f64 / self.ratio - self.radius)
+            .ceil()
+            .max(0.0) as usize;
+        if first > self.base {
+            let drop = (first - self.base).min(self.buffer.len());
+            self.buffer.drain(..drop);
+            self.base += drop;
+        }
+        resampled
+    }
+
+    /// Returns the rest of the output once the input has ended.
+    pub fn finish(mut self) -> Vec<f32> {
+        if self.ratio == 1.0 {
+            return Vec::new();
+        }
+        let len = (self.taken as f64 * self.ratio).round() as usize;
+        let mut resampled = Vec::with_capacity(len.saturating_sub(self.next));
+        while self.next < len {
+            resampled.push(self.sample(self.next as f64 / self.ratio));
+            self.next += 1;
+        }
+        resampled
+    }
+
+    /// The output at input position `t`, from the input taken so far.
+    fn sample(&self, t: f64) -> f32 {
+        let first = ((t - self.radius).ceil().max(0.0) as usize).max(self.base);
+        let last = ((t + self.radius).floor() as usize).min(self.taken - 1);
+        let sum: f64 = (first..=last)
+            .map(|k| {
+                let x = (k as f64 - t) * self.cutoff;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * x).sin() / (PI * x)
+                };
+                let window = 0.5 + 0.5 * (PI * x / Self::ZERO_CROSSINGS).cos();
+                f64::from(self.buffer[k - self.base]) * sinc * window
+            })
+            .sum();
+        (sum * self.cutoff) as f32
+    }
+}
+
+/// A second-order IIR filter section (RBJ audio EQ cookbook, Butterworth Q).
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    /// 12 dB/octave low-pass at `freq` Hz.
+    pub fn lowpass(freq: f64, rate: f64) -> Self {
+        let (cos, alpha) = Self::prewarp(freq, rate);
+        Self::new(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            cos,
+            alpha,
+        )
+    }
+
+    /// 12 dB/octave high-pass at `freq` Hz.
+    pub fn highpass(freq: f64, rate: f64) -> Self {
+        let (cos, alpha) = Self::prewarp(freq, rate);
+        Self::new(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            cos,
+            alpha,
+        )
+    }
+
+    /// Notch at `freq` Hz, `freq / q` Hz wide.
+    pub fn notch(freq: f64, q: f64, rate: f64) -> Self {
+        let w0 = TAU * freq / rate;
+        let cos = w0.cos();
+        Self::new([1.0, -2.0 * cos, 1.0], cos, w0.sin() / (2.0 * q))
+    }
+
+    /// A filter from coefficients already normalized so that `a0` is 1.
+    pub fn from_coefficients(b: [f64; 3], a: [f64; 2]) -> Self {
+        Biquad {
+            b,
+            a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    fn prewarp(freq: f64, rate: f64) -> (f64, f64) {
+        let w0 = TAU * freq / rate;
+        (w0.cos(), w0.sin() * std::f64::consts::FRAC_1_SQRT_2)
+    }
+
+    fn new(b: [f64; 3], cos: f64, alpha: f64) -> Self {
+        let a0 = 1.0 + alpha;
+        Biquad {
+            b: b.map(|b| b / a0),
+            a: [-2.0 * cos / a0, (1.0 - alpha) / a0],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f64, rate: f64, secs: f64) -> impl Iterator<Item = f64> {
+        (0..(rate * secs) as usize).map(move |i| (TAU * freq * i as f64 / rate).sin())
+    }
+
+    /// The steady-state gain of `filter` at `freq` Hz, from the peak over
+    /// the second half of two seconds of a sine.
+    fn gain(mut filter: Biquad, freq: f64, rate: f64) -> f64 {
+        let output: Vec<f64> = sine(freq, rate, 2.0).map(|x| filter.process(x)).collect();
+        output[output.len() / 2..]
+            .iter()
+            .fold(0.0, |peak, y| y.abs().max(peak))
+    }
+
+    #[test]
+    fn fft_puts_a_sine_in_its_bin() {
+        let n = 64;
+        let mut re: Vec<f64> = (0..n)
+            .map(|i| (TAU * 5.0 * i as f64 / n as f64).cos())
+            .collect();
+        let mut im = vec![0.0; n];
+        fft(&mut re, &mut im);
+        for k in 0..n {
+            let magnitude = re[k].hypot(im[k]);
+            let expected = if k == 5 || k == n - 5 {
+                n as f64 / 2.0
+            } else {
+                0.0
+            };
+            assert!((magnitude - expected).abs() < 1e-9, "bin {k}: {magnitude}");
+        }
+    }
+
+    #[test]
+    fn a_full_scale_sine_reads_0_dbfs() {
+        // Bin 32 of a 512-point frame at 16 kHz is 1000 Hz
+        let samples: Vec<f32> = sine(1000.0, 16000.0, 0.1).map(|x| x as f32).collect();
+        let frames = spectrogram(&samples, 512, 256);
+        assert_eq!(frames[0].len(), 256);
+        assert!(frames[0][32].abs() < 0.01, "{}", frames[0][32]);
+        assert!(frames[0][100] < -60.0);
+    }
+
+    #[test]
+    fn biquads_pass_and_stop_the_right_bands() {
+        let rate = 16000.0;
+        assert!(gain(Biquad::highpass(100.0, rate), 20.0, rate) < 0.05);
+        assert!((gain(Biquad::highpass(100.0, rate), 100.0, rate) - 0.707).abs() < 0.01);
+        assert!(gain(Biquad::highpass(100.0, rate), 1000.0, rate) > 0.99);
+        assert!(gain(Biquad::lowpass(1000.0, rate), 100.0, rate) > 0.99);
+        assert!((gain(Biquad::lowpass(1000.0, rate), 1000.0, rate) - 0.707).abs() < 0.01);
+        assert!(gain(Biquad::lowpass(1000.0, rate), 6000.0, rate) < 0.05);
+        assert!(gain(Biquad::notch(50.0, 30.0, rate), 50.0, rate) < 0.05);
+        assert!(gain(Biquad::notch(50.0, 30.0, rate), 100.0, rate) > 0.95);
+    }
+
+    #[test]
+    fn streaming_resampling_matches_resampling_at_once() {
+        let samples: Vec<f32> = sine(440.0, 48000.0, 0.5).map(|x| x as f32).collect();
+        let whole = resample(&samples, 48000, 16000);
+        assert_eq!(whole.len(), samples.len() / 3);
+
+        let mut resampler = Resampler::new(48000, 16000);
+        let mut streamed: Vec<f32> = samples
+            .chunks(1000)
+            .flat_map(|block| resampler.push(block))
+            .collect();
+        streamed.extend(resampler.finish());
+        assert_eq!(streamed, whole);
+
+        // Away from the edges the sine keeps its level
+        let peak = whole[1000..7000]
+            .iter()
+            .fold(0f32, |peak, s| s.abs().max(peak));
+        assert!((peak - 1.0).abs() < 0.01, "{peak}");
+    }
+
+    #[test]
+    fn resampling_at_the_same_rate_changes_nothing() {
+        let samples = [0.1, -0.2, 0.3];
+        assert_eq!(resample(&samples, 16000, 16000), samples);
+    }
+}
@@ -0,0 +1,93 @@
+//! Failure classes of the pipeline. Errors are tagged with a class where they
+//! arise, and the class travels with the failure into events, the
+//! `--notify-url` summary and job status, so callers can branch on the kind
+//! of failure instead of parsing its message.
+
+use serde::Serialize;
+use std::fmt;
+
+/// What kind of failure stopped a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DenoiseError {
+    /// The input is not audio the pipeline can take: a broken or unsupported
+    /// WAV file, or raw PCM of the wrong size.
+    InvalidFormat,
+    /// `--decoder ffmpeg` could not decode the input.
+    DecoderFailed,
+    /// The denoiser itself failed: an API error, a crashed command or worker.
+    BackendFailed,
+    /// A `--plugin` pre or post stage failed.
+    PluginFailed,
+    /// Reading or writing a file failed.
+    IoError,
+    /// A request or subprocess took too long.
+    Timeout,
+    /// Anything else.
+    Internal,
+}
+
+impl DenoiseError {
+    /// The class of `error`: a timeout anywhere in its chain, else the
+    /// outermost class it was tagged with, else an I/O error if one caused it.
+    pub fn classify(error: &anyhow::Error) -> Self {
+        let timed_out = error.chain().any(|cause| {
+            cause
+                .downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == std::io::ErrorKind::TimedOut)
+                || matches!(cause.downcast_ref(), Some(ureq::Error::Timeout(_)))
+        });
+        if timed_out {
+            return DenoiseError::Timeout;
+        }
+        if let Some(&class) = error.downcast_ref::<DenoiseError>() {
+            return class;
+        }
+        if error.chain().any(|cause| cause.is::<std::io::Error>()) {
+            return DenoiseError::IoError;
+        }
+        DenoiseError::Internal
+    }
+}
+
+impl fmt::Display for DenoiseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DenoiseError::InvalidFormat => "Invalid input",
+            DenoiseError::DecoderFailed => "Decoding failed",
+            DenoiseError::BackendFailed => "The denoiser failed",
+            DenoiseError::PluginFailed => "A plugin failed",
+            DenoiseError::IoError => "I/O error",
+            DenoiseError::Timeout => "Timed out",
+            DenoiseError::Internal => "Internal error",
+        })
+    }
+}
+
+impl std::error::Error for DenoiseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Context, Result};
+
+    #[test]
+    fn the_outermost_tag_names_the_class() {
+        let error = anyhow::Error::new(DenoiseError::DecoderFailed)
+            .context("ffmpeg exited with status 1")
+            .context(DenoiseError::BackendFailed);
+        assert_eq!(DenoiseError::classify(&error), DenoiseError::BackendFailed);
+        let untagged = anyhow::anyhow!("unexpected state");
+        assert_eq!(DenoiseError::classify(&untagged), DenoiseError::Internal);
+    }
+
+    #[test]
+    fn timeouts_and_io_errors_are_found_anywhere_in_the_chain() {
+        let timed_out: Result<()> = Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into());
+        let error = timed_out.context(DenoiseError::BackendFailed).unwrap_err();
+        assert_eq!(DenoiseError::classify(&error), DenoiseError::Timeout);
+        let missing: Result<()> = Err(std::io::Error::from(std::io::ErrorKind::NotFound).into());
+        let error = missing.context("reading in.wav").unwrap_err();
+        assert_eq!(DenoiseError::classify(&error), DenoiseError::IoError);
+    }
+}
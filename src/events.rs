@@ -0,0 +1,115 @@
+//! The `--events` stream: one JSON object per line for every step a file takes
+//! through a batch, so a supervising process can follow the run live.
+
+use crate::error::DenoiseError;
+use crate::loudness::Loudness;
+use crate::noise::NoiseProfile;
+use crate::preflight::Preflight;
+use crate::quality::Quality;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Formats the event stream can be written in.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventFormat {
+    /// JSON Lines: one object per line
+    Jsonl,
+}
+
+/// A lifecycle event of one input file, named by its path relative to the input directory.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event<'a> {
+    /// What the run is about to process, before the first file starts.
+    Preflight {
+        #[serde(flatten)]
+        summary: &'a Preflight,
+    },
+    /// The file matched and will be handed to a worker.
+    Discovered { file: &'a str, bytes: u64 },
+    /// A worker picked the file up.
+    Started { file: &'a str },
+    /// The file (after pre plugins) is in a format the denoiser accepts.
+    Validated { file: &'a str },
+    /// The denoised output was written.
+    Finished {
+        file: &'a str,
+        output: &'a Path,
+        secs: f64,
+        /// With `--measure-loudness`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        loudness: Option<Loudness>,
+        /// With `--noise-report`: the noise of the input.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        noise: Option<NoiseProfile>,
+        /// With `--reference-dir`: the scores against the reference.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        quality: Option<Quality>,
+    },
+    /// The file was left alone, e.g. as already denoised or in a format the denoiser does not take.
+    Skipped {
+        file: &'a str,
+        reason: &'a str,
+        /// `invalid_format` for inputs the denoiser does not take.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        code: Option<DenoiseError>,
+    },
+    Failed {
+        file: &'a str,
+        reason: &'a str,
+        code: DenoiseError,
+    },
+    /// `--verify` found something wrong with the written output.
+    Anomaly { file: &'a str, reason: &'a str },
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    /// Seconds since the Unix epoch.
+    time: f64,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+/// Where events are written; shared by the workers.
+pub struct Events {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Events {
+    /// Writes to `path`, or to stdout when it is `None` or `-`.
+    pub fn open(path: Option<&Path>) -> Result<Self> {
+        let out: Box<dyn Write + Send> = match path {
+            Some(path) if path != Path::new("-") => Box::new(
+                std::fs::File::create(path)
+                    .with_context(|| format!("Failed to create file: {}", path.display()))?,
+            ),
+            _ => Box::new(std::io::stdout()),
+        };
+        Ok(Events {
+            out: Mutex::new(out),
+        })
+    }
+
+    pub fn emit(&self, event: Event) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64());
+        let mut line = serde_json::to_vec(&Line {
+            time,
+            event: &event,
+        })
+        .unwrap_or_default();
+        line.push(b'\n');
+        // Whole lines, flushed at once, so a reader never sees half an event
+        let mut out = self.out.lock().unwrap();
+        if let Err(e) = out.write_all(&line).and_then(|()| out.flush()) {
+            eprintln!("Warning: failed to write event: {e}");
+        }
+    }
+}
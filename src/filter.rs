@@ -0,0 +1,353 @@
+//! The `--post-filter` chain applied to each denoised file, and the
+//! `--gate-db` noise gate at its end.
+
+use crate::audio;
+use crate::dsp::Biquad;
+use anyhow::{Result, bail};
+use serde::Serialize;
+use std::path::Path;
+use std::str::FromStr;
+
+/// One filter of the chain, written `NAME` or `NAME=HZ`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostFilter {
+    /// Removes rumble below the cutoff (default 80 Hz).
+    Highpass(f64),
+    /// Removes hiss above the cutoff.
+    Lowpass(f64),
+    /// Compresses sibilance above the given frequency (default 5000 Hz).
+    Deess(f64),
+    /// Removes a constant offset.
+    DcRemove,
+    /// Mutes stretches that stay below `db` dBFS for longer than `hold_ms`;
+    /// added by `--gate-db` rather than written in the chain.
+    Gate { db: f64, hold_ms: u64 },
+}
+
+/// How fast the gate opens once the level crosses the threshold.
+const GATE_OPEN_SECS: f64 = 0.001;
+/// How fast the gate fades out once the hold time has passed.
+const GATE_CLOSE_SECS: f64 = 0.02;
+/// Time constant of the RMS level the gate listens to; a peak level would
+/// let the noise peaks reopen it.
+const GATE_LEVEL_SECS: f64 = 0.01;
+
+impl FromStr for PostFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = match s.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (s.trim(), None),
+        };
+        let hz = |default: Option<f64>| match value {
+            Some(v) => match v.parse::<f64>() {
+                Ok(hz) if hz > 0.0 => Ok(hz),
+                _ => Err(format!(
+                    "expected a frequency in Hz for '{name}', got '{v}'"
+                )),
+            },
+            None => default.ok_or_else(|| format!("'{name}' needs a frequency, e.g. {name}=7000")),
+        };
+        match name {
+            "highpass" => hz(Some(80.0)).map(PostFilter::Highpass),
+            "lowpass" => hz(None).map(PostFilter::Lowpass),
+            "deess" => hz(Some(5000.0)).map(PostFilter::Deess),
+            "dcremove" if value.is_none() => Ok(PostFilter::DcRemove),
+            "dcremove" => Err("'dcremove' takes no value".to_string()),
+            other => Err(format!(
+                "unknown post filter '{other}' (highpass, lowpass, deess, dcremove)"
+            )),
+        }
+    }
+}
+
+impl PostFilter {
+    /// This filter with fresh state, for one channel at `rate` Hz.
+    fn stage(self, rate: f64) -> Result<Stage> {
+        let cutoff = match self {
+            PostFilter::Highpass(hz) | PostFilter::Lowpass(hz) | PostFilter::Deess(hz) => hz,
+            PostFilter::DcRemove | PostFilter::Gate { .. } => 0.0,
+        };
+        if cutoff >= rate / 2.0 {
+            bail!("Post filter frequency {cutoff} Hz is above the Nyquist frequency");
+        }
+        Ok(match self {
+            PostFilter::Highpass(hz) => Stage::Biquad(Biquad::highpass(hz, rate)),
+            PostFilter::Lowpass(hz) => Stage::Biquad(Biquad::lowpass(hz, rate)),
+            PostFilter::Deess(hz) => Stage::Deess {
+                band: Biquad::highpass(hz, rate),
+                envelope: 0.0,
+                attack: (-1.0 / (0.001 * rate)).exp(),
+                release: (-1.0 / (0.05 * rate)).exp(),
+            },
+            // One-pole DC blocker with its corner at 5 Hz
+            PostFilter::DcRemove => Stage::DcRemove {
+                pole: 1.0 - std::f64::consts::TAU * 5.0 / rate,
+                last_x: 0.0,
+                last_y: 0.0,
+            },
+            PostFilter::Gate { db, hold_ms } => Stage::Gate {
+                threshold: 10f64.powf(db / 10.0),
+                power: 0.0,
+                smoothing: (-1.0 / (GATE_LEVEL_SECS * rate)).exp(),
+                hold: (hold_ms as f64 / 1000.0 * rate) as u64,
+                quiet: 0,
+                gain: 1.0,
+                open: (-1.0 / (GATE_OPEN_SECS * rate)).exp(),
+                close: (-1.0 / (GATE_CLOSE_SECS * rate)).exp(),
+            },
+        })
+    }
+}
+
+/// A filter running over one channel, sample by sample.
+enum Stage {
+    Biquad(Biquad),
+    /// Turns down the band above the high-pass `band` by 4:1 while its level exceeds -30 dBFS.
+    Deess {
+        band: Biquad,
+        envelope: f64,
+        attack: f64,
+        release: f64,
+    },
+    DcRemove {
+        pole: f64,
+        last_x: f64,
+        last_y: f64,
+    },
+    /// Fades to silence once the mean power has been below `threshold` for
+    /// `hold` samples, and back in as soon as it rises above it.
+    Gate {
+        threshold: f64,
+        power: f64,
+        smoothing: f64,
+        hold: u64,
+        /// Samples the envelope has been below the threshold.
+        quiet: u64,
+        gain: f64,
+        open: f64,
+        close: f64,
+    },
+}
+
+impl Stage {
+    fn process(&mut self, x: f64) -> f64 {
+        match self {
+            Stage::Biquad(filter) => filter.process(x),
+            Stage::Deess {
+                band,
+                envelope,
+                attack,
+                release,
+            } => {
+                const THRESHOLD: f64 = 0.0316;
+                const SLOPE: f64 = 1.0 - 1.0 / 4.0;
+                let sibilance = band.process(x);
+                let coeff = if sibilance.abs() > *envelope {
+                    *attack
+                } else {
+                    *release
+                };
+                *envelope = coeff * *envelope + (1.0 - coeff) * sibilance.abs();
+                let gain = if *envelope > THRESHOLD {
+                    (THRESHOLD / *envelope).powf(SLOPE)
+                } else {
+                    1.0
+                };
+                x - sibilance * (1.0 - gain)
+            }
+            Stage::DcRemove {
+                pole,
+                last_x,
+                last_y,
+            } => {
+                *last_y = x - *last_x + *pole * *last_y;
+                *last_x = x;
+                *last_y
+            }
+            Stage::Gate {
+                threshold,
+                power,
+                smoothing,
+                hold,
+                quiet,
+                gain,
+                open,
+                close,
+            } => {
+                *power = *smoothing * *power + (1.0 - *smoothing) * x * x;
+                *quiet = if *power >= *threshold {
+                    0
+                } else {
+                    (*quiet + 1).min(*hold + 1)
+                };
+                let (target, coeff) = if *quiet > *hold {
+                    (0.0, *close)
+                } else {
+                    (1.0, *open)
+                };
+                *gain = target + (*gain - target) * coeff;
+                x * *gain
+            }
+        }
+    }
+}
+
+/// Harmonics of the mains frequency removed by `--dehum`, the fundamental included.
+const DEHUM_HARMONICS: u32 = 8;
+/// Quality of each `--dehum` notch, about 1.7 Hz wide at 50 Hz.
+const DEHUM_Q: f64 = 30.0;
+
+/// Notches out `mains` Hz and its harmonics below the Nyquist frequency,
+/// in every channel of the WAV file at `input`.
+pub fn dehum_file(input: &Path, output: &Path, mains: u32) -> Result<()> {
+    run_file(input, output, |rate| {
+        Ok((1..=DEHUM_HARMONICS)
+            .map(|harmonic| f64::from(mains * harmonic))
+            .take_while(|&freq| freq < rate / 2.0)
+            .map(|freq| Stage::Biquad(Biquad::notch(freq, DEHUM_Q, rate)))
+            .collect())
+    })
+}
+
+/// Runs `filters` in order over every channel of the WAV file at `input`,
+/// writing the result to `output` in the same format.
+pub fn apply_file(input: &Path, output: &Path, filters: &[PostFilter]) -> Result<()> {
+    run_file(input, output, |rate| {
+        filters.iter().map(|filter| filter.stage(rate)).collect()
+    })
+}
+
+/// Streams `input` through a chain of stages per channel, made by `chain`
+/// for the file's sample rate, into `output`.
+fn run_file(input: &Path, output: &Path, chain: impl Fn(f64) -> Result<Vec<Stage>>) -> Result<()> {
+    let mut reader = audio::FrameReader::open(input)?;
+    let spec = reader.spec();
+    let channels = usize::from(spec.channels.max(1));
+    let mut chains = (0..channels)
+        .map(|_| chain(f64::from(spec.sample_rate)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut writer = audio::FrameWriter::create(output, spec, false)?;
+    while let Some(mut block) = reader.next_block()? {
+        for frame in block.chunks_mut(channels) {
+            for (s, stages) in frame.iter_mut().zip(&mut chains) {
+                *s = stages
+                    .iter_mut()
+                    .fold(*s, |x, stage| stage.process(f64::from(x)) as f32);
+            }
+        }
+        writer.write(&block)?;
+    }
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(filter: PostFilter, rate: f64, input: impl Iterator<Item = f64>) -> Vec<f64> {
+        let mut stage = filter.stage(rate).unwrap();
+        input.map(|x| stage.process(x)).collect()
+    }
+
+    #[test]
+    fn parses_filters_with_and_without_frequencies() {
+        assert_eq!("highpass".parse(), Ok(PostFilter::Highpass(80.0)));
+        assert_eq!(" lowpass = 7000 ".parse(), Ok(PostFilter::Lowpass(7000.0)));
+        assert_eq!("deess".parse(), Ok(PostFilter::Deess(5000.0)));
+        assert_eq!("dcremove".parse(), Ok(PostFilter::DcRemove));
+        for bad in [
+            "lowpass",
+            "highpass=-5",
+            "highpass=abc",
+            "dcremove=5",
+            "reverb",
+        ] {
+            assert!(bad.parse::<PostFilter>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn rejects_frequencies_above_nyquist() {
+        assert!(PostFilter::Lowpass(9000.0).stage(16000.0).is_err());
+        assert!(PostFilter::Lowpass(7000.0).stage(16000.0).is_ok());
+    }
+
+    #[test]
+    fn dcremove_takes_out_a_constant_offset() {
+        let output = run(
+            PostFilter::DcRemove,
+            16000.0,
+            std::iter::repeat_n(0.5, 16000),
+        );
+        assert!(output.last().unwrap().abs() < 0.01);
+    }
+
+    #[test]
+    fn deess_turns_down_only_loud_sibilance() {
+        let rate = 16000.0;
+        let tone = |freq: f64, level: f64| {
+            (0..16000).map(move |i| level * (std::f64::consts::TAU * freq * i as f64 / rate).sin())
+        };
+        let peak = |output: Vec<f64>| output[8000..].iter().fold(0f64, |p, y| y.abs().max(p));
+        let filter = PostFilter::Deess(5000.0);
+        assert!(peak(run(filter, rate, tone(7000.0, 0.5))) < 0.3);
+        assert!(peak(run(filter, rate, tone(7000.0, 0.01))) > 0.009);
+        assert!(peak(run(filter, rate, tone(500.0, 0.5))) > 0.49);
+    }
+
+    #[test]
+    fn the_gate_mutes_quiet_stretches_after_its_hold_time() {
+        let rate = 16000.0;
+        let gate = PostFilter::Gate {
+            db: -40.0,
+            hold_ms: 50,
+        };
+        let loud = (0..8000).map(|i| 0.5 * (std::f64::consts::TAU * 440.0 * i as f64 / rate).sin());
+        let quiet = (0..8000).map(|i| if i % 2 == 0 { 0.001 } else { -0.001 });
+        let output = run(gate, rate, loud.chain(quiet));
+        let peak =
+            |range: std::ops::Range<usize>| output[range].iter().fold(0f64, |p, y| y.abs().max(p));
+        assert!(peak(4000..8000) > 0.49);
+        // Open while the level decays and through the hold time, then faded out
+        assert!(peak(9000..9500) > 0.0009);
+        assert!(peak(14000..16000) < 1e-6);
+    }
+
+    #[test]
+    fn filters_files_in_their_own_format() {
+        let dir = std::env::temp_dir().join(format!(
+            "{}-filter-{}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (input, output) = (dir.join("in.wav"), dir.join("out.wav"));
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 16000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&input, spec).unwrap();
+        for _ in 0..16000 {
+            writer.write_sample(8000i16).unwrap();
+            writer.write_sample(-8000i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        apply_file(&input, &output, &[PostFilter::DcRemove]).unwrap();
+        let mut reader = hound::WavReader::open(&output).unwrap();
+        assert_eq!(reader.spec(), spec);
+        let samples: Vec<i16> = reader.samples().map(Result::unwrap).collect();
+        assert_eq!(samples.len(), 32000);
+        assert!(
+            samples[31998..].iter().all(|s| s.abs() < 100),
+            "{samples:?}"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
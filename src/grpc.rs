@@ -0,0 +1,231 @@
+//! `serve-grpc`: the denoiser as a gRPC `DenoiseService`, described for
+//! clients in `proto/denoise.proto`, so services on a gRPC mesh can denoise
+//! audio without files or the HTTP job API. Built with `--features grpc`.
+//!
+//! `Denoise` streams the audio of the HTTP server's `/stream` both ways:
+//! 48kHz 16-bit little-endian mono PCM in, and the denoised samples back as
+//! each 10ms frame completes, about one frame behind. An empty chunk ends the
+//! audio: the rest of the output follows, and a new stream may start on the
+//! same call, as does the end of the call itself. The built-in model (or
+//! `--model` weights in nnnoiseless' format) runs in-process, on a thread of
+//! the call's own, so no audio touches the disk.
+//!
+//! Calls are checked against the tokens of [`crate::auth`], sent as
+//! `authorization: Bearer <token>` metadata.
+
+use crate::auth::{Auth, AuthArgs, Denied};
+use crate::backend::Nnnoiseless;
+use crate::models::ModelStore;
+use anyhow::{Context, Result, bail};
+use std::net::ToSocketAddrs;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, Streaming};
+
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/denoise.v1.DenoiseService.rs"));
+}
+use generated::denoise_service_server::{DenoiseService, DenoiseServiceServer};
+
+/// Largest chunk accepted: about 10 seconds of audio.
+const MAX_CHUNK: usize = 1 << 20;
+
+/// Sample rate of the streamed audio.
+const STREAM_RATE: usize = 48000;
+
+/// Denoised chunks a slow client may leave unread before denoising pauses.
+const OUTGOING_CHUNKS: usize = 16;
+
+/// One message of a `Denoise` call, either way.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AudioChunk {
+    /// Little-endian 16-bit samples.
+    #[prost(bytes = "vec", tag = "1")]
+    pub pcm: Vec<u8>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct GrpcArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:50051")]
+    listen: String,
+
+    /// Weights file in nnnoiseless' format, or the name of a downloaded model [default: the built-in RNNoise model]
+    #[arg(long)]
+    model: Option<String>,
+
+    /// Overlap of the denoiser's frames in percent (0, 50, 75, 80 or 90); more is smoother and slower
+    #[arg(long, value_name = "PERCENT", default_value_t = 0)]
+    overlap: u32,
+
+    /// Calls served at once
+    #[arg(long, default_value_t = 8, value_name = "N")]
+    max_streams: usize,
+
+    /// Denoised audio collected before it is sent, a multiple of 10 ms; more means fewer, later chunks
+    #[arg(long, default_value_t = 10, value_name = "MS")]
+    frame_size: usize,
+
+    #[command(flatten)]
+    auth: AuthArgs,
+}
+
+struct Service {
+    live: Arc<Nnnoiseless>,
+    auth: Auth,
+    /// Samples sent per chunk, `--frame-size` long.
+    frame: usize,
+    streams: Arc<AtomicUsize>,
+    max_streams: usize,
+}
+
+/// Counts a call against `--max-streams` while held.
+struct Claim(Arc<AtomicUsize>);
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Runs the gRPC service until the process is stopped.
+pub fn serve(args: &GrpcArgs, models_dir: Option<&Path>) -> Result<()> {
+    if args.frame_size == 0 || !args.frame_size.is_multiple_of(10) {
+        bail!("--frame-size must be a positive multiple of 10 ms");
+    }
+    let address = args
+        .listen
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .with_context(|| format!("Invalid --listen address: {}", args.listen))?;
+    // A model name that matches a cached download is loaded from its file
+    let model = args.model.as_ref().map(|name| {
+        ModelStore::new(models_dir)
+            .ok()
+            .and_then(|store| store.resolve(name))
+            .map_or_else(|| name.clone(), |path| path.to_string_lossy().to_string())
+    });
+    let service = Service {
+        live: Arc::new(Nnnoiseless::new(model.as_deref(), args.overlap)?),
+        auth: Auth::new(&args.auth)?,
+        frame: args.frame_size * STREAM_RATE / 1000,
+        streams: Arc::new(AtomicUsize::new(0)),
+        max_streams: args.max_streams,
+    };
+    if !service.auth.enabled() {
+        eprintln!("Warning: no tokens configured; the service accepts unauthenticated calls");
+    }
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("Failed to start the gRPC runtime")?;
+    eprintln!("Listening for gRPC on {address}");
+    runtime
+        .block_on(
+            tonic::transport::Server::builder()
+                .add_service(
+                    DenoiseServiceServer::new(service).max_decoding_message_size(MAX_CHUNK),
+                )
+                .serve(address),
+        )
+        .with_context(|| format!("Failed to serve gRPC on {}", args.listen))
+}
+
+#[tonic::async_trait]
+impl DenoiseService for Service {
+    type DenoiseStream = ReceiverStream<Result<AudioChunk, Status>>;
+
+    async fn denoise(
+        &self,
+        request: Request<Streaming<AudioChunk>>,
+    ) -> Result<Response<Self::DenoiseStream>, Status> {
+        let authorization = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok());
+        let remote = request
+            .remote_addr()
+            .map_or_else(String::new, |addr| addr.ip().to_string());
+        match self.auth.check(authorization, &remote) {
+            Ok(_) => {}
+            Err(Denied::Unauthorized) => {
+                return Err(Status::unauthenticated("missing or invalid bearer token"));
+            }
+            Err(Denied::RateLimited(retry)) => {
+                return Err(Status::resource_exhausted(format!(
+                    "rate limit exceeded; retry after {retry} s"
+                )));
+            }
+        }
+        self.streams
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < self.max_streams).then_some(n + 1)
+            })
+            .map_err(|_| Status::resource_exhausted("too many streams are open"))?;
+        let claim = Claim(Arc::clone(&self.streams));
+
+        let mut inbound = request.into_inner();
+        let (outbound, received) = tokio::sync::mpsc::channel(OUTGOING_CHUNKS);
+        let live = Arc::clone(&self.live);
+        let frame = self.frame;
+        let runtime = tokio::runtime::Handle::current();
+        tokio::task::spawn_blocking(move || {
+            let _claim = claim;
+            let sent = session(
+                &live,
+                frame,
+                || runtime.block_on(inbound.message()),
+                |pcm| outbound.blocking_send(Ok(AudioChunk { pcm })).is_ok(),
+            );
+            if let Err(status) = sent {
+                let _ = outbound.blocking_send(Err(status));
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(received)))
+    }
+}
+
+/// Denoises the chunks `next` gives until the call ends, handing the denoised
+/// PCM to `send` in chunks of `frame` samples, and the rest of each stream at
+/// its end. Returns early when `send` finds the client gone.
+fn session(
+    live: &Nnnoiseless,
+    frame: usize,
+    mut next: impl FnMut() -> Result<Option<AudioChunk>, Status>,
+    mut send: impl FnMut(Vec<u8>) -> bool,
+) -> Result<(), Status> {
+    let mut stream = live.live();
+    let mut outgoing: Vec<i16> = Vec::new();
+    let mut send = |samples: Vec<i16>| {
+        samples.is_empty() || send(samples.iter().flat_map(|s| s.to_le_bytes()).collect())
+    };
+    while let Some(chunk) = next()? {
+        if chunk.pcm.len() % 2 != 0 {
+            return Err(Status::invalid_argument("chunks hold whole 16-bit samples"));
+        }
+        let denoised = if chunk.pcm.is_empty() {
+            // The end of a stream sends the rest, however short
+            outgoing.extend(std::mem::replace(&mut stream, live.live()).finish());
+            std::mem::take(&mut outgoing)
+        } else {
+            let samples: Vec<i16> = chunk
+                .pcm
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                .collect();
+            outgoing.extend(stream.push(&samples));
+            let whole = outgoing.len() - outgoing.len() % frame;
+            outgoing.drain(..whole).collect()
+        };
+        if !send(denoised) {
+            return Ok(());
+        }
+    }
+    outgoing.extend(stream.finish());
+    send(outgoing);
+    Ok(())
+}
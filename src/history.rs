@@ -0,0 +1,133 @@
+//! The `--db` history: every file a run handles is recorded in an SQLite
+//! database with its hash, the settings and the result, and inputs already
+//! denoised with the same settings are skipped by later runs, on any machine
+//! that shares the database.
+//!
+//! SQLite is built into the binary; a run keeps one connection open to the
+//! database and writes through a prepared statement.
+
+use crate::checksum;
+use crate::clock;
+use crate::lock;
+use anyhow::{Context, Result};
+use rusqlite::{Connection, params};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS settings (
+    sha256 TEXT PRIMARY KEY,
+    json TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS history (
+    id INTEGER PRIMARY KEY,
+    input TEXT NOT NULL,
+    output TEXT NOT NULL,
+    input_sha256 TEXT NOT NULL,
+    settings_sha256 TEXT NOT NULL REFERENCES settings (sha256),
+    status TEXT NOT NULL,
+    reason TEXT,
+    secs REAL NOT NULL,
+    processed_at TEXT NOT NULL,
+    host TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS history_done ON history (settings_sha256, input_sha256, status);
+";
+
+/// How long a statement waits for another run's write to finish.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+const INSERT: &str = "INSERT INTO history \
+     (input, output, input_sha256, settings_sha256, status, reason, secs, processed_at, host) \
+     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)";
+
+/// One handled file.
+pub struct Record<'a> {
+    /// Path relative to the input directory.
+    pub input: &'a str,
+    /// Path relative to the output directory.
+    pub output: &'a str,
+    pub input_sha256: &'a str,
+    /// `done`, `skipped`, `invalid` or `failed`.
+    pub status: &'a str,
+    pub reason: Option<&'a str>,
+    pub secs: f64,
+}
+
+pub struct History {
+    path: PathBuf,
+    settings_sha256: String,
+    /// Hashes of the inputs denoised before with these settings.
+    done: HashSet<String>,
+    /// One write at a time from this process; SQLite locks out other processes.
+    connection: Mutex<Connection>,
+}
+
+impl History {
+    /// Opens (creating if needed) the database at `path` for a run with `settings`.
+    pub fn open(path: &Path, settings: &impl Serialize) -> Result<Self> {
+        let settings = serde_json::to_string(settings)?;
+        let settings_sha256 = checksum::sha256_reader(settings.as_bytes())?;
+        let failed = || format!("Failed to open the history database: {}", path.display());
+        let connection = Connection::open(path).with_context(failed)?;
+        connection.busy_timeout(BUSY_TIMEOUT).with_context(failed)?;
+        connection.execute_batch(SCHEMA).with_context(failed)?;
+        connection
+            .execute(
+                "INSERT OR IGNORE INTO settings VALUES (?1, ?2)",
+                params![settings_sha256, settings],
+            )
+            .with_context(failed)?;
+        let done = connection
+            .prepare(
+                "SELECT DISTINCT input_sha256 FROM history WHERE settings_sha256 = ?1 AND status = 'done'",
+            )
+            .and_then(|mut select| {
+                select
+                    .query_map([&settings_sha256], |row| row.get(0))?
+                    .collect::<rusqlite::Result<HashSet<String>>>()
+            })
+            .with_context(failed)?;
+        Ok(History {
+            path: path.to_path_buf(),
+            settings_sha256,
+            done,
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Whether an input with this hash was denoised before with the same settings.
+    pub fn was_done(&self, input_sha256: &str) -> bool {
+        self.done.contains(input_sha256)
+    }
+
+    pub fn record(&self, record: &Record) -> Result<()> {
+        let connection = self.connection.lock().unwrap();
+        // Prepared once and kept in the connection's statement cache
+        connection
+            .prepare_cached(INSERT)
+            .and_then(|mut insert| {
+                insert.execute(params![
+                    record.input,
+                    record.output,
+                    record.input_sha256,
+                    self.settings_sha256,
+                    record.status,
+                    record.reason,
+                    record.secs,
+                    clock::iso8601_now(),
+                    lock::hostname(),
+                ])
+            })
+            .with_context(|| {
+                format!(
+                    "Failed to write to the history database: {}",
+                    self.path.display()
+                )
+            })?;
+        Ok(())
+    }
+}
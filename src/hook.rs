@@ -0,0 +1,71 @@
+//! `--post-process-cmd`: a program run after each file is denoised, e.g. to
+//! upload, tag or transcode it while the rest of the batch is still running.
+
+use crate::concurrency::Limiter;
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+/// A command such as `upload.sh {input} {output}`. Each word is split like a
+/// shell would, without running one, and `{input}`, `{output}` and `{file}`
+/// (the input's path relative to the input directory) are replaced in every
+/// word, so paths with spaces or quotes reach the program intact.
+#[derive(Debug, Clone)]
+pub struct PostCommand(Vec<String>);
+
+impl FromStr for PostCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words = shlex::split(s)
+            .filter(|w| !w.is_empty())
+            .ok_or_else(|| format!("invalid command: '{s}'"))?;
+        if words.is_empty() {
+            return Err("the post-process command is empty".to_string());
+        }
+        Ok(PostCommand(words))
+    }
+}
+
+/// Runs the post-process command for finished files, at most `--post-process-jobs` at once.
+pub struct PostProcess {
+    command: PostCommand,
+    limiter: Option<Limiter>,
+}
+
+impl PostProcess {
+    pub fn new(command: PostCommand, jobs: Option<usize>) -> Self {
+        PostProcess {
+            command,
+            limiter: jobs.map(|n| Limiter::new(n.max(1))),
+        }
+    }
+
+    /// Runs the command for the input `file` (relative) at `input`, denoised
+    /// to `output`, and waits for it. Its stdout goes to stderr, so it never
+    /// mixes with an `--events` stream.
+    pub fn run(&self, file: &str, input: &Path, output: &Path) -> Result<()> {
+        let words: Vec<String> = self
+            .command
+            .0
+            .iter()
+            .map(|w| {
+                w.replace("{input}", &input.to_string_lossy())
+                    .replace("{output}", &output.to_string_lossy())
+                    .replace("{file}", file)
+            })
+            .collect();
+        let _permit = self.limiter.as_ref().map(Limiter::acquire);
+        let status = Command::new(&words[0])
+            .args(&words[1..])
+            .stdin(Stdio::null())
+            .stdout(std::io::stderr())
+            .status()
+            .with_context(|| format!("Failed to start post-process command: {}", words[0]))?;
+        if !status.success() {
+            bail!("Post-process command {} failed ({status})", words[0]);
+        }
+        Ok(())
+    }
+}
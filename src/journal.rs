@@ -0,0 +1,220 @@
+//! Append-only journal of the outputs a run has finished, kept in the output
+//! directory.
+//!
+//! A file is journaled only once its output is completely written and reads
+//! back as a whole WAV file, so a run killed mid-write leaves no entry for the
+//! file it was on. Resuming with `--state-file` or `--db` trusts an output
+//! only when it is journaled and still has the journaled size, never because
+//! a file of that name merely exists.
+
+use crate::riff;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Name of the journal inside the output directory.
+const JOURNAL_FILE: &str = ".wav-files-denoise-api.journal";
+
+/// One line of the journal.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// Input path relative to the input directory.
+    file: String,
+    /// Output path relative to the output directory.
+    output: String,
+    bytes: u64,
+}
+
+pub struct Journal {
+    dir: PathBuf,
+    /// The last entry of each input from earlier runs.
+    entries: HashMap<String, Entry>,
+    file: Mutex<File>,
+}
+
+impl Journal {
+    /// Opens the journal of `dir`, reading what earlier runs recorded.
+    ///
+    /// A last line cut short by a crash is ignored, as are lines that do not
+    /// parse.
+    pub fn open(dir: &Path) -> Result<Self> {
+        let path = dir.join(JOURNAL_FILE);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read journal: {}", path.display()));
+            }
+        };
+        let complete = text.rfind('\n').map_or("", |end| &text[..end]);
+        let entries = complete
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Entry>(line).ok())
+            .map(|entry| (entry.file.clone(), entry))
+            .collect();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open journal: {}", path.display()))?;
+        // Start on a fresh line after a cut-short one
+        if !text.is_empty() && !text.ends_with('\n') {
+            file.write_all(b"\n")?;
+        }
+        Ok(Journal {
+            dir: dir.to_path_buf(),
+            entries,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Whether an earlier run journaled the input `file` and its output is
+    /// still there with the size it had then.
+    pub fn trusts(&self, file: &str) -> bool {
+        self.entries.get(file).is_some_and(|entry| {
+            std::fs::metadata(self.dir.join(&entry.output)).is_ok_and(|m| m.len() == entry.bytes)
+        })
+    }
+
+    /// Journals the input `file` once its `output` reads back whole, and
+    /// waits until the entry is on disk.
+    pub fn record(&self, file: &str, output: &Path) -> Result<()> {
+        let format = riff::format(output)?;
+        if format.truncated {
+            bail!("{} ends before its data chunk does", output.display());
+        }
+        let entry = Entry {
+            file: file.to_string(),
+            output: output
+                .strip_prefix(&self.dir)
+                .unwrap_or(output)
+                .to_string_lossy()
+                .to_string(),
+            bytes: std::fs::metadata(output)?.len(),
+        };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        let mut journal = self.file.lock().unwrap();
+        journal.write_all(&line)?;
+        journal.sync_data()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for one test's files.
+    fn scratch(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "{}-journal-{}-{test}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn wav(path: &Path, samples: usize) {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 16000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for n in 0..samples {
+            writer.write_sample(n as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    #[test]
+    fn a_resumed_run_trusts_only_journaled_outputs() {
+        let dir = scratch("resume");
+        wav(&dir.join("a.wav"), 1600);
+        wav(&dir.join("b.wav"), 1600);
+        let journal = Journal::open(&dir).unwrap();
+        journal.record("in/a.wav", &dir.join("a.wav")).unwrap();
+        // Entries count from the next run on
+        assert!(!journal.trusts("in/a.wav"));
+        drop(journal);
+
+        let resumed = Journal::open(&dir).unwrap();
+        assert!(resumed.trusts("in/a.wav"));
+        assert!(
+            !resumed.trusts("in/b.wav"),
+            "b.wav exists but was never journaled"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn outputs_changed_or_removed_since_are_not_trusted() {
+        let dir = scratch("changed");
+        wav(&dir.join("a.wav"), 1600);
+        wav(&dir.join("b.wav"), 1600);
+        let journal = Journal::open(&dir).unwrap();
+        journal.record("a.wav", &dir.join("a.wav")).unwrap();
+        journal.record("b.wav", &dir.join("b.wav")).unwrap();
+        drop(journal);
+        wav(&dir.join("a.wav"), 800);
+        std::fs::remove_file(dir.join("b.wav")).unwrap();
+
+        let resumed = Journal::open(&dir).unwrap();
+        assert!(!resumed.trusts("a.wav"));
+        assert!(!resumed.trusts("b.wav"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_to_journal_a_truncated_output() {
+        let dir = scratch("truncated");
+        let path = dir.join("a.wav");
+        wav(&path, 1600);
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(1000).unwrap();
+        drop(file);
+        let journal = Journal::open(&dir).unwrap();
+        let err = journal.record("a.wav", &path).unwrap_err();
+        assert!(
+            err.to_string().contains("ends before its data chunk does"),
+            "{err:#}"
+        );
+        assert!(!Journal::open(&dir).unwrap().trusts("a.wav"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_line_cut_short_by_a_crash_is_ignored() {
+        let dir = scratch("crash");
+        wav(&dir.join("a.wav"), 1600);
+        wav(&dir.join("b.wav"), 1600);
+        let journal = Journal::open(&dir).unwrap();
+        journal.record("a.wav", &dir.join("a.wav")).unwrap();
+        drop(journal);
+        let path = dir.join(JOURNAL_FILE);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"file":"b.wav","output":"b.wav","by"#)
+            .unwrap();
+        drop(file);
+
+        let resumed = Journal::open(&dir).unwrap();
+        assert!(resumed.trusts("a.wav"));
+        assert!(!resumed.trusts("b.wav"));
+        // The next entry starts on a line of its own
+        resumed.record("b.wav", &dir.join("b.wav")).unwrap();
+        drop(resumed);
+        let resumed = Journal::open(&dir).unwrap();
+        assert!(resumed.trusts("a.wav"));
+        assert!(resumed.trusts("b.wav"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,7 @@
+//! The command-line tool is the crate's interface; the library exposes the
+//! failure classes found in its reports, events and API responses, so Rust
+//! callers reading them can match on [`DenoiseError`] rather than on strings.
+
+pub mod error;
+
+pub use error::DenoiseError;
@@ -0,0 +1,124 @@
+//! Advisory lock file that keeps two runs from writing the same output directory.
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the lock file inside the output directory.
+const LOCK_FILE: &str = ".wav-files-denoise-api.lock";
+
+/// Who holds the lock, as written into the lock file.
+#[derive(Serialize, Deserialize)]
+struct Holder {
+    pid: u32,
+    host: String,
+    /// Seconds since the Unix epoch.
+    started: u64,
+}
+
+/// A held lock; the lock file is removed on drop.
+pub struct OutputLock {
+    path: PathBuf,
+}
+
+impl OutputLock {
+    /// Takes the lock on `dir`, failing if another live run holds it.
+    ///
+    /// A lock left behind by a process that no longer runs on this host is
+    /// taken over; `force` takes over any lock.
+    pub fn acquire(dir: &Path, force: bool) -> Result<Self> {
+        let path = dir.join(LOCK_FILE);
+        let holder = Holder {
+            pid: std::process::id(),
+            host: hostname(),
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        };
+        let contents = serde_json::to_vec(&holder)?;
+        loop {
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(mut file) => {
+                    file.write_all(&contents).with_context(|| {
+                        format!("Failed to write lock file: {}", path.display())
+                    })?;
+                    return Ok(OutputLock { path });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("Failed to create lock file: {}", path.display())
+                    });
+                }
+            }
+
+            // Unreadable lock files are treated as held, so only --force-lock removes them
+            let existing = std::fs::read(&path)
+                .ok()
+                .and_then(|bytes| serde_json::from_slice::<Holder>(&bytes).ok());
+            let stale = existing
+                .as_ref()
+                .is_some_and(|h| h.host == holder.host && !is_running(h.pid));
+            if !force && !stale {
+                let who = existing.map_or_else(
+                    || "an unknown process".to_string(),
+                    |h| format!("process {} on {} (started at {})", h.pid, h.host, h.started),
+                );
+                bail!(
+                    "{} is locked by {who}; another run is writing there. \
+                     Remove {} or pass --force-lock if that run is gone",
+                    dir.display(),
+                    path.display()
+                );
+            }
+            eprintln!("Taking over the lock {}", path.display());
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove lock file: {}", path.display()))?;
+        }
+    }
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Name of this machine, or empty when it cannot be found.
+pub fn hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut buf = [0u8; 256];
+        // SAFETY: the buffer is valid for its whole length
+        if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } == 0 {
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+            return String::from_utf8_lossy(&buf[..len]).to_string();
+        }
+    }
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks that the process exists
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    // EPERM: it exists but belongs to another user
+    alive || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Without a way to ask, every holder is assumed to be alive.
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
@@ -0,0 +1,190 @@
+//! Loudness and true-peak measurement after ITU-R BS.1770-4, with the loudness
+//! range of EBU Tech 3342, for `--measure-loudness`.
+
+use crate::audio;
+use crate::dsp::Biquad;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::path::Path;
+
+/// Loudness figures of one file; `None` where the file is too short or too quiet to measure.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Loudness {
+    /// Integrated loudness, LUFS.
+    pub integrated_lufs: Option<f64>,
+    /// Loudness range, LU.
+    pub range_lu: Option<f64>,
+    /// True peak (4x oversampled), dBTP.
+    pub true_peak_dbtp: f64,
+}
+
+/// Blocks quieter than this never count, in LUFS.
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Oversampling factor of the true-peak meter.
+const OVERSAMPLE: usize = 4;
+/// Taps on each side of the true-peak interpolation filter.
+const TAPS: usize = 12;
+
+/// Measures the WAV file at `path`.
+pub fn measure_file(path: &Path) -> Result<Loudness> {
+    let audio = audio::read_wav(path)?;
+    let channels: Vec<Vec<f32>> = (0..usize::from(audio.spec.channels.max(1)))
+        .map(|c| audio.channel(c))
+        .collect();
+    Ok(measure(&channels, f64::from(audio.spec.sample_rate)))
+}
+
+pub fn measure(channels: &[Vec<f32>], rate: f64) -> Loudness {
+    // Mean square of the K-weighted signal per 100 ms, summed over channels with their weights
+    let step = (rate / 10.0).round() as usize;
+    let frames = channels.first().map_or(0, Vec::len);
+    let mut steps = vec![0.0; frames / step.max(1)];
+    for (c, samples) in channels.iter().enumerate() {
+        let weight = channel_weight(c, channels.len());
+        if weight == 0.0 {
+            continue;
+        }
+        let [mut shelf, mut highpass] = k_weighting(rate);
+        for (i, &s) in samples.iter().enumerate().take(steps.len() * step) {
+            let y = highpass.process(shelf.process(f64::from(s)));
+            steps[i / step] += weight * y * y / step as f64;
+        }
+    }
+
+    // 400 ms momentary blocks (75% overlap) for the integrated loudness
+    let momentary = windows(&steps, 4);
+    let integrated = gated_mean(&momentary, -10.0).map(power_to_lufs);
+
+    // 3 s short-term blocks for the loudness range: 10th to 95th percentile
+    let short_term = windows(&steps, 30);
+    let range = gated_mean(&short_term, -20.0).map(|gate_power| {
+        let gate = power_to_lufs(gate_power) - 20.0;
+        let mut levels: Vec<f64> = short_term
+            .iter()
+            .map(|&p| power_to_lufs(p))
+            .filter(|&l| l > ABSOLUTE_GATE && l > gate)
+            .collect();
+        levels.sort_by(f64::total_cmp);
+        let at = |q: f64| levels[((levels.len() - 1) as f64 * q).round() as usize];
+        at(0.95) - at(0.10)
+    });
+
+    let peak = channels.iter().map(|c| true_peak(c)).fold(0.0, f64::max);
+    Loudness {
+        integrated_lufs: integrated,
+        range_lu: range,
+        true_peak_dbtp: 20.0 * peak.max(1e-10).log10(),
+    }
+}
+
+/// BS.1770 channel weights: surround channels count 1.41, the LFE of 5.1 not at all.
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    match (channels, channel) {
+        (6, 3) => 0.0,
+        (5, 3 | 4) | (6, 4 | 5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+/// The two K-weighting stages, a high shelf and a high-pass, derived for `rate`.
+fn k_weighting(rate: f64) -> [Biquad; 2] {
+    let shelf = {
+        let (f0, gain_db, q) = (
+            1_681.974_450_955_533,
+            3.999_843_853_973_347,
+            0.707_175_236_955_419_6,
+        );
+        let k = (PI * f0 / rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.499_666_774_154_541_6);
+        let a0 = 1.0 + k / q + k * k;
+        Biquad::from_coefficients(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        )
+    };
+    let highpass = {
+        let (f0, q) = (38.135_470_876_024_44, 0.500_327_037_323_877_3);
+        let k = (PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Biquad::from_coefficients(
+            [1.0, -2.0, 1.0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        )
+    };
+    [shelf, highpass]
+}
+
+/// Mean power of each run of `len` consecutive 100 ms steps.
+fn windows(steps: &[f64], len: usize) -> Vec<f64> {
+    steps
+        .windows(len)
+        .map(|w| w.iter().sum::<f64>() / len as f64)
+        .collect()
+}
+
+fn power_to_lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.max(1e-20).log10()
+}
+
+/// Mean power of the blocks above the absolute gate and above the relative
+/// gate `relative` LU below their own mean.
+fn gated_mean(blocks: &[f64], relative: f64) -> Option<f64> {
+    let mean = |blocks: &mut dyn Iterator<Item = f64>| {
+        let (sum, count) = blocks.fold((0.0, 0usize), |(s, n), p| (s + p, n + 1));
+        (count > 0).then(|| sum / count as f64)
+    };
+    let loud = |&p: &f64| power_to_lufs(p) > ABSOLUTE_GATE;
+    let ungated = mean(&mut blocks.iter().copied().filter(loud))?;
+    let gate = power_to_lufs(ungated) + relative;
+    mean(
+        &mut blocks
+            .iter()
+            .copied()
+            .filter(loud)
+            .filter(|&p| power_to_lufs(p) > gate),
+    )
+}
+
+/// Peak of the signal interpolated at `OVERSAMPLE` times the sample rate.
+fn true_peak(samples: &[f32]) -> f64 {
+    // Hann-windowed sinc kernels for the fractional positions between samples
+    let kernels: Vec<Vec<f64>> = (1..OVERSAMPLE)
+        .map(|phase| {
+            let frac = phase as f64 / OVERSAMPLE as f64;
+            (0..2 * TAPS)
+                .map(|j| {
+                    let t = j as f64 - (TAPS - 1) as f64 - frac;
+                    let sinc = if t == 0.0 {
+                        1.0
+                    } else {
+                        (PI * t).sin() / (PI * t)
+                    };
+                    let window = 0.5 + 0.5 * (PI * t / (TAPS as f64 + 1.0)).cos();
+                    sinc * window
+                })
+                .collect()
+        })
+        .collect();
+    let mut peak = samples
+        .iter()
+        .fold(0.0f64, |p, &s| p.max(f64::from(s).abs()));
+    for i in 0..samples.len() {
+        for kernel in &kernels {
+            let mut sum = 0.0;
+            for (j, &k) in kernel.iter().enumerate() {
+                // Taps run from TAPS - 1 samples before i to TAPS after it
+                if let Some(&s) = (i + j).checked_sub(TAPS - 1).and_then(|n| samples.get(n)) {
+                    sum += k * f64::from(s);
+                }
+            }
+            peak = peak.max(sum.abs());
+        }
+    }
+    peak
+}
@@ -1,151 +1,2063 @@
+mod archive;
+mod audio;
+mod auth;
+mod backend;
+mod bench;
+mod capture;
+mod checksum;
+mod chunk;
+mod client;
+mod clock;
+mod compare;
+mod completions;
+mod concat;
+mod concurrency;
+mod config;
+mod corpus;
+mod daemon;
+mod dashboard;
+mod decode;
+mod diff;
+mod dsp;
+mod events;
+mod filter;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod history;
+mod hook;
+mod journal;
+mod lock;
+mod loudness;
+mod manifest;
+mod metrics;
+mod models;
+mod naming;
+mod noise;
+#[cfg(feature = "onnx")]
+mod onnx;
+mod openapi;
+mod parquet;
+mod paths;
+mod pipeline;
+mod plugin;
+mod preflight;
+mod prepare;
+mod provenance;
+mod qa;
+mod quality;
+mod queue;
+mod raw;
+mod regions;
+mod report;
+mod retry;
+mod riff;
+mod rng;
+mod rules;
+mod s3;
+mod scratch;
+mod server;
+mod space;
+mod split;
+mod stages;
+mod state;
+mod stats;
+mod validate;
+mod walk;
+mod websocket;
+
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use concurrency::{Jobs, Limiter, Throttle};
+use dashboard::Dashboard;
+use decode::Decoder;
+use error::DenoiseError;
+use events::{Event, EventFormat, Events};
+use history::History;
+use hook::{PostCommand, PostProcess};
+use manifest::Manifest;
+use models::{ModelCommand, ModelStore};
+use naming::{Collision, DatasetSplit, NameTemplate};
+use pipeline::{Outcome, Pipeline, PipelineArgs, Prepared};
+use preflight::Preflight;
+use provenance::{ReprocessPolicy, RunInfo};
 use rayon::prelude::*;
-use serde::Serialize;
+use report::{FailOn, FileRecord, OutputFormat, ReportFormat, ReportSort, Totals};
+use rules::ModelRules;
+use s3::S3Location;
+use space::ByteSize;
+use stages::Gauges;
+use state::RunState;
+use std::collections::HashMap;
+use std::io::IsTerminal;
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use walkdir::WalkDir;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, mpsc};
+use std::time::Instant;
+use walk::{Order, Passthrough, SymlinkPolicy, WalkOptions};
+use wav_files_denoise_api::error;
 
 /// CLI arguments for wav-files-denoise.
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Recursively denoise WAV files using an external API", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Input directory containing WAV files (processed recursively)
-    input_dir: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Input directory containing WAV files (processed recursively), or - to read one file from stdin
+    #[arg(required = true)]
+    input_dir: Option<PathBuf>,
+
+    /// Output directory for denoised files, or - to write to stdout after an input of -;
+    /// without one, a single WAV file input is denoised beside itself as NAME_denoised.wav
+    output_dir: Option<PathBuf>,
+
+    /// Replace each input with its denoised version, keeping the original as FILE.bak
+    #[arg(long, conflicts_with_all = ["output_dir", "name_template", "flatten", "dedupe"])]
+    in_place: bool,
+
+    /// Keep --in-place originals under this directory, mirroring the input tree
+    #[arg(long, value_name = "DIR", requires = "in_place")]
+    backup_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    pipeline: PipelineArgs,
+
+    /// Maximum directory depth to scan (1 = only files directly in the input directory)
+    #[arg(long)]
+    max_depth: Option<usize>,
+
+    /// Comma-separated file extensions to process, matched case-sensitively
+    #[arg(long, value_delimiter = ',', default_value = "wav")]
+    extensions: Vec<String>,
+
+    /// Descend into symlinked directories (symlink loops are detected and skipped)
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// How to treat WAV files that are symlinks
+    #[arg(long, value_enum, default_value_t = SymlinkPolicy::Skip)]
+    symlink_files: SymlinkPolicy,
+
+    /// Output file name pattern, e.g. "{stem}_denoised.{ext}" (placeholders: stem, ext, parent, model, date)
+    #[arg(long)]
+    name_template: Option<NameTemplate>,
+
+    /// Write every output directly into the output directory, encoding subdirectories in the name
+    #[arg(long)]
+    flatten: bool,
+
+    /// Route outputs into train/, val/ and test/ subdirectories by these weights, e.g. 80/10/10
+    #[arg(long, value_name = "TRAIN/VAL/TEST", conflicts_with = "in_place")]
+    split: Option<DatasetSplit>,
 
-    /// Output directory for denoised files
-    output_dir: PathBuf,
+    /// Seed of the hash that --split assigns files by
+    #[arg(long, value_name = "N", default_value_t = 0, requires = "split")]
+    split_seed: u64,
 
-    /// Comma-separated list of API server addresses
-    #[arg(long, value_delimiter = ',')]
-    addr_api: Vec<String>,
+    /// What to do when inputs map to the same output path, e.g. through --name-template or only differing in case
+    #[arg(long, value_enum, value_name = "ACTION", default_value_t = Collision::Error, conflicts_with = "in_place")]
+    on_collision: Collision,
 
-    /// Model to use for denoising
+    /// NFC-normalize output paths (macOS stores file names decomposed, as NFD)
     #[arg(long)]
-    model: Option<String>,
+    normalize_unicode: bool,
+
+    /// Also put input files that are not denoised (other extensions, invalid or skipped) into the output tree
+    #[arg(long, value_enum, value_name = "MODE", conflicts_with = "in_place")]
+    passthrough: Option<Passthrough>,
+
+    /// Copy the files beside each input with its stem and these extensions (e.g. txt,json transcripts) beside its output
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "EXTS",
+        conflicts_with = "in_place"
+    )]
+    copy_sidecars: Vec<String>,
+
+    /// Symlink the --copy-sidecars files to the originals instead of copying them
+    #[arg(long, requires = "copy_sidecars")]
+    symlink_sidecars: bool,
+
+    /// Write OUTPUT.wav.json beside each output with its source, time, tool version, model and settings
+    #[arg(long)]
+    sidecar: bool,
+
+    /// Compare the input tree with the existing output tree and list the outputs that are missing, stale or orphaned, without denoising
+    #[arg(long, conflicts_with = "in_place")]
+    diff: bool,
+
+    /// Delete the outputs, with their sidecars, whose inputs no longer exist before denoising
+    #[arg(long, conflicts_with_all = ["in_place", "diff"])]
+    prune: bool,
+
+    /// With --prune, list the outputs it would delete and stop, deleting and denoising nothing
+    #[arg(long, requires = "prune")]
+    dry_run: bool,
+
+    /// TOML rules picking each input's model from the attributes in a JSON sidecar beside it (NAME.wav.json or NAME.json)
+    #[arg(long, value_name = "FILE", conflicts_with = "dedupe")]
+    model_rules: Option<PathBuf>,
+
+    /// Measure integrated loudness, loudness range and true peak (BS.1770) of each output
+    #[arg(long)]
+    measure_loudness: bool,
+
+    /// Estimate each input's noise level and kind (hum, hiss, intermittent) from its quiet stretches
+    #[arg(long)]
+    noise_report: bool,
+
+    /// Score each output against the clean recording at the same relative path here (STOI, SI-SDR, approximate PESQ)
+    #[arg(long, value_name = "DIR")]
+    reference_dir: Option<PathBuf>,
+
+    /// Re-read each output and flag wrong headers, lengths and silent results
+    #[arg(long)]
+    verify: bool,
+
+    /// Also write a stereo file per input (original left, denoised right) under this directory
+    #[arg(long, value_name = "DIR")]
+    ab_output: Option<PathBuf>,
+
+    /// Also render before/after spectrogram PNGs per input under this directory
+    #[arg(long, value_name = "DIR")]
+    spectrograms: Option<PathBuf>,
+
+    /// Run this command after each file is denoised, e.g. "upload.sh {input} {output}" (placeholders: input, output, file)
+    #[arg(long, value_name = "COMMAND")]
+    post_process_cmd: Option<PostCommand>,
+
+    /// Most --post-process-cmd commands running at once [default: one per --write-jobs thread]
+    #[arg(long, value_name = "N", requires = "post_process_cmd")]
+    post_process_jobs: Option<usize>,
+
+    /// After the run, join the WAV files of each output directory in name order into DIR/DIRNAME.concat.wav
+    #[arg(long)]
+    concat_per_dir: bool,
+
+    /// Seconds of silence between the files joined by --concat-per-dir
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 0.0,
+        requires = "concat_per_dir"
+    )]
+    concat_silence: f64,
+
+    /// Denoise byte-identical inputs once and hard-link (or copy) the result to the other outputs
+    #[arg(long)]
+    dedupe: bool,
+
+    /// Skip files shorter than this many seconds
+    #[arg(long, value_name = "SECS")]
+    min_duration: Option<f64>,
+
+    /// Skip files longer than this many seconds
+    #[arg(long, value_name = "SECS")]
+    max_duration: Option<f64>,
+
+    /// Process only files modified within this span before the run, e.g. 24h or 7d
+    #[arg(long, value_name = "AGE", conflicts_with = "modified_since")]
+    newer_than: Option<clock::Age>,
+
+    /// Process only files modified at or after this time, e.g. 2024-05-01T00:00:00Z or @1714521600
+    #[arg(long, value_name = "TIMESTAMP")]
+    modified_since: Option<clock::Timestamp>,
+
+    /// Process only this many randomly chosen matching files, e.g. for a quality spot-check
+    #[arg(long, value_name = "N", conflicts_with = "sample_percent")]
+    sample: Option<usize>,
+
+    /// Process only this percentage of the matching files, chosen at random
+    #[arg(long, value_name = "P")]
+    sample_percent: Option<f64>,
+
+    /// Seed for --sample, --sample-percent and --order random [default: from the clock, printed]
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Order in which files are handed to the workers [default: as discovered]
+    #[arg(long, value_enum)]
+    order: Option<Order>,
+
+    /// Skip files larger than this, e.g. 2G, reporting each one
+    #[arg(long, value_name = "SIZE")]
+    max_file_size: Option<ByteSize>,
+
+    /// Files processed concurrently, or 'auto' to scale with host load [default: one per API address]
+    #[arg(long)]
+    jobs: Option<Jobs>,
+
+    /// Threads decoding and converting inputs ahead of the denoise workers
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    read_jobs: usize,
+
+    /// Threads writing outputs behind the denoise workers: conversion back, measurements, sidecars, manifests, hooks
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    write_jobs: usize,
+
+    /// Files that can wait between two stages before the earlier one pauses [default: --jobs]
+    #[arg(long, value_name = "N")]
+    queue_depth: Option<usize>,
+
+    /// Print how many files are in each stage and queue every few seconds
+    #[arg(long, short = 'v', conflicts_with = "tui")]
+    verbose: bool,
+
+    /// Lower bound for --jobs auto
+    #[arg(long, default_value_t = 1)]
+    min_jobs: usize,
+
+    /// Upper bound for --jobs auto [default: number of CPUs]
+    #[arg(long)]
+    max_jobs: Option<usize>,
+
+    /// Realtime factor one worker reaches (see the bench subcommand), for the time estimate printed before the run
+    #[arg(long, value_name = "X", default_value_t = 1.0)]
+    estimate_speed: f64,
+
+    /// Start without asking, even when the estimate exceeds an hour
+    #[arg(long, short = 'y')]
+    yes: bool,
+
+    /// Run at this CPU niceness (19 is the most polite; below 0 needs privileges)
+    #[arg(long, value_name = "N", allow_hyphen_values = true,
+          value_parser = clap::value_parser!(i32).range(-20..=19))]
+    nice: Option<i32>,
+
+    /// Limit the input data handed to the denoiser, in megabytes per second
+    #[arg(long, value_name = "MB/S")]
+    max_throughput: Option<f64>,
+
+    /// Parallel transfers when the input or output is an s3:// location
+    #[arg(long, default_value_t = 8)]
+    s3_concurrency: usize,
+
+    /// Local directory for staging S3 objects and archive contents [default: system temp directory]
+    #[arg(long)]
+    staging_dir: Option<PathBuf>,
+
+    /// Free space the output filesystem must have before the run starts, e.g. 20G; 0 skips the check [default: the estimated output size]
+    #[arg(long, value_name = "SIZE")]
+    min_free_space: Option<ByteSize>,
+
+    /// JSON file recording progress so an interrupted run can resume
+    #[arg(long)]
+    state_file: Option<PathBuf>,
+
+    /// SQLite database recording every file handled; inputs denoised before with the same settings are skipped
+    #[arg(long, value_name = "FILE")]
+    db: Option<PathBuf>,
+
+    /// Write the state file after this many finished files
+    #[arg(long, default_value_t = 50)]
+    checkpoint_every: usize,
+
+    /// Write a report of the run with a row per file to FILE (add .gz to compress JSON and CSV)
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+
+    /// Format of --report [default: from its extension, .csv or .parquet, else json]
+    #[arg(long, value_enum, value_name = "FORMAT", requires = "report")]
+    report_format: Option<ReportFormat>,
+
+    /// Order of the --report rows, so the reports of two runs can be diffed
+    #[arg(long, value_enum, value_name = "ORDER", default_value_t = ReportSort::Path)]
+    sort: ReportSort,
+
+    /// Collect what backend programs and plugins print for each file into --report, instead of the console
+    #[arg(long)]
+    capture_backend_output: bool,
+
+    /// Also write each file's collected backend output beside its output, as OUTPUT.log
+    #[arg(long, requires = "capture_backend_output")]
+    backend_log: bool,
+
+    /// URL to POST a JSON summary to when the run finishes or aborts
+    #[arg(long)]
+    notify_url: Option<String>,
+
+    /// Show a live dashboard on the terminal instead of scrolling progress output
+    #[arg(long)]
+    tui: bool,
+
+    /// What the run prints: progress lines, or only a one-line JSON summary on stdout when it ends
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = OutputFormat::Text, conflicts_with = "tui")]
+    output: OutputFormat,
+
+    /// Emit an event per file lifecycle step (discovered, started, validated, finished, skipped, failed)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    events: Option<EventFormat>,
+
+    /// File to write --events to instead of stdout
+    #[arg(long, requires = "events")]
+    events_file: Option<PathBuf>,
+
+    /// CSV file listing input, output, their SHA-256, duration and model of every processed file
+    #[arg(long, value_name = "FILE")]
+    manifest: Option<PathBuf>,
+
+    /// Take over the output directory's lock even if another run seems to hold it
+    #[arg(long)]
+    force_lock: bool,
+
+    /// Abort the whole run on the first invalid input or denoiser error
+    #[arg(long)]
+    strict: bool,
+
+    /// Exit non-zero when files fail: any, all, or threshold=N% [default: always exit 0]
+    #[arg(long, value_name = "POLICY")]
+    fail_on: Option<FailOn>,
+
+    /// Directory holding downloaded models (defaults to the user cache directory)
+    #[arg(long, global = true)]
+    models_dir: Option<PathBuf>,
+
+    /// Directory for intermediate files, one subdirectory per worker thread; also the TMPDIR of subprocesses [default: beside each output]
+    #[arg(long, global = true, value_name = "DIR")]
+    temp_dir: Option<PathBuf>,
+
+    /// TOML file providing defaults for any option (see also WAVDENOISE_* variables)
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Preset of options for a kind of material: podcast, telephony, field-recording, asr-dataset, or a [profiles.NAME] table of --config
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Download, list and verify cached models
+    Model {
+        #[command(subcommand)]
+        action: ModelCommand,
+    },
+    /// Time the pipeline at increasing worker counts
+    Bench(bench::BenchArgs),
+    /// Denoise the same inputs with several models and tabulate how each did
+    Compare(compare::CompareArgs),
+    /// Synthesize a corpus of noisy test signals and broken files
+    #[command(alias = "gen-test-data")]
+    GenCorpus(corpus::CorpusArgs),
+    /// Serve an HTTP job API that denoises uploaded files
+    Serve(server::ServeArgs),
+    /// Serve a gRPC DenoiseService that denoises streamed audio
+    #[cfg(feature = "grpc")]
+    ServeGrpc(grpc::GrpcArgs),
+    /// Send files to a `serve` instance and download the results
+    Client(client::ClientArgs),
+    /// Denoise the jobs described by files dropped into a queue directory
+    Daemon(daemon::DaemonArgs),
+    /// Decode and check inputs into a staging directory, for a later `denoise STAGING_DIR OUTPUT_DIR` run
+    Prepare(prepare::PrepareArgs),
+    /// Re-process the files a previous run's state file lists as failed
+    Retry(retry::RetryArgs),
+    /// Report the format of every WAV file in a directory and why any would not be denoised
+    Validate(validate::ValidateArgs),
+    /// Summarize the sample rates, formats, channels and lengths of the WAV files in a directory
+    Stats(stats::StatsArgs),
+    /// Print a shell completion script
+    Completions(completions::CompletionsArgs),
+}
+
+fn main() -> Result<ExitCode> {
+    let matches = Args::command().get_matches_from(config::effective_args(Args::command())?);
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let _scratch = args.temp_dir.as_deref().map(scratch::init).transpose()?;
+
+    match &args.command {
+        Some(Command::Model { action }) => ModelStore::new(args.models_dir.as_deref())?.run(action),
+        Some(Command::Bench(bench_args)) => bench::run(bench_args, args.models_dir.as_deref()),
+        Some(Command::Compare(compare_args)) => {
+            compare::run(compare_args, args.models_dir.as_deref())
+        }
+        Some(Command::GenCorpus(corpus_args)) => corpus::generate(corpus_args),
+        Some(Command::Serve(serve_args)) => server::serve(serve_args, args.models_dir.as_deref()),
+        #[cfg(feature = "grpc")]
+        Some(Command::ServeGrpc(grpc_args)) => grpc::serve(grpc_args, args.models_dir.as_deref()),
+        Some(Command::Daemon(daemon_args)) => daemon::run(daemon_args, args.models_dir.as_deref()),
+        Some(Command::Completions(completions_args)) => {
+            completions::run(completions_args, Args::command())
+        }
+        Some(Command::Validate(validate_args)) => return validate::run(validate_args),
+        Some(Command::Stats(stats_args)) => stats::run(stats_args),
+        Some(Command::Prepare(prepare_args)) => return prepare::run(prepare_args),
+        Some(Command::Client(client_args)) => return client::run(client_args),
+        Some(Command::Retry(retry_args)) => {
+            return retry::run(retry_args, args.models_dir.as_deref());
+        }
+        None if args.input_dir.as_deref() == Some(Path::new("-")) => return denoise_stdio(&args),
+        None if args.output_dir.is_none() && !args.in_place => match &args.input_dir {
+            Some(input) if is_single_file(&args, input) => return denoise_dropped(&args, input),
+            _ => Args::command()
+                .error(
+                    clap::error::ErrorKind::MissingRequiredArgument,
+                    "the following required arguments were not provided:\n  <OUTPUT_DIR>",
+                )
+                .exit(),
+        },
+        None if args.diff => return diff_tree(&args),
+        None => return run(&args, &matches),
+    }?;
+    Ok(ExitCode::SUCCESS)
 }
 
-#[derive(Serialize)]
-struct DenoiseRequestBody {
-    filename: String,
-    filename_denoised: String,
-    model: Option<String>,
+/// Denoises a single file from stdin to stdout, e.g. a raw capture in a shell pipeline.
+fn denoise_stdio(args: &Args) -> Result<ExitCode> {
+    if args.output_dir.as_deref() != Some(Path::new("-")) {
+        anyhow::bail!("An input of - (stdin) needs an output of - (stdout)");
+    }
+    let pipeline = Pipeline::new(&args.pipeline, args.models_dir.as_deref())?;
+    let dir = args
+        .staging_dir
+        .clone()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(format!(
+            "{}-stdio-{}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    let extension = if args.pipeline.raw.raw { "raw" } else { "wav" };
+    let input = dir.join(format!("stdin.{extension}"));
+    let output = dir.join(format!("stdout.{extension}"));
+
+    let result = (|| {
+        let mut file = std::fs::File::create(&input)
+            .with_context(|| format!("Failed to create file: {}", input.display()))?;
+        std::io::copy(&mut std::io::stdin().lock(), &mut file).context("Failed to read stdin")?;
+        match pipeline.process(&input, &output)? {
+            Outcome::Processed => {}
+            Outcome::Skipped(reason) | Outcome::Invalid(reason) | Outcome::Failed(_, reason) => {
+                eprintln!("Error: stdin was not denoised: {reason}");
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        let mut denoised = std::fs::File::open(&output)
+            .with_context(|| format!("Failed to open file: {}", output.display()))?;
+        std::io::copy(&mut denoised, &mut std::io::stdout().lock())
+            .context("Failed to write stdout")?;
+        Ok(ExitCode::SUCCESS)
+    })();
+    let _ = std::fs::remove_dir_all(&dir);
+    result
 }
 
-/// Validates a WAV file matches the expected format: mono, 16-bit PCM, 16kHz sample rate.
-fn validate_wav(path: &Path) -> Result<bool> {
-    let reader = hound::WavReader::open(path)
-        .with_context(|| format!("Failed to open WAV file: {}", path.display()))?;
+/// Runs a batch and sends the `--notify-url` summary however it ends.
+fn run(args: &Args, matches: &clap::ArgMatches) -> Result<ExitCode> {
+    // Before any worker threads exist, so they all inherit it
+    if let Some(niceness) = args.nice {
+        concurrency::renice(niceness)?;
+    }
+    let started = Instant::now();
+    let totals = Totals::default();
+    let mut run = RunInfo::new(matches);
+    let result = denoise_tree(args, &totals, &mut run);
 
-    let spec = reader.spec();
-    Ok(spec.channels == 1 && spec.sample_rate == 16000 && spec.bits_per_sample == 16)
+    let summary = totals.summary(&run.id, started.elapsed(), result.as_ref().err());
+    if args.output == OutputFormat::Json {
+        match report::summary_line(&summary, &totals) {
+            Ok(line) => println!("{line}"),
+            Err(e) => eprintln!("Warning: {e:#}"),
+        }
+    } else if result.is_ok() && !args.dry_run {
+        status_line(
+            args,
+            &format!(
+                "Denoising complete: {} files processed, {} skipped.",
+                summary.processed,
+                summary.skipped + summary.failed
+            ),
+        );
+        status_line(args, &summary.throughput());
+        if let Some(loudness) = &summary.loudness {
+            status_line(args, &loudness.describe());
+        }
+        if let Some(noise) = &summary.noise {
+            status_line(args, &noise.describe());
+        }
+        if let Some(quality) = &summary.quality {
+            status_line(args, &quality.describe());
+        }
+        if summary.anomalies > 0 {
+            status_line(
+                args,
+                &format!(
+                    "Verification flagged {} outputs (see the warnings above).",
+                    summary.anomalies
+                ),
+            );
+        }
+    }
+    if let Some(path) = &args.report {
+        let format = args
+            .report_format
+            .unwrap_or_else(|| ReportFormat::for_path(path));
+        if let Err(e) = report::write_report(path, format, args.sort, &run, &summary, &totals) {
+            eprintln!("Warning: {e:#}");
+        }
+    }
+    if let Some(url) = &args.notify_url
+        && let Err(e) = report::notify(url, &summary)
+    {
+        eprintln!("Warning: {e:#}");
+    }
+    result?;
+    Ok(totals.exit_code(args.fail_on))
 }
 
-fn main() -> Result<()> {
-    let mut args = Args::parse();
+/// Prints how the output tree stands against the input tree (`--diff`);
+/// exits with 1 when any output is missing, stale or orphaned.
+fn diff_tree(args: &Args) -> Result<ExitCode> {
+    let (Some(input_dir), Some(output_dir)) = (&args.input_dir, &args.output_dir) else {
+        unreachable!("input and output directories are required");
+    };
+    for dir in [input_dir, output_dir] {
+        if S3Location::parse(dir).is_some() || archive::kind(dir).is_some() {
+            anyhow::bail!("--diff needs local input and output directories");
+        }
+    }
+    let input_dir = input_dir.canonicalize().with_context(|| {
+        format!(
+            "Failed to find canonical path for input directory: {}",
+            input_dir.display()
+        )
+    })?;
+    // A tree not written yet has every output missing
+    let output_dir = paths::extended(&output_dir.canonicalize().unwrap_or(output_dir.clone()));
+    let discovered = walk::discover(
+        &input_dir,
+        &WalkOptions {
+            follow_symlinks: args.follow_symlinks,
+            symlink_files: args.symlink_files,
+            max_depth: args.max_depth,
+            extensions: args.extensions.clone(),
+        },
+    );
+    let files = &discovered.files;
+    let rules = args
+        .model_rules
+        .as_deref()
+        .map(ModelRules::load)
+        .transpose()?;
+    let chosen = rules
+        .as_ref()
+        .map(|rules| rules.choose(&input_dir, files))
+        .unwrap_or_default();
+    let pairs: Vec<(PathBuf, PathBuf)> = output_names(args, &input_dir, files, &chosen)?
+        .iter()
+        .zip(files)
+        .map(|(output, e)| (e.path().to_path_buf(), paths::join(&output_dir, output)))
+        .collect();
+    let diff = diff::Diff::compare(
+        &input_dir,
+        &output_dir,
+        &pairs,
+        &discovered.sources(),
+        &args.extensions,
+    );
+    if args.output == OutputFormat::Json {
+        println!("{}", serde_json::to_string(&diff)?);
+    } else {
+        diff.print();
+    }
+    let in_sync = diff.missing.is_empty() && diff.stale.is_empty() && diff.orphaned.is_empty();
+    Ok(if in_sync {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+/// Whether `input` is a single file to denoise beside itself, e.g. one
+/// dropped onto the executable.
+fn is_single_file(args: &Args, input: &Path) -> bool {
+    input.is_file() && walk::has_extension(input, &args.extensions)
+}
+
+/// Denoises one file into NAME_denoised.EXT beside it, asking before
+/// overwriting an earlier result.
+fn denoise_dropped(args: &Args, input: &Path) -> Result<ExitCode> {
+    let result = (|| {
+        let mut name = input.file_stem().unwrap_or_default().to_os_string();
+        name.push("_denoised");
+        if let Some(ext) = input.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        let output = input.with_file_name(name);
+        if output.exists() && !args.yes {
+            let question = format!("{} already exists. Overwrite it? [y/N] ", output.display());
+            if !std::io::stdin().is_terminal() || !preflight::ask(&question)? {
+                eprintln!(
+                    "Left {} as it was (pass --yes to overwrite).",
+                    output.display()
+                );
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        let pipeline = Pipeline::new(&args.pipeline, args.models_dir.as_deref())?;
+        let canonical = input
+            .canonicalize()
+            .with_context(|| format!("Failed to find canonical path for: {}", input.display()))?;
+        println!("Denoising {}...", input.display());
+        match pipeline.process(
+            &canonical,
+            &canonical.with_file_name(output.file_name().unwrap()),
+        )? {
+            Outcome::Processed => {
+                println!("Saved {}", output.display());
+                Ok(ExitCode::SUCCESS)
+            }
+            Outcome::Skipped(reason) | Outcome::Invalid(reason) | Outcome::Failed(_, reason) => {
+                eprintln!("Error: {} was not denoised: {reason}", input.display());
+                Ok(ExitCode::FAILURE)
+            }
+        }
+    })();
+    // A console opened for a file dropped onto the executable closes as soon as it exits
+    if cfg!(windows) && std::io::stdin().is_terminal() {
+        let code = result.unwrap_or_else(|e| {
+            eprintln!("Error: {e:#}");
+            ExitCode::FAILURE
+        });
+        eprint!("Press Enter to close this window.");
+        let _ = std::io::stdin().read_line(&mut String::new());
+        return Ok(code);
+    }
+    result
+}
+
+/// Prints a status line to stdout, or to stderr while stdout carries the
+/// event stream; nothing with `--output json`.
+fn status_line(args: &Args, line: &str) {
+    if args.output == OutputFormat::Json {
+        return;
+    }
+    if args.events.is_some() && args.events_file.is_none() {
+        eprintln!("{line}");
+    } else {
+        println!("{line}");
+    }
+}
+
+/// The output path of each of `files`, relative to the output directory,
+/// following --name-template, --flatten, --split and --on-collision, with
+/// the models --model-rules `chosen`.
+fn output_names(
+    args: &Args,
+    input_dir: &Path,
+    files: &[walkdir::DirEntry],
+    chosen: &HashMap<String, &str>,
+) -> Result<Vec<PathBuf>> {
+    let model_of = |key: &str| chosen.get(key).copied().or(args.pipeline.model.as_deref());
+    let root_name = input_dir
+        .file_name()
+        .map_or_else(String::new, |n| n.to_string_lossy().to_string());
+    let (date, _) = clock::utc_now();
+    let date = format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]);
+    let relative_key = |path: &Path| {
+        path.strip_prefix(input_dir)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string()
+    };
+    let mut outputs: Vec<PathBuf> = files
+        .iter()
+        .map(|entry| {
+            let relative = entry.path().strip_prefix(input_dir).unwrap_or(entry.path());
+            let relative = match &args.name_template {
+                Some(template) => template.render(
+                    relative,
+                    &root_name,
+                    model_of(&relative_key(entry.path())),
+                    &date,
+                ),
+                None => relative.to_path_buf(),
+            };
+            let relative = if args.flatten {
+                naming::flatten(&relative)
+            } else {
+                relative
+            };
+            let relative = match &args.split {
+                Some(split) => {
+                    Path::new(split.subset(&relative_key(entry.path()), args.split_seed))
+                        .join(relative)
+                }
+                None => relative,
+            };
+            // Decoded inputs are written as WAV whatever they were
+            let decoded = args.pipeline.decoder == Decoder::Ffmpeg
+                && !relative
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"));
+            let relative = if decoded {
+                relative.with_extension("wav")
+            } else {
+                relative
+            };
+            if args.normalize_unicode {
+                paths::nfc(&relative)
+            } else {
+                relative
+            }
+        })
+        .collect();
+    // These can all map different inputs to one name
+    if args.flatten || args.normalize_unicode || args.pipeline.decoder == Decoder::Ffmpeg {
+        let inputs: Vec<String> = files.iter().map(|e| relative_key(e.path())).collect();
+        naming::disambiguate(&mut outputs, &inputs);
+    }
+    // Anything else that still collides would silently overwrite one result with another
+    let clashes = if args.in_place {
+        Vec::new()
+    } else {
+        naming::collisions(&outputs)
+    };
+    if !clashes.is_empty() {
+        let inputs: Vec<String> = files.iter().map(|e| relative_key(e.path())).collect();
+        if args.on_collision == Collision::Suffix {
+            naming::disambiguate(&mut outputs, &inputs);
+        } else {
+            let mut message = String::from("Several inputs map to the same output path:");
+            for group in clashes.iter().take(10) {
+                let names: Vec<&str> = group.iter().map(|&i| inputs[i].as_str()).collect();
+                message.push_str(&format!(
+                    "\n  {} <- {}",
+                    outputs[group[0]].display(),
+                    names.join(", ")
+                ));
+            }
+            if clashes.len() > 10 {
+                message.push_str(&format!("\n  ... and {} more", clashes.len() - 10));
+            }
+            message.push_str(
+                "\nRename the inputs, change --name-template or pass --on-collision suffix",
+            );
+            anyhow::bail!(message);
+        }
+    }
+    Ok(outputs)
+}
+
+/// A file on its way from one stage to the next: its index, when it was
+/// started, where the backend's output is collected and the file so far.
+type Decoded = (usize, Instant, Option<capture::Log>, Result<Prepared>);
+
+/// A written output for the rest of the writer stage: its index, what became
+/// of it, the input as it now is (the backup, in place), when it was started
+/// and the backend's output.
+type Denoised = (usize, Outcome, PathBuf, Instant, Option<String>);
+
+/// Denoises every matching WAV file under the input directory.
+fn denoise_tree(args: &Args, totals: &Totals, run: &mut RunInfo) -> Result<()> {
+    // Required by clap whenever no subcommand is given
+    let Some(input_dir) = &args.input_dir else {
+        unreachable!("input directory is required");
+    };
+    if args.output == OutputFormat::Json && args.events.is_some() && args.events_file.is_none() {
+        anyhow::bail!(
+            "--output json and --events both write to stdout; give --events an --events-file"
+        );
+    }
+    // Without an output directory (--in-place) outputs replace the inputs
+    let output_dir = match &args.output_dir {
+        Some(dir) => dir,
+        None if S3Location::parse(input_dir).is_some() || input_dir.is_file() => {
+            anyhow::bail!("--in-place needs a local input directory");
+        }
+        None if args.pipeline.decoder == Decoder::Ffmpeg => {
+            anyhow::bail!("--in-place cannot replace inputs decoded with --decoder ffmpeg");
+        }
+        None => input_dir,
+    };
+    // The paths as given, for the manifest; staging and canonicalizing replace them below
+    let listed_root = (input_dir.clone(), output_dir.clone());
+
+    // S3 locations and archives are staged through local directories
+    let s3_input = S3Location::parse(input_dir);
+    let s3_output = S3Location::parse(output_dir);
+    let s3_client = match (&s3_input, &s3_output) {
+        (None, None) => None,
+        _ => Some(s3::Client::from_env()?),
+    };
+    let input_archive = archive::kind(input_dir).is_some() && input_dir.is_file();
+    let output_archive = archive::kind(output_dir).is_some();
+    let staged_io = s3_client.is_some() || input_archive || output_archive;
+    let staging = args.staging_dir.clone().unwrap_or_else(|| {
+        std::env::temp_dir().join(format!("{}-{}", env!("CARGO_PKG_NAME"), std::process::id()))
+    });
+    let input_dir = match (&s3_input, &s3_client) {
+        (Some(loc), Some(client)) => {
+            let dir = staging.join("input");
+            s3::stage_input(client, loc, &dir, &args.extensions, args.s3_concurrency)?;
+            dir
+        }
+        _ if input_archive => {
+            let dir = staging.join("input");
+            let count = archive::extract(input_dir, &dir, &args.extensions)?;
+            eprintln!("Extracted {count} files from {}", input_dir.display());
+            dir
+        }
+        _ => input_dir.clone(),
+    };
+    let archive_path = output_archive.then(|| output_dir.clone());
+    let output_dir = if s3_output.is_some() || output_archive {
+        staging.join("output")
+    } else {
+        output_dir.clone()
+    };
+    let input_dir = &input_dir;
+    let output_dir = &output_dir;
 
     // Resolve to absolute paths to avoid ambiguity
-    args.input_dir = args.input_dir.canonicalize().with_context(|| {
+    let input_dir = input_dir.canonicalize().with_context(|| {
         format!(
             "Failed to find canonical path for input directory: {}",
-            args.input_dir.display()
+            input_dir.display()
         )
     })?;
 
     // Ensure output directory exists
-    std::fs::create_dir_all(&args.output_dir).with_context(|| {
+    std::fs::create_dir_all(output_dir).with_context(|| {
         format!(
             "Failed to create output directory: {}",
-            args.output_dir.display()
+            output_dir.display()
         )
     })?;
-    args.output_dir = args.output_dir.canonicalize().with_context(|| {
+    let output_dir = output_dir.canonicalize().with_context(|| {
         format!(
             "Failed to find canonical path for output directory: {}",
-            args.output_dir.display()
+            output_dir.display()
         )
     })?;
+    // Deep output trees may exceed MAX_PATH on Windows
+    let output_dir = paths::extended(&output_dir);
+    // Staged outputs live in a directory of this run's own
+    let (_lock, journal) = if s3_output.is_none() && !output_archive {
+        (
+            Some(lock::OutputLock::acquire(&output_dir, args.force_lock)?),
+            Some(journal::Journal::open(&output_dir)?),
+        )
+    } else {
+        (None, None)
+    };
+    // Without a journal, a finished output is only known by its existence
+    let trusted = |key: &str, output: &Path| match &journal {
+        Some(journal) => journal.trusts(key),
+        None => output.exists(),
+    };
 
-    let processed = AtomicUsize::new(0);
-    let skipped = AtomicUsize::new(0);
+    let pipeline = Pipeline::new(&args.pipeline, args.models_dir.as_deref())?;
+    run.backend = clap::ValueEnum::to_possible_value(&pipeline.backend_kind())
+        .map(|value| value.get_name().to_string());
+    run.model = pipeline.model().map(str::to_string);
+    run.model_sha256 = pipeline
+        .model()
+        .map(Path::new)
+        .filter(|path| path.is_file())
+        .and_then(|path| checksum::sha256_file(path).ok());
+    let run = &*run;
+    let rules = args
+        .model_rules
+        .as_deref()
+        .map(ModelRules::load)
+        .transpose()?;
+    // A pipeline for each other model the rules can pick
+    let mut ruled: HashMap<&str, (PipelineArgs, Pipeline)> = HashMap::new();
+    for model in rules.iter().flat_map(ModelRules::models) {
+        if Some(model) != args.pipeline.model.as_deref() {
+            let mut pipeline_args = args.pipeline.clone();
+            pipeline_args.model = Some(model.to_string());
+            let pipeline = Pipeline::new(&pipeline_args, args.models_dir.as_deref())?;
+            ruled.insert(model, (pipeline_args, pipeline));
+        }
+    }
 
-    if args.addr_api.is_empty() {
-        anyhow::bail!("At least one API address must be provided via --addr-api");
+    let mut discovered = walk::discover(
+        &input_dir,
+        &WalkOptions {
+            follow_symlinks: args.follow_symlinks,
+            symlink_files: args.symlink_files,
+            max_depth: args.max_depth,
+            extensions: args.extensions.clone(),
+        },
+    );
+    let mut wav_files = std::mem::take(&mut discovered.files);
+    // Every input counts for --prune, whatever the filters below leave of them
+    let all_files = args.prune.then(|| wav_files.clone());
+    totals.matched.store(wav_files.len(), Ordering::SeqCst);
+    // Unreadable entries fail like unreadable files do, without stopping the others
+    if let Some((path, reason)) = discovered.unreadable.first()
+        && args.strict
+    {
+        anyhow::bail!(
+            "Aborting (--strict): cannot read {}: {reason}",
+            path.display()
+        );
     }
+    totals
+        .failed
+        .fetch_add(discovered.unreadable.len(), Ordering::SeqCst);
 
-    let api_endpoints = Mutex::new(args.addr_api.iter().cycle());
+    if let (Some(min), Some(max)) = (args.min_duration, args.max_duration)
+        && min > max
+    {
+        anyhow::bail!("--min-duration ({min}) is greater than --max-duration ({max})");
+    }
+    if args.min_duration.is_some() || args.max_duration.is_some() {
+        let total = wav_files.len();
+        // Unreadable headers are left for validation to report
+        wav_files.retain(|e| {
+            walk::wav_duration(e.path()).is_none_or(|secs| {
+                args.min_duration.is_none_or(|min| secs >= min)
+                    && args.max_duration.is_none_or(|max| secs <= max)
+            })
+        });
+        let excluded = total - wav_files.len();
+        if excluded > 0 {
+            eprintln!("Skipping {excluded} files outside the duration range");
+            totals.skipped.fetch_add(excluded, Ordering::SeqCst);
+        }
+    }
 
-    let wav_files: Vec<_> = WalkDir::new(&args.input_dir)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .filter(|e| e.path().extension().and_then(|s| s.to_str()) == Some("wav"))
-        .collect();
+    let cutoff = args
+        .newer_than
+        .map(clock::Age::before_now)
+        .or(args.modified_since.map(|t| t.0));
+    if let Some(cutoff) = cutoff {
+        let total = wav_files.len();
+        // Files whose time cannot be read are kept rather than silently dropped
+        wav_files.retain(|e| {
+            e.metadata()
+                .ok()
+                .and_then(|m| m.modified().ok())
+                .is_none_or(|modified| modified >= cutoff)
+        });
+        let excluded = total - wav_files.len();
+        if excluded > 0 {
+            eprintln!(
+                "Skipping {excluded} files last modified before {}",
+                clock::iso8601(cutoff)
+            );
+            totals.skipped.fetch_add(excluded, Ordering::SeqCst);
+        }
+    }
+
+    // Kept to be reported as skipped events once the stream is open
+    let mut oversized: Vec<(String, u64)> = Vec::new();
+    if let Some(max) = args.max_file_size {
+        wav_files.retain(|e| {
+            let bytes = e.metadata().map_or(0, |m| m.len());
+            if bytes <= max.0 {
+                return true;
+            }
+            eprintln!(
+                "Skipping {}: {} is larger than --max-file-size {max}",
+                e.path().display(),
+                ByteSize(bytes)
+            );
+            let key = e.path().strip_prefix(&input_dir).unwrap_or(e.path());
+            oversized.push((key.to_string_lossy().to_string(), bytes));
+            false
+        });
+        totals.skipped.fetch_add(oversized.len(), Ordering::SeqCst);
+    }
+
+    let seed = args.seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+    });
+    let sample_size = match (args.sample, args.sample_percent) {
+        (Some(n), _) => Some(n),
+        (None, Some(p)) if p > 0.0 && p <= 100.0 => {
+            Some((wav_files.len() as f64 * p / 100.0).ceil() as usize)
+        }
+        (None, Some(p)) => anyhow::bail!("--sample-percent must be in (0, 100], got {p}"),
+        (None, None) => None,
+    };
+    if let Some(count) = sample_size
+        && count < wav_files.len()
+    {
+        let total = wav_files.len();
+        walk::sample(&mut wav_files, count, seed);
+        status_line(
+            args,
+            &format!("Sampling {count} of {total} files (--seed {seed})."),
+        );
+    }
+
+    // In place, symlinks are already where the mirror would put them
+    let links = if args.in_place {
+        &[][..]
+    } else {
+        &discovered.links[..]
+    };
+    for link in links {
+        if let Err(e) = walk::mirror_link(&input_dir, &output_dir, link.path()) {
+            eprintln!("Error mirroring {}: {e:#}", link.path().display());
+        }
+    }
+    if !links.is_empty() {
+        status_line(args, &format!("Mirrored {} symlinked files.", links.len()));
+    }
+    let passthrough = args.passthrough.map(|mode| match mode {
+        Passthrough::Symlink if staged_io => Passthrough::Copy,
+        mode => mode,
+    });
+    if let Some(mode) = passthrough {
+        for path in &discovered.others {
+            let relative = path.strip_prefix(&input_dir).unwrap_or(path);
+            if let Err(e) = walk::pass_through(path, &paths::join(&output_dir, relative), mode) {
+                eprintln!("Error passing through {}: {e:#}", path.display());
+            }
+        }
+        if !discovered.others.is_empty() {
+            status_line(
+                args,
+                &format!(
+                    "Passed through {} files with other extensions.",
+                    discovered.others.len()
+                ),
+            );
+        }
+    }
+
+    let relative_key = |path: &Path| {
+        path.strip_prefix(&input_dir)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string()
+    };
+
+    let state = match &args.state_file {
+        Some(path) => Some(RunState::load(path, args.checkpoint_every)?),
+        None => None,
+    };
+    // Staging directories are gone once the run ends, so such runs cannot be retried
+    if !staged_io {
+        totals.set_dirs(&input_dir, &output_dir);
+        if let Some(state) = &state {
+            state.set_dirs(&input_dir, &output_dir);
+        }
+    }
+    if let Some(state) = &state {
+        let total = wav_files.len();
+        wav_files.retain(|e| {
+            let key = relative_key(e.path());
+            !(state.is_done(&key) && journal.as_ref().is_none_or(|j| j.trusts(&key)))
+        });
+        if wav_files.len() < total {
+            eprintln!(
+                "Resuming: {} files already done, {} remaining ({} to retry)",
+                total - wav_files.len(),
+                wav_files.len(),
+                state.failed_count()
+            );
+        }
+    }
+
+    if args.pipeline.reprocess_policy == ReprocessPolicy::Error {
+        let denoised: Vec<_> = wav_files
+            .par_iter()
+            .filter(|e| provenance::is_denoised(e.path()))
+            .map(|e| e.path().display().to_string())
+            .collect();
+        if !denoised.is_empty() {
+            anyhow::bail!(
+                "{} input files are already denoised (e.g. {}); use --reprocess-policy to override",
+                denoised.len(),
+                denoised[0]
+            );
+        }
+    }
+
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let default_jobs = pipeline.default_jobs();
+    let min_jobs = args.min_jobs.max(1);
+    let max_jobs = args.max_jobs.unwrap_or(cpus).max(min_jobs);
+    let (num_threads, initial_jobs) = match args.jobs {
+        None => (default_jobs, default_jobs),
+        Some(Jobs::Fixed(n)) => (n, n),
+        Some(Jobs::Auto) => (max_jobs, default_jobs.clamp(min_jobs, max_jobs)),
+    };
+    let limiter = Limiter::new(initial_jobs);
+    let throttle = match args.max_throughput {
+        Some(rate) if rate > 0.0 => Some(Throttle::new(rate * 1e6)),
+        Some(rate) => anyhow::bail!("--max-throughput must be positive, got {rate}"),
+        None => None,
+    };
+    if !args.estimate_speed.is_finite() || args.estimate_speed <= 0.0 {
+        anyhow::bail!("--estimate-speed must be positive");
+    }
+    if !args.concat_silence.is_finite() || args.concat_silence < 0.0 {
+        anyhow::bail!("--concat-silence must not be negative");
+    }
+
+    if let Some(order) = args.order {
+        walk::sort(&mut wav_files, order, seed);
+    }
+
+    // Sidecars are read once, before the names that may contain {model} are made
+    let chosen = rules
+        .as_ref()
+        .map(|rules| rules.choose(&input_dir, &wav_files))
+        .unwrap_or_default();
+    if rules.is_some() {
+        status_line(
+            args,
+            &format!(
+                "--model-rules picked the model of {} of {} files.",
+                chosen.len(),
+                wav_files.len()
+            ),
+        );
+    }
+    let model_of = |key: &str| chosen.get(key).copied().or(args.pipeline.model.as_deref());
+    let mut outputs = output_names(args, &input_dir, &wav_files, &chosen)?;
+    for output in &mut outputs {
+        *output = paths::join(&output_dir, output);
+    }
+
+    if let Some(all_files) = &all_files {
+        if s3_output.is_some() || output_archive {
+            anyhow::bail!("--prune needs a local output directory");
+        }
+        if output_dir.starts_with(&input_dir) || input_dir.starts_with(&output_dir) {
+            anyhow::bail!(
+                "--prune needs an output directory apart from the input directory, not inside it or around it"
+            );
+        }
+        // An input directory that could not be read would look like deleted inputs
+        if !discovered.unreadable.is_empty() {
+            eprintln!(
+                "Warning: not pruning, {} input entries could not be read",
+                discovered.unreadable.len()
+            );
+        } else {
+            let chosen = rules
+                .as_ref()
+                .map(|rules| rules.choose(&input_dir, all_files))
+                .unwrap_or_default();
+            let expected: Vec<PathBuf> = output_names(args, &input_dir, all_files, &chosen)?
+                .iter()
+                .map(|output| paths::join(&output_dir, output))
+                .collect();
+            let expected: Vec<&Path> = expected.iter().map(PathBuf::as_path).collect();
+            let sources: Vec<&Path> = all_files
+                .iter()
+                .map(walkdir::DirEntry::path)
+                .chain(discovered.sources())
+                .collect();
+            let orphans = diff::orphans(&output_dir, &expected, &sources, &args.extensions);
+            for orphan in &orphans {
+                let relative = orphan.strip_prefix(&output_dir).unwrap_or(orphan);
+                let (counter, status, reason) = if args.dry_run {
+                    eprintln!("Would prune {}", relative.display());
+                    (
+                        &totals.would_prune,
+                        "would_prune",
+                        "its input no longer exists; kept with --dry-run",
+                    )
+                } else if let Err(e) = diff::prune(&output_dir, orphan, &args.copy_sidecars) {
+                    eprintln!("Warning: {e:#}");
+                    continue;
+                } else {
+                    eprintln!("Pruned {}", relative.display());
+                    (&totals.pruned, "pruned", "its input no longer exists")
+                };
+                counter.fetch_add(1, Ordering::SeqCst);
+                if args.report.is_some() || args.output == OutputFormat::Json {
+                    let relative = relative.to_string_lossy().to_string();
+                    totals.record_file(FileRecord {
+                        file: relative.clone(),
+                        output: relative,
+                        status,
+                        code: None,
+                        reason: Some(reason.to_string()),
+                        secs: 0.0,
+                        audio_secs: 0.0,
+                        bytes: 0,
+                        log: None,
+                    });
+                }
+            }
+            let (verb, count) = if args.dry_run {
+                ("Would prune", &totals.would_prune)
+            } else {
+                ("Pruned", &totals.pruned)
+            };
+            let count = count.load(Ordering::SeqCst);
+            status_line(args, &format!("{verb} {count} orphaned outputs."));
+            if args.dry_run {
+                return Ok(());
+            }
+        }
+    }
+
+    // Inputs with a recorded result for these settings are skipped while their output is still there
+    let history = match &args.db {
+        Some(path) => Some(History::open(path, &args.pipeline)?),
+        None => None,
+    };
+    let mut input_hashes: HashMap<String, String> = HashMap::new();
+    let mut unchanged: Vec<(String, u64)> = Vec::new();
+    if let Some(history) = &history {
+        let hashes: Vec<Option<String>> = wav_files
+            .par_iter()
+            .map(|e| match checksum::sha256_file(e.path()) {
+                Ok(hash) => Some(hash),
+                Err(e) => {
+                    eprintln!("Warning: {e:#}; it is not recorded in --db");
+                    None
+                }
+            })
+            .collect();
+        let mut keep = Vec::with_capacity(hashes.len());
+        for (i, hash) in hashes.into_iter().enumerate() {
+            let key = relative_key(wav_files[i].path());
+            let done =
+                hash.as_ref().is_some_and(|h| history.was_done(h)) && trusted(&key, &outputs[i]);
+            if done {
+                unchanged.push((key, wav_files[i].metadata().map_or(0, |m| m.len())));
+            } else if let Some(hash) = hash {
+                input_hashes.insert(key, hash);
+            }
+            keep.push(!done);
+        }
+        let mut keep_iter = keep.iter();
+        wav_files.retain(|_| *keep_iter.next().unwrap());
+        let mut keep_iter = keep.iter();
+        outputs.retain(|_| *keep_iter.next().unwrap());
+        if !unchanged.is_empty() {
+            totals.skipped.fetch_add(unchanged.len(), Ordering::SeqCst);
+            status_line(
+                args,
+                &format!(
+                    "Skipping {} inputs already denoised with these settings (--db).",
+                    unchanged.len()
+                ),
+            );
+        }
+    }
+
+    let events = match args.events {
+        Some(EventFormat::Jsonl) => Some(Events::open(args.events_file.as_deref())?),
+        None => None,
+    };
+    let manifest = args.manifest.as_deref().map(Manifest::new);
+    let journal_output = |key: &str, output: &Path| {
+        if let Some(journal) = &journal
+            && let Err(e) = journal.record(key, output)
+        {
+            eprintln!(
+                "Warning: {key} is missing from the journal, so it is not trusted on resume: {e:#}"
+            );
+        }
+    };
+    let add_to_manifest = |key: &str, output: &Path, input_file: &Path, duration: f64| {
+        let Some(manifest) = &manifest else {
+            return;
+        };
+        let listed_output = output.strip_prefix(&output_dir).unwrap_or(output);
+        if let Err(e) = manifest.record(
+            &listed_root.0.join(key),
+            &listed_root.1.join(listed_output),
+            input_file,
+            output,
+            duration,
+            model_of(key),
+        ) {
+            eprintln!(
+                "Warning: {} is missing from the manifest: {e:#}",
+                output.display()
+            );
+        }
+    };
+    let measure = |output: &Path| {
+        if !args.measure_loudness {
+            return None;
+        }
+        match loudness::measure_file(output) {
+            Ok(loudness) => {
+                totals.record_loudness(&loudness);
+                Some(loudness)
+            }
+            Err(e) => {
+                eprintln!("Warning: no loudness for {}: {e:#}", output.display());
+                None
+            }
+        }
+    };
+    let profile_noise = |key: &str, input: &Path| {
+        if !args.noise_report {
+            return None;
+        }
+        match noise::profile_file(input) {
+            Ok(profile) => {
+                totals.record_noise(key, profile);
+                Some(profile)
+            }
+            Err(e) => {
+                eprintln!("Warning: no noise profile for {}: {e:#}", input.display());
+                None
+            }
+        }
+    };
+    let score = |key: &str, output: &Path| {
+        let dir = args.reference_dir.as_ref()?;
+        let Some(reference) = quality::find_reference(dir, key) else {
+            eprintln!("Warning: no reference for {key} in {}", dir.display());
+            return None;
+        };
+        match quality::compare_files(&reference, output) {
+            Ok(quality) => {
+                totals.record_quality(key, quality);
+                Some(quality)
+            }
+            Err(e) => {
+                eprintln!("Warning: no quality scores for {}: {e:#}", output.display());
+                None
+            }
+        }
+    };
+    let write_sidecar = |key: &str, output: &Path, source: &Path, loudness, noise, quality| {
+        if args.sidecar
+            && let Err(e) = provenance::write_sidecar(
+                output,
+                source,
+                model_of(key),
+                model_of(key)
+                    .and_then(|model| ruled.get(model))
+                    .map_or(&args.pipeline, |(settings, _)| settings),
+                run,
+                (loudness, noise, quality),
+            )
+        {
+            eprintln!("Warning: {e:#}");
+        }
+    };
+    // Staged inputs are removed after the run, so links to them would dangle
+    let companions = if args.symlink_sidecars && !staged_io {
+        Passthrough::Symlink
+    } else {
+        Passthrough::Copy
+    };
+    let copy_companions = |output: &Path, input: &Path| {
+        if let Err(e) = walk::copy_companions(input, output, &args.copy_sidecars, companions) {
+            eprintln!("Warning: {e:#}");
+        }
+    };
+    let post_process = args
+        .post_process_cmd
+        .clone()
+        .map(|command| PostProcess::new(command, args.post_process_jobs));
+    let run_post_process = |key: &str, input: &Path, output: &Path| {
+        if let Some(post_process) = &post_process
+            && let Err(e) = post_process.run(key, input, output)
+        {
+            eprintln!("Warning: {e:#} for {}", output.display());
+        }
+    };
+    let emit = |event: Event| {
+        if let Some(events) = &events {
+            events.emit(event);
+        }
+    };
+    let record_history = |key: &str, output: &Path, outcome: &Outcome, secs: f64| {
+        let (Some(history), Some(hash)) = (&history, input_hashes.get(key)) else {
+            return;
+        };
+        let (status, reason) = outcome.status();
+        let output = output.strip_prefix(&output_dir).unwrap_or(output);
+        if let Err(e) = history.record(&history::Record {
+            input: key,
+            output: &output.to_string_lossy(),
+            input_sha256: hash,
+            status,
+            reason,
+            secs,
+        }) {
+            eprintln!("Warning: {key} is missing from --db: {e:#}");
+        }
+    };
+    let record_file =
+        |key: &str, output: Option<&Path>, outcome: &Outcome, secs: f64, log: Option<String>| {
+            if args.report.is_none() && args.output == OutputFormat::Text {
+                return;
+            }
+            let (status, reason) = outcome.status();
+            let done = output.filter(|_| matches!(outcome, Outcome::Processed));
+            totals.record_file(FileRecord {
+                file: key.to_string(),
+                output: output.map_or_else(String::new, |output| {
+                    let relative = output.strip_prefix(&output_dir).unwrap_or(output);
+                    relative.to_string_lossy().to_string()
+                }),
+                status,
+                code: outcome.code(),
+                reason: reason.map(str::to_string),
+                secs,
+                audio_secs: done.and_then(walk::wav_duration).unwrap_or(0.0),
+                bytes: done
+                    .and_then(|output| std::fs::metadata(output).ok())
+                    .map_or(0, |m| m.len()),
+                log,
+            });
+        };
+    for entry in &wav_files {
+        emit(Event::Discovered {
+            file: &relative_key(entry.path()),
+            bytes: entry.metadata().map_or(0, |m| m.len()),
+        });
+    }
+    for (path, reason) in &discovered.unreadable {
+        emit(Event::Failed {
+            file: &relative_key(path),
+            reason,
+            code: DenoiseError::IoError,
+        });
+        let outcome = Outcome::Failed(DenoiseError::IoError, reason.clone());
+        record_file(&relative_key(path), None, &outcome, 0.0, None);
+    }
+    for (file, bytes) in &oversized {
+        emit(Event::Discovered {
+            file,
+            bytes: *bytes,
+        });
+        emit(Event::Skipped {
+            file,
+            reason: "larger than --max-file-size",
+            code: None,
+        });
+        let outcome = Outcome::Skipped("larger than --max-file-size".to_string());
+        record_file(file, None, &outcome, 0.0, None);
+    }
+    for (file, bytes) in &unchanged {
+        emit(Event::Discovered {
+            file,
+            bytes: *bytes,
+        });
+        emit(Event::Skipped {
+            file,
+            reason: "already denoised with these settings (--db)",
+            code: None,
+        });
+        let outcome = Outcome::Skipped("already denoised with these settings (--db)".to_string());
+        record_file(file, None, &outcome, 0.0, None);
+    }
+
+    // Byte-identical inputs are denoised once; the others receive a link to that output
+    let mut duplicates: Vec<(usize, PathBuf, String)> = Vec::new();
+    if args.dedupe {
+        let hashes: Vec<Option<String>> = wav_files
+            .par_iter()
+            .map(|e| checksum::sha256_file(e.path()).ok())
+            .collect();
+        let mut first: HashMap<String, usize> = HashMap::new();
+        let mut keep = Vec::with_capacity(hashes.len());
+        let mut kept = 0;
+        for (i, hash) in hashes.into_iter().enumerate() {
+            match hash.as_ref().and_then(|h| first.get(h).copied()) {
+                Some(primary) => {
+                    let key = relative_key(wav_files[i].path());
+                    duplicates.push((primary, outputs[i].clone(), key));
+                    keep.push(false);
+                }
+                None => {
+                    // Indices refer to the list that remains after duplicates are removed
+                    if let Some(hash) = hash {
+                        first.insert(hash, kept);
+                    }
+                    kept += 1;
+                    keep.push(true);
+                }
+            }
+        }
+        let mut keep_iter = keep.iter();
+        wav_files.retain(|_| *keep_iter.next().unwrap());
+        let mut keep_iter = keep.iter();
+        outputs.retain(|_| *keep_iter.next().unwrap());
+        if !duplicates.is_empty() {
+            eprintln!(
+                "Found {} duplicate inputs; each unique file is denoised once",
+                duplicates.len()
+            );
+        }
+    }
+    let finished: Vec<AtomicBool> = outputs.iter().map(|_| AtomicBool::new(false)).collect();
+
+    let needed = args
+        .min_free_space
+        .unwrap_or_else(|| space::estimate_outputs(&wav_files));
+    if needed.0 > 0
+        && let Some(free) = space::free_space(&output_dir)?
+        && free < needed.0
+    {
+        anyhow::bail!(
+            "Not enough free space in {}: the outputs need about {needed}, {} is available. \
+             Free some space, or pass --min-free-space to override the estimate",
+            output_dir.display(),
+            ByteSize(free)
+        );
+    }
+
+    if args.tui && !std::io::stderr().is_terminal() {
+        anyhow::bail!("--tui needs stderr to be a terminal");
+    }
+
+    let preflight = Preflight::measure(&wav_files, initial_jobs, args.estimate_speed);
+    status_line(args, &preflight.describe());
+    emit(Event::Preflight {
+        summary: &preflight,
+    });
+    // Only a person at a terminal can answer; scripts and services go ahead
+    if !args.yes
+        && preflight.estimated_secs > preflight::CONFIRM_ABOVE_SECS
+        && std::io::stdin().is_terminal()
+        && !preflight.confirm()?
+    {
+        anyhow::bail!("Cancelled before starting; pass --yes to skip this question");
+    }
+    let dashboard = args
+        .tui
+        .then(|| Dashboard::new(wav_files.len(), num_threads, totals));
+    // Tells the helper threads that the workers are done
+    let stop_helpers = AtomicBool::new(false);
 
     let pool = rayon::ThreadPoolBuilder::new()
-        .num_threads(args.addr_api.len())
+        .num_threads(num_threads)
         .build()
         .context("Failed to create thread pool")?;
+    let gauges = Gauges::new(args.queue_depth.unwrap_or(num_threads).max(1));
+    let (ready_tx, ready_rx) = mpsc::sync_channel::<Decoded>(gauges.depth);
+    let (denoised_tx, denoised_rx) = mpsc::sync_channel::<Decoded>(gauges.depth);
+    let denoised_rx = Mutex::new(denoised_rx);
+    let next_file = AtomicUsize::new(0);
 
-    pool.install(|| {
-        wav_files.par_iter().for_each(|entry| {
-            let input_path = entry.path();
+    // In place, the output is written beside the input and swapped in when complete
+    let target_of = |i: usize| {
+        let output_path = &outputs[i];
+        if args.in_place {
+            output_path.with_file_name(format!(
+                ".{}.in-place.tmp.wav",
+                output_path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+            ))
+        } else {
+            output_path.clone()
+        }
+    };
+    let pipeline_of = |key: &str| {
+        model_of(key)
+            .and_then(|model| ruled.get(model))
+            .map_or(&pipeline, |(_, pipeline)| pipeline)
+    };
+    // Set by the first --strict failure, which stops the readers and workers
+    let abort: Mutex<Option<anyhow::Error>> = Mutex::new(None);
+    let aborting = AtomicBool::new(false);
 
-            // The closure for `for_each` doesn't return a Result, so we handle errors inside.
-            let process = || -> Result<()> {
-                if !validate_wav(input_path)? {
-                    eprintln!("Skipping invalid WAV file: {}", input_path.display());
-                    skipped.fetch_add(1, Ordering::SeqCst);
-                    return Ok(());
-                }
+    // The read stage: decoding, repairing and converting each input for the backend
+    let read = |i: usize| -> Decoded {
+        let input_path = wav_files[i].path();
+        let key = relative_key(input_path);
+        // Per-file errors become failures; only --strict turns them into an abort
+        let file_started = Instant::now();
+        emit(Event::Started { file: &key });
+        let log = args.capture_backend_output.then(capture::Log::default);
+        let prepared = capture::collect(log.clone(), || {
+            pipeline_of(&key).prepare(input_path, &target_of(i), &|| {
+                emit(Event::Validated { file: &key })
+            })
+        });
+        (i, file_started, log, prepared)
+    };
 
-                let relative = input_path.strip_prefix(&args.input_dir)?;
-                let output_path = args.output_dir.join(relative);
+    // The first half of the writer stage: the output in the input's format, and the in-place swap
+    let write = |(i, file_started, log, prepared): Decoded| -> Denoised {
+        let input_path = wav_files[i].path();
+        let key = relative_key(input_path);
+        let target = target_of(i);
+        let outcome = capture::collect(log.clone(), || {
+            prepared.and_then(|prepared| pipeline_of(&key).finish(prepared))
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("Error processing {}: {:?}", input_path.display(), e);
+            Outcome::failed(&e)
+        });
 
-                if let Some(parent) = output_path.parent() {
-                    std::fs::create_dir_all(parent).with_context(|| {
-                        format!(
-                            "Failed to create output directory for: {}",
-                            output_path.display()
-                        )
-                    })?;
+        let mut original = input_path.to_path_buf();
+        let outcome = match outcome {
+            Outcome::Processed if args.in_place => {
+                let backup = match &args.backup_dir {
+                    Some(dir) => dir.join(relative_key(input_path)),
+                    None => {
+                        let mut name = input_path.as_os_str().to_owned();
+                        name.push(".bak");
+                        PathBuf::from(name)
+                    }
+                };
+                match walk::replace_with_backup(input_path, &target, &backup) {
+                    Ok(()) => {
+                        original = backup;
+                        Outcome::Processed
+                    }
+                    Err(e) => {
+                        eprintln!("Error replacing {}: {e:#}", input_path.display());
+                        Outcome::failed(&e)
+                    }
                 }
+            }
+            outcome => outcome,
+        };
+        if args.in_place {
+            let _ = std::fs::remove_file(&target);
+        }
 
-                let body = DenoiseRequestBody {
-                    filename: input_path.to_string_lossy().to_string(),
-                    filename_denoised: output_path.to_string_lossy().to_string(),
-                    model: args.model.clone(),
-                };
+        let counter = match &outcome {
+            Outcome::Processed => &totals.processed,
+            Outcome::Skipped(_) | Outcome::Invalid(_) => &totals.skipped,
+            Outcome::Failed(..) => &totals.failed,
+        };
+        counter.fetch_add(1, Ordering::SeqCst);
+        match &outcome {
+            Outcome::Invalid(_) => totals.record_skip(DenoiseError::InvalidFormat),
+            Outcome::Failed(code, _) => totals.record_failure(*code),
+            Outcome::Processed | Outcome::Skipped(_) => {}
+        }
 
-                let api_addr = api_endpoints.lock().unwrap().next().unwrap();
+        if let Outcome::Invalid(reason) | Outcome::Failed(_, reason) = &outcome
+            && args.strict
+        {
+            let mut abort = abort.lock().unwrap();
+            if abort.is_none() {
+                *abort = Some(anyhow::anyhow!(
+                    "Aborting (--strict) at {}: {reason}",
+                    input_path.display()
+                ));
+                aborting.store(true, Ordering::SeqCst);
+            }
+        }
+        let log = log.as_ref().and_then(capture::text);
+        (i, outcome, original, file_started, log)
+    };
 
-                let resp = ureq::post(api_addr).send_json(&body)?;
+    // The rest of the writer stage: measurements, sidecars, manifests and hooks
+    let finish = |(i, outcome, original, file_started, log): Denoised| {
+        let input_path = wav_files[i].path();
+        let output_path = &outputs[i];
+        let key = relative_key(input_path);
+        let (loudness, noise, quality) = match &outcome {
+            Outcome::Processed => (
+                measure(output_path),
+                profile_noise(&key, &original),
+                score(&key, output_path),
+            ),
+            _ => (None, None, None),
+        };
+        if let Some(dashboard) = &dashboard {
+            let failure = match &outcome {
+                Outcome::Failed(_, reason) => Some(reason.as_str()),
+                _ => None,
+            };
+            dashboard.finish(&key, failure);
+        }
+        emit(match &outcome {
+            Outcome::Processed => Event::Finished {
+                file: &key,
+                output: output_path,
+                secs: file_started.elapsed().as_secs_f64(),
+                loudness,
+                noise,
+                quality,
+            },
+            Outcome::Skipped(reason) | Outcome::Invalid(reason) => Event::Skipped {
+                file: &key,
+                reason,
+                code: outcome.code(),
+            },
+            Outcome::Failed(code, reason) => Event::Failed {
+                file: &key,
+                reason,
+                code: *code,
+            },
+        });
+        if let (Some(mode), Outcome::Skipped(_) | Outcome::Invalid(_)) = (passthrough, &outcome)
+            && let Err(e) = walk::pass_through(input_path, output_path, mode)
+        {
+            eprintln!("Error passing through {}: {e:#}", input_path.display());
+        }
+        if matches!(outcome, Outcome::Processed) {
+            finished[i].store(true, Ordering::SeqCst);
+            let duration = walk::wav_duration(&original)
+                .or_else(|| walk::wav_duration(output_path))
+                .unwrap_or(0.0);
+            totals.record_denoised(
+                duration,
+                file_started.elapsed(),
+                std::fs::metadata(output_path).map_or(0, |m| m.len()),
+            );
+            add_to_manifest(&key, output_path, &original, duration);
+            journal_output(&key, output_path);
+            write_sidecar(&key, output_path, input_path, loudness, noise, quality);
+            copy_companions(output_path, input_path);
 
-                if resp.status() == 200 {
-                    processed.fetch_add(1, Ordering::SeqCst);
-                } else {
+            if args.verify {
+                let anomalies = qa::verify(
+                    &original,
+                    output_path,
+                    pipeline.kept_channels(),
+                    pipeline.downmixes(),
+                );
+                if !anomalies.is_empty() {
+                    totals.anomalies.fetch_add(1, Ordering::SeqCst);
+                }
+                for reason in &anomalies {
                     eprintln!(
-                        "Denoising failed for {}: API returned status {}",
-                        input_path.display(),
-                        resp.status()
+                        "Warning: verification of {}: {reason}",
+                        output_path.display()
                     );
-                    skipped.fetch_add(1, Ordering::SeqCst);
+                    emit(Event::Anomaly { file: &key, reason });
                 }
-                Ok(())
-            };
+            }
+
+            let relative = output_path.strip_prefix(&output_dir).unwrap_or(output_path);
+            if let Some(dir) = &args.ab_output
+                && let Err(e) = qa::write_ab(&original, output_path, &dir.join(relative))
+            {
+                eprintln!("Warning: no A/B file for {}: {e:#}", input_path.display());
+            }
+            if let Some(dir) = &args.spectrograms
+                && let Err(e) = qa::write_spectrograms(
+                    &original,
+                    output_path,
+                    &dir.join(relative).with_extension("png"),
+                )
+            {
+                eprintln!(
+                    "Warning: no spectrogram for {}: {e:#}",
+                    input_path.display()
+                );
+            }
+            run_post_process(&key, &original, output_path);
+        }
 
-            if let Err(e) = process() {
-                eprintln!("Error processing {}: {:?}", input_path.display(), e);
-                skipped.fetch_add(1, Ordering::SeqCst);
+        if let Some(state) = &state {
+            let saved = match &outcome {
+                Outcome::Processed => state.record_done(&key, loudness),
+                Outcome::Failed(_, reason) => {
+                    let output = output_path.strip_prefix(&output_dir).unwrap_or(output_path);
+                    state.record_failed(&key, &output.to_string_lossy(), reason)
+                }
+                Outcome::Skipped(_) | Outcome::Invalid(_) => Ok(()),
+            };
+            if let Err(e) = saved {
+                eprintln!("Warning: failed to checkpoint progress: {e:#}");
             }
+        }
+        let secs = file_started.elapsed().as_secs_f64();
+        record_history(&key, output_path, &outcome, secs);
+        if args.backend_log
+            && let Some(log) = &log
+            && let Err(e) = capture::write_log(output_path, log)
+        {
+            eprintln!("Warning: {e:#}");
+        }
+        record_file(&key, Some(output_path), &outcome, secs, log);
+    };
+
+    let result = std::thread::scope(|scope| {
+        if args.jobs == Some(Jobs::Auto) {
+            scope.spawn(|| concurrency::adapt(&limiter, min_jobs, max_jobs, &stop_helpers));
+        }
+        if let Some(dashboard) = &dashboard {
+            scope.spawn(|| dashboard.run(totals, &stop_helpers));
+        }
+        if args.verbose {
+            scope.spawn(|| stages::report(&gauges, &stop_helpers));
+        }
+        // Readers take the files in order, so workers receive them roughly in order too
+        for _ in 0..args.read_jobs.max(1) {
+            let ready = ready_tx.clone();
+            scope.spawn(|| {
+                stages::read_ahead(
+                    &wav_files,
+                    &next_file,
+                    throttle.as_ref(),
+                    &gauges,
+                    &aborting,
+                    read,
+                    ready,
+                )
+            });
+        }
+        drop(ready_tx);
+        let writers: Vec<_> = (0..args.write_jobs.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    loop {
+                        let Ok(denoised) = denoised_rx.lock().unwrap().recv() else {
+                            return;
+                        };
+                        gauges.to_write.taken();
+                        let _writing = stages::busy(&gauges.writing);
+                        finish(write(denoised));
+                    }
+                })
+            })
+            .collect();
+
+        pool.install(|| {
+            ready_rx
+                .into_iter()
+                .par_bridge()
+                .for_each(|(i, file_started, log, prepared)| {
+                    gauges.to_denoise.taken();
+                    // After a --strict abort the files still queued are dropped
+                    if aborting.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    let key = relative_key(wav_files[i].path());
+                    let prepared = prepared.and_then(|mut prepared| {
+                        let _permit = limiter.acquire();
+                        let _denoising = stages::busy(&gauges.denoising);
+                        if let Some(dashboard) = &dashboard {
+                            dashboard.start(&key);
+                        }
+                        capture::collect(log.clone(), || {
+                            pipeline_of(&key).denoise_prepared(&mut prepared, &|| {
+                                emit(Event::Validated { file: &key })
+                            })
+                        })?;
+                        Ok(prepared)
+                    });
+                    // The writers only stop once every worker is done
+                    let _ = denoised_tx.send((i, file_started, log, prepared));
+                    gauges.to_write.sent();
+                })
         });
+        // Lets the writers finish the queued files and stop
+        drop(denoised_tx);
+        for writer in writers {
+            let _ = writer.join();
+        }
+
+        stop_helpers.store(true, Ordering::SeqCst);
+        abort.lock().unwrap().take().map_or(Ok(()), Err)
     });
 
-    println!(
-        "Denoising complete: {} files processed, {} skipped.",
-        processed.load(Ordering::SeqCst),
-        skipped.load(Ordering::SeqCst)
-    );
+    if result.is_ok() {
+        for (primary, output, key) in &duplicates {
+            if !finished[*primary].load(Ordering::SeqCst) {
+                totals.skipped.fetch_add(1, Ordering::SeqCst);
+                emit(Event::Skipped {
+                    file: key,
+                    reason: "duplicate of a file that was not denoised",
+                    code: None,
+                });
+                let reason = "duplicate of a file that was not denoised".to_string();
+                record_file(key, Some(output), &Outcome::Skipped(reason), 0.0, None);
+                continue;
+            }
+            match walk::link_or_copy(&outputs[*primary], output) {
+                Ok(()) => {
+                    totals.processed.fetch_add(1, Ordering::SeqCst);
+                    let input = input_dir.join(key);
+                    let duration = walk::wav_duration(&input).unwrap_or(0.0);
+                    add_to_manifest(key, output, &input, duration);
+                    journal_output(key, output);
+                    let loudness = measure(output);
+                    let noise = profile_noise(key, &input);
+                    let quality = score(key, output);
+                    write_sidecar(key, output, &input, loudness, noise, quality);
+                    copy_companions(output, &input);
+                    emit(Event::Finished {
+                        file: key,
+                        output,
+                        secs: 0.0,
+                        loudness,
+                        noise,
+                        quality,
+                    });
+                    if let Some(state) = &state
+                        && let Err(e) = state.record_done(key, loudness)
+                    {
+                        eprintln!("Warning: failed to checkpoint progress: {e:#}");
+                    }
+                    record_history(key, output, &Outcome::Processed, 0.0);
+                    record_file(key, Some(output), &Outcome::Processed, 0.0, None);
+                    run_post_process(key, &input, output);
+                }
+                Err(e) => {
+                    eprintln!("Error linking duplicate {}: {e:#}", output.display());
+                    totals.failed.fetch_add(1, Ordering::SeqCst);
+                    let code = DenoiseError::classify(&e);
+                    totals.record_failure(code);
+                    emit(Event::Failed {
+                        file: key,
+                        reason: &format!("{e:#}"),
+                        code,
+                    });
+                    record_file(
+                        key,
+                        Some(output),
+                        &Outcome::Failed(code, format!("{e:#}")),
+                        0.0,
+                        None,
+                    );
+                }
+            }
+        }
+    }
+
+    // Keep the progress of an aborted run so it can be resumed
+    if let Some(state) = &state {
+        state.save()?;
+    }
+    if let Some(manifest) = &manifest {
+        manifest.save()?;
+    }
+    result?;
+
+    if args.concat_per_dir {
+        // Only what this run wrote, not whatever else the output tree holds
+        let written: Vec<&Path> = outputs
+            .iter()
+            .zip(&finished)
+            .filter(|(_, done)| done.load(Ordering::SeqCst))
+            .map(|(output, _)| output.as_path())
+            .chain(
+                duplicates
+                    .iter()
+                    .map(|(_, output, _)| output.as_path())
+                    .filter(|output| output.is_file()),
+            )
+            .collect();
+        let count = concat::per_dir(&output_dir, &written, args.concat_silence);
+        eprintln!("Joined the files of {count} directories (--concat-per-dir)");
+    }
+    if let (Some(loc), Some(client)) = (&s3_output, &s3_client) {
+        s3::upload_tree(client, loc, &output_dir, args.s3_concurrency)?;
+    }
+    if let Some(path) = &archive_path {
+        let count = archive::pack(&output_dir, path)?;
+        eprintln!("Packed {count} files into {}", path.display());
+    }
+    if staged_io {
+        for dir in [staging.join("input"), staging.join("output")] {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+        if args.staging_dir.is_none() {
+            let _ = std::fs::remove_dir(&staging);
+        }
+    }
     Ok(())
 }
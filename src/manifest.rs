@@ -0,0 +1,85 @@
+//! The `--manifest` CSV: one row per processed file with the SHA-256 of its
+//! input and output, for chain-of-custody records.
+
+use crate::checksum::sha256_file;
+use anyhow::{Context, Result};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+struct Row {
+    input: String,
+    output: String,
+    input_sha256: String,
+    output_sha256: String,
+    duration_secs: f64,
+    model: String,
+}
+
+/// Rows collected by the workers, written sorted by input path at the end of the run.
+pub struct Manifest {
+    path: PathBuf,
+    rows: Mutex<Vec<Row>>,
+}
+
+impl Manifest {
+    pub fn new(path: &Path) -> Self {
+        Manifest {
+            path: path.to_path_buf(),
+            rows: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Hashes `input_file` and `output_file` and adds their row, listing them
+    /// under the names `input` and `output`.
+    pub fn record(
+        &self,
+        input: &Path,
+        output: &Path,
+        input_file: &Path,
+        output_file: &Path,
+        duration_secs: f64,
+        model: Option<&str>,
+    ) -> Result<()> {
+        let row = Row {
+            input: input.to_string_lossy().to_string(),
+            output: output.to_string_lossy().to_string(),
+            input_sha256: sha256_file(input_file)?,
+            output_sha256: sha256_file(output_file)?,
+            duration_secs,
+            model: model.unwrap_or_default().to_string(),
+        };
+        self.rows.lock().unwrap().push(row);
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let mut rows = self.rows.lock().unwrap();
+        rows.sort_by(|a, b| a.input.cmp(&b.input));
+        let mut csv = String::from("input,output,input_sha256,output_sha256,duration_secs,model\n");
+        for row in rows.iter() {
+            writeln!(
+                csv,
+                "{},{},{},{},{:.3},{}",
+                field(&row.input),
+                field(&row.output),
+                row.input_sha256,
+                row.output_sha256,
+                row.duration_secs,
+                field(&row.model)
+            )
+            .unwrap();
+        }
+        std::fs::write(&self.path, csv)
+            .with_context(|| format!("Failed to write manifest: {}", self.path.display()))
+    }
+}
+
+/// Quotes a CSV field when it contains a separator, quote or line break.
+pub fn field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
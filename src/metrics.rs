@@ -0,0 +1,111 @@
+//! Counters for the server mode, rendered in the Prometheus text format.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds (seconds) of the processing latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+#[derive(Default)]
+pub struct Metrics {
+    processed: AtomicU64,
+    failed: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    /// Per-bucket (non-cumulative) counts; the last slot is `+Inf`.
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    latency_micros: AtomicU64,
+}
+
+impl Metrics {
+    pub fn record_upload(&self, bytes: u64) {
+        self.bytes_in.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_done(&self, bytes_out: u64, elapsed: Duration) {
+        self.processed.fetch_add(1, Ordering::Relaxed);
+        self.bytes_out.fetch_add(bytes_out, Ordering::Relaxed);
+        self.record_latency(elapsed);
+    }
+
+    pub fn record_failed(&self, elapsed: Duration) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        self.record_latency(elapsed);
+    }
+
+    fn record_latency(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| secs <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.latency_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Renders all metrics; `queued` and `running` are sampled by the caller.
+    pub fn render(&self, queued: usize, running: usize) -> String {
+        let mut out = String::new();
+        let mut counter = |name: &str, help: &str, value: u64| {
+            let _ = writeln!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}"
+            );
+        };
+        counter(
+            "wavdenoise_files_processed_total",
+            "Files denoised successfully.",
+            self.processed.load(Ordering::Relaxed),
+        );
+        counter(
+            "wavdenoise_files_failed_total",
+            "Files that could not be denoised.",
+            self.failed.load(Ordering::Relaxed),
+        );
+        counter(
+            "wavdenoise_bytes_in_total",
+            "Bytes uploaded for denoising.",
+            self.bytes_in.load(Ordering::Relaxed),
+        );
+        counter(
+            "wavdenoise_bytes_out_total",
+            "Bytes of denoised output produced.",
+            self.bytes_out.load(Ordering::Relaxed),
+        );
+
+        for (name, help, value) in [
+            (
+                "wavdenoise_queue_depth",
+                "Jobs waiting for a worker.",
+                queued,
+            ),
+            ("wavdenoise_jobs_running", "Jobs being denoised.", running),
+        ] {
+            let _ = writeln!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}"
+            );
+        }
+
+        let name = "wavdenoise_processing_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Time spent denoising a file.\n# TYPE {name} histogram"
+        );
+        let mut cumulative = 0;
+        for (i, count) in self.latency_buckets.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+            let bound = LATENCY_BUCKETS
+                .get(i)
+                .map_or_else(|| "+Inf".to_string(), |b| b.to_string());
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let sum = self.latency_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{name}_sum {sum}\n{name}_count {cumulative}");
+        out
+    }
+}
@@ -0,0 +1,283 @@
+//! Local cache of downloadable denoising models.
+//!
+//! Models live in a cache directory together with a `models.json` index that
+//! records where each one came from and its SHA-256, so `--model NAME` can refer
+//! to a cached model by name and `model verify` can detect corrupted files.
+
+use crate::checksum;
+use anyhow::{Context, Result, bail};
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+const INDEX_FILE: &str = "models.json";
+
+/// Actions of the `model` subcommand.
+#[derive(Subcommand, Debug)]
+pub enum ModelCommand {
+    /// Download a model into the cache
+    Download {
+        /// Name under which the model is stored and referenced via --model
+        name: String,
+
+        /// URL to download from (otherwise NAME is looked up in --registry)
+        #[arg(long)]
+        url: Option<String>,
+
+        /// Expected SHA-256 of the model file
+        #[arg(long)]
+        sha256: Option<String>,
+
+        /// URL of a JSON registry: [{"name": ..., "url": ..., "sha256": ...}]
+        #[arg(long)]
+        registry: Option<String>,
+    },
+    /// List cached models
+    List,
+    /// Re-check the SHA-256 of cached models
+    Verify {
+        /// Only verify this model
+        name: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ModelEntry {
+    name: String,
+    file: String,
+    sha256: String,
+    source: String,
+}
+
+#[derive(Deserialize)]
+struct RegistryEntry {
+    name: String,
+    url: String,
+    sha256: Option<String>,
+}
+
+/// A models cache directory and its index.
+pub struct ModelStore {
+    dir: PathBuf,
+}
+
+impl ModelStore {
+    /// Uses `dir` if given, otherwise the per-user cache directory.
+    pub fn new(dir: Option<&Path>) -> Result<Self> {
+        let dir = match dir {
+            Some(dir) => dir.to_path_buf(),
+            None => default_dir().context(
+                "Cannot determine a cache directory for models; pass --models-dir explicitly",
+            )?,
+        };
+        Ok(ModelStore { dir })
+    }
+
+    /// Returns the cached file for `name`, if such a model has been downloaded.
+    pub fn resolve(&self, name: &str) -> Option<PathBuf> {
+        let entries = self.load_index().ok()?;
+        entries
+            .into_iter()
+            .find(|e| e.name == name)
+            .map(|e| self.dir.join(e.file))
+    }
+
+    pub fn run(&self, command: &ModelCommand) -> Result<()> {
+        match command {
+            ModelCommand::Download {
+                name,
+                url,
+                sha256,
+                registry,
+            } => self.download(name, url.as_deref(), sha256.as_deref(), registry.as_deref()),
+            ModelCommand::List => self.list(),
+            ModelCommand::Verify { name } => self.verify(name.as_deref()),
+        }
+    }
+
+    fn download(
+        &self,
+        name: &str,
+        url: Option<&str>,
+        sha256: Option<&str>,
+        registry: Option<&str>,
+    ) -> Result<()> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+        {
+            bail!("Invalid model name '{name}': use letters, digits, '-', '_' and '.'");
+        }
+
+        let (url, expected) = match (url, registry) {
+            (Some(url), _) => (url.to_string(), sha256.map(str::to_string)),
+            (None, Some(registry)) => {
+                let entries: Vec<RegistryEntry> = ureq::get(registry)
+                    .call()
+                    .with_context(|| format!("Failed to fetch model registry: {registry}"))?
+                    .body_mut()
+                    .read_json()
+                    .with_context(|| format!("Malformed model registry: {registry}"))?;
+                let entry = entries
+                    .into_iter()
+                    .find(|e| e.name == name)
+                    .with_context(|| format!("Model '{name}' not found in registry {registry}"))?;
+                (entry.url, sha256.map(str::to_string).or(entry.sha256))
+            }
+            (None, None) => bail!("Either --url or --registry is required to download a model"),
+        };
+
+        std::fs::create_dir_all(&self.dir).with_context(|| {
+            format!("Failed to create models directory: {}", self.dir.display())
+        })?;
+
+        let extension = url
+            .rsplit('/')
+            .next()
+            .and_then(|last| last.rsplit_once('.'))
+            .map(|(_, ext)| ext)
+            .filter(|ext| !ext.is_empty() && ext.chars().all(|c| c.is_ascii_alphanumeric()))
+            .unwrap_or("rnn");
+        let file = format!("{name}.{extension}");
+        let path = self.dir.join(&file);
+        let partial = path.with_extension(format!("{extension}.part"));
+
+        eprintln!("Downloading {name} from {url}");
+        let response = ureq::get(&url)
+            .call()
+            .with_context(|| format!("Failed to download model: {url}"))?;
+        let out = std::fs::File::create(&partial)
+            .with_context(|| format!("Failed to create file: {}", partial.display()))?;
+        let digest = checksum::sha256_reader(Tee {
+            reader: response.into_body().into_reader(),
+            writer: std::io::BufWriter::new(out),
+        })
+        .with_context(|| format!("Failed to download model: {url}"))?;
+
+        if let Some(expected) = expected
+            && !expected.eq_ignore_ascii_case(&digest)
+        {
+            let _ = std::fs::remove_file(&partial);
+            bail!("Checksum mismatch for {name}: expected {expected}, got {digest}");
+        }
+        std::fs::rename(&partial, &path)
+            .with_context(|| format!("Failed to store model: {}", path.display()))?;
+
+        let mut entries = self.load_index()?;
+        entries.retain(|e| e.name != name);
+        entries.push(ModelEntry {
+            name: name.to_string(),
+            file,
+            sha256: digest.clone(),
+            source: url,
+        });
+        self.save_index(&entries)?;
+
+        println!(
+            "Stored model {name} at {} (sha256 {digest})",
+            path.display()
+        );
+        Ok(())
+    }
+
+    fn list(&self) -> Result<()> {
+        let entries = self.load_index()?;
+        if entries.is_empty() {
+            println!("No models cached in {}", self.dir.display());
+        }
+        for entry in entries {
+            println!(
+                "{}\t{}\t{}",
+                entry.name,
+                self.dir.join(&entry.file).display(),
+                entry.source
+            );
+        }
+        Ok(())
+    }
+
+    fn verify(&self, name: Option<&str>) -> Result<()> {
+        let entries: Vec<_> = self
+            .load_index()?
+            .into_iter()
+            .filter(|e| name.is_none_or(|n| e.name == n))
+            .collect();
+        if let Some(name) = name
+            && entries.is_empty()
+        {
+            bail!("Model '{name}' is not cached");
+        }
+
+        let mut bad = 0;
+        for entry in &entries {
+            let path = self.dir.join(&entry.file);
+            match checksum::sha256_file(&path) {
+                Ok(digest) if digest == entry.sha256 => println!("OK\t{}", entry.name),
+                Ok(digest) => {
+                    bad += 1;
+                    println!(
+                        "MISMATCH\t{}\texpected {}, got {digest}",
+                        entry.name, entry.sha256
+                    );
+                }
+                Err(e) => {
+                    bad += 1;
+                    println!("MISSING\t{}\t{e:#}", entry.name);
+                }
+            }
+        }
+        if bad > 0 {
+            bail!(
+                "{bad} of {} cached models failed verification",
+                entries.len()
+            );
+        }
+        Ok(())
+    }
+
+    fn load_index(&self) -> Result<Vec<ModelEntry>> {
+        let path = self.dir.join(INDEX_FILE);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let data = std::fs::read(&path)
+            .with_context(|| format!("Failed to read model index: {}", path.display()))?;
+        serde_json::from_slice(&data)
+            .with_context(|| format!("Malformed model index: {}", path.display()))
+    }
+
+    fn save_index(&self, entries: &[ModelEntry]) -> Result<()> {
+        let path = self.dir.join(INDEX_FILE);
+        std::fs::write(&path, serde_json::to_vec_pretty(entries)?)
+            .with_context(|| format!("Failed to write model index: {}", path.display()))
+    }
+}
+
+/// `$XDG_CACHE_HOME`, `~/.cache` or `%LOCALAPPDATA%`, plus the tool's subdirectory.
+fn default_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+    Some(base.join(env!("CARGO_PKG_NAME")).join("models"))
+}
+
+/// Copies everything read from `reader` into `writer` on the way through.
+struct Tee<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: Read, W: Write> Read for Tee<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        if n == 0 {
+            self.writer.flush()?;
+        } else {
+            self.writer.write_all(&buf[..n])?;
+        }
+        Ok(n)
+    }
+}
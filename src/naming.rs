@@ -0,0 +1,296 @@
+//! Output file naming: `--name-template` placeholders, `--flatten` and
+//! `--on-collision`.
+
+use crate::checksum;
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+const PLACEHOLDERS: [&str; 5] = ["stem", "ext", "parent", "model", "date"];
+
+/// A file name pattern such as `{stem}_denoised.{ext}`.
+///
+/// Placeholders: `{stem}` and `{ext}` of the input file name, `{parent}` (the
+/// input's directory name), `{model}` (or `default`) and `{date}` (UTC,
+/// `YYYY-MM-DD`). The result may contain `/` to add subdirectories.
+#[derive(Debug, Clone)]
+pub struct NameTemplate(String);
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            let Some(len) = rest[start..].find('}') else {
+                return Err(format!("unclosed '{{' in name template '{s}'"));
+            };
+            let name = &rest[start + 1..start + len];
+            if !PLACEHOLDERS.contains(&name) {
+                return Err(format!(
+                    "unknown placeholder '{{{name}}}'; expected one of {}",
+                    PLACEHOLDERS.map(|p| format!("{{{p}}}")).join(", ")
+                ));
+            }
+            rest = &rest[start + len + 1..];
+        }
+        let path = Path::new(s);
+        if s.is_empty()
+            || !path
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(format!(
+                "name template '{s}' must be a relative path without '..'"
+            ));
+        }
+        Ok(NameTemplate(s.to_string()))
+    }
+}
+
+impl NameTemplate {
+    /// Output path, relative to the output directory, for the input at `relative`.
+    pub fn render(
+        &self,
+        relative: &Path,
+        root_name: &str,
+        model: Option<&str>,
+        date: &str,
+    ) -> PathBuf {
+        let part = |p: Option<&std::ffi::OsStr>| p.map(|s| s.to_string_lossy().to_string());
+        let stem = part(relative.file_stem()).unwrap_or_default();
+        let ext = part(relative.extension()).unwrap_or_default();
+        let parent = part(relative.parent().and_then(Path::file_name))
+            .unwrap_or_else(|| root_name.to_string());
+
+        // Model names may be paths to cached files; only their stem is used
+        let model = model
+            .and_then(|m| part(Path::new(m).file_stem()))
+            .unwrap_or_else(|| "default".to_string());
+
+        // Single pass, so values that contain braces are not expanded again
+        let mut name = String::new();
+        let mut rest = self.0.as_str();
+        while let Some(start) = rest.find('{') {
+            name.push_str(&rest[..start]);
+            let end = start + rest[start..].find('}').expect("validated when parsed");
+            name.push_str(match &rest[start + 1..end] {
+                "stem" => &stem,
+                "ext" => &ext,
+                "parent" => &parent,
+                "model" => &model,
+                _ => date,
+            });
+            rest = &rest[end + 1..];
+        }
+        name.push_str(rest);
+        relative.with_file_name(name)
+    }
+}
+
+/// Subdirectories `--split` routes outputs into, in the order of its weights.
+const SUBSETS: [&str; 3] = ["train", "val", "test"];
+
+/// Weights of the train, validation and test subsets, e.g. `80/10/10`.
+#[derive(Debug, Clone, Copy)]
+pub struct DatasetSplit([u64; 3]);
+
+impl FromStr for DatasetSplit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let weights: Vec<u64> = s
+            .split('/')
+            .map(|w| w.trim().parse::<u32>().map(u64::from))
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("invalid split '{s}'; expected TRAIN/VAL/TEST, e.g. 80/10/10"))?;
+        let [train, val, test] = weights[..] else {
+            return Err(format!(
+                "split '{s}' needs three weights, for train, val and test"
+            ));
+        };
+        if train + val + test == 0 {
+            return Err(format!("split '{s}' puts no files anywhere"));
+        }
+        Ok(DatasetSplit([train, val, test]))
+    }
+}
+
+impl DatasetSplit {
+    /// The subset for the input at `relative`, picked by a hash of its path
+    /// and `seed`, so every run with the same seed routes the file alike.
+    pub fn subset(&self, relative: &str, seed: u64) -> &'static str {
+        let hash =
+            checksum::sha256_reader(format!("{seed}:{relative}").as_bytes()).unwrap_or_default();
+        let total: u64 = self.0.iter().sum();
+        let mut point = u64::from_str_radix(&hash[..16], 16).unwrap_or(0) % total;
+        for (subset, &weight) in SUBSETS.iter().zip(&self.0) {
+            if point < weight {
+                return subset;
+            }
+            point -= weight;
+        }
+        unreachable!("the point is below the total of the weights")
+    }
+}
+
+/// Encodes a relative output path into one file name: `a/b/c.wav` becomes `a__b__c.wav`.
+pub fn flatten(relative: &Path) -> PathBuf {
+    let parts: Vec<_> = relative
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    PathBuf::from(parts.join("__"))
+}
+
+/// What to do when several inputs map to one output path.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collision {
+    /// Stop before processing anything, listing the colliding inputs
+    Error,
+    /// Append a short hash of the input path to each colliding output name
+    Suffix,
+}
+
+/// The outputs that share a path with another, as groups of indices in input
+/// order. Paths are compared case-insensitively, as they would be on a
+/// case-insensitive filesystem.
+pub fn collisions(outputs: &[PathBuf]) -> Vec<Vec<usize>> {
+    let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, output) in outputs.iter().enumerate() {
+        groups.entry(key(output)).or_default().push(i);
+    }
+    let mut clashes: Vec<Vec<usize>> = groups.into_values().filter(|g| g.len() > 1).collect();
+    clashes.sort();
+    clashes
+}
+
+fn key(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+/// Appends a short hash of the input path to outputs whose names would collide.
+///
+/// Names are compared case-insensitively so the result is also safe on
+/// case-insensitive filesystems; `inputs[i]` is the relative input path of `outputs[i]`.
+pub fn disambiguate(outputs: &mut [PathBuf], inputs: &[String]) {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for output in outputs.iter() {
+        *counts.entry(key(output)).or_default() += 1;
+    }
+    for (output, input) in outputs.iter_mut().zip(inputs) {
+        if counts[&key(output)] < 2 {
+            continue;
+        }
+        let hash = checksum::sha256_reader(input.as_bytes()).unwrap_or_default();
+        let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match output.extension() {
+            Some(ext) => format!("{stem}-{}.{}", &hash[..8], ext.to_string_lossy()),
+            None => format!("{stem}-{}", &hash[..8]),
+        };
+        output.set_file_name(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, relative: &str, model: Option<&str>) -> PathBuf {
+        let template: NameTemplate = template.parse().unwrap();
+        template.render(Path::new(relative), "raw", model, "2024-05-01")
+    }
+
+    #[test]
+    fn fills_in_every_placeholder() {
+        assert_eq!(
+            render("{stem}_denoised.{ext}", "interviews/a.wav", None),
+            Path::new("interviews/a_denoised.wav")
+        );
+        assert_eq!(
+            render(
+                "{date}/{parent}-{stem}-{model}.wav",
+                "interviews/a.wav",
+                None
+            ),
+            Path::new("interviews/2024-05-01/interviews-a-default.wav")
+        );
+        // Cached model files are named by their stem
+        assert_eq!(
+            render(
+                "{stem}.{model}.wav",
+                "a.wav",
+                Some("/cache/models/rnnoise-v2.onnx")
+            ),
+            Path::new("a.rnnoise-v2.wav")
+        );
+    }
+
+    #[test]
+    fn inputs_at_the_root_take_the_root_as_their_parent() {
+        assert_eq!(
+            render("{parent}_{stem}.wav", "a.wav", None),
+            Path::new("raw_a.wav")
+        );
+    }
+
+    #[test]
+    fn values_with_braces_are_not_expanded_again() {
+        assert_eq!(
+            render("{stem}.wav", "{ext}.wav", None),
+            Path::new("{ext}.wav")
+        );
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        for (template, error) in [
+            ("{stem", "unclosed"),
+            ("{name}.wav", "unknown placeholder '{name}'"),
+            ("", "relative path"),
+            ("../{stem}.wav", "relative path"),
+            ("/tmp/{stem}.wav", "relative path"),
+        ] {
+            let err = template.parse::<NameTemplate>().unwrap_err();
+            assert!(err.contains(error), "{template}: {err}");
+        }
+    }
+
+    #[test]
+    fn flattens_directories_into_the_file_name() {
+        assert_eq!(flatten(Path::new("a/b/c.wav")), Path::new("a__b__c.wav"));
+        assert_eq!(flatten(Path::new("./c.wav")), Path::new("c.wav"));
+    }
+
+    #[test]
+    fn groups_outputs_that_differ_only_in_case() {
+        let outputs = ["a.wav", "b.wav", "A.WAV", "sub/b.wav", "B.wav"].map(PathBuf::from);
+        assert_eq!(collisions(&outputs), [vec![0, 2], vec![1, 4]]);
+        assert!(collisions(&outputs[..2]).is_empty());
+    }
+
+    #[test]
+    fn suffixes_only_the_names_that_collide() {
+        let mut outputs = vec![
+            PathBuf::from("a__b.wav"),
+            PathBuf::from("A__B.wav"),
+            PathBuf::from("c.wav"),
+        ];
+        let inputs = ["a/b.wav", "A__B.wav", "c.wav"].map(str::to_string);
+        disambiguate(&mut outputs, &inputs);
+        let hash =
+            |input: &str| checksum::sha256_reader(input.as_bytes()).unwrap()[..8].to_string();
+        assert_eq!(
+            outputs,
+            [
+                PathBuf::from(format!("a__b-{}.wav", hash("a/b.wav"))),
+                PathBuf::from(format!("A__B-{}.wav", hash("A__B.wav"))),
+                PathBuf::from("c.wav"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Rough noise profiling for `--noise-report`: the level and kind of the noise
+//! in the quiet stretches of an input, to help pick models per subset.
+
+use crate::audio;
+use crate::dsp;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::TAU;
+use std::path::Path;
+
+/// What the noise of a file mostly is.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum NoiseKind {
+    /// The noise floor is below [`QUIET_DB`]; there is little to remove.
+    Quiet,
+    /// Mains hum at 50 or 60 Hz and its harmonics.
+    Hum,
+    /// The noise floor comes and goes (traffic, machinery, crowd).
+    Intermittent,
+    /// Steady broadband noise with a flat spectrum (hiss, fans).
+    Hiss,
+    /// Steady noise with a colored spectrum.
+    Other,
+}
+
+impl NoiseKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            NoiseKind::Quiet => "quiet",
+            NoiseKind::Hum => "hum",
+            NoiseKind::Intermittent => "intermittent",
+            NoiseKind::Hiss => "hiss",
+            NoiseKind::Other => "other",
+        }
+    }
+}
+
+/// Noise estimate of one file, taken from its quietest frames.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct NoiseProfile {
+    pub kind: NoiseKind,
+    /// RMS level of the quiet frames, dBFS.
+    pub level_dbfs: f64,
+    /// Mains frequency when hum stands out of the noise spectrum.
+    pub hum_hz: Option<u32>,
+    /// Spectral flatness of the noise (1 for white noise, near 0 for tones).
+    pub flatness: f64,
+    /// Spread of the local noise floor over the file, dB.
+    pub floor_spread_db: f64,
+}
+
+/// Noise floors below this, in dBFS, count as quiet.
+const QUIET_DB: f64 = -70.0;
+/// Frames within this many dB of the 10th-percentile frame count as noise.
+const QUIET_MARGIN_DB: f64 = 6.0;
+/// Harmonic peaks this far above the surrounding spectrum, in dB, are hum.
+const HUM_PROMINENCE_DB: f64 = 10.0;
+/// Flatness above which steady noise is hiss.
+const HISS_FLATNESS: f64 = 0.3;
+/// Floor spread above which the noise is intermittent, in dB.
+const INTERMITTENT_SPREAD_DB: f64 = 12.0;
+/// Length of the windows whose floors are compared for intermittency, in seconds.
+const FLOOR_WINDOW_SECS: f64 = 4.0;
+
+/// Profiles the first channel of the WAV file at `path`.
+pub fn profile_file(path: &Path) -> Result<NoiseProfile> {
+    let audio = audio::read_wav(path)?;
+    Ok(profile(&audio.channel(0), audio.spec.sample_rate))
+}
+
+pub fn profile(samples: &[f32], rate: u32) -> NoiseProfile {
+    // About 4 Hz resolution, enough to tell 50 Hz harmonics from 60 Hz ones
+    let size = (rate as usize / 4).next_power_of_two().max(256);
+    let window: Vec<f64> = (0..size)
+        .map(|i| 0.5 - 0.5 * (TAU * i as f64 / size as f64).cos())
+        .collect();
+    let frames: Vec<&[f32]> = samples.chunks_exact(size).collect();
+    if frames.is_empty() {
+        return NoiseProfile {
+            kind: NoiseKind::Quiet,
+            level_dbfs: db(mean_square(samples)),
+            hum_hz: None,
+            flatness: 0.0,
+            floor_spread_db: 0.0,
+        };
+    }
+
+    let levels: Vec<f64> = frames.iter().map(|f| db(mean_square(f))).collect();
+    let mut sorted = levels.clone();
+    sorted.sort_by(f64::total_cmp);
+    let threshold = percentile(&sorted, 0.1) + QUIET_MARGIN_DB;
+    let quiet: Vec<usize> = (0..frames.len())
+        .filter(|&i| levels[i] <= threshold)
+        .collect();
+    let level_dbfs = db(quiet
+        .iter()
+        .map(|&i| 10f64.powf(levels[i] / 10.0))
+        .sum::<f64>()
+        / quiet.len() as f64);
+
+    // Average power spectrum of the quiet frames
+    let mut spectrum = vec![0.0; size / 2];
+    for &i in &quiet {
+        let mut re: Vec<f64> = frames[i]
+            .iter()
+            .zip(&window)
+            .map(|(&s, w)| f64::from(s) * w)
+            .collect();
+        let mut im = vec![0.0; size];
+        dsp::fft(&mut re, &mut im);
+        for (k, power) in spectrum.iter_mut().enumerate() {
+            *power += (re[k] * re[k] + im[k] * im[k]) / quiet.len() as f64;
+        }
+    }
+    let bin_hz = f64::from(rate) / size as f64;
+    let hum_hz = [50, 60]
+        .into_iter()
+        .map(|base| (base, hum_prominence(&spectrum, f64::from(base) / bin_hz)))
+        .filter(|&(_, prominence)| prominence >= HUM_PROMINENCE_DB)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(base, _)| base);
+
+    let lo = (100.0 / bin_hz).ceil() as usize;
+    let hi = ((7000.0f64).min(f64::from(rate) * 0.45) / bin_hz) as usize;
+    let band = &spectrum[lo.min(spectrum.len())..hi.min(spectrum.len())];
+    let flatness = if band.is_empty() {
+        0.0
+    } else {
+        let mean = band.iter().sum::<f64>() / band.len() as f64;
+        let log_mean = band.iter().map(|p| p.max(1e-30).ln()).sum::<f64>() / band.len() as f64;
+        if mean > 0.0 {
+            log_mean.exp() / mean
+        } else {
+            0.0
+        }
+    };
+
+    // The quietest frame of each window is its local noise floor
+    let per_window = ((FLOOR_WINDOW_SECS * f64::from(rate)) as usize / size).max(1);
+    let mut floors: Vec<f64> = levels
+        .chunks(per_window)
+        .map(|w| w.iter().copied().fold(f64::INFINITY, f64::min))
+        .collect();
+    floors.sort_by(f64::total_cmp);
+    let floor_spread_db = percentile(&floors, 0.9) - percentile(&floors, 0.1);
+
+    // Noise that comes and goes is judged by its loud stretches, which the
+    // quiet frames leave out
+    let kind = if floor_spread_db > INTERMITTENT_SPREAD_DB && percentile(&floors, 0.9) >= QUIET_DB {
+        NoiseKind::Intermittent
+    } else if level_dbfs < QUIET_DB {
+        NoiseKind::Quiet
+    } else if hum_hz.is_some() {
+        NoiseKind::Hum
+    } else if flatness >= HISS_FLATNESS {
+        NoiseKind::Hiss
+    } else {
+        NoiseKind::Other
+    };
+    NoiseProfile {
+        kind,
+        level_dbfs,
+        hum_hz,
+        flatness,
+        floor_spread_db,
+    }
+}
+
+/// How far, in dB, the first four harmonics of `base` (in bins) stand above
+/// the spectrum around them, on average.
+fn hum_prominence(spectrum: &[f64], base: f64) -> f64 {
+    let mut total = 0.0;
+    for harmonic in 1..=4 {
+        let centre = (base * f64::from(harmonic)).round() as usize;
+        if centre + 12 >= spectrum.len() || centre < 12 {
+            return 0.0;
+        }
+        let peak = spectrum[centre - 1..=centre + 1]
+            .iter()
+            .copied()
+            .fold(0.0, f64::max);
+        let mut around: Vec<f64> = (centre - 12..centre - 3)
+            .chain(centre + 4..=centre + 12)
+            .map(|k| spectrum[k])
+            .collect();
+        around.sort_by(f64::total_cmp);
+        total += db(peak) - db(around[around.len() / 2]);
+    }
+    total / 4.0
+}
+
+fn mean_square(samples: &[f32]) -> f64 {
+    samples.iter().map(|&s| f64::from(s).powi(2)).sum::<f64>() / samples.len().max(1) as f64
+}
+
+fn db(power: f64) -> f64 {
+    10.0 * power.max(1e-20).log10()
+}
+
+/// The value at `fraction` of the way through `sorted`.
+fn percentile(sorted: &[f64], fraction: f64) -> f64 {
+    sorted[((sorted.len() - 1) as f64 * fraction).round() as usize]
+}
@@ -0,0 +1,374 @@
+//! `--backend onnx`: RNNoise-style ONNX models run in-process by ONNX Runtime.
+//!
+//! Built with `--features onnx`. ONNX Runtime itself is loaded at run time,
+//! on CUDA or DirectML when the build has `onnx-cuda` or `onnx-directml` and
+//! the machine can, else on the CPU.
+
+use crate::backend::{Backend, Downsampled, MODEL_RATE, Upsampled};
+use anyhow::{Context, Result, bail};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// An ONNX model the pipeline hands files to.
+///
+/// The model's first input takes a float tensor of 48kHz samples in -1..1,
+/// one frame at a time, and its first output gives the denoised frame. The
+/// frame is the input's last dimension, or 10ms when that is dynamic. Any
+/// further inputs carry recurrent state: they start as zeros and are fed the
+/// output at the same position after each frame.
+pub struct Onnx {
+    model: PathBuf,
+    /// Shape of each input, dynamic dimensions fixed.
+    inputs: Vec<(String, Vec<i64>)>,
+    frame: usize,
+    /// Sessions not in use; a session runs one file at a time.
+    idle: Mutex<Vec<ort::session::Session>>,
+}
+
+impl Onnx {
+    /// Loads ONNX Runtime and the model at `model`.
+    pub fn new(model: Option<&str>) -> Result<Self> {
+        let model = PathBuf::from(model.context("--backend onnx needs --model MODEL.onnx")?);
+        let session = open_session(&model)?;
+        let mut inputs = Vec::new();
+        for input in session.inputs() {
+            let shape = match input.dtype() {
+                ort::value::ValueType::Tensor {
+                    ty: ort::value::TensorElementType::Float32,
+                    shape,
+                    ..
+                } => shape,
+                _ => bail!(
+                    "{}: input {} is not a float tensor",
+                    model.display(),
+                    input.name()
+                ),
+            };
+            inputs.push((input.name().to_string(), shape.to_vec()));
+        }
+        let Some((_, audio)) = inputs.first() else {
+            bail!("{} has no inputs", model.display());
+        };
+        let frame = match audio.last() {
+            Some(&len) if len > 0 => len as usize,
+            _ => MODEL_RATE as usize / 100,
+        };
+        if session.outputs().len() < inputs.len() {
+            bail!(
+                "{} gives fewer outputs than it takes inputs, so its state cannot be fed back",
+                model.display()
+            );
+        }
+        // One batch of one frame; state is as large as the model declares it
+        for (_, shape) in &mut inputs {
+            for dim in shape.iter_mut().filter(|d| **d <= 0) {
+                *dim = 1;
+            }
+        }
+        if let Some(len) = inputs[0].1.last_mut() {
+            *len = frame as i64;
+        }
+        Ok(Onnx {
+            model,
+            inputs,
+            frame,
+            idle: Mutex::new(vec![session]),
+        })
+    }
+
+    /// Frames for one file, with the model's state zeroed.
+    fn frames(&self) -> Frames {
+        let states = self.inputs[1..]
+            .iter()
+            .map(|(_, shape)| vec![0.0; shape.iter().product::<i64>() as usize])
+            .collect();
+        Frames::new(self.frame, states)
+    }
+
+    /// Runs `inputs` through `session`, giving back as many outputs.
+    fn infer(
+        &self,
+        session: &mut ort::session::Session,
+        inputs: Vec<Vec<f32>>,
+    ) -> Result<Vec<Vec<f32>>> {
+        let mut values = Vec::with_capacity(inputs.len());
+        for ((name, shape), data) in self.inputs.iter().zip(inputs) {
+            let tensor = ort::value::Tensor::from_array((shape.clone(), data))?;
+            values.push((
+                std::borrow::Cow::from(name.as_str()),
+                ort::session::SessionInputValue::from(tensor),
+            ));
+        }
+        let outputs = session
+            .run(values)
+            .with_context(|| format!("ONNX model {} failed", self.model.display()))?;
+        (0..self.inputs.len())
+            .map(|i| Ok(outputs[i].try_extract_tensor::<f32>()?.1.to_vec()))
+            .collect()
+    }
+}
+
+impl Backend for Onnx {
+    fn denoise(&self, input: &Path, output: &Path, _model: Option<&str>) -> Result<()> {
+        let mut input = Upsampled::open(input)?;
+        let mut output = Downsampled::create(output, MODEL_RATE, input.spec())?;
+        let idle = self.idle.lock().unwrap().pop();
+        let mut session = match idle {
+            Some(session) => session,
+            None => open_session(&self.model)?,
+        };
+        let mut frames = self.frames();
+        let mut infer = |inputs: Vec<Vec<f32>>| self.infer(&mut session, inputs);
+        while let Some(block) = input.next_block()? {
+            output.write(&frames.push(&block, &mut infer)?)?;
+        }
+        output.write(&frames.finish(&mut infer)?)?;
+        self.idle.lock().unwrap().push(session);
+        output.finish()
+    }
+}
+
+/// The model's signature: the frame and then each state in, the denoised
+/// frame and then each next state out.
+type Infer<'a> = dyn FnMut(Vec<Vec<f32>>) -> Result<Vec<Vec<f32>>> + 'a;
+
+/// Audio cut into a model's frames, with the model's recurrent state carried
+/// from each frame to the next.
+struct Frames {
+    frame: usize,
+    states: Vec<Vec<f32>>,
+    /// Samples short of a whole frame, waiting for the next push.
+    pending: Vec<f32>,
+}
+
+impl Frames {
+    fn new(frame: usize, states: Vec<Vec<f32>>) -> Self {
+        Frames {
+            frame,
+            states,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Denoises the whole frames `samples` completes, keeping the rest for
+    /// the next call.
+    fn push(&mut self, samples: &[f32], infer: &mut Infer) -> Result<Vec<f32>> {
+        let mut pending = std::mem::take(&mut self.pending);
+        pending.extend_from_slice(samples);
+        let whole = pending.len() - pending.len() % self.frame;
+        let mut denoised = Vec::with_capacity(whole);
+        for frame in pending[..whole].chunks_exact(self.frame) {
+            denoised.extend(self.step(frame, infer)?);
+        }
+        pending.drain(..whole);
+        self.pending = pending;
+        Ok(denoised)
+    }
+
+    /// Denoises what is left, padded with silence to a frame and cut back.
+    fn finish(mut self, infer: &mut Infer) -> Result<Vec<f32>> {
+        let mut last = std::mem::take(&mut self.pending);
+        if last.is_empty() {
+            return Ok(last);
+        }
+        let len = last.len();
+        last.resize(self.frame, 0.0);
+        let mut denoised = self.step(&last, infer)?;
+        denoised.truncate(len);
+        Ok(denoised)
+    }
+
+    /// Runs one frame, keeping the state it gives for the next.
+    fn step(&mut self, frame: &[f32], infer: &mut Infer) -> Result<Vec<f32>> {
+        let mut inputs = Vec::with_capacity(1 + self.states.len());
+        inputs.push(frame.to_vec());
+        inputs.extend(self.states.iter().cloned());
+        let mut outputs = infer(inputs)?.into_iter();
+        let denoised = outputs.next().context("The ONNX model gave no output")?;
+        if denoised.len() != frame.len() {
+            bail!(
+                "The ONNX model gave {} samples for a frame of {}",
+                denoised.len(),
+                frame.len()
+            );
+        }
+        for (i, state) in self.states.iter_mut().enumerate() {
+            let next = outputs
+                .next()
+                .with_context(|| format!("The ONNX model gave no state output {}", i + 1))?;
+            if next.len() != state.len() {
+                bail!(
+                    "State output {} changed size from one frame to the next",
+                    i + 1
+                );
+            }
+            *state = next;
+        }
+        Ok(denoised)
+    }
+}
+
+/// Loads ONNX Runtime, once per process, from `ORT_DYLIB_PATH` or else the
+/// library search path.
+pub fn runtime() -> Result<()> {
+    static LOADED: OnceLock<Result<(), String>> = OnceLock::new();
+    LOADED
+        .get_or_init(|| {
+            let library = std::env::var("ORT_DYLIB_PATH")
+                .ok()
+                .filter(|path| !path.is_empty())
+                .unwrap_or_else(|| {
+                    let name = if cfg!(windows) {
+                        "onnxruntime.dll"
+                    } else if cfg!(target_os = "macos") {
+                        "libonnxruntime.dylib"
+                    } else {
+                        "libonnxruntime.so"
+                    };
+                    name.to_string()
+                });
+            let environment = ort::init_from(&library).map_err(|e| {
+                format!(
+                    "Failed to load ONNX Runtime from {library}: {e}; set ORT_DYLIB_PATH to the onnxruntime library"
+                )
+            })?;
+            environment.commit();
+            Ok(())
+        })
+        .clone()
+        .map_err(anyhow::Error::msg)
+}
+
+/// A session of the model at `model`, on the first execution provider of the
+/// build that works here, else on the CPU.
+fn open_session(model: &Path) -> Result<ort::session::Session> {
+    runtime()?;
+    type Provider = (&'static str, ort::ep::ExecutionProviderDispatch);
+    let providers: Vec<Provider> = Vec::from([
+        #[cfg(feature = "onnx-cuda")]
+        ("CUDA", ort::ep::CUDA::default().build()),
+        #[cfg(feature = "onnx-directml")]
+        ("DirectML", ort::ep::DirectML::default().build()),
+    ]);
+
+    let mut builder = ort::session::Session::builder()?;
+    for (name, provider) in providers {
+        match builder.with_execution_providers([provider.error_on_failure()]) {
+            Ok(with) => {
+                builder = with;
+                break;
+            }
+            Err(e) => {
+                eprintln!(
+                    "Warning: --backend onnx: {name} is not available ({e}); trying the next"
+                );
+                builder = e.recover();
+            }
+        }
+    }
+    builder
+        .commit_from_file(model)
+        .with_context(|| format!("Failed to load ONNX model: {}", model.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A model that adds its one state to each sample and counts the state up
+    /// by one a frame, noting the frames it was given.
+    fn counting(
+        seen: &mut Vec<Vec<f32>>,
+    ) -> impl FnMut(Vec<Vec<f32>>) -> Result<Vec<Vec<f32>>> + '_ {
+        move |inputs| {
+            let [frame, state] = <[Vec<f32>; 2]>::try_from(inputs).unwrap();
+            seen.push(frame.clone());
+            let denoised = frame.iter().map(|s| s + state[0]).collect();
+            Ok(vec![denoised, vec![state[0] + 1.0]])
+        }
+    }
+
+    #[test]
+    fn runs_whole_frames_across_pushes() {
+        let mut seen = Vec::new();
+        let mut infer = counting(&mut seen);
+        let mut frames = Frames::new(4, vec![vec![0.0]]);
+        assert!(
+            frames
+                .push(&[1.0, 2.0, 3.0], &mut infer)
+                .unwrap()
+                .is_empty()
+        );
+        let denoised = frames
+            .push(&[4.0, 5.0, 6.0, 7.0, 8.0, 9.0], &mut infer)
+            .unwrap();
+        assert_eq!(denoised, [1.0, 2.0, 3.0, 4.0, 6.0, 7.0, 8.0, 9.0]);
+        assert_eq!(frames.pending, [9.0]);
+        drop(infer);
+        assert_eq!(seen, [vec![1.0, 2.0, 3.0, 4.0], vec![5.0, 6.0, 7.0, 8.0]]);
+    }
+
+    #[test]
+    fn pads_the_last_frame_and_cuts_it_back() {
+        let mut seen = Vec::new();
+        let mut infer = counting(&mut seen);
+        let mut frames = Frames::new(4, vec![vec![0.0]]);
+        frames.push(&[1.0, 1.0, 1.0, 1.0, 1.0], &mut infer).unwrap();
+        assert_eq!(frames.finish(&mut infer).unwrap(), [2.0]);
+        drop(infer);
+        assert_eq!(seen[1], [1.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn finishing_on_a_frame_boundary_runs_nothing() {
+        let mut seen = Vec::new();
+        let mut infer = counting(&mut seen);
+        let mut frames = Frames::new(2, vec![vec![0.0]]);
+        frames.push(&[1.0, 1.0], &mut infer).unwrap();
+        assert!(frames.finish(&mut infer).unwrap().is_empty());
+        drop(infer);
+        assert_eq!(seen.len(), 1);
+    }
+
+    #[test]
+    fn carries_state_from_frame_to_frame() {
+        let mut states = Vec::new();
+        let mut infer = |inputs: Vec<Vec<f32>>| -> Result<Vec<Vec<f32>>> {
+            states.push(inputs[1..].to_vec());
+            let next = inputs[1..]
+                .iter()
+                .map(|state| state.iter().map(|s| s * 2.0).collect())
+                .collect::<Vec<Vec<f32>>>();
+            Ok([vec![inputs[0].clone()], next].concat())
+        };
+        let mut frames = Frames::new(1, vec![vec![1.0, 2.0], vec![3.0]]);
+        frames.push(&[0.0, 0.0, 0.0], &mut infer).unwrap();
+        assert_eq!(
+            states,
+            [
+                vec![vec![1.0, 2.0], vec![3.0]],
+                vec![vec![2.0, 4.0], vec![6.0]],
+                vec![vec![4.0, 8.0], vec![12.0]],
+            ]
+        );
+        assert_eq!(frames.states, [vec![8.0, 16.0], vec![24.0]]);
+    }
+
+    #[test]
+    fn refuses_a_frame_of_the_wrong_length() {
+        let mut infer = |_: Vec<Vec<f32>>| -> Result<Vec<Vec<f32>>> { Ok(vec![vec![0.0; 3]]) };
+        let mut frames = Frames::new(4, Vec::new());
+        let e = frames.push(&[0.0; 4], &mut infer).unwrap_err();
+        assert!(format!("{e}").contains("3 samples for a frame of 4"));
+    }
+
+    #[test]
+    fn refuses_a_state_that_changes_size() {
+        let mut infer = |inputs: Vec<Vec<f32>>| -> Result<Vec<Vec<f32>>> {
+            Ok(vec![inputs[0].clone(), vec![0.0; 2]])
+        };
+        let mut frames = Frames::new(1, vec![vec![0.0]]);
+        let e = frames.push(&[0.0], &mut infer).unwrap_err();
+        assert!(format!("{e}").contains("State output 1 changed size"));
+    }
+}
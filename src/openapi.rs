@@ -0,0 +1,268 @@
+//! The OpenAPI 3 description of the server mode's REST API, served at
+//! `/openapi.json`, and the Swagger UI page that renders it.
+
+use serde_json::{Value, json};
+
+/// The API description; `authenticated` adds the bearer-token requirement,
+/// and `swagger_ui` the `/docs` page.
+pub fn document(authenticated: bool, swagger_ui: bool) -> Value {
+    let error = |description: &str| {
+        json!({
+            "description": description,
+            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Error" } } }
+        })
+    };
+    let id = json!({
+        "name": "id",
+        "in": "path",
+        "required": true,
+        "description": "Job ID returned by `POST /jobs`",
+        "schema": { "type": "string" }
+    });
+    let mut document = json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "wav-files-denoise-api",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Asynchronous denoising jobs: upload a WAV file, poll the job, download the result."
+        },
+        "paths": {
+            "/jobs": {
+                "post": {
+                    "operationId": "submitJob",
+                    "summary": "Queue a WAV file for denoising",
+                    "parameters": [{
+                        "name": "priority",
+                        "in": "query",
+                        "required": false,
+                        "description": "Queue line of the job; high jobs get the largest share of the workers (see --priority-weights)",
+                        "schema": { "type": "string", "enum": ["high", "normal", "low"], "default": "normal" }
+                    }],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "audio/wav": { "schema": { "type": "string", "format": "binary" } } }
+                    },
+                    "responses": {
+                        "202": {
+                            "description": "Queued",
+                            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/JobCreated" } } }
+                        },
+                        "400": error("Unknown priority"),
+                        "413": error("The upload is larger than --max-upload-size"),
+                        "429": error("The client has --max-jobs-per-client jobs queued or running, or its rate limit is used up"),
+                        "500": error("The upload could not be stored"),
+                        "503": error("The job queue is full; retry later"),
+                        "507": error("The spool directory holds --max-spool-size; retry later")
+                    }
+                }
+            },
+            "/jobs/{id}": {
+                "parameters": [id],
+                "get": {
+                    "operationId": "getJob",
+                    "summary": "Report the status of a job",
+                    "responses": {
+                        "200": {
+                            "description": "The job",
+                            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Job" } } }
+                        },
+                        "404": error("No such job, or it expired after --job-ttl")
+                    }
+                },
+                "delete": {
+                    "operationId": "deleteJob",
+                    "summary": "Forget a job that is not running and remove its files",
+                    "responses": {
+                        "204": { "description": "Deleted" },
+                        "404": error("No such job"),
+                        "409": error("The job is running")
+                    }
+                }
+            },
+            "/jobs/{id}/result": {
+                "parameters": [id],
+                "get": {
+                    "operationId": "getJobResult",
+                    "summary": "Download the denoised file of a finished job",
+                    "responses": {
+                        "200": {
+                            "description": "The denoised WAV file",
+                            "content": { "audio/wav": { "schema": { "type": "string", "format": "binary" } } }
+                        },
+                        "404": error("No such job"),
+                        "409": error("The job failed or is not finished"),
+                        "410": error("The result is no longer available")
+                    }
+                }
+            },
+            "/stream": {
+                "get": {
+                    "operationId": "streamDenoise",
+                    "summary": "Denoise live audio over a WebSocket",
+                    "description": "Upgrades to a WebSocket. Send binary messages of 48kHz 16-bit little-endian mono PCM; denoised PCM comes back in binary messages as each --frame-size worth (10ms by default) completes. An empty message ends the audio and flushes the rest of the output.",
+                    "responses": {
+                        "101": { "description": "Switched to the WebSocket protocol" },
+                        "400": error("Not a WebSocket upgrade"),
+                        "426": error("WebSocket version 13 is required"),
+                        "503": error("--max-streams streams are already open")
+                    }
+                }
+            },
+            "/metrics": {
+                "get": {
+                    "operationId": "getMetrics",
+                    "summary": "Counters, gauges and latencies in the Prometheus text format",
+                    "security": [],
+                    "responses": {
+                        "200": {
+                            "description": "Metrics",
+                            "content": { "text/plain": { "schema": { "type": "string" } } }
+                        }
+                    }
+                }
+            },
+            "/healthz": {
+                "get": {
+                    "operationId": "getHealth",
+                    "summary": "Liveness probe: answers while the process serves requests",
+                    "security": [],
+                    "responses": {
+                        "200": {
+                            "description": "Alive",
+                            "content": { "application/json": { "schema": {
+                                "type": "object",
+                                "properties": { "status": { "type": "string", "enum": ["ok"] } }
+                            } } }
+                        }
+                    }
+                }
+            },
+            "/readyz": {
+                "get": {
+                    "operationId": "getReadiness",
+                    "summary": "Readiness probe: whether the server takes jobs now",
+                    "security": [],
+                    "responses": {
+                        "200": { "description": "Ready", "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Readiness" } } } },
+                        "503": { "description": "Loading the pipeline, or the queue is full", "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Readiness" } } } }
+                    }
+                }
+            },
+            "/openapi.json": {
+                "get": {
+                    "operationId": "getOpenApi",
+                    "summary": "This OpenAPI document",
+                    "security": [],
+                    "responses": {
+                        "200": {
+                            "description": "The document",
+                            "content": { "application/json": { "schema": { "type": "object" } } }
+                        }
+                    }
+                }
+            }
+        },
+        "components": {
+            "schemas": {
+                "Readiness": {
+                    "type": "object",
+                    "required": ["ready"],
+                    "properties": {
+                        "ready": { "type": "boolean" },
+                        "reason": { "type": "string", "description": "Why the server is not ready" }
+                    }
+                },
+                "JobCreated": {
+                    "type": "object",
+                    "required": ["id"],
+                    "properties": { "id": { "type": "string" } }
+                },
+                "Job": {
+                    "type": "object",
+                    "required": ["id", "status", "priority"],
+                    "properties": {
+                        "id": { "type": "string" },
+                        "status": { "type": "string", "enum": ["queued", "running", "done", "failed"] },
+                        "priority": { "type": "string", "enum": ["high", "normal", "low"] },
+                        "code": {
+                            "type": "string",
+                            "enum": ["invalid_format", "decoder_failed", "backend_failed", "plugin_failed", "io_error", "timeout", "internal"],
+                            "description": "Class of the failure of a failed job"
+                        },
+                        "error": { "type": "string", "description": "Why the job failed" }
+                    }
+                },
+                "Error": {
+                    "type": "object",
+                    "required": ["error"],
+                    "properties": { "error": { "type": "string" } }
+                }
+            }
+        }
+    });
+
+    if swagger_ui {
+        document["paths"]["/docs"] = json!({
+            "get": {
+                "operationId": "getDocs",
+                "summary": "Swagger UI rendering this document",
+                "security": [],
+                "responses": {
+                    "200": {
+                        "description": "The Swagger UI page",
+                        "content": { "text/html": { "schema": { "type": "string" } } }
+                    }
+                }
+            }
+        });
+    }
+
+    // Every request counts against the client's rate limit
+    for operation in operations(&mut document) {
+        let responses = &mut operation["responses"];
+        if responses.get("429").is_none() {
+            responses["429"] = error("The client's rate limit is used up; see Retry-After");
+        }
+    }
+    if authenticated {
+        document["components"]["securitySchemes"] =
+            json!({ "bearer": { "type": "http", "scheme": "bearer" } });
+        document["security"] = json!([{ "bearer": [] }]);
+        for operation in operations(&mut document) {
+            let responses = &mut operation["responses"];
+            responses["401"] = error("Missing or invalid bearer token");
+        }
+    }
+    document
+}
+
+/// The operations under every path, skipping shared path parameters and the
+/// public routes, which bypass authorization and rate limits.
+fn operations(document: &mut Value) -> impl Iterator<Item = &mut Value> {
+    document["paths"]
+        .as_object_mut()
+        .into_iter()
+        .flat_map(|paths| paths.values_mut())
+        .filter_map(Value::as_object_mut)
+        .flat_map(|path| path.iter_mut())
+        .filter(|(key, operation)| *key != "parameters" && operation.get("security").is_none())
+        .map(|(_, operation)| operation)
+}
+
+/// A Swagger UI page for the document, with the UI itself loaded from a CDN.
+pub const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>wav-files-denoise-api</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
@@ -0,0 +1,340 @@
+//! A minimal Parquet writer for `--report-format parquet`: one row group of
+//! required columns, each a single PLAIN-encoded page compressed with gzip.
+//! That is all a run report needs, so no Arrow stack is linked in.
+
+use anyhow::{Context, Result};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::io::Write;
+use std::path::Path;
+
+const MAGIC: &[u8] = b"PAR1";
+
+/// Parquet physical types, converted types, encodings and codecs used here.
+const TYPE_INT64: i32 = 2;
+const TYPE_DOUBLE: i32 = 5;
+const TYPE_BYTE_ARRAY: i32 = 6;
+const CONVERTED_UTF8: i32 = 0;
+const REQUIRED: i32 = 0;
+const ENCODING_PLAIN: i32 = 0;
+const ENCODING_RLE: i32 = 3;
+const CODEC_GZIP: i32 = 2;
+const PAGE_DATA: i32 = 0;
+
+/// The values of one column, a row each.
+pub enum Column {
+    Text(Vec<String>),
+    Double(Vec<f64>),
+    Int(Vec<i64>),
+}
+
+impl Column {
+    fn len(&self) -> usize {
+        match self {
+            Column::Text(values) => values.len(),
+            Column::Double(values) => values.len(),
+            Column::Int(values) => values.len(),
+        }
+    }
+
+    fn physical_type(&self) -> i32 {
+        match self {
+            Column::Text(_) => TYPE_BYTE_ARRAY,
+            Column::Double(_) => TYPE_DOUBLE,
+            Column::Int(_) => TYPE_INT64,
+        }
+    }
+
+    /// The values in PLAIN encoding.
+    fn plain(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            Column::Text(values) => {
+                for value in values {
+                    out.extend((value.len() as u32).to_le_bytes());
+                    out.extend(value.as_bytes());
+                }
+            }
+            Column::Double(values) => values.iter().for_each(|v| out.extend(v.to_le_bytes())),
+            Column::Int(values) => values.iter().for_each(|v| out.extend(v.to_le_bytes())),
+        }
+        out
+    }
+}
+
+/// Writes `columns`, which must all have the same number of rows, to `path`.
+pub fn write(path: &Path, columns: &[(&str, Column)]) -> Result<()> {
+    let rows = columns.first().map_or(0, |(_, c)| c.len()) as i64;
+    let mut file = MAGIC.to_vec();
+    let mut chunks = Vec::new();
+    for (name, column) in columns {
+        let plain = column.plain();
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&plain)?;
+        let compressed = gzip.finish()?;
+
+        let mut header = Thrift::default();
+        header.i32(1, PAGE_DATA);
+        header.i32(2, plain.len() as i32);
+        header.i32(3, compressed.len() as i32);
+        header.begin(5);
+        header.i32(1, column.len() as i32);
+        header.i32(2, ENCODING_PLAIN);
+        header.i32(3, ENCODING_RLE);
+        header.i32(4, ENCODING_RLE);
+        header.end();
+        header.stop();
+
+        let offset = file.len() as i64;
+        file.extend(&header.out);
+        file.extend(&compressed);
+        chunks.push(Chunk {
+            name,
+            physical_type: column.physical_type(),
+            values: column.len() as i64,
+            offset,
+            uncompressed: (header.out.len() + plain.len()) as i64,
+            compressed: (header.out.len() + compressed.len()) as i64,
+        });
+    }
+
+    let mut meta = Thrift::default();
+    meta.i32(1, 1);
+    meta.list(2, Thrift::STRUCT, columns.len() + 1);
+    meta.begin_element();
+    meta.binary(4, b"schema");
+    meta.i32(5, columns.len() as i32);
+    meta.end();
+    for (name, column) in columns {
+        meta.begin_element();
+        meta.i32(1, column.physical_type());
+        meta.i32(3, REQUIRED);
+        meta.binary(4, name.as_bytes());
+        if matches!(column, Column::Text(_)) {
+            meta.i32(6, CONVERTED_UTF8);
+        }
+        meta.end();
+    }
+    meta.i64(3, rows);
+    meta.list(4, Thrift::STRUCT, 1);
+    meta.begin_element();
+    meta.list(1, Thrift::STRUCT, chunks.len());
+    for chunk in &chunks {
+        meta.begin_element();
+        meta.i64(2, chunk.offset);
+        meta.begin(3);
+        meta.i32(1, chunk.physical_type);
+        meta.list(2, Thrift::I32, 1);
+        meta.varint(zigzag(i64::from(ENCODING_PLAIN)));
+        meta.list(3, Thrift::BINARY, 1);
+        meta.varint(chunk.name.len() as u64);
+        meta.out.extend(chunk.name.as_bytes());
+        meta.i32(4, CODEC_GZIP);
+        meta.i64(5, chunk.values);
+        meta.i64(6, chunk.uncompressed);
+        meta.i64(7, chunk.compressed);
+        meta.i64(9, chunk.offset);
+        meta.end();
+        meta.end();
+    }
+    meta.i64(2, chunks.iter().map(|c| c.uncompressed).sum());
+    meta.i64(3, rows);
+    meta.end();
+    meta.binary(
+        6,
+        concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")).as_bytes(),
+    );
+    meta.stop();
+
+    file.extend(&meta.out);
+    file.extend((meta.out.len() as u32).to_le_bytes());
+    file.extend(MAGIC);
+    std::fs::write(path, file).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+struct Chunk<'a> {
+    name: &'a str,
+    physical_type: i32,
+    values: i64,
+    offset: i64,
+    uncompressed: i64,
+    compressed: i64,
+}
+
+/// Thrift's compact protocol, just the parts Parquet metadata needs.
+#[derive(Default)]
+struct Thrift {
+    out: Vec<u8>,
+    /// Id of the last field written in the current struct.
+    last: i16,
+    /// `last` of the enclosing structs.
+    outer: Vec<i16>,
+}
+
+impl Thrift {
+    const I32: u8 = 5;
+    const I64: u8 = 6;
+    const BINARY: u8 = 8;
+    const LIST: u8 = 9;
+    const STRUCT: u8 = 12;
+
+    fn field(&mut self, id: i16, kind: u8) {
+        let delta = id - self.last;
+        if (1..=15).contains(&delta) {
+            self.out.push((delta as u8) << 4 | kind);
+        } else {
+            self.out.push(kind);
+            self.varint(zigzag(i64::from(id)));
+        }
+        self.last = id;
+    }
+
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.out.push(value as u8);
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, Self::I32);
+        self.varint(zigzag(i64::from(value)));
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, Self::I64);
+        self.varint(zigzag(value));
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, Self::BINARY);
+        self.varint(value.len() as u64);
+        self.out.extend(value);
+    }
+
+    /// Starts a list field of `len` elements of `kind`.
+    fn list(&mut self, id: i16, kind: u8, len: usize) {
+        self.field(id, Self::LIST);
+        if len < 15 {
+            self.out.push((len as u8) << 4 | kind);
+        } else {
+            self.out.push(0xf0 | kind);
+            self.varint(len as u64);
+        }
+    }
+
+    /// Starts a struct field; [`Thrift::end`] closes it.
+    fn begin(&mut self, id: i16) {
+        self.field(id, Self::STRUCT);
+        self.begin_element();
+    }
+
+    /// Starts a struct that is an element of a list.
+    fn begin_element(&mut self) {
+        self.outer.push(self.last);
+        self.last = 0;
+    }
+
+    fn end(&mut self) {
+        self.stop();
+        self.last = self.outer.pop().unwrap_or_default();
+    }
+
+    /// Ends the outermost struct.
+    fn stop(&mut self) {
+        self.out.push(0);
+    }
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::parquet::basic::Compression as Codec;
+    use ::parquet::file::reader::{FileReader, SerializedFileReader};
+    use ::parquet::record::RowAccessor;
+    use std::path::PathBuf;
+
+    fn scratch(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "{}-parquet-{}-{test}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn read(path: &Path) -> SerializedFileReader<std::fs::File> {
+        SerializedFileReader::new(std::fs::File::open(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn the_parquet_crate_reads_back_what_was_written() {
+        let dir = scratch("round-trip");
+        let path = dir.join("report.parquet");
+        let files: Vec<String> = (0..40).map(|n| format!("sub/ü-{n}.wav")).collect();
+        let secs: Vec<f64> = (0..40).map(|n| n as f64 * 0.25).collect();
+        let bytes: Vec<i64> = (0..40).map(|n| n * 1_000_000_007).collect();
+        write(
+            &path,
+            &[
+                ("file", Column::Text(files.clone())),
+                ("secs", Column::Double(secs.clone())),
+                ("bytes", Column::Int(bytes.clone())),
+            ],
+        )
+        .unwrap();
+
+        let reader = read(&path);
+        let meta = reader.metadata();
+        assert_eq!(meta.file_metadata().num_rows(), 40);
+        let schema = meta.file_metadata().schema_descr();
+        let names: Vec<&str> = schema.columns().iter().map(|c| c.name()).collect();
+        assert_eq!(names, ["file", "secs", "bytes"]);
+        assert_eq!(meta.num_row_groups(), 1);
+        for column in meta.row_group(0).columns() {
+            assert!(matches!(column.compression(), Codec::GZIP(_)));
+        }
+        let rows: Vec<_> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        assert_eq!(rows.len(), 40);
+        for (n, row) in rows.iter().enumerate() {
+            assert_eq!(row.get_string(0).unwrap(), &files[n]);
+            assert_eq!(row.get_double(1).unwrap(), secs[n]);
+            assert_eq!(row.get_long(2).unwrap(), bytes[n]);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn wide_and_empty_tables_are_readable() {
+        let dir = scratch("wide");
+        let path = dir.join("wide.parquet");
+        let names: Vec<String> = (0..20).map(|n| format!("column_{n}")).collect();
+        let columns: Vec<(&str, Column)> = names
+            .iter()
+            .map(|name| (name.as_str(), Column::Int(Vec::new())))
+            .collect();
+        write(&path, &columns).unwrap();
+
+        let reader = read(&path);
+        let meta = reader.metadata();
+        assert_eq!(meta.file_metadata().num_rows(), 0);
+        assert_eq!(meta.file_metadata().schema_descr().num_columns(), 20);
+        assert_eq!(
+            meta.row_group(0).column(19).column_path().string(),
+            "column_19"
+        );
+        assert_eq!(reader.get_row_iter(None).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1088 @@
+//! Denoising a single file: pre plugins, the backend, post filters, post
+//! plugins and provenance tagging. Shared by batch runs and the server mode.
+
+use crate::audio::{self, Downmix};
+use crate::backend::{self, ArgsTemplate, Backend, BackendKind, CommandTemplate};
+use crate::capture;
+use crate::chunk::{self, ChunkArgs, Chunker};
+use crate::decode::{self, Decoder};
+use crate::error::DenoiseError;
+use crate::filter::{self, PostFilter};
+use crate::models::ModelStore;
+use crate::plugin::{Plugin, Stage, Staged};
+use crate::provenance::{self, ReprocessPolicy};
+use crate::qa::Levels;
+use crate::raw::{self, RawArgs};
+use crate::regions::{self, Region};
+use crate::riff;
+use crate::scratch;
+use crate::split::{SplitArgs, Splitter};
+use anyhow::{Context, Result, bail};
+use clap::builder::TypedValueParser;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Options describing how each file is denoised; serialized as the settings of `--sidecar` files.
+#[derive(clap::Args, Debug, Clone, Serialize)]
+pub struct PipelineArgs {
+    /// Comma-separated list of API server addresses
+    #[arg(long, value_delimiter = ',')]
+    pub addr_api: Vec<String>,
+
+    /// Model to use for denoising
+    #[arg(long)]
+    pub model: Option<String>,
+
+    /// Denoiser to use [default: plugin when a backend plugin is registered, otherwise api]
+    #[arg(long, value_enum)]
+    pub backend: Option<BackendKind>,
+
+    /// Command for --backend command, with {in}, {out} and optional {model} placeholders
+    #[arg(long, value_name = "TEMPLATE")]
+    pub backend_command: Option<CommandTemplate>,
+
+    /// Worker program for --backend stream, started once per concurrent job
+    #[arg(long, value_name = "COMMAND")]
+    pub stream_command: Option<String>,
+
+    /// Program run by --backend rnnoise or deepfilternet instead of rnnoise_demo or deep-filter
+    #[arg(long, value_name = "PROGRAM")]
+    pub backend_program: Option<String>,
+
+    /// Arguments for the --backend rnnoise or deepfilternet program, e.g. "--model={model} {in} {out}"
+    #[arg(long, value_name = "TEMPLATE", allow_hyphen_values = true)]
+    pub backend_args: Option<ArgsTemplate>,
+
+    /// Overlap of the built-in denoiser's frames in percent (0, 50, 75, 80 or 90); more is smoother and slower
+    #[arg(long, value_name = "PERCENT", default_value_t = 0)]
+    pub overlap: u32,
+
+    /// Run the denoiser this many times over each file, for very noisy material
+    #[arg(long, value_name = "N", default_value_t = 1,
+          value_parser = clap::value_parser!(u32).range(1..=16))]
+    pub passes: u32,
+
+    /// DSP filters applied after denoising, e.g. "highpass=80,dcremove" (highpass, lowpass, deess, dcremove)
+    #[arg(long, value_delimiter = ',', value_name = "FILTERS")]
+    pub post_filter: Vec<PostFilter>,
+
+    /// Mute residual noise wherever the denoised output stays below this level, e.g. -50
+    #[arg(long, value_name = "DBFS", allow_hyphen_values = true)]
+    pub gate_db: Option<f64>,
+
+    /// How long the output must stay below --gate-db before the gate closes
+    #[arg(long, value_name = "MS", default_value_t = 200, requires = "gate_db")]
+    pub gate_hold_ms: u64,
+
+    /// External plugin as STAGE=COMMAND, where STAGE is pre, backend or post (repeatable)
+    #[arg(long = "plugin", value_name = "STAGE=COMMAND")]
+    pub plugins: Vec<Plugin>,
+
+    /// How to treat inputs that are already tagged as denoised by this tool
+    #[arg(long, value_enum, default_value_t = ReprocessPolicy::Skip)]
+    pub reprocess_policy: ReprocessPolicy,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub target: TargetArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub raw: RawArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub split: SplitArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub chunk: ChunkArgs,
+
+    /// How inputs are read; ffmpeg decodes non-WAV inputs to the --expect-* format
+    #[arg(long, value_enum, default_value_t = Decoder::Builtin)]
+    pub decoder: Decoder,
+
+    /// Repair WAV inputs whose data chunk length does not match the file (cut short, unset, trailing garbage) instead of failing them
+    #[arg(long)]
+    pub tolerant_parse: bool,
+
+    /// Append a CodingHistory line to each output's BWF bext chunk (copied from the input or created)
+    #[arg(long)]
+    pub coding_history: bool,
+
+    /// Denoise only these channels (0-based) of multichannel inputs, each on its own; outputs keep just them
+    #[arg(long, alias = "channel", value_delimiter = ',', value_name = "N,...")]
+    pub channels: Vec<u16>,
+
+    /// Mix multichannel inputs (or the --channels kept) into mono before validation
+    #[arg(long)]
+    pub downmix: bool,
+
+    /// How --downmix combines the channels
+    #[arg(long, value_enum, default_value_t = Downmix::Average)]
+    pub downmix_method: Downmix,
+
+    /// Warn about clipped samples and DC offset in each input and output
+    #[arg(long)]
+    pub check_levels: bool,
+
+    /// Subtract the DC offset of inputs whose offset exceeds -40 dBFS before denoising
+    #[arg(long)]
+    pub remove_dc: bool,
+
+    /// Notch out mains hum at this frequency and its harmonics before denoising
+    #[arg(long, value_name = "HZ",
+          value_parser = clap::builder::PossibleValuesParser::new(["50", "60"])
+              .map(|s| s.parse::<u32>().unwrap()))]
+    pub dehum: Option<u32>,
+
+    /// Add TPDF dither when reducing inputs to a lower --expect-bits for the denoiser
+    #[arg(long)]
+    pub dither: bool,
+
+    /// Denoise only labelled regions: an Audacity label file, a directory of STEM.txt label files, or "cue" for the input's cue points
+    #[arg(long, value_name = "SOURCE")]
+    pub segments: Option<PathBuf>,
+
+    /// Also write each --segments region as its own denoised clip beside the output, STEM_NN_LABEL.wav
+    #[arg(long, requires = "segments")]
+    pub segment_clips: bool,
+}
+
+/// Result of handling a single input file.
+pub enum Outcome {
+    Processed,
+    Skipped(String),
+    /// The input (after pre plugins) is not in the format the denoiser expects.
+    Invalid(String),
+    /// Denoising went wrong, with the class of the failure.
+    Failed(DenoiseError, String),
+}
+
+impl Outcome {
+    /// The failure `error` stands for, classified.
+    pub fn failed(error: &anyhow::Error) -> Self {
+        Outcome::Failed(DenoiseError::classify(error), format!("{error:#}"))
+    }
+
+    /// The status recorded by `--db` and `--report`, and the reason for it.
+    pub fn status(&self) -> (&'static str, Option<&str>) {
+        match self {
+            Outcome::Processed => ("done", None),
+            Outcome::Skipped(reason) => ("skipped", Some(reason)),
+            Outcome::Invalid(reason) => ("invalid", Some(reason)),
+            Outcome::Failed(_, reason) => ("failed", Some(reason)),
+        }
+    }
+
+    /// The failure class of invalid and failed files.
+    pub fn code(&self) -> Option<DenoiseError> {
+        match self {
+            Outcome::Invalid(_) => Some(DenoiseError::InvalidFormat),
+            Outcome::Failed(code, _) => Some(*code),
+            Outcome::Processed | Outcome::Skipped(_) => None,
+        }
+    }
+}
+
+/// The format the denoiser is fed. Inputs must match its channels and
+/// sample rate; other bit depths are converted to it and back.
+#[derive(clap::Args, Debug, Clone, Copy, Serialize)]
+pub struct TargetArgs {
+    /// Sample rate inputs must have
+    #[arg(long, value_name = "HZ", default_value_t = 16000)]
+    pub expect_rate: u32,
+
+    /// Channel count inputs must have
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    pub expect_channels: u16,
+
+    /// Bit depth the denoiser is fed (8, 16 or 24-bit PCM, or 32 for float)
+    #[arg(long, value_name = "BITS", default_value_t = 16,
+          value_parser = clap::builder::PossibleValuesParser::new(["8", "16", "24", "32"])
+              .map(|s| s.parse::<u16>().unwrap()))]
+    pub expect_bits: u16,
+}
+
+impl TargetArgs {
+    pub fn spec(&self) -> hound::WavSpec {
+        hound::WavSpec {
+            channels: self.expect_channels,
+            sample_rate: self.expect_rate,
+            bits_per_sample: self.expect_bits,
+            sample_format: if self.expect_bits == 32 {
+                hound::SampleFormat::Float
+            } else {
+                hound::SampleFormat::Int
+            },
+        }
+    }
+
+    /// Why audio with `channels` at `sample_rate` cannot go to the denoiser, if it cannot.
+    pub fn rejection(&self, channels: u16, sample_rate: u32) -> Option<String> {
+        let mut reasons = Vec::new();
+        if channels != self.expect_channels {
+            let hint = if self.expect_channels == 1 {
+                " (see --downmix and --channels)"
+            } else {
+                ""
+            };
+            reasons.push(format!(
+                "has {channels} channels; the denoiser needs {}{hint}",
+                self.expect_channels
+            ));
+        }
+        if sample_rate != self.expect_rate {
+            reasons.push(format!(
+                "is {sample_rate} Hz; the denoiser needs {} Hz",
+                self.expect_rate
+            ));
+        }
+        (!reasons.is_empty()).then(|| reasons.join(", and "))
+    }
+}
+
+/// Sample data per segment when RF64 inputs are split for the denoiser.
+const SEGMENT_BYTES: u64 = 1 << 30;
+
+/// Length of the crossfades that splice `--segments` regions back in.
+const REGION_CROSSFADE_SECS: f64 = 0.01;
+
+/// Reads the format of a WAV file.
+fn read_spec(path: &Path) -> Result<hound::WavSpec> {
+    let reader = hound::WavReader::open(path)
+        .with_context(|| format!("Failed to open WAV file: {}", path.display()))
+        .context(DenoiseError::InvalidFormat)?;
+    Ok(reader.spec())
+}
+
+/// A file between the steps of [`Pipeline::process`].
+pub struct Prepared {
+    input_path: PathBuf,
+    /// Where the output is written; for raw files its staged WAV.
+    output_path: PathBuf,
+    raw_output: Option<PathBuf>,
+    staged: Staged,
+    work: Work,
+}
+
+/// What is left to do for a [`Prepared`] file.
+enum Work {
+    /// A WAV file in the denoiser's format, for the backend.
+    Plain(Plain),
+    /// A source denoised piece by piece, with `--segments`, `--channels`,
+    /// `--downmix` or when it is RF64.
+    Whole(PathBuf),
+    /// Denoised into the output, or settled without denoising.
+    Done(Outcome),
+}
+
+/// One backend run and the file it gives.
+struct Plain {
+    /// What the backend reads.
+    source: PathBuf,
+    /// What the backend writes.
+    denoised: PathBuf,
+    /// The format to restore, when the input was converted.
+    original_spec: Option<hound::WavSpec>,
+}
+
+impl Plain {
+    /// Moves the denoised file to `output`, converted back to the input's format.
+    fn write(&self, output: &Path) -> Result<()> {
+        match self.original_spec {
+            Some(spec) => audio::convert(&self.denoised, output, spec, false),
+            None => scratch::persist(&self.denoised, output),
+        }
+    }
+}
+
+pub struct Pipeline {
+    /// The backend in use, as resolved by `--backend auto`.
+    kind: BackendKind,
+    model: Option<String>,
+    pre: Vec<Plugin>,
+    backend: Box<dyn Backend>,
+    /// Times the backend runs over each file, with `--passes`.
+    passes: usize,
+    post: Vec<Plugin>,
+    post_filters: Vec<PostFilter>,
+    reprocess_policy: ReprocessPolicy,
+    dither: bool,
+    decoder: Decoder,
+    tolerant_parse: bool,
+    coding_history: bool,
+    check_levels: bool,
+    remove_dc: bool,
+    dehum: Option<u32>,
+    /// Format of headerless inputs and outputs, with `--raw`.
+    raw: Option<hound::WavSpec>,
+    channels: Vec<u16>,
+    downmix: Option<Downmix>,
+    target: TargetArgs,
+    /// Where the regions to denoise come from, with `--segments`.
+    regions: Option<regions::Source>,
+    region_clips: bool,
+    /// Cuts outputs into clips at their pauses, with `--split-on-silence`.
+    splitter: Option<Splitter>,
+    /// Cuts long files into chunks denoised in parallel, with `--chunk-secs`.
+    chunker: Option<Chunker>,
+}
+
+impl Pipeline {
+    pub fn new(args: &PipelineArgs, models_dir: Option<&Path>) -> Result<Self> {
+        let stage_plugins = |stage| {
+            args.plugins
+                .iter()
+                .filter(|p| p.stage == stage)
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let mut backends = stage_plugins(Stage::Backend);
+        if backends.len() > 1 {
+            bail!("At most one backend plugin may be registered");
+        }
+        let plugin = backends.pop();
+
+        // A model name that matches a cached download is sent as its file path
+        let model = args.model.as_ref().map(|name| {
+            ModelStore::new(models_dir)
+                .ok()
+                .and_then(|store| store.resolve(name))
+                .map_or_else(|| name.clone(), |path| path.to_string_lossy().to_string())
+        });
+
+        let kind = match args.backend {
+            Some(BackendKind::Auto) => {
+                let (kind, reason) = backend::Probe {
+                    plugin: plugin.is_some(),
+                    addresses: &args.addr_api,
+                    model: model.as_deref(),
+                    command: args.backend_command.is_some(),
+                    stream: args.stream_command.is_some(),
+                    program: args.backend_program.as_deref(),
+                    args: args.backend_args.as_ref(),
+                }
+                .select()?;
+                let name = clap::ValueEnum::to_possible_value(&kind)
+                    .map_or_else(String::new, |v| v.get_name().to_string());
+                eprintln!("Backend: {name} ({reason})");
+                kind
+            }
+            Some(kind) => kind,
+            None if plugin.is_some() => BackendKind::Plugin,
+            None => BackendKind::Api,
+        };
+        if plugin.is_some() && kind != BackendKind::Plugin {
+            bail!("A backend plugin is registered but --backend selects another backend");
+        }
+        let regions = args
+            .segments
+            .as_deref()
+            .map(regions::Source::open)
+            .transpose()?;
+        if regions.is_some() && (!args.channels.is_empty() || args.downmix) {
+            bail!("--segments cannot be combined with --channels or --downmix");
+        }
+        if args.split.split_on_silence && args.raw.raw {
+            bail!("--split-on-silence cannot be combined with --raw");
+        }
+        if matches!(regions, Some(regions::Source::Cue)) && args.raw.raw {
+            bail!("--segments cue needs WAV inputs; raw files have no cue points");
+        }
+
+        if (args.backend_program.is_some() || args.backend_args.is_some())
+            && !matches!(kind, BackendKind::Rnnoise | BackendKind::Deepfilternet)
+        {
+            bail!(
+                "--backend-program and --backend-args apply to --backend rnnoise or deepfilternet"
+            );
+        }
+
+        let spec = args.target.spec();
+        let backend: Box<dyn Backend> = match kind {
+            BackendKind::Api => Box::new(backend::Api::new(&args.addr_api)?),
+            BackendKind::Plugin => Box::new(plugin.context(
+                "--backend plugin needs a plugin registered with --plugin backend=COMMAND",
+            )?),
+            BackendKind::Command => Box::new(
+                args.backend_command
+                    .clone()
+                    .context("--backend command needs --backend-command")?,
+            ),
+            BackendKind::Stream => Box::new(backend::Stream::new(
+                args.stream_command
+                    .as_deref()
+                    .context("--backend stream needs --stream-command")?,
+                spec,
+            )?),
+            BackendKind::Nnnoiseless => {
+                Box::new(backend::Nnnoiseless::new(model.as_deref(), args.overlap)?)
+            }
+            BackendKind::Auto => unreachable!("resolved above"),
+            BackendKind::Rnnoise => Box::new(backend::Rnnoise::new(
+                args.backend_program.as_deref(),
+                args.backend_args.clone(),
+            )),
+            BackendKind::Deepfilternet => Box::new(backend::DeepFilterNet::new(
+                args.backend_program.as_deref(),
+                args.backend_args.clone(),
+            )),
+            #[cfg(feature = "onnx")]
+            BackendKind::Onnx => Box::new(crate::onnx::Onnx::new(model.as_deref())?),
+            #[cfg(not(feature = "onnx"))]
+            BackendKind::Onnx => bail!("--backend onnx needs a build with --features onnx"),
+        };
+
+        // The gate comes last, so it listens to the filtered signal
+        let mut post_filters = args.post_filter.clone();
+        if let Some(db) = args.gate_db {
+            if !db.is_finite() || db >= 0.0 {
+                bail!("--gate-db must be below 0 dBFS");
+            }
+            post_filters.push(PostFilter::Gate {
+                db,
+                hold_ms: args.gate_hold_ms,
+            });
+        }
+
+        Ok(Pipeline {
+            kind,
+            model,
+            pre: stage_plugins(Stage::Pre),
+            backend,
+            passes: args.passes as usize,
+            post: stage_plugins(Stage::Post),
+            post_filters,
+            reprocess_policy: args.reprocess_policy,
+            dither: args.dither,
+            raw: args.raw.spec(),
+            decoder: args.decoder,
+            tolerant_parse: args.tolerant_parse,
+            coding_history: args.coding_history,
+            check_levels: args.check_levels,
+            remove_dc: args.remove_dc,
+            dehum: args.dehum,
+            channels: args.channels.clone(),
+            downmix: args.downmix.then_some(args.downmix_method),
+            target: args.target,
+            regions,
+            region_clips: args.segment_clips,
+            splitter: args.split.splitter(),
+            chunker: args.chunk.chunker()?,
+        })
+    }
+
+    /// Channels of the input kept in every output, or empty for all of them.
+    pub fn kept_channels(&self) -> &[u16] {
+        &self.channels
+    }
+
+    /// Whether the kept channels are mixed into a mono output.
+    pub fn downmixes(&self) -> bool {
+        self.downmix.is_some()
+    }
+
+    pub fn backend_kind(&self) -> BackendKind {
+        self.kind
+    }
+
+    /// The model asked for, with names of downloaded models resolved to their files.
+    pub fn model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+
+    /// Number of files worth processing at once when the user doesn't say.
+    pub fn default_jobs(&self) -> usize {
+        self.backend.default_jobs()
+    }
+
+    /// Denoises `input_path` into `output_path`, creating its parent directory.
+    pub fn process(&self, input_path: &Path, output_path: &Path) -> Result<Outcome> {
+        let mut prepared = self.prepare(input_path, output_path, &|| ())?;
+        self.denoise_prepared(&mut prepared, &|| ())?;
+        self.finish(prepared)
+    }
+
+    /// The first of the three steps of [`Pipeline::process`], which batch
+    /// runs take on stages of their own: decodes, repairs and converts the
+    /// input into the denoiser's format, running the pre plugins, and calls
+    /// `validated` once it has passed the format check.
+    pub fn prepare(
+        &self,
+        input_path: &Path,
+        output_path: &Path,
+        validated: &dyn Fn(),
+    ) -> Result<Prepared> {
+        let mut prepared = Prepared {
+            input_path: input_path.to_path_buf(),
+            output_path: output_path.to_path_buf(),
+            raw_output: None,
+            staged: Staged::default(),
+            work: Work::Done(Outcome::Processed),
+        };
+        if self.reprocess_policy != ReprocessPolicy::Allow && provenance::is_denoised(input_path) {
+            if self.reprocess_policy == ReprocessPolicy::Error {
+                bail!("Input is already denoised: {}", input_path.display());
+            }
+            eprintln!("Skipping already denoised file: {}", input_path.display());
+            prepared.work = Work::Done(Outcome::Skipped("already denoised".to_string()));
+            return Ok(prepared);
+        }
+
+        if let Some(parent) = output_path.parent() {
+            std::fs::create_dir_all(parent).with_context(|| {
+                format!(
+                    "Failed to create output directory for: {}",
+                    output_path.display()
+                )
+            })?;
+        }
+
+        let model = self.model.as_deref();
+        let staged = &mut prepared.staged;
+        let mut source = input_path.to_path_buf();
+        // Raw files go through the pipeline as WAV, and the output is unwrapped at the end
+        if let Some(spec) = self.raw {
+            let wrapped = staged.path(output_path, "raw");
+            raw::to_wav(&source, &wrapped, spec).context(DenoiseError::InvalidFormat)?;
+            source = wrapped;
+            prepared.output_path = staged.path(output_path, "wav");
+            prepared.raw_output = Some(output_path.to_path_buf());
+        }
+        let output_path = prepared.output_path.as_path();
+        if self.decoder == Decoder::Ffmpeg && !riff::is_wav(&source) {
+            let decoded = staged.path(output_path, "decoded");
+            decode::ffmpeg(&source, &decoded, self.target.spec())
+                .context(DenoiseError::DecoderFailed)?;
+            source = decoded;
+        }
+        if self.tolerant_parse && riff::is_wav(&source) {
+            let repaired = staged.path(output_path, "repaired");
+            let fixes = riff::repair(&source, &repaired).context(DenoiseError::InvalidFormat)?;
+            if !fixes.is_empty() {
+                eprintln!(
+                    "Warning: repaired {}: {}",
+                    input_path.display(),
+                    fixes.join("; ")
+                );
+                source = repaired;
+            }
+        }
+        for (i, plugin) in self.pre.iter().enumerate() {
+            let staged_path = staged.path(output_path, &format!("pre{i}"));
+            plugin
+                .run(&source, &staged_path, model)
+                .context(DenoiseError::PluginFailed)?;
+            source = staged_path;
+        }
+
+        // Regions, RF64 segments and single channels are cut up and converted piece by piece
+        let whole = self.regions.is_some()
+            || riff::is_rf64(&source)
+            || !self.channels.is_empty()
+            || (self.downmix.is_some() && read_spec(&source)?.channels != 1);
+        prepared.work = if whole {
+            Work::Whole(source)
+        } else {
+            match self.prepare_wav(input_path, &source, output_path, staged, validated)? {
+                Ok(plain) => Work::Plain(plain),
+                Err(outcome) => Work::Done(outcome),
+            }
+        };
+        Ok(prepared)
+    }
+
+    /// The second step of [`Pipeline::process`]: runs the backend and the
+    /// post filters, or with `--segments`, RF64 inputs, `--channels` and
+    /// `--downmix` the whole of the rest of the conversion.
+    pub fn denoise_prepared(&self, prepared: &mut Prepared, validated: &dyn Fn()) -> Result<()> {
+        match std::mem::replace(&mut prepared.work, Work::Done(Outcome::Processed)) {
+            Work::Plain(plain) => {
+                self.denoise_plain(&plain, &mut prepared.staged)?;
+                prepared.work = Work::Plain(plain);
+            }
+            Work::Whole(source) => {
+                let outcome = self.denoise_whole(prepared, &source, validated)?;
+                prepared.work = Work::Done(outcome);
+            }
+            work => prepared.work = work,
+        }
+        Ok(())
+    }
+
+    /// The last step of [`Pipeline::process`]: writes the output in the
+    /// input's format, runs the post plugins, tags its provenance and cuts
+    /// it into clips.
+    pub fn finish(&self, mut prepared: Prepared) -> Result<Outcome> {
+        let output_path = prepared.output_path.clone();
+        let output_path = output_path.as_path();
+        match std::mem::replace(&mut prepared.work, Work::Done(Outcome::Processed)) {
+            Work::Plain(plain) => plain.write(output_path)?,
+            Work::Done(Outcome::Processed) => {}
+            Work::Done(outcome) => return Ok(outcome),
+            Work::Whole(_) => bail!("{} was not denoised", prepared.input_path.display()),
+        }
+
+        let model = self.model.as_deref();
+        for (i, plugin) in self.post.iter().enumerate() {
+            let staged_path = prepared.staged.path(output_path, &format!("post{i}"));
+            plugin
+                .run(output_path, &staged_path, model)
+                .context(DenoiseError::PluginFailed)?;
+            scratch::persist(&staged_path, output_path)?;
+        }
+
+        // RF64 outputs are beyond what the WAV reader handles
+        if self.check_levels && !riff::is_rf64(output_path) {
+            match Levels::measure_file(output_path) {
+                Ok((levels, total)) => {
+                    for warning in levels.warnings(total) {
+                        eprintln!("Warning: output {}: {warning}", output_path.display());
+                    }
+                }
+                Err(e) => eprintln!("Warning: could not check levels: {e:#}"),
+            }
+        }
+
+        // Raw outputs have nowhere to keep provenance
+        if let Some(raw_output) = &prepared.raw_output {
+            raw::from_wav(output_path, raw_output)?;
+            return Ok(Outcome::Processed);
+        }
+        if let Err(e) = provenance::stamp(output_path, model) {
+            eprintln!(
+                "Warning: could not tag {} with provenance: {e:#}",
+                output_path.display()
+            );
+        }
+        if self.coding_history
+            && let Err(e) =
+                provenance::append_coding_history(&prepared.input_path, output_path, model)
+        {
+            eprintln!(
+                "Warning: could not add coding history to {}: {e:#}",
+                output_path.display()
+            );
+        }
+
+        if let Some(splitter) = &self.splitter {
+            let clips = splitter
+                .split(output_path)
+                .with_context(|| format!("Failed to split {}", output_path.display()))?;
+            for clip in &clips {
+                if let Err(e) = provenance::stamp(clip, model) {
+                    eprintln!(
+                        "Warning: could not tag {} with provenance: {e:#}",
+                        clip.display()
+                    );
+                }
+            }
+        }
+
+        Ok(Outcome::Processed)
+    }
+
+    /// Denoises the regions, segments or channels of `source` into the
+    /// output of `prepared`.
+    fn denoise_whole(
+        &self,
+        prepared: &mut Prepared,
+        source: &Path,
+        validated: &dyn Fn(),
+    ) -> Result<Outcome> {
+        let input_path = prepared.input_path.as_path();
+        let output_path = prepared.output_path.as_path();
+        let staged = &mut prepared.staged;
+        let Some(source_regions) = &self.regions else {
+            return if riff::is_rf64(source) {
+                self.denoise_segmented(input_path, source, output_path, staged, validated)
+            } else {
+                self.denoise(input_path, source, output_path, staged, validated)
+            };
+        };
+        if riff::is_rf64(source) {
+            bail!("--segments does not support RF64 inputs");
+        }
+        // Clips go beside the real output, not the staged WAV of a raw one
+        let beside = prepared.raw_output.as_deref().unwrap_or(output_path);
+        let stem = beside.file_stem().unwrap_or_default().to_string_lossy();
+        let plan: Vec<(Region, Option<PathBuf>)> = source_regions
+            .regions(input_path)?
+            .into_iter()
+            .enumerate()
+            .map(|(i, region)| {
+                let clip = self.region_clips.then(|| {
+                    let label = regions::file_label(&region.label);
+                    let name = match label.as_str() {
+                        "" => format!("{stem}_{:02}.wav", i + 1),
+                        label => format!("{stem}_{:02}_{label}.wav", i + 1),
+                    };
+                    beside.with_file_name(name)
+                });
+                (region, clip)
+            })
+            .collect();
+        self.denoise_regions(input_path, source, output_path, &plan, staged, validated)
+    }
+
+    /// Runs the backend on one WAV file, on its `--downmix` to mono, or on each
+    /// of its `--channels` in turn, interleaving the results into `output_path`.
+    fn denoise(
+        &self,
+        input_path: &Path,
+        source: &Path,
+        output_path: &Path,
+        staged: &mut Staged,
+        validated: &dyn Fn(),
+    ) -> Result<Outcome> {
+        if self.channels.is_empty() && (self.downmix.is_none() || read_spec(source)?.channels == 1)
+        {
+            return self.denoise_wav(input_path, source, output_path, staged, validated);
+        }
+        let audio = audio::read_wav(source)?;
+        let available = audio.spec.channels;
+        if let Some(&missing) = self.channels.iter().find(|&&c| c >= available) {
+            eprintln!("Skipping invalid WAV file: {}", input_path.display());
+            return Ok(Outcome::Invalid(format!(
+                "has {available} channels, so there is no channel {missing}"
+            )));
+        }
+
+        let mono_spec = hound::WavSpec {
+            channels: 1,
+            ..audio.spec
+        };
+        if let Some(method) = self.downmix {
+            let kept: Vec<usize> = match &self.channels[..] {
+                [] => (0..usize::from(available)).collect(),
+                channels => channels.iter().map(|&c| usize::from(c)).collect(),
+            };
+            let mixed = staged.path(output_path, "mix");
+            audio::write_spec(&mixed, &method.mix(&audio, &kept), mono_spec, false)?;
+            return self.denoise_wav(input_path, &mixed, output_path, staged, validated);
+        }
+        let mut denoised = Vec::new();
+        for (i, &c) in self.channels.iter().enumerate() {
+            let mono = staged.path(output_path, &format!("ch{c}"));
+            audio::write_spec(&mono, &audio.channel(usize::from(c)), mono_spec, false)?;
+            let out = staged.path(output_path, &format!("ch{c}.out"));
+            // Every channel shares the format of the first
+            let validated = if i == 0 { validated } else { &|| () };
+            let outcome = self.denoise_wav(input_path, &mono, &out, staged, validated)?;
+            if !matches!(outcome, Outcome::Processed) {
+                return Ok(outcome);
+            }
+            denoised.push(out);
+        }
+
+        if let [only] = &denoised[..] {
+            return scratch::persist(only, output_path).map(|()| Outcome::Processed);
+        }
+        let channels = denoised
+            .iter()
+            .map(|path| audio::read_wav(path))
+            .collect::<Result<Vec<_>>>()?;
+        let frames = channels.iter().map(|a| a.samples.len()).max().unwrap_or(0);
+        let mut interleaved = Vec::with_capacity(frames * channels.len());
+        for i in 0..frames {
+            for channel in &channels {
+                interleaved.push(channel.samples.get(i).copied().unwrap_or(0.0));
+            }
+        }
+        let spec = hound::WavSpec {
+            channels: channels.len() as u16,
+            ..channels[0].spec
+        };
+        audio::write_spec(output_path, &interleaved, spec, false)?;
+        Ok(Outcome::Processed)
+    }
+
+    /// Runs the backend on one WAV file, converting other bit depths to the
+    /// denoiser's format and back.
+    fn denoise_wav(
+        &self,
+        input_path: &Path,
+        source: &Path,
+        output_path: &Path,
+        staged: &mut Staged,
+        validated: &dyn Fn(),
+    ) -> Result<Outcome> {
+        match self.prepare_wav(input_path, source, output_path, staged, validated)? {
+            Ok(plain) => {
+                self.denoise_plain(&plain, staged)?;
+                plain.write(output_path)?;
+                Ok(Outcome::Processed)
+            }
+            Err(outcome) => Ok(outcome),
+        }
+    }
+
+    /// Checks one WAV file against the denoiser's format and converts it to
+    /// the file the backend reads, or gives the outcome of an invalid one.
+    fn prepare_wav(
+        &self,
+        input_path: &Path,
+        source: &Path,
+        output_path: &Path,
+        staged: &mut Staged,
+        validated: &dyn Fn(),
+    ) -> Result<Result<Plain, Outcome>> {
+        let mut source = source.to_path_buf();
+        // Extensible headers are rewritten as plain PCM, which every backend reads
+        let unwrapped = staged.path(output_path, "plain");
+        if riff::unwrap_extensible(&source, &unwrapped)? {
+            source = unwrapped;
+        }
+        let spec = read_spec(&source)?;
+        if let Some(reason) = self.target.rejection(spec.channels, spec.sample_rate) {
+            eprintln!(
+                "Skipping invalid WAV file {}: {reason}",
+                input_path.display()
+            );
+            return Ok(Err(Outcome::Invalid(reason)));
+        }
+        if hound::WavReader::open(&source)?.duration() == 0 {
+            let reason = "the data chunk holds no samples".to_string();
+            eprintln!(
+                "Skipping invalid WAV file {}: {reason}",
+                input_path.display()
+            );
+            return Ok(Err(Outcome::Invalid(reason)));
+        }
+        validated();
+
+        if self.check_levels || self.remove_dc {
+            let (levels, total) = Levels::measure_file(&source)?;
+            if self.check_levels {
+                for warning in levels.warnings(total) {
+                    eprintln!("Warning: input {}: {warning}", input_path.display());
+                }
+                if levels.clipped > 0 {
+                    eprintln!(
+                        "  Clipped inputs rarely denoise well; consider re-recording or declipping first"
+                    );
+                }
+            }
+            if self.remove_dc && levels.has_dc_offset() {
+                let channels = levels.dc_offsets.len();
+                let centered = staged.path(output_path, "dc");
+                let mut reader = audio::FrameReader::open(&source)?;
+                let mut writer = audio::FrameWriter::create(&centered, spec, false)?;
+                while let Some(mut block) = reader.next_block()? {
+                    for (i, s) in block.iter_mut().enumerate() {
+                        *s = (f64::from(*s) - levels.dc_offsets[i % channels]) as f32;
+                    }
+                    writer.write(&block)?;
+                }
+                writer.finish()?;
+                source = centered;
+            }
+        }
+        if let Some(mains) = self.dehum {
+            let dehummed = staged.path(output_path, "dehum");
+            filter::dehum_file(&source, &dehummed, mains)?;
+            source = dehummed;
+        }
+
+        // Other bit depths are denoised at --expect-bits and restored afterwards
+        let target = self.target.spec();
+        let original_spec = (spec != target).then_some(spec);
+        if original_spec.is_some() {
+            let converted = staged.path(output_path, "converted");
+            audio::convert(&source, &converted, target, self.dither)?;
+            source = converted;
+        }
+        // The backend writes beside the output, and the result is moved over it once complete
+        Ok(Ok(Plain {
+            source,
+            denoised: staged.path(output_path, "denoised"),
+            original_spec,
+        }))
+    }
+
+    /// Runs the backend and the post filters from `plain.source` into `plain.denoised`.
+    fn denoise_plain(&self, plain: &Plain, staged: &mut Staged) -> Result<()> {
+        self.run_backend(&plain.source, &plain.denoised, staged)?;
+        if !self.post_filters.is_empty() {
+            let filtered = staged.path(&plain.denoised, "filtered");
+            filter::apply_file(&plain.denoised, &filtered, &self.post_filters)?;
+            scratch::persist(&filtered, &plain.denoised)?;
+        }
+        Ok(())
+    }
+
+    /// Runs the backend on `source`, or with `--chunk-secs` on overlapping
+    /// chunks of it in parallel, stitching their results into `output`.
+    fn run_backend(&self, source: &Path, output: &Path, staged: &mut Staged) -> Result<()> {
+        let ranges = match &self.chunker {
+            Some(chunker) => chunker.plan(source)?,
+            None => Vec::new(),
+        };
+        // Passes that cannot stay in memory go through files of their own
+        let mut passes = |tag: &str| -> Vec<PathBuf> {
+            (1..self.passes)
+                .map(|p| staged.path(output, &format!("{tag}pass{p}")))
+                .collect()
+        };
+        if ranges.len() < 2 {
+            let between = passes("");
+            return self
+                .denoise_passes(source, output, &between)
+                .context(DenoiseError::BackendFailed);
+        }
+        let between: Vec<Vec<PathBuf>> = (0..ranges.len())
+            .map(|i| passes(&format!("chunk{i}.")))
+            .collect();
+        let pieces: Vec<PathBuf> = (0..ranges.len())
+            .map(|i| staged.path(output, &format!("chunk{i}")))
+            .collect();
+        let outputs: Vec<PathBuf> = (0..ranges.len())
+            .map(|i| staged.path(output, &format!("chunk{i}.out")))
+            .collect();
+        chunk::cut(source, &ranges, &pieces)?;
+        let log = capture::current();
+        pieces
+            .par_iter()
+            .zip(&outputs)
+            .zip(&between)
+            .try_for_each(|((piece, out), between)| {
+                let result =
+                    capture::collect(log.clone(), || self.denoise_passes(piece, out, between))
+                        .context(DenoiseError::BackendFailed);
+                let _ = std::fs::remove_file(piece);
+                result
+            })?;
+        chunk::stitch(&outputs, &ranges, output)
+    }
+
+    /// Runs the backend `--passes` times from `input` to `output`, in memory
+    /// where the backend can, else through the files `between`.
+    fn denoise_passes(&self, input: &Path, output: &Path, between: &[PathBuf]) -> Result<()> {
+        let model = self.model.as_deref();
+        if let Some(result) = self
+            .backend
+            .denoise_passes(input, output, model, self.passes)
+        {
+            return result;
+        }
+        let mut from = input;
+        for (i, to) in between
+            .iter()
+            .map(PathBuf::as_path)
+            .chain([output])
+            .enumerate()
+        {
+            self.backend.denoise(from, to, model)?;
+            if i > 0 {
+                let _ = std::fs::remove_file(from);
+            }
+            from = to;
+        }
+        Ok(())
+    }
+
+    /// Denoises an RF64 file in segments small enough for plain WAV tools and
+    /// joins the results, writing RF64 again when needed.
+    fn denoise_segmented(
+        &self,
+        input_path: &Path,
+        source: &Path,
+        output_path: &Path,
+        staged: &mut Staged,
+        validated: &dyn Fn(),
+    ) -> Result<Outcome> {
+        let segments = riff::split(source, SEGMENT_BYTES, |i| {
+            staged.path(output_path, &format!("seg{i}"))
+        })?;
+        let mut denoised = Vec::new();
+        for (i, segment) in segments.iter().enumerate() {
+            let out = staged.path(output_path, &format!("seg{i}.out"));
+            // Every segment shares the format of the first
+            let validated = if i == 0 { validated } else { &|| () };
+            let outcome = self.denoise(input_path, segment, &out, staged, validated)?;
+            if !matches!(outcome, Outcome::Processed) {
+                return Ok(outcome);
+            }
+            // Free the disk space of each segment as soon as it is done
+            let _ = std::fs::remove_file(segment);
+            denoised.push(out);
+        }
+        riff::join(&denoised, output_path)?;
+        Ok(Outcome::Processed)
+    }
+
+    /// Denoises only the `--segments` regions of `source`, splicing each back
+    /// into the untouched audio with short crossfades, and copies each
+    /// denoised region to its clip path when it has one.
+    fn denoise_regions(
+        &self,
+        input_path: &Path,
+        source: &Path,
+        output_path: &Path,
+        plan: &[(Region, Option<PathBuf>)],
+        staged: &mut Staged,
+        validated: &dyn Fn(),
+    ) -> Result<Outcome> {
+        let mut audio = audio::read_wav(source)?;
+        let channels = usize::from(audio.spec.channels.max(1));
+        let frames = audio.samples.len() / channels;
+        let rate = f64::from(audio.spec.sample_rate);
+        if plan.is_empty() {
+            eprintln!(
+                "No labelled regions in {}; copying it unchanged",
+                input_path.display()
+            );
+        }
+
+        let mut first = true;
+        for (i, (region, clip)) in plan.iter().enumerate() {
+            let start = ((region.start * rate).round() as usize).min(frames);
+            let end = ((region.end * rate).round() as usize).min(frames);
+            if start >= end {
+                eprintln!(
+                    "Warning: region {:.3}-{:.3}s is past the end of {}",
+                    region.start,
+                    region.end,
+                    input_path.display()
+                );
+                continue;
+            }
+            let piece = staged.path(output_path, &format!("region{i}"));
+            let samples = &audio.samples[start * channels..end * channels];
+            audio::write_spec(&piece, samples, audio.spec, false)?;
+            let out = staged.path(output_path, &format!("region{i}.out"));
+            // Every region shares the format of the first
+            let validated = if first { validated } else { &|| () };
+            first = false;
+            let outcome = self.denoise(input_path, &piece, &out, staged, validated)?;
+            if !matches!(outcome, Outcome::Processed) {
+                return Ok(outcome);
+            }
+
+            let denoised = audio::read_wav(&out)?;
+            if denoised.spec.channels != audio.spec.channels {
+                bail!(
+                    "The denoiser returned {} channels for a {channels}-channel region",
+                    denoised.spec.channels
+                );
+            }
+            let len = end - start;
+            let fade = ((rate * REGION_CROSSFADE_SECS) as usize).clamp(1, (len / 2).max(1));
+            for f in 0..len.min(denoised.samples.len() / channels) {
+                let weight = ((f + 1).min(len - f) as f32 / fade as f32).min(1.0);
+                for c in 0..channels {
+                    let sample = &mut audio.samples[(start + f) * channels + c];
+                    *sample += (denoised.samples[f * channels + c] - *sample) * weight;
+                }
+            }
+            if let Some(clip) = clip {
+                std::fs::copy(&out, clip)
+                    .with_context(|| format!("Failed to write clip: {}", clip.display()))?;
+            }
+        }
+        audio::write_spec(output_path, &audio.samples, audio.spec, false)?;
+        Ok(Outcome::Processed)
+    }
+}
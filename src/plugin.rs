@@ -0,0 +1,162 @@
+//! JSON-over-stdio plugin protocol.
+//!
+//! A plugin is any executable that reads one JSON request per line on stdin and
+//! answers each with one JSON response line on stdout. Requests look like
+//! `{"stage":"pre","input":"/abs/in.wav","output":"/abs/out.wav","model":null}`
+//! and the plugin replies with `{"ok":true}` or `{"ok":false,"error":"..."}`.
+//! Every stage reads `input` and writes its result to `output`; anything the
+//! plugin prints to stderr is passed through to the console, unless
+//! `--capture-backend-output` collects it (see [`crate::capture`]).
+
+use crate::capture;
+use crate::scratch;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+/// Where in the per-file pipeline a plugin runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    /// Transforms the input before validation and denoising.
+    Pre,
+    /// Replaces the API call as the denoiser.
+    Backend,
+    /// Transforms the denoised output.
+    Post,
+}
+
+/// An external program registered for one pipeline stage.
+#[derive(Debug, Clone, Serialize)]
+pub struct Plugin {
+    pub stage: Stage,
+    program: String,
+    args: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    stage: Stage,
+    input: &'a Path,
+    output: &'a Path,
+    model: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    ok: bool,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+impl FromStr for Plugin {
+    type Err = String;
+
+    /// Parses `STAGE=COMMAND`, e.g. `pre=python3 trim_silence.py --db -40`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (stage, command) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected STAGE=COMMAND, got '{s}'"))?;
+        let stage = match stage.trim() {
+            "pre" => Stage::Pre,
+            "backend" => Stage::Backend,
+            "post" => Stage::Post,
+            other => {
+                return Err(format!(
+                    "unknown plugin stage '{other}' (pre, backend, post)"
+                ));
+            }
+        };
+        let mut words = shlex::split(command)
+            .filter(|w| !w.is_empty())
+            .ok_or_else(|| format!("invalid plugin command: '{command}'"))?
+            .into_iter();
+        let program = words
+            .next()
+            .ok_or_else(|| format!("empty plugin command for stage '{s}'"))?;
+        Ok(Plugin {
+            stage,
+            program,
+            args: words.collect(),
+        })
+    }
+}
+
+impl Plugin {
+    /// Runs the plugin once, asking it to turn `input` into `output`.
+    pub fn run(&self, input: &Path, output: &Path, model: Option<&str>) -> Result<()> {
+        let mut child = capture::pipe_stderr(
+            Command::new(&self.program)
+                .args(&self.args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped()),
+        )
+        .spawn()
+        .with_context(|| format!("Failed to start plugin: {}", self.program))?;
+        let stderr = capture::Stderr::start(&mut child);
+
+        let request = Request {
+            stage: self.stage,
+            input,
+            output,
+            model,
+        };
+        {
+            let mut stdin = child.stdin.take().context("Plugin stdin unavailable")?;
+            let mut line = serde_json::to_vec(&request)?;
+            line.push(b'\n');
+            stdin
+                .write_all(&line)
+                .with_context(|| format!("Failed to send request to plugin: {}", self.program))?;
+        }
+
+        let mut reply = String::new();
+        let stdout = child.stdout.take().context("Plugin stdout unavailable")?;
+        BufReader::new(stdout)
+            .read_line(&mut reply)
+            .with_context(|| format!("Failed to read reply from plugin: {}", self.program))?;
+        let status = child.wait()?;
+        stderr.finish();
+
+        if reply.trim().is_empty() {
+            bail!("Plugin {} exited ({status}) without a reply", self.program);
+        }
+        let response: Response = serde_json::from_str(reply.trim())
+            .with_context(|| format!("Malformed reply from plugin {}: {reply}", self.program))?;
+        if !response.ok {
+            bail!(
+                "Plugin {} failed: {}",
+                self.program,
+                response.error.as_deref().unwrap_or("no error message")
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Temporary files created next to an output (or in its `--temp-dir` worker
+/// directory) while plugin stages run; removed on drop.
+#[derive(Default)]
+pub struct Staged(Vec<PathBuf>);
+
+impl Staged {
+    /// Returns a fresh hidden path beside `output`, tagged with `tag`.
+    pub fn path(&mut self, output: &Path, tag: &str) -> PathBuf {
+        let name = output.file_name().unwrap_or_default().to_string_lossy();
+        let path = scratch::path(&format!("{name}.{tag}.tmp.wav"))
+            .unwrap_or_else(|| output.with_file_name(format!(".{name}.{tag}.tmp.wav")));
+        self.0.push(path.clone());
+        path
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
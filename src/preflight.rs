@@ -0,0 +1,106 @@
+//! The summary printed before a batch starts: how many files and how much
+//! audio it covers and roughly how long it will take, so a run of a week is
+//! not launched by accident.
+
+use crate::space::ByteSize;
+use crate::walk;
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::io::Write;
+use walkdir::DirEntry;
+
+/// Estimated run time above which an interactive run asks before starting.
+pub const CONFIRM_ABOVE_SECS: f64 = 3600.0;
+
+/// What a run is about to process.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Preflight {
+    pub files: usize,
+    pub bytes: u64,
+    pub audio_secs: f64,
+    /// Inputs whose length their header does not tell, e.g. ones to decode.
+    pub unknown_length: usize,
+    pub workers: usize,
+    /// Assumed realtime factor of one worker.
+    pub speed: f64,
+    pub estimated_secs: f64,
+}
+
+impl Preflight {
+    /// Sizes up `files` for `workers` workers that each denoise `speed`
+    /// times faster than realtime.
+    pub fn measure(files: &[DirEntry], workers: usize, speed: f64) -> Self {
+        let (bytes, audio_secs, unknown_length) = files
+            .par_iter()
+            .map(|e| {
+                let bytes = e.metadata().map_or(0, |m| m.len());
+                match walk::wav_duration(e.path()) {
+                    Some(secs) => (bytes, secs, 0),
+                    None => (bytes, 0.0, 1),
+                }
+            })
+            .reduce(|| (0, 0.0, 0), |a, b| (a.0 + b.0, a.1 + b.1, a.2 + b.2));
+        let workers = workers.max(1);
+        Preflight {
+            files: files.len(),
+            bytes,
+            audio_secs,
+            unknown_length,
+            workers,
+            speed,
+            estimated_secs: audio_secs / (speed * workers as f64),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let unknown = if self.unknown_length > 0 {
+            format!(" ({} of unknown length)", self.unknown_length)
+        } else {
+            String::new()
+        };
+        format!(
+            "About to denoise {} files, {} of audio{unknown}, {}; at {}x realtime per worker with {} workers, about {}.",
+            self.files,
+            span(self.audio_secs),
+            ByteSize(self.bytes),
+            self.speed,
+            self.workers,
+            span(self.estimated_secs)
+        )
+    }
+
+    /// Asks on the terminal whether to go ahead.
+    pub fn confirm(&self) -> Result<bool> {
+        ask(&format!(
+            "This run will take about {}. Continue? [y/N] ",
+            span(self.estimated_secs)
+        ))
+    }
+}
+
+/// Asks a yes/no `question` on the terminal; anything but yes is no.
+pub fn ask(question: &str) -> Result<bool> {
+    eprint!("{question}");
+    std::io::stderr().flush()?;
+    let mut reply = String::new();
+    std::io::stdin()
+        .read_line(&mut reply)
+        .context("Failed to read the answer")?;
+    Ok(matches!(reply.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Formats seconds in the two largest units that matter, e.g. `2 d 5 h`.
+fn span(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    let (days, hours, mins) = (secs / 86400, secs % 86400 / 3600, secs % 3600 / 60);
+    if days > 0 {
+        format!("{days} d {hours} h")
+    } else if hours > 0 {
+        format!("{hours} h {mins} min")
+    } else if mins > 0 {
+        format!("{mins} min")
+    } else {
+        format!("{secs} s")
+    }
+}
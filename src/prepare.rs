@@ -0,0 +1,155 @@
+//! The `prepare` subcommand, the first half of a run split in two: inputs are
+//! decoded, rewritten as plain PCM and checked against the denoiser's format
+//! into a staging directory, e.g. on the host that holds the recordings. A
+//! `denoise` run, e.g. on a compute node, then takes the staging directory as
+//! its input and has only the denoising left to do.
+
+use crate::decode::{self, Decoder};
+use crate::pipeline::TargetArgs;
+use crate::riff;
+use crate::walk::{self, SymlinkPolicy, WalkOptions};
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[derive(clap::Args, Debug)]
+pub struct PrepareArgs {
+    /// Directory of the inputs (processed recursively)
+    input_dir: PathBuf,
+
+    /// Directory to stage the prepared WAV files in, mirroring the input tree
+    staging_dir: PathBuf,
+
+    /// Comma-separated file extensions to prepare, matched case-sensitively
+    #[arg(long, value_delimiter = ',', default_value = "wav")]
+    extensions: Vec<String>,
+
+    /// How inputs are read; with ffmpeg, non-WAV inputs are decoded to the denoiser's format
+    #[arg(long, value_enum, default_value_t = Decoder::Builtin)]
+    decoder: Decoder,
+
+    /// Stage inputs whatever their channel count, for a denoise run with --downmix or --channels
+    #[arg(long)]
+    any_channels: bool,
+
+    /// Files to prepare at once [default: number of CPUs]
+    #[arg(long, value_name = "N")]
+    jobs: Option<usize>,
+
+    #[command(flatten)]
+    target: TargetArgs,
+}
+
+/// What became of one input.
+enum Prepared {
+    Staged,
+    /// The staged file is newer than the input, from an earlier run.
+    Current,
+    Rejected(String),
+}
+
+/// Stages every input; exits with 1 when any could not be staged.
+pub fn run(args: &PrepareArgs) -> Result<ExitCode> {
+    let discovered = walk::discover(
+        &args.input_dir,
+        &WalkOptions {
+            follow_symlinks: false,
+            symlink_files: SymlinkPolicy::Process,
+            max_depth: None,
+            extensions: args.extensions.clone(),
+        },
+    );
+    for (path, reason) in &discovered.unreadable {
+        eprintln!("Error reading {}: {reason}", path.display());
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs.unwrap_or(0))
+        .build()?;
+    let results: Vec<Result<Prepared>> = pool.install(|| {
+        discovered
+            .files
+            .par_iter()
+            .map(|entry| {
+                let input = entry.path();
+                let relative = input.strip_prefix(&args.input_dir).unwrap_or(input);
+                let staged = args.staging_dir.join(relative).with_extension("wav");
+                let result = prepare(args, input, &staged);
+                match &result {
+                    Ok(Prepared::Rejected(reason)) => {
+                        eprintln!("Skipping invalid file {}: {reason}", input.display());
+                    }
+                    Err(e) => eprintln!("Error preparing {}: {e:#}", input.display()),
+                    Ok(_) => {}
+                }
+                result
+            })
+            .collect()
+    });
+
+    let count =
+        |f: fn(&Prepared) -> bool| results.iter().filter(|r| r.as_ref().is_ok_and(f)).count();
+    let staged = count(|p| matches!(p, Prepared::Staged));
+    let current = count(|p| matches!(p, Prepared::Current));
+    let rejected = count(|p| matches!(p, Prepared::Rejected(_)));
+    let failed = results.iter().filter(|r| r.is_err()).count() + discovered.unreadable.len();
+    println!(
+        "Staged {staged} files in {} ({current} already staged), {rejected} rejected, {failed} failed.",
+        args.staging_dir.display()
+    );
+    Ok(if rejected + failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Writes `input` to `staged` as plain PCM WAV in the denoiser's sample rate
+/// and channel count, unless a staged file newer than the input is there.
+fn prepare(args: &PrepareArgs, input: &Path, staged: &Path) -> Result<Prepared> {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    if let (Some(input_time), Some(staged_time)) = (modified(input), modified(staged))
+        && staged_time >= input_time
+    {
+        return Ok(Prepared::Current);
+    }
+    if let Some(parent) = staged.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    // Written under a name no WAV walk matches, so a denoise run never picks up half a file
+    let mut partial = staged.as_os_str().to_owned();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+
+    let result = (|| {
+        if riff::is_wav(input) {
+            let format = riff::format(input)?;
+            let channels = if args.any_channels {
+                args.target.expect_channels
+            } else {
+                format.channels
+            };
+            if let Some(reason) = args.target.rejection(channels, format.sample_rate) {
+                // A file staged from an earlier version of the input is no longer wanted
+                let _ = std::fs::remove_file(staged);
+                return Ok(Prepared::Rejected(reason));
+            }
+            if !riff::unwrap_extensible(input, &partial)? {
+                std::fs::copy(input, &partial)
+                    .with_context(|| format!("Failed to write file: {}", partial.display()))?;
+            }
+        } else if args.decoder == Decoder::Ffmpeg {
+            decode::ffmpeg(input, &partial, args.target.spec())?;
+        } else {
+            return Ok(Prepared::Rejected(
+                "is not a WAV file (see --decoder ffmpeg)".to_string(),
+            ));
+        }
+        std::fs::rename(&partial, staged)
+            .with_context(|| format!("Failed to write file: {}", staged.display()))?;
+        Ok(Prepared::Staged)
+    })();
+    let _ = std::fs::remove_file(&partial);
+    result
+}
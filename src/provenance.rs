@@ -0,0 +1,254 @@
+//! Provenance tags embedded in denoised outputs.
+//!
+//! Every output gets a `LIST/INFO` chunk naming this tool in `ISFT`, which lets a
+//! later run recognise files that have already been denoised. With `--sidecar`
+//! the same facts, and the settings used, are also written to a JSON file
+//! beside each output, and `--coding-history` records the step in the BWF
+//! `bext` chunk broadcast tools read.
+
+use crate::loudness::Loudness;
+use crate::noise::NoiseProfile;
+use crate::quality::Quality;
+use crate::{clock, riff};
+use anyhow::{Context, Result};
+use clap::{ArgMatches, ValueEnum};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What to do with inputs that carry our provenance tag.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReprocessPolicy {
+    /// Leave already denoised inputs alone
+    Skip,
+    /// Denoise them again
+    Allow,
+    /// Abort the run before processing anything
+    Error,
+}
+
+fn software() -> String {
+    format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+}
+
+/// What a batch run was and everything that shaped its outputs, recorded in
+/// the `--report` and the sidecars so an output can be traced to its run.
+#[derive(Serialize)]
+pub struct RunInfo {
+    /// A random (version 4) UUID.
+    pub id: String,
+    pub tool: String,
+    /// The backend that ran, after `--backend auto` chose one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    /// The model asked for, with names of downloaded models resolved to their files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// SHA-256 of the model file, when the model is a file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_sha256: Option<String>,
+    /// Every option after the config file, the environment and the command
+    /// line are merged, defaults included; secrets are left out.
+    pub config: BTreeMap<String, serde_json::Value>,
+}
+
+/// Options whose values are never recorded.
+const SECRET_WORDS: [&str; 4] = ["token", "secret", "password", "key"];
+
+impl RunInfo {
+    /// A new run with the options the top-level command line was parsed into.
+    pub fn new(matches: &ArgMatches) -> Self {
+        let mut config = BTreeMap::new();
+        for id in matches.ids() {
+            let id = id.as_str();
+            // Argument groups have no values of their own
+            let Ok(Some(values)) = matches.try_get_raw(id) else {
+                continue;
+            };
+            let value = if SECRET_WORDS.iter().any(|word| id.contains(word)) {
+                "(redacted)".into()
+            } else {
+                let mut values: Vec<serde_json::Value> = values
+                    .map(|v| v.to_string_lossy().into_owned().into())
+                    .collect();
+                match values.len() {
+                    1 => values.pop().unwrap(),
+                    _ => values.into(),
+                }
+            };
+            config.insert(id.to_string(), value);
+        }
+        RunInfo {
+            id: uuid_v4(),
+            tool: software(),
+            backend: None,
+            model: None,
+            model_sha256: None,
+            config,
+        }
+    }
+}
+
+/// A random UUID, from the standard library's per-process random hash keys
+/// mixed with the clock and the process ID.
+fn uuid_v4() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos()),
+    );
+    hasher.write_u32(std::process::id());
+    let mut rng = crate::rng::Rng::new(hasher.finish());
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&rng.next_u64().to_le_bytes());
+    bytes[8..].copy_from_slice(&rng.next_u64().to_le_bytes());
+    bytes[6] = bytes[6] & 0x0f | 0x40;
+    bytes[8] = bytes[8] & 0x3f | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Returns whether `path` was written by this tool.
+pub fn is_denoised(path: &Path) -> bool {
+    // Anything that is not a readable RIFF file cannot carry our tag
+    riff::read_info(path).is_ok_and(|entries| {
+        entries
+            .iter()
+            .any(|(tag, value)| tag == b"ISFT" && value.starts_with(env!("CARGO_PKG_NAME")))
+    })
+}
+
+/// Tags a finished output with the tool version and model.
+pub fn stamp(path: &Path, model: Option<&str>) -> Result<()> {
+    let comment = match model {
+        Some(model) => format!("Denoised with model {model}"),
+        None => "Denoised".to_string(),
+    };
+    riff::append_info(path, &[(*b"ISFT", software()), (*b"ICMT", comment)])
+}
+
+/// Contents of an `--sidecar` file.
+#[derive(Serialize)]
+struct Sidecar<'a, S: Serialize> {
+    source: &'a Path,
+    /// UTC, ISO 8601.
+    processed_at: String,
+    tool: String,
+    model: Option<&'a str>,
+    settings: &'a S,
+    run: &'a RunInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    loudness: Option<Loudness>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    noise: Option<NoiseProfile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quality: Option<Quality>,
+}
+
+/// Path of the sidecar of `output`: its full name with `.json` appended.
+fn sidecar_path(output: &Path) -> PathBuf {
+    let mut name = output.as_os_str().to_owned();
+    name.push(".json");
+    PathBuf::from(name)
+}
+
+/// Writes the sidecar of `output`, recording `source` it was denoised from,
+/// the `model` and `settings`, the `run`, and the output's loudness, the
+/// input's noise and the scores against the reference when measured.
+pub fn write_sidecar(
+    output: &Path,
+    source: &Path,
+    model: Option<&str>,
+    settings: &impl Serialize,
+    run: &RunInfo,
+    (loudness, noise, quality): (Option<Loudness>, Option<NoiseProfile>, Option<Quality>),
+) -> Result<()> {
+    let sidecar = Sidecar {
+        source,
+        processed_at: clock::iso8601_now(),
+        tool: software(),
+        model,
+        settings,
+        run,
+        loudness,
+        noise,
+        quality,
+    };
+    let path = sidecar_path(output);
+    std::fs::write(&path, serde_json::to_vec_pretty(&sidecar)?)
+        .with_context(|| format!("Failed to write sidecar: {}", path.display()))
+}
+
+/// Size of the fixed part of a `bext` chunk, before the CodingHistory text.
+const BEXT_FIXED: usize = 602;
+/// Offsets of fields in the fixed part of a `bext` chunk (EBU Tech 3285).
+const BEXT_ORIGINATOR: usize = 256;
+const BEXT_ORIGINATION_DATE: usize = 320;
+const BEXT_ORIGINATION_TIME: usize = 330;
+const BEXT_VERSION: usize = 346;
+
+/// Gives `output` the `bext` chunk of `input` (or a new one) with a
+/// CodingHistory line for the denoising step appended.
+pub fn append_coding_history(input: &Path, output: &Path, model: Option<&str>) -> Result<()> {
+    let (date, time) = clock::utc_now();
+    let date = format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]);
+    let existing = match riff::read_chunk(input, b"bext") {
+        Ok(Some(bext)) => Some(bext),
+        // The backend may have copied it already, or the input may not be a WAV file
+        _ => riff::read_chunk(output, b"bext")?,
+    };
+    let mut bext = match existing {
+        Some(mut bext) if bext.len() >= BEXT_FIXED => {
+            // CodingHistory is NUL-padded; the new line follows the last one
+            while bext.len() > BEXT_FIXED && bext.last() == Some(&0) {
+                bext.pop();
+            }
+            bext
+        }
+        _ => {
+            let mut bext = vec![0u8; BEXT_FIXED];
+            let put = |bext: &mut Vec<u8>, at: usize, len: usize, text: &str| {
+                let bytes = &text.as_bytes()[..text.len().min(len)];
+                bext[at..at + bytes.len()].copy_from_slice(bytes);
+            };
+            put(&mut bext, BEXT_ORIGINATOR, 32, env!("CARGO_PKG_NAME"));
+            put(&mut bext, BEXT_ORIGINATION_DATE, 10, &date);
+            let time = format!("{}:{}:{}", &time[..2], &time[2..4], &time[4..]);
+            put(&mut bext, BEXT_ORIGINATION_TIME, 8, &time);
+            bext[BEXT_VERSION..BEXT_VERSION + 2].copy_from_slice(&1u16.to_le_bytes());
+            bext
+        }
+    };
+
+    let reader = hound::WavReader::open(output)
+        .with_context(|| format!("Failed to open WAV file: {}", output.display()))?;
+    let spec = reader.spec();
+    let mode = match spec.channels {
+        1 => "mono".to_string(),
+        2 => "stereo".to_string(),
+        n => format!("{n}ch"),
+    };
+    let line = format!(
+        "A=PCM,F={},W={},M={mode},T={}; model={}; date={date}\r\n",
+        spec.sample_rate,
+        spec.bits_per_sample,
+        software(),
+        model.unwrap_or("default")
+    );
+    bext.extend_from_slice(line.as_bytes());
+
+    riff::junk_chunks(output, b"bext")?;
+    riff::append_chunk(output, b"bext", &bext)
+}
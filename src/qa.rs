@@ -0,0 +1,251 @@
+//! Review artifacts written next to the denoised outputs, and checks of the
+//! audio going in and coming out.
+
+use crate::audio::{self, write_wav};
+use crate::dsp;
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+
+/// Writes a 16-bit stereo file with `original` on the left and `denoised` on the right.
+///
+/// The shorter signal is padded with silence so both stay aligned from the start.
+pub fn write_ab(original: &Path, denoised: &Path, dest: &Path) -> Result<()> {
+    let before = audio::read_wav(original)?;
+    let after = audio::read_wav(denoised)?;
+    if before.spec.sample_rate != after.spec.sample_rate {
+        bail!(
+            "Sample rates differ ({} Hz vs {} Hz)",
+            before.spec.sample_rate,
+            after.spec.sample_rate
+        );
+    }
+    let (left, right) = (before.channel(0), after.channel(0));
+
+    let len = left.len().max(right.len());
+    let mut interleaved = Vec::with_capacity(len * 2);
+    for i in 0..len {
+        interleaved.push(left.get(i).copied().unwrap_or(0.0));
+        interleaved.push(right.get(i).copied().unwrap_or(0.0));
+    }
+
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    write_wav(dest, &interleaved, after.spec.sample_rate, 16, 2)
+}
+
+/// FFT size of the spectrogram frames; the image is half as tall.
+const FFT_SIZE: usize = 512;
+/// Widest spectrogram drawn; longer files use a larger hop between frames.
+const MAX_COLUMNS: usize = 2048;
+/// Lowest level drawn, in dBFS; anything quieter is black.
+const FLOOR_DB: f32 = -100.0;
+
+/// Renders spectrograms of `original` (top) and `denoised` (bottom) into one PNG.
+pub fn write_spectrograms(original: &Path, denoised: &Path, dest: &Path) -> Result<()> {
+    let panels: Vec<Vec<Vec<f32>>> = [original, denoised]
+        .iter()
+        .map(|path| {
+            let samples = audio::read_wav(path)?.channel(0);
+            let hop = samples.len().div_ceil(MAX_COLUMNS).max(FFT_SIZE / 4);
+            Ok(dsp::spectrogram(&samples, FFT_SIZE, hop))
+        })
+        .collect::<Result<_>>()?;
+
+    const GAP: usize = 4;
+    let bins = FFT_SIZE / 2;
+    let width = panels.iter().map(Vec::len).max().unwrap_or(1).max(1);
+    let height = bins * 2 + GAP;
+    let mut pixels = vec![255u8; width * height * 3];
+    for (p, frames) in panels.iter().enumerate() {
+        for (x, frame) in frames.iter().enumerate() {
+            for (bin, &db) in frame.iter().enumerate() {
+                // Low frequencies at the bottom of each panel
+                let y = p * (bins + GAP) + (bins - 1 - bin);
+                let offset = (y * width + x) * 3;
+                pixels[offset..offset + 3].copy_from_slice(&heat(db / -FLOOR_DB + 1.0));
+            }
+        }
+    }
+
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    let file = std::io::BufWriter::new(
+        std::fs::File::create(dest)
+            .with_context(|| format!("Failed to create image: {}", dest.display()))?,
+    );
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .with_context(|| format!("Failed to write image: {}", dest.display()))
+}
+
+/// Maps `level` in `[0, 1]` to a black-red-yellow-white color ramp.
+fn heat(level: f32) -> [u8; 3] {
+    let v = level.clamp(0.0, 1.0) * 3.0;
+    let channel = |start: f32| ((v - start).clamp(0.0, 1.0) * 255.0) as u8;
+    [channel(0.0), channel(1.0), channel(2.0)]
+}
+
+/// Length difference tolerated by `verify`, in seconds, for backends that pad to whole frames.
+const LENGTH_TOLERANCE_SECS: f64 = 0.1;
+/// RMS level, in dBFS, below which `verify` considers a signal silent.
+const SILENCE_DB: f64 = -80.0;
+
+/// Re-reads a written output and describes what is wrong with it: a header
+/// that differs from the original's, a different length, or silence where the
+/// original was not silent. `channels` are the original's channels the output
+/// holds, or empty for all of them, mixed into one when `downmixed`. Returns
+/// no anomalies for RF64 originals, which cannot be read back here.
+pub fn verify(original: &Path, output: &Path, channels: &[u16], downmixed: bool) -> Vec<String> {
+    if crate::riff::is_rf64(original) {
+        return Vec::new();
+    }
+    let after = match audio::read_wav(output) {
+        Ok(after) => after,
+        Err(e) => return vec![format!("output cannot be read: {e:#}")],
+    };
+    let mut anomalies = Vec::new();
+    let all_zeros = after.samples.iter().all(|&s| s == 0.0);
+    if all_zeros {
+        anomalies.push("output is all zeros".to_string());
+    }
+    // Decoded (non-WAV) originals can only be compared by their output
+    let Ok(before) = audio::read_wav(original) else {
+        return anomalies;
+    };
+    let expected_spec = hound::WavSpec {
+        channels: match channels.len() {
+            _ if downmixed => 1,
+            0 => before.spec.channels,
+            n => n as u16,
+        },
+        ..before.spec
+    };
+
+    if expected_spec != after.spec {
+        anomalies.push(format!(
+            "header is {} ch, {} Hz, {}-bit {:?}; expected {} ch, {} Hz, {}-bit {:?}",
+            after.spec.channels,
+            after.spec.sample_rate,
+            after.spec.bits_per_sample,
+            after.spec.sample_format,
+            expected_spec.channels,
+            expected_spec.sample_rate,
+            expected_spec.bits_per_sample,
+            expected_spec.sample_format
+        ));
+    }
+    let frames = |a: &audio::Audio| a.samples.len() / usize::from(a.spec.channels.max(1));
+    let (expected, actual) = (frames(&before), frames(&after));
+    let tolerance = (f64::from(before.spec.sample_rate) * LENGTH_TOLERANCE_SECS) as usize;
+    if expected.abs_diff(actual) > tolerance {
+        anomalies.push(format!(
+            "output has {actual} samples per channel; the input has {expected}"
+        ));
+    }
+    let rms_db = |samples: &[f32]| {
+        let power = samples.iter().map(|&s| f64::from(s).powi(2)).sum::<f64>()
+            / samples.len().max(1) as f64;
+        10.0 * power.max(1e-20).log10()
+    };
+    let kept: Vec<f32> = channels
+        .iter()
+        .flat_map(|&c| before.channel(usize::from(c)))
+        .collect();
+    let level_in = rms_db(if channels.is_empty() {
+        &before.samples
+    } else {
+        &kept
+    });
+    let level_out = rms_db(&after.samples);
+    if !all_zeros && level_in >= SILENCE_DB && level_out < SILENCE_DB {
+        anomalies.push(format!(
+            "output is silent ({level_out:.0} dBFS) but the input is at {level_in:.0} dBFS"
+        ));
+    }
+    anomalies
+}
+
+/// Magnitude at or above which a sample counts as full scale.
+const CLIP_LEVEL: f32 = 0.999;
+/// Consecutive full-scale samples that make a clipped stretch, so isolated peaks don't count.
+const CLIP_RUN: usize = 3;
+/// Mean level above which a channel has a DC offset worth reporting (-40 dBFS).
+pub const DC_OFFSET_LIMIT: f64 = 0.01;
+
+/// Clipping and DC offset of a signal.
+pub struct Levels {
+    /// Samples in clipped stretches, over all channels.
+    pub clipped: usize,
+    /// Mean of each channel.
+    pub dc_offsets: Vec<f64>,
+}
+
+impl Levels {
+    /// Measures the WAV file at `path` a block at a time; also returns its
+    /// number of samples, for [`Levels::warnings`].
+    pub fn measure_file(path: &Path) -> Result<(Self, usize)> {
+        let mut reader = audio::FrameReader::open(path)?;
+        let channels = usize::from(reader.spec().channels.max(1));
+        let mut clipped = 0;
+        let mut runs = vec![0; channels];
+        let mut sums = vec![0.0; channels];
+        let mut total = 0;
+        while let Some(block) = reader.next_block()? {
+            total += block.len();
+            for frame in block.chunks(channels) {
+                for (c, &s) in frame.iter().enumerate() {
+                    sums[c] += f64::from(s);
+                    if s.abs() >= CLIP_LEVEL {
+                        runs[c] += 1;
+                    } else {
+                        if runs[c] >= CLIP_RUN {
+                            clipped += runs[c];
+                        }
+                        runs[c] = 0;
+                    }
+                }
+            }
+        }
+        clipped += runs.iter().filter(|&&run| run >= CLIP_RUN).sum::<usize>();
+        let frames = (total / channels).max(1) as f64;
+        let levels = Levels {
+            clipped,
+            dc_offsets: sums.iter().map(|sum| sum / frames).collect(),
+        };
+        Ok((levels, total))
+    }
+
+    /// Whether any channel's offset exceeds [`DC_OFFSET_LIMIT`].
+    pub fn has_dc_offset(&self) -> bool {
+        self.dc_offsets.iter().any(|dc| dc.abs() > DC_OFFSET_LIMIT)
+    }
+
+    /// One line per problem found, for a signal of `total` samples.
+    pub fn warnings(&self, total: usize) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.clipped > 0 {
+            warnings.push(format!(
+                "{} clipped samples ({:.2}%)",
+                self.clipped,
+                self.clipped as f64 * 100.0 / total.max(1) as f64
+            ));
+        }
+        for (c, dc) in self.dc_offsets.iter().enumerate() {
+            if dc.abs() > DC_OFFSET_LIMIT {
+                warnings.push(format!(
+                    "DC offset of {dc:+.4} ({:.1} dBFS) on channel {c}",
+                    20.0 * dc.abs().log10()
+                ));
+            }
+        }
+        warnings
+    }
+}
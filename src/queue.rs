@@ -0,0 +1,215 @@
+//! The server's job queue: one line per priority, drained by a smooth
+//! weighted round robin so busy high-priority lines go first most of the
+//! time without starving the others.
+
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+
+/// How urgently a submitted job should run.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Interactive uploads someone is waiting for
+    High,
+    #[default]
+    Normal,
+    /// Bulk re-processing that can wait
+    Low,
+}
+
+impl Priority {
+    const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    /// The name used in `?priority=` and job status.
+    pub fn name(self) -> String {
+        self.to_possible_value()
+            .map(|v| v.get_name().to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Jobs waiting for a worker, bounded in total.
+pub struct JobQueue {
+    state: Mutex<State>,
+    ready: Condvar,
+    capacity: usize,
+    weights: [i64; 3],
+}
+
+#[derive(Default)]
+struct State {
+    lines: [VecDeque<String>; 3],
+    /// Running credit of each line for the round robin.
+    credit: [i64; 3],
+    /// Workers blocked in [`JobQueue::pop`].
+    idle: usize,
+}
+
+impl JobQueue {
+    /// A queue holding up to `capacity` jobs beyond those idle workers take
+    /// at once, served in proportion to the high, normal and low `weights`.
+    pub fn new(capacity: usize, weights: [u32; 3]) -> Self {
+        JobQueue {
+            state: Mutex::new(State::default()),
+            ready: Condvar::new(),
+            capacity,
+            weights: weights.map(i64::from),
+        }
+    }
+
+    /// Queues the job `id`, or gives it back when the queue is full.
+    pub fn push(&self, id: String, priority: Priority) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        let waiting: usize = state.lines.iter().map(VecDeque::len).sum();
+        if waiting >= self.capacity + state.idle {
+            return Err(id);
+        }
+        state.lines[priority.index()].push_back(id);
+        self.ready.notify_one();
+        Ok(())
+    }
+
+    /// Whether [`JobQueue::push`] would turn a job away now.
+    pub fn is_full(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        let waiting: usize = state.lines.iter().map(VecDeque::len).sum();
+        waiting >= self.capacity + state.idle
+    }
+
+    /// Blocks until a job is queued and returns the one whose line is due.
+    pub fn pop(&self) -> String {
+        let mut state = self.state.lock().unwrap();
+        state.idle += 1;
+        while state.lines.iter().all(VecDeque::is_empty) {
+            state = self.ready.wait(state).unwrap();
+        }
+        state.idle -= 1;
+
+        // Every waiting line earns its weight, and the richest pays for its turn
+        let busy: Vec<usize> = Priority::ALL
+            .iter()
+            .map(|p| p.index())
+            .filter(|&i| !state.lines[i].is_empty())
+            .collect();
+        let total: i64 = busy.iter().map(|&i| self.weights[i]).sum();
+        for &i in &busy {
+            state.credit[i] += self.weights[i];
+        }
+        let due = busy
+            .iter()
+            .copied()
+            .max_by_key(|&i| (state.credit[i], std::cmp::Reverse(i)))
+            .unwrap_or_default();
+        state.credit[due] -= total;
+        let id = state.lines[due].pop_front().unwrap_or_default();
+        // Lines that run dry start from nothing when work arrives again
+        let State { lines, credit, .. } = &mut *state;
+        for (line, credit) in lines.iter().zip(credit.iter_mut()) {
+            if line.is_empty() {
+                *credit = 0;
+            }
+        }
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(weights: [u32; 3], per_line: usize) -> JobQueue {
+        let queue = JobQueue::new(usize::MAX / 2, weights);
+        for priority in Priority::ALL {
+            for n in 0..per_line {
+                queue
+                    .push(format!("{}-{n}", priority.name()), priority)
+                    .unwrap();
+            }
+        }
+        queue
+    }
+
+    /// The lines of the next `n` jobs popped, by their first letter.
+    fn lines(queue: &JobQueue, n: usize) -> String {
+        (0..n)
+            .map(|_| queue.pop().chars().next().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn busy_lines_are_served_in_proportion_to_their_weights() {
+        let queue = filled([8, 2, 1], 100);
+        for _ in 0..5 {
+            let round = lines(&queue, 11);
+            let count = |line| round.chars().filter(|&c| c == line).count();
+            assert_eq!((count('h'), count('n'), count('l')), (8, 2, 1), "{round}");
+        }
+    }
+
+    #[test]
+    fn turns_are_spread_out_rather_than_bunched() {
+        let queue = filled([2, 1, 1], 100);
+        assert_eq!(lines(&queue, 8), "hnlhhnlh");
+    }
+
+    #[test]
+    fn each_line_is_first_in_first_out() {
+        let queue = filled([1, 1, 1], 3);
+        let popped: Vec<String> = (0..9).map(|_| queue.pop()).collect();
+        for priority in Priority::ALL {
+            let line: Vec<&String> = popped
+                .iter()
+                .filter(|id| id.starts_with(&priority.name()))
+                .collect();
+            assert_eq!(line.len(), 3);
+            assert!(line.is_sorted(), "{line:?}");
+        }
+    }
+
+    #[test]
+    fn lines_that_ran_dry_start_without_credit() {
+        let queue = JobQueue::new(100, [1, 1, 1]);
+        for n in 0..4 {
+            queue.push(format!("high-{n}"), Priority::High).unwrap();
+        }
+        assert_eq!(lines(&queue, 2), "hh");
+        queue.push("low-0".to_string(), Priority::Low).unwrap();
+        // Equal weights alternate from the moment both lines are busy
+        assert_eq!(lines(&queue, 3), "hlh");
+    }
+
+    #[test]
+    fn turns_jobs_away_when_full() {
+        let queue = JobQueue::new(2, [1, 1, 1]);
+        assert!(queue.push("a".to_string(), Priority::Low).is_ok());
+        assert!(!queue.is_full());
+        assert!(queue.push("b".to_string(), Priority::High).is_ok());
+        assert!(queue.is_full());
+        assert_eq!(
+            queue.push("c".to_string(), Priority::High),
+            Err("c".to_string())
+        );
+        assert_eq!(queue.pop(), "b");
+        assert!(!queue.is_full());
+    }
+
+    #[test]
+    fn idle_workers_take_jobs_beyond_the_capacity() {
+        let queue = JobQueue::new(0, [1, 1, 1]);
+        assert!(queue.is_full());
+        std::thread::scope(|scope| {
+            let worker = scope.spawn(|| queue.pop());
+            while queue.is_full() {
+                std::thread::yield_now();
+            }
+            queue.push("a".to_string(), Priority::Normal).unwrap();
+            assert_eq!(worker.join().unwrap(), "a");
+        });
+    }
+}
@@ -0,0 +1,112 @@
+//! Headerless PCM for `--raw`: inputs are given a WAV header before the
+//! pipeline reads them, and outputs lose it again once the pipeline is done.
+
+use crate::riff;
+use anyhow::{Context, Result, bail};
+use clap::builder::TypedValueParser;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// The format of `--raw` inputs; outputs are written in the same one.
+#[derive(clap::Args, Debug, Clone, Copy, Serialize)]
+pub struct RawArgs {
+    /// Read inputs and write outputs as headerless little-endian PCM in the --rate, --bits and --raw-channels format
+    #[arg(long)]
+    pub raw: bool,
+
+    /// Sample rate of --raw inputs
+    #[arg(long, value_name = "HZ", default_value_t = 16000, requires = "raw")]
+    pub rate: u32,
+
+    /// Sample size of --raw inputs (8-bit unsigned, 16 or 24-bit signed, or 32 for float)
+    #[arg(long, value_name = "BITS", default_value_t = 16, requires = "raw",
+          value_parser = clap::builder::PossibleValuesParser::new(["8", "16", "24", "32"])
+              .map(|s| s.parse::<u16>().unwrap()))]
+    pub bits: u16,
+
+    /// Interleaved channels of --raw inputs
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "raw")]
+    pub raw_channels: u16,
+}
+
+impl RawArgs {
+    /// The format raw files hold, when `--raw` is given.
+    pub fn spec(&self) -> Option<hound::WavSpec> {
+        self.raw.then_some(hound::WavSpec {
+            channels: self.raw_channels,
+            sample_rate: self.rate,
+            bits_per_sample: self.bits,
+            sample_format: if self.bits == 32 {
+                hound::SampleFormat::Float
+            } else {
+                hound::SampleFormat::Int
+            },
+        })
+    }
+}
+
+/// Writes the PCM in `input` to `output` as a WAV file with `spec`.
+pub fn to_wav(input: &Path, output: &Path, spec: hound::WavSpec) -> Result<()> {
+    let mut pcm =
+        File::open(input).with_context(|| format!("Failed to open file: {}", input.display()))?;
+    let len = pcm.metadata()?.len();
+    if spec.channels == 0 {
+        bail!("--raw-channels must be at least 1");
+    }
+    let block_align = u32::from(spec.channels) * u32::from(spec.bits_per_sample / 8);
+    if len % u64::from(block_align) != 0 {
+        bail!(
+            "{} is not whole {}-bit {}-channel frames ({len} bytes)",
+            input.display(),
+            spec.bits_per_sample,
+            spec.channels
+        );
+    }
+    let Some(data_size) = u32::try_from(len).ok().filter(|&n| n <= u32::MAX - 36) else {
+        bail!("{} is too large for --raw (over 4 GB)", input.display());
+    };
+
+    let format_tag: u16 = match spec.sample_format {
+        hound::SampleFormat::Float => 3,
+        hound::SampleFormat::Int => 1,
+    };
+    let mut header = Vec::with_capacity(44);
+    header.extend(b"RIFF");
+    header.extend((36 + data_size).to_le_bytes());
+    header.extend(b"WAVEfmt ");
+    header.extend(16u32.to_le_bytes());
+    header.extend(format_tag.to_le_bytes());
+    header.extend(spec.channels.to_le_bytes());
+    header.extend(spec.sample_rate.to_le_bytes());
+    header.extend((spec.sample_rate * block_align).to_le_bytes());
+    header.extend((block_align as u16).to_le_bytes());
+    header.extend(spec.bits_per_sample.to_le_bytes());
+    header.extend(b"data");
+    header.extend(data_size.to_le_bytes());
+
+    let file = File::create(output)
+        .with_context(|| format!("Failed to create file: {}", output.display()))?;
+    let mut wav = BufWriter::new(file);
+    wav.write_all(&header)?;
+    std::io::copy(&mut pcm, &mut wav)
+        .and_then(|_| wav.flush())
+        .with_context(|| format!("Failed to write file: {}", output.display()))
+}
+
+/// Writes the samples of the WAV file `input` to `output` without a header.
+pub fn from_wav(input: &Path, output: &Path) -> Result<()> {
+    let mut wav =
+        File::open(input).with_context(|| format!("Failed to open file: {}", input.display()))?;
+    let data = riff::chunks(&mut wav)?
+        .into_iter()
+        .find(|c| &c.id == b"data")
+        .with_context(|| format!("No data chunk in {}", input.display()))?;
+    wav.seek(SeekFrom::Start(data.offset))?;
+    let mut pcm = File::create(output)
+        .with_context(|| format!("Failed to create file: {}", output.display()))?;
+    std::io::copy(&mut wav.take(data.size), &mut pcm)
+        .with_context(|| format!("Failed to write file: {}", output.display()))?;
+    Ok(())
+}
@@ -0,0 +1,183 @@
+//! Labelled regions for `--segments`: the stretches of an input that are
+//! denoised, from Audacity label files or the input's own `cue ` chunk.
+
+use crate::riff;
+use anyhow::{Context, Result, bail};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// A labelled stretch of audio, in seconds from the start.
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub start: f64,
+    pub end: f64,
+    pub label: String,
+}
+
+/// Where the regions of each input come from.
+pub enum Source {
+    /// Cue points with a length (`ltxt`) in the input's `cue ` chunk.
+    Cue,
+    /// One label file for every input.
+    File(Vec<Region>),
+    /// A directory of label files named after each input's stem, `STEM.txt`.
+    Dir(PathBuf),
+}
+
+impl Source {
+    /// Resolves `--segments`: `cue`, a label file or a directory of them.
+    pub fn open(arg: &Path) -> Result<Self> {
+        if arg == Path::new("cue") {
+            return Ok(Source::Cue);
+        }
+        if arg.is_dir() {
+            return Ok(Source::Dir(arg.to_path_buf()));
+        }
+        Ok(Source::File(parse_labels(arg)?))
+    }
+
+    /// The regions of `input` in order, overlapping ones merged.
+    pub fn regions(&self, input: &Path) -> Result<Vec<Region>> {
+        let mut regions = match self {
+            Source::Cue => read_cue(input)?,
+            Source::File(regions) => regions.clone(),
+            Source::Dir(dir) => {
+                let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+                let labels = dir.join(format!("{stem}.txt"));
+                if !labels.exists() {
+                    return Ok(Vec::new());
+                }
+                parse_labels(&labels)?
+            }
+        };
+        regions.sort_by(|a, b| a.start.total_cmp(&b.start));
+        let mut merged: Vec<Region> = Vec::with_capacity(regions.len());
+        for region in regions {
+            match merged.last_mut() {
+                Some(last) if region.start <= last.end => {
+                    last.end = last.end.max(region.end);
+                    if !region.label.is_empty() {
+                        last.label = format!("{}+{}", last.label, region.label);
+                    }
+                }
+                _ => merged.push(region),
+            }
+        }
+        Ok(merged)
+    }
+}
+
+/// Reads an Audacity label file: `START<TAB>END<TAB>LABEL` lines in seconds.
+/// Frequency lines (starting with `\`) and point labels are skipped.
+pub fn parse_labels(path: &Path) -> Result<Vec<Region>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read label file: {}", path.display()))?;
+    let mut regions = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('\\') {
+            continue;
+        }
+        let mut fields = line.splitn(3, '\t');
+        let mut time = || {
+            fields
+                .next()
+                .and_then(|f| f.trim().parse::<f64>().ok())
+                .filter(|t| t.is_finite() && *t >= 0.0)
+        };
+        let (Some(start), Some(end)) = (time(), time()) else {
+            bail!(
+                "Invalid label on line {} of {}: expected START<TAB>END<TAB>LABEL",
+                i + 1,
+                path.display()
+            );
+        };
+        let label = fields.next().unwrap_or_default().trim().to_string();
+        if end > start {
+            regions.push(Region { start, end, label });
+        }
+    }
+    Ok(regions)
+}
+
+/// Reads the regions of a WAV file's `cue ` chunk: cue points given a length
+/// by an `ltxt` entry in the `LIST/adtl` chunk, named by its `labl` entries.
+fn read_cue(path: &Path) -> Result<Vec<Region>> {
+    let Some(cue) = riff::read_chunk(path, b"cue ")? else {
+        return Ok(Vec::new());
+    };
+    let rate = f64::from(riff::format(path)?.sample_rate);
+    let u32_at = |data: &[u8], i: usize| {
+        u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]])
+    };
+    // Entries: ID, position, data chunk ID, chunk start, block start, sample offset
+    let points: Vec<(u32, u32)> = cue
+        .get(4..)
+        .unwrap_or_default()
+        .chunks_exact(24)
+        .map(|entry| (u32_at(entry, 0), u32_at(entry, 20)))
+        .collect();
+
+    let mut lengths = Vec::new();
+    let mut labels = Vec::new();
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
+    for chunk in riff::chunks(&mut file)? {
+        if &chunk.id != b"LIST" || chunk.size < 4 {
+            continue;
+        }
+        let mut data = Vec::new();
+        file.seek(SeekFrom::Start(chunk.offset))?;
+        (&mut file).take(chunk.size).read_to_end(&mut data)?;
+        if &data[0..4] != b"adtl" {
+            continue;
+        }
+        let mut rest = &data[4..];
+        while rest.len() >= 12 {
+            let size = u32_at(rest, 4) as usize;
+            let body = &rest[8..(8 + size).min(rest.len())];
+            if body.len() >= 4 {
+                let id = u32_at(body, 0);
+                match &rest[0..4] {
+                    b"ltxt" if body.len() >= 8 => lengths.push((id, u32_at(body, 4))),
+                    b"labl" => {
+                        let text = body[4..].split(|&b| b == 0).next().unwrap_or_default();
+                        labels.push((id, String::from_utf8_lossy(text).trim().to_string()));
+                    }
+                    _ => {}
+                }
+            }
+            rest = &rest[(8 + size + (size & 1)).min(rest.len())..];
+        }
+    }
+
+    Ok(points
+        .into_iter()
+        .filter_map(|(id, offset)| {
+            let (_, length) = lengths.iter().find(|(l, n)| *l == id && *n > 0)?;
+            let label = labels
+                .iter()
+                .find(|(l, _)| *l == id)
+                .map_or_else(String::new, |(_, text)| text.clone());
+            Some(Region {
+                start: f64::from(offset) / rate,
+                end: (f64::from(offset) + f64::from(*length)) / rate,
+                label,
+            })
+        })
+        .collect())
+}
+
+/// A label made safe for a file name: runs of anything but letters, digits,
+/// `-` and `_` become one `_`.
+pub fn file_label(label: &str) -> String {
+    let mut name = String::new();
+    for c in label.chars() {
+        if c.is_alphanumeric() || c == '-' || c == '_' {
+            name.push(c);
+        } else if !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    name.trim_matches('_').to_string()
+}
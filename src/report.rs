@@ -0,0 +1,794 @@
+//! Run totals, the summary sent to `--notify-url` when a batch ends, the
+//! `--report` file and the `--fail-on` exit code policy.
+
+use crate::error::DenoiseError;
+use crate::loudness::Loudness;
+use crate::manifest;
+use crate::noise::{NoiseKind, NoiseProfile};
+use crate::parquet::{self, Column};
+use crate::provenance::RunInfo;
+use crate::quality::Quality;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Exit code when `--fail-on` is set and no input file matched.
+pub const EXIT_NOTHING_MATCHED: u8 = 3;
+/// Exit code when `--fail-on` trips and some files succeeded.
+pub const EXIT_PARTIAL_FAILURE: u8 = 4;
+/// Exit code when `--fail-on` trips and every attempted file failed.
+pub const EXIT_TOTAL_FAILURE: u8 = 5;
+
+/// When failed files should make the run exit non-zero.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailOn {
+    /// At least one file failed.
+    Any,
+    /// Every attempted file failed.
+    All,
+    /// More than this percentage of attempted files failed.
+    Threshold(f64),
+}
+
+impl FromStr for FailOn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(FailOn::Any),
+            "all" => Ok(FailOn::All),
+            _ => {
+                let percent = s
+                    .strip_prefix("threshold=")
+                    .map(|t| t.trim_end_matches('%'))
+                    .and_then(|t| t.parse::<f64>().ok())
+                    .filter(|p| (0.0..=100.0).contains(p));
+                percent.map(FailOn::Threshold).ok_or_else(|| {
+                    format!("expected 'any', 'all' or 'threshold=N%' with N in 0..=100, got '{s}'")
+                })
+            }
+        }
+    }
+}
+
+/// Per-outcome file counts and throughput figures, updated by the workers.
+#[derive(Default)]
+pub struct Totals {
+    /// Files found in the input before resumed ones were dropped.
+    pub matched: AtomicUsize,
+    pub processed: AtomicUsize,
+    pub skipped: AtomicUsize,
+    pub failed: AtomicUsize,
+    /// Outputs that `--verify` flagged.
+    pub anomalies: AtomicUsize,
+    /// Orphaned outputs `--prune` deleted.
+    pub pruned: AtomicUsize,
+    /// Orphaned outputs `--prune` would have deleted but for `--dry-run`.
+    pub would_prune: AtomicUsize,
+    /// Length of the denoised inputs, in microseconds of audio.
+    audio_micros: AtomicU64,
+    /// Time the workers spent on denoised files, summed, in microseconds.
+    busy_micros: AtomicU64,
+    /// Files `busy_micros` covers; linked duplicates are not timed.
+    timed: AtomicUsize,
+    bytes_written: AtomicU64,
+    /// Quietest and loudest integrated loudness and highest true peak measured.
+    loudness: Mutex<Option<LoudnessExtremes>>,
+    /// Noise profiles of the denoised inputs, with `--noise-report`.
+    noise: Mutex<BTreeMap<String, NoiseProfile>>,
+    /// Scores of the outputs against `--reference-dir`.
+    quality: Mutex<BTreeMap<String, Quality>>,
+    /// Failed files by the class of their failure.
+    failures: Mutex<BTreeMap<DenoiseError, usize>>,
+    /// Skipped files the denoiser does not take, by class.
+    skips: Mutex<BTreeMap<DenoiseError, usize>>,
+    /// What happened to each file, with `--report`.
+    outcomes: Mutex<Vec<FileRecord>>,
+    /// The input and output directories, for runs `retry` can repeat.
+    dirs: Mutex<Option<(PathBuf, PathBuf)>>,
+}
+
+/// One row of the `--report`.
+#[derive(Serialize, Clone)]
+pub struct FileRecord {
+    /// Path relative to the input directory; for pruned outputs, which have
+    /// no input, the same as `output`.
+    pub file: String,
+    /// Path relative to the output directory; empty for files never handed to a worker.
+    pub output: String,
+    /// `done`, `skipped`, `invalid`, `failed`, `pruned` or `would_prune`.
+    pub status: &'static str,
+    pub code: Option<DenoiseError>,
+    pub reason: Option<String>,
+    pub secs: f64,
+    /// Length of the output, for denoised files.
+    pub audio_secs: f64,
+    pub bytes: u64,
+    /// What the backend printed, with `--capture-backend-output`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log: Option<String>,
+}
+
+/// File formats of the `--report`.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// The run summary and a list of the files
+    Json,
+    /// A row per file
+    Csv,
+    /// A row per file, gzip-compressed columns
+    Parquet,
+}
+
+impl ReportFormat {
+    /// The format a report file named `path` is meant to be in.
+    pub fn for_path(path: &Path) -> Self {
+        let name = path.to_string_lossy().to_lowercase();
+        let name = name.strip_suffix(".gz").unwrap_or(&name);
+        if name.ends_with(".csv") {
+            ReportFormat::Csv
+        } else if name.ends_with(".parquet") {
+            ReportFormat::Parquet
+        } else {
+            ReportFormat::Json
+        }
+    }
+}
+
+/// What a batch run prints on stdout.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Progress and summary lines
+    #[default]
+    Text,
+    /// Nothing but one JSON object when the run ends
+    Json,
+}
+
+/// Order of the rows of the `--report`, whatever order the files finished in.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportSort {
+    /// By relative path, directory by directory
+    #[default]
+    Path,
+    /// Longest audio first
+    Duration,
+    /// Failed, invalid, skipped, then denoised files
+    Status,
+}
+
+impl ReportSort {
+    /// Sorts `files`, falling back to the path so that equal keys keep one order.
+    fn apply(self, files: &mut [FileRecord]) {
+        // Component-wise and byte-wise, so it is the same on every platform and locale
+        let path = |r: &FileRecord| Path::new(&r.file).to_path_buf();
+        let rank = |r: &FileRecord| match r.status {
+            "failed" => 0,
+            "invalid" => 1,
+            "skipped" => 2,
+            _ => 3,
+        };
+        match self {
+            ReportSort::Path => files.sort_by_cached_key(path),
+            ReportSort::Duration => files.sort_by(|a, b| {
+                b.audio_secs
+                    .total_cmp(&a.audio_secs)
+                    .then_with(|| path(a).cmp(&path(b)))
+            }),
+            ReportSort::Status => files.sort_by_cached_key(|r| (rank(r), path(r))),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy)]
+pub struct LoudnessExtremes {
+    pub quietest_lufs: Option<f64>,
+    pub loudest_lufs: Option<f64>,
+    pub max_true_peak_dbtp: f64,
+}
+
+#[derive(Serialize)]
+pub struct NoiseReport {
+    /// How many inputs have each kind of noise.
+    pub kinds: BTreeMap<NoiseKind, usize>,
+    /// The profile of each input, by its path relative to the input directory.
+    pub files: BTreeMap<String, NoiseProfile>,
+}
+
+#[derive(Serialize)]
+pub struct QualityReport {
+    /// Means over the scored outputs; STOI and the PESQ approximation leave out
+    /// files too short to score.
+    pub mean_stoi: Option<f64>,
+    pub mean_pesq_approx: Option<f64>,
+    pub mean_si_sdr_db: f64,
+    /// The scores of each output, by its input's path relative to the input directory.
+    pub files: BTreeMap<String, Quality>,
+}
+
+#[derive(Serialize)]
+pub struct Summary {
+    /// The UUID of the run, as in the sidecars and the `--report`.
+    pub run_id: String,
+    /// `completed`, or `aborted` when the run stopped on an error.
+    pub status: &'static str,
+    pub matched: usize,
+    pub processed: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    /// Outputs that failed `--verify`; they still count as processed.
+    pub anomalies: usize,
+    /// Orphaned outputs `--prune` deleted.
+    pub pruned: usize,
+    /// Orphaned outputs `--prune --dry-run` kept.
+    pub would_prune: usize,
+    pub duration_secs: f64,
+    /// Length of the denoised audio.
+    pub audio_secs: f64,
+    /// Seconds of audio denoised per second of wall-clock time.
+    pub realtime_factor: f64,
+    /// Mean time a worker spent on one denoised file.
+    pub avg_latency_secs: f64,
+    pub bytes_written: u64,
+    /// Failed files by failure class, when there are any; they add up to `failed`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub failures: BTreeMap<DenoiseError, usize>,
+    /// Invalid files by class, when there are any; they count towards `skipped`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub skipped_by_class: BTreeMap<DenoiseError, usize>,
+    /// With `--measure-loudness`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loudness: Option<LoudnessExtremes>,
+    /// With `--noise-report`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub noise: Option<NoiseReport>,
+    /// With `--reference-dir`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<QualityReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Totals {
+    /// Adds the figures of one denoised file: its length in seconds, the time
+    /// taken and the size of the output.
+    pub fn record_denoised(&self, audio_secs: f64, latency: Duration, bytes: u64) {
+        self.audio_micros
+            .fetch_add((audio_secs * 1e6) as u64, Ordering::SeqCst);
+        self.busy_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::SeqCst);
+        self.timed.fetch_add(1, Ordering::SeqCst);
+        self.bytes_written.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    pub fn record_loudness(&self, loudness: &Loudness) {
+        let mut extremes = self.loudness.lock().unwrap();
+        let lufs = loudness.integrated_lufs;
+        *extremes = Some(match *extremes {
+            None => LoudnessExtremes {
+                quietest_lufs: lufs,
+                loudest_lufs: lufs,
+                max_true_peak_dbtp: loudness.true_peak_dbtp,
+            },
+            Some(e) => {
+                let pick = |a: Option<f64>, f: fn(f64, f64) -> f64| match (a, lufs) {
+                    (Some(a), Some(b)) => Some(f(a, b)),
+                    (a, b) => a.or(b),
+                };
+                LoudnessExtremes {
+                    quietest_lufs: pick(e.quietest_lufs, f64::min),
+                    loudest_lufs: pick(e.loudest_lufs, f64::max),
+                    max_true_peak_dbtp: e.max_true_peak_dbtp.max(loudness.true_peak_dbtp),
+                }
+            }
+        });
+    }
+
+    pub fn record_noise(&self, relative: &str, profile: NoiseProfile) {
+        self.noise
+            .lock()
+            .unwrap()
+            .insert(relative.to_string(), profile);
+    }
+
+    pub fn record_failure(&self, code: DenoiseError) {
+        *self.failures.lock().unwrap().entry(code).or_insert(0) += 1;
+    }
+
+    pub fn record_skip(&self, code: DenoiseError) {
+        *self.skips.lock().unwrap().entry(code).or_insert(0) += 1;
+    }
+
+    pub fn record_file(&self, record: FileRecord) {
+        self.outcomes.lock().unwrap().push(record);
+    }
+
+    pub fn set_dirs(&self, input_dir: &Path, output_dir: &Path) {
+        *self.dirs.lock().unwrap() = Some((input_dir.to_path_buf(), output_dir.to_path_buf()));
+    }
+
+    pub fn record_quality(&self, relative: &str, quality: Quality) {
+        self.quality
+            .lock()
+            .unwrap()
+            .insert(relative.to_string(), quality);
+    }
+
+    pub fn summary(
+        &self,
+        run_id: &str,
+        duration: Duration,
+        error: Option<&anyhow::Error>,
+    ) -> Summary {
+        let audio_secs = self.audio_micros.load(Ordering::SeqCst) as f64 / 1e6;
+        let timed = self.timed.load(Ordering::SeqCst);
+        Summary {
+            run_id: run_id.to_string(),
+            status: if error.is_some() {
+                "aborted"
+            } else {
+                "completed"
+            },
+            matched: self.matched.load(Ordering::SeqCst),
+            processed: self.processed.load(Ordering::SeqCst),
+            skipped: self.skipped.load(Ordering::SeqCst),
+            failed: self.failed.load(Ordering::SeqCst),
+            anomalies: self.anomalies.load(Ordering::SeqCst),
+            pruned: self.pruned.load(Ordering::SeqCst),
+            would_prune: self.would_prune.load(Ordering::SeqCst),
+            duration_secs: duration.as_secs_f64(),
+            audio_secs,
+            realtime_factor: audio_secs / duration.as_secs_f64().max(1e-9),
+            avg_latency_secs: if timed == 0 {
+                0.0
+            } else {
+                self.busy_micros.load(Ordering::SeqCst) as f64 / 1e6 / timed as f64
+            },
+            bytes_written: self.bytes_written.load(Ordering::SeqCst),
+            failures: self.failures.lock().unwrap().clone(),
+            skipped_by_class: self.skips.lock().unwrap().clone(),
+            loudness: *self.loudness.lock().unwrap(),
+            noise: {
+                let files = self.noise.lock().unwrap().clone();
+                (!files.is_empty()).then(|| {
+                    let mut kinds = BTreeMap::new();
+                    for profile in files.values() {
+                        *kinds.entry(profile.kind).or_insert(0) += 1;
+                    }
+                    NoiseReport { kinds, files }
+                })
+            },
+            quality: {
+                let files = self.quality.lock().unwrap().clone();
+                (!files.is_empty()).then(|| {
+                    let mean = |scores: Vec<f64>| {
+                        (!scores.is_empty())
+                            .then(|| scores.iter().sum::<f64>() / scores.len() as f64)
+                    };
+                    QualityReport {
+                        mean_stoi: mean(files.values().filter_map(|q| q.stoi).collect()),
+                        mean_pesq_approx: mean(
+                            files.values().filter_map(|q| q.pesq_approx).collect(),
+                        ),
+                        mean_si_sdr_db: mean(files.values().map(|q| q.si_sdr_db).collect())
+                            .unwrap_or(0.0),
+                        files,
+                    }
+                })
+            },
+            error: error.map(|e| format!("{e:#}")),
+        }
+    }
+
+    /// Exit code for a run that finished without aborting, given the `--fail-on` policy.
+    pub fn exit_code(&self, fail_on: Option<FailOn>) -> ExitCode {
+        let Some(fail_on) = fail_on else {
+            return ExitCode::SUCCESS;
+        };
+        if self.matched.load(Ordering::SeqCst) == 0 {
+            return ExitCode::from(EXIT_NOTHING_MATCHED);
+        }
+        let failed = self.failed.load(Ordering::SeqCst);
+        // Skipped files were never sent to the denoiser, so they don't count either way
+        let attempted = failed + self.processed.load(Ordering::SeqCst);
+        if failed == 0 {
+            return ExitCode::SUCCESS;
+        }
+        let tripped = match fail_on {
+            FailOn::Any => true,
+            FailOn::All => failed == attempted,
+            FailOn::Threshold(percent) => failed as f64 * 100.0 / attempted as f64 > percent,
+        };
+        match (tripped, failed == attempted) {
+            (false, _) => ExitCode::SUCCESS,
+            (true, true) => ExitCode::from(EXIT_TOTAL_FAILURE),
+            (true, false) => ExitCode::from(EXIT_PARTIAL_FAILURE),
+        }
+    }
+}
+
+impl LoudnessExtremes {
+    /// The loudness line printed after a completed run.
+    pub fn describe(&self) -> String {
+        let lufs = |l: Option<f64>| l.map_or_else(|| "-".to_string(), |l| format!("{l:.1}"));
+        format!(
+            "Loudness: {} to {} LUFS integrated, true peak up to {:.1} dBTP.",
+            lufs(self.quietest_lufs),
+            lufs(self.loudest_lufs),
+            self.max_true_peak_dbtp
+        )
+    }
+}
+
+impl NoiseReport {
+    /// The noise line printed after a completed run.
+    pub fn describe(&self) -> String {
+        let kinds: Vec<String> = self
+            .kinds
+            .iter()
+            .map(|(kind, count)| format!("{count} {}", kind.name()))
+            .collect();
+        format!("Noise: {}.", kinds.join(", "))
+    }
+}
+
+impl QualityReport {
+    /// The quality line printed after a completed run.
+    pub fn describe(&self) -> String {
+        let score = |s: Option<f64>, digits: usize| {
+            s.map_or_else(|| "-".to_string(), |s| format!("{s:.digits$}"))
+        };
+        format!(
+            "Quality against the references ({} files): STOI {}, PESQ approx. {}, SI-SDR {:.1} dB.",
+            self.files.len(),
+            score(self.mean_stoi, 3),
+            score(self.mean_pesq_approx, 2),
+            self.mean_si_sdr_db
+        )
+    }
+}
+
+impl Summary {
+    /// The throughput line printed after a completed run.
+    pub fn throughput(&self) -> String {
+        format!(
+            "Audio: {:.1} s in {:.1} s wall-clock ({:.1}x realtime), {:.1} ms average per file, {:.1} MB written.",
+            self.audio_secs,
+            self.duration_secs,
+            self.realtime_factor,
+            self.avg_latency_secs * 1e3,
+            self.bytes_written as f64 / 1e6
+        )
+    }
+}
+
+/// The `--output json` object on one line: the `summary`, plus the failed
+/// and invalid files recorded in `totals` in path order.
+pub fn summary_line(summary: &Summary, totals: &Totals) -> Result<String> {
+    #[derive(Serialize)]
+    struct Output<'a> {
+        #[serde(flatten)]
+        summary: &'a Summary,
+        failed_files: Vec<FileRecord>,
+    }
+    let mut failed_files: Vec<FileRecord> = totals
+        .outcomes
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|r| matches!(r.status, "failed" | "invalid"))
+        .cloned()
+        .collect();
+    ReportSort::Path.apply(&mut failed_files);
+    Ok(serde_json::to_string(&Output {
+        summary,
+        failed_files,
+    })?)
+}
+
+/// Writes the `--report` of a run to `path`: the files recorded in `totals`,
+/// in `sort` order with the ID of the `run`, and, as JSON, the `summary` and
+/// the `run`'s configuration. JSON and CSV are gzip-compressed when the name
+/// ends in `.gz`.
+pub fn write_report(
+    path: &Path,
+    format: ReportFormat,
+    sort: ReportSort,
+    run: &RunInfo,
+    summary: &Summary,
+    totals: &Totals,
+) -> Result<()> {
+    let mut files = totals.outcomes.lock().unwrap().clone();
+    sort.apply(&mut files);
+    let text = match format {
+        ReportFormat::Parquet => return parquet::write(path, &columns(&files, &run.id)),
+        ReportFormat::Json => {
+            #[derive(Serialize)]
+            struct Report<'a> {
+                summary: &'a Summary,
+                run: &'a RunInfo,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                input_dir: Option<&'a Path>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                output_dir: Option<&'a Path>,
+                files: &'a [FileRecord],
+            }
+            let dirs = totals.dirs.lock().unwrap();
+            serde_json::to_string_pretty(&Report {
+                summary,
+                run,
+                input_dir: dirs.as_ref().map(|(input, _)| input.as_path()),
+                output_dir: dirs.as_ref().map(|(_, output)| output.as_path()),
+                files: &files,
+            })?
+        }
+        ReportFormat::Csv => {
+            let mut csv =
+                String::from("file,output,status,code,reason,secs,audio_secs,bytes,log,run_id\n");
+            for row in &files {
+                writeln!(
+                    csv,
+                    "{},{},{},{},{},{:.3},{:.3},{},{},{}",
+                    manifest::field(&row.file),
+                    manifest::field(&row.output),
+                    row.status,
+                    row.code.map(code_name).unwrap_or_default(),
+                    manifest::field(row.reason.as_deref().unwrap_or_default()),
+                    row.secs,
+                    row.audio_secs,
+                    row.bytes,
+                    manifest::field(row.log.as_deref().unwrap_or_default()),
+                    run.id
+                )
+                .unwrap();
+            }
+            csv
+        }
+    };
+    let bytes = if path.extension().is_some_and(|ext| ext == "gz") {
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(text.as_bytes())?;
+        gzip.finish()?
+    } else {
+        text.into_bytes()
+    };
+    std::fs::write(path, bytes)
+        .with_context(|| format!("Failed to write report: {}", path.display()))
+}
+
+/// What `retry` takes from a JSON `--report`.
+pub struct Retryable {
+    /// The run's input and output directories; absent when it staged its
+    /// input or output through S3 or an archive.
+    pub dirs: Option<(PathBuf, PathBuf)>,
+    /// The failed and invalid files, with their relative output paths.
+    pub failed: Vec<(String, Option<String>)>,
+}
+
+/// Reads the failed files of a JSON `--report`, gzip-compressed or not.
+pub fn read_retryable(path: &Path) -> Result<Retryable> {
+    #[derive(Deserialize)]
+    struct Report {
+        input_dir: Option<PathBuf>,
+        output_dir: Option<PathBuf>,
+        files: Vec<Row>,
+    }
+    #[derive(Deserialize)]
+    struct Row {
+        file: String,
+        output: String,
+        status: String,
+    }
+
+    if ReportFormat::for_path(path) != ReportFormat::Json {
+        anyhow::bail!(
+            "{} is not a JSON report; only those record the run's directories",
+            path.display()
+        );
+    }
+    let bytes = std::fs::read(path)
+        .with_context(|| format!("Failed to read report: {}", path.display()))?;
+    let mut text = String::new();
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        flate2::read::GzDecoder::new(&bytes[..])
+            .read_to_string(&mut text)
+            .with_context(|| format!("Failed to decompress report: {}", path.display()))?;
+    } else {
+        text = String::from_utf8(bytes)
+            .with_context(|| format!("Malformed report: {}", path.display()))?;
+    }
+    let report: Report = serde_json::from_str(&text)
+        .with_context(|| format!("Malformed report: {}", path.display()))?;
+    let failed = report
+        .files
+        .into_iter()
+        .filter(|row| matches!(row.status.as_str(), "failed" | "invalid"))
+        .map(|row| {
+            (
+                row.file,
+                Some(row.output).filter(|output| !output.is_empty()),
+            )
+        })
+        .collect();
+    Ok(Retryable {
+        dirs: report.input_dir.zip(report.output_dir),
+        failed,
+    })
+}
+
+/// The name of `code` in events and reports, e.g. `backend_failed`.
+fn code_name(code: DenoiseError) -> String {
+    serde_json::to_value(code)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn columns(files: &[FileRecord], run_id: &str) -> Vec<(&'static str, Column)> {
+    let text = |f: &dyn Fn(&FileRecord) -> String| Column::Text(files.iter().map(f).collect());
+    vec![
+        ("file", text(&|r| r.file.clone())),
+        ("output", text(&|r| r.output.clone())),
+        ("status", text(&|r| r.status.to_string())),
+        ("code", text(&|r| r.code.map(code_name).unwrap_or_default())),
+        ("reason", text(&|r| r.reason.clone().unwrap_or_default())),
+        (
+            "secs",
+            Column::Double(files.iter().map(|r| r.secs).collect()),
+        ),
+        (
+            "audio_secs",
+            Column::Double(files.iter().map(|r| r.audio_secs).collect()),
+        ),
+        (
+            "bytes",
+            Column::Int(files.iter().map(|r| r.bytes as i64).collect()),
+        ),
+        ("log", text(&|r| r.log.clone().unwrap_or_default())),
+        ("run_id", text(&|_| run_id.to_string())),
+    ]
+}
+
+/// POSTs `summary` as JSON to `url`.
+pub fn notify(url: &str, summary: &Summary) -> Result<()> {
+    ureq::post(url)
+        .send_json(summary)
+        .with_context(|| format!("Failed to notify {url}"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for one test's files.
+    fn scratch(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "{}-report-{}-{test}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn record(file: &str, output: &str, status: &'static str) -> FileRecord {
+        FileRecord {
+            file: file.to_string(),
+            output: output.to_string(),
+            status,
+            code: None,
+            reason: None,
+            secs: 0.0,
+            audio_secs: 0.0,
+            bytes: 0,
+            log: None,
+        }
+    }
+
+    fn write(path: &Path, totals: &Totals) {
+        let run = RunInfo {
+            id: "run".to_string(),
+            tool: env!("CARGO_PKG_NAME").to_string(),
+            backend: None,
+            model: None,
+            model_sha256: None,
+            config: BTreeMap::new(),
+        };
+        let summary = totals.summary(&run.id, Duration::ZERO, None);
+        let format = ReportFormat::for_path(path);
+        write_report(path, format, ReportSort::Path, &run, &summary, totals).unwrap();
+    }
+
+    #[test]
+    fn retry_reads_back_the_failed_files_of_a_json_report() {
+        let dir = scratch("retryable");
+        for name in ["run.json", "run.json.gz"] {
+            let totals = Totals::default();
+            totals.set_dirs(Path::new("/in"), Path::new("/out"));
+            totals.record_file(record("a.wav", "a.wav", "done"));
+            totals.record_file(record("sub/b.wav", "sub__b.wav", "failed"));
+            totals.record_file(record("c.wav", "", "invalid"));
+            totals.record_file(record("d.wav", "d.wav", "skipped"));
+            let path = dir.join(name);
+            write(&path, &totals);
+
+            let retryable = read_retryable(&path).unwrap();
+            assert_eq!(
+                retryable.dirs,
+                Some((PathBuf::from("/in"), PathBuf::from("/out")))
+            );
+            assert_eq!(
+                retryable.failed,
+                [
+                    ("c.wav".to_string(), None),
+                    ("sub/b.wav".to_string(), Some("sub__b.wav".to_string())),
+                ]
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reports_of_staged_runs_have_no_directories() {
+        let dir = scratch("staged");
+        let path = dir.join("run.json");
+        let totals = Totals::default();
+        totals.record_file(record("a.wav", "a.wav", "failed"));
+        write(&path, &totals);
+        assert!(
+            !std::fs::read_to_string(&path)
+                .unwrap()
+                .contains("input_dir")
+        );
+        assert_eq!(read_retryable(&path).unwrap().dirs, None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_files_are_classed_with_the_skipped_ones() {
+        let totals = Totals::default();
+        totals.failed.fetch_add(2, Ordering::SeqCst);
+        totals.record_failure(DenoiseError::BackendFailed);
+        totals.record_failure(DenoiseError::Timeout);
+        totals.skipped.fetch_add(3, Ordering::SeqCst);
+        totals.record_skip(DenoiseError::InvalidFormat);
+        let summary = totals.summary("run", Duration::ZERO, None);
+        assert_eq!(summary.failures.values().sum::<usize>(), summary.failed);
+        assert_eq!(
+            summary.skipped_by_class,
+            BTreeMap::from([(DenoiseError::InvalidFormat, 1)])
+        );
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["failures"]["backend_failed"], 1);
+        assert_eq!(json["skipped_by_class"]["invalid_format"], 1);
+        assert!(
+            Totals::default()
+                .summary("run", Duration::ZERO, None)
+                .skipped_by_class
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn retry_needs_a_json_report() {
+        let dir = scratch("csv");
+        let path = dir.join("run.csv");
+        write(&path, &Totals::default());
+        let err = read_retryable(&path).err().unwrap();
+        assert!(err.to_string().contains("not a JSON report"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
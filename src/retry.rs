@@ -0,0 +1,147 @@
+//! The `retry` subcommand: re-processes the files a previous run's state file
+//! or JSON report lists as failed, writing them to the output paths that run
+//! chose.
+
+use crate::journal::Journal;
+use crate::pipeline::{Outcome, Pipeline, PipelineArgs};
+use crate::report::{self, FailOn, Totals};
+use crate::state::RunState;
+use anyhow::{Context, Result, bail};
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::Ordering;
+
+#[derive(clap::Args, Debug)]
+#[group(id = "previous", required = true, args = ["state_file", "report"])]
+pub struct RetryArgs {
+    /// The --state-file of the earlier run; it is updated as files succeed
+    #[arg(long, value_name = "FILE", conflicts_with = "report")]
+    state_file: Option<PathBuf>,
+
+    /// The JSON --report of the earlier run, instead of its state file
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+
+    /// Files processed concurrently [default: one per API address]
+    #[arg(long)]
+    jobs: Option<usize>,
+
+    #[command(flatten)]
+    pipeline: PipelineArgs,
+}
+
+/// Retries the failed files; exits non-zero (as with `--fail-on any`) if some still fail.
+pub fn run(args: &RetryArgs, models_dir: Option<&Path>) -> Result<ExitCode> {
+    let previous = args
+        .state_file
+        .as_ref()
+        .or(args.report.as_ref())
+        .expect("clap requires one of them");
+    if !previous.exists() {
+        bail!("File not found: {}", previous.display());
+    }
+    let state = match &args.state_file {
+        Some(path) => Some(RunState::load(path, 1)?),
+        None => None,
+    };
+    let (dirs, failed) = match &state {
+        Some(state) => (state.dirs(), state.failed_files()),
+        None => {
+            let report = report::read_retryable(previous)?;
+            (report.dirs, report.failed)
+        }
+    };
+    let Some((input_dir, output_dir)) = dirs else {
+        bail!(
+            "{} does not record the run's directories (it was written by an older version, \
+             or the run staged its input or output through S3 or an archive)",
+            previous.display()
+        );
+    };
+    if input_dir == output_dir {
+        bail!(
+            "Runs with --in-place cannot be retried; run again with --in-place and the same --state-file"
+        );
+    }
+    if failed.is_empty() {
+        println!("No failed files in {}.", previous.display());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let pipeline = Pipeline::new(&args.pipeline, models_dir)?;
+    // Later runs resuming from the state file only trust journaled outputs
+    let journal = Journal::open(&output_dir)?;
+    let jobs = args.jobs.unwrap_or_else(|| pipeline.default_jobs()).max(1);
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .context("Failed to create thread pool")?;
+    println!(
+        "Retrying {} failed files from {}",
+        failed.len(),
+        previous.display()
+    );
+
+    let totals = Totals::default();
+    totals.matched.store(failed.len(), Ordering::SeqCst);
+    pool.install(|| {
+        failed.par_iter().for_each(|(key, output)| {
+            let input_path = input_dir.join(key);
+            // Reports from before output paths were recorded mirror the input layout
+            let output_path = output_dir.join(output.as_deref().unwrap_or(key));
+            let outcome = if input_path.is_file() {
+                pipeline
+                    .process(&input_path, &output_path)
+                    .unwrap_or_else(|e| {
+                        eprintln!("Error processing {}: {:?}", input_path.display(), e);
+                        Outcome::failed(&e)
+                    })
+            } else {
+                eprintln!("Input is gone: {}", input_path.display());
+                Outcome::Skipped("input no longer exists".to_string())
+            };
+
+            let relative_output = output_path
+                .strip_prefix(&output_dir)
+                .unwrap_or(&output_path)
+                .to_string_lossy()
+                .to_string();
+            let counter = match &outcome {
+                Outcome::Processed => &totals.processed,
+                Outcome::Failed(..) | Outcome::Invalid(_) => &totals.failed,
+                Outcome::Skipped(_) => &totals.skipped,
+            };
+            // Retries from a report leave it as it was
+            let saved = match (&outcome, &state) {
+                (Outcome::Processed, Some(state)) => state.record_done(key, None),
+                (Outcome::Failed(_, reason) | Outcome::Invalid(reason), Some(state)) => {
+                    state.record_failed(key, &relative_output, reason)
+                }
+                _ => Ok(()),
+            };
+            counter.fetch_add(1, Ordering::SeqCst);
+            if matches!(outcome, Outcome::Processed)
+                && let Err(e) = journal.record(key, &output_path)
+            {
+                eprintln!(
+                    "Warning: {key} is missing from the journal, so it is not trusted on resume: {e:#}"
+                );
+            }
+            if let Err(e) = saved {
+                eprintln!("Warning: failed to update the state file: {e:#}");
+            }
+        });
+    });
+    if let Some(state) = &state {
+        state.save()?;
+    }
+
+    println!(
+        "Retry complete: {} files processed, {} still failing, {} skipped.",
+        totals.processed.load(Ordering::SeqCst),
+        totals.failed.load(Ordering::SeqCst),
+        totals.skipped.load(Ordering::SeqCst)
+    );
+    Ok(totals.exit_code(Some(FailOn::Any)))
+}
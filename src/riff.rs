@@ -0,0 +1,812 @@
+//! Minimal RIFF chunk access for metadata hound does not expose, plus RF64
+//! (the 64-bit WAV variant used for recordings over 4 GB).
+
+use crate::error::DenoiseError;
+use anyhow::{Context, Result, anyhow, bail};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// A top-level chunk inside a RIFF/WAVE file.
+#[derive(Debug, Clone, Copy)]
+pub struct Chunk {
+    pub id: [u8; 4],
+    /// Offset of the chunk payload (just past its 8-byte header).
+    pub offset: u64,
+    /// Payload size; for RF64 files the real size from the `ds64` chunk.
+    pub size: u64,
+}
+
+/// Size field value meaning "see the `ds64` chunk" in RF64 files.
+const RF64_SIZE: u32 = u32::MAX;
+
+/// Lists the top-level chunks of a RIFF/WAVE stream, stopping at the first
+/// truncated or unreadable chunk header.
+pub fn chunks<R: Read + Seek>(reader: &mut R) -> Result<Vec<Chunk>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    let mut header = [0u8; 12];
+    reader
+        .read_exact(&mut header)
+        .context("File too short for a RIFF header")?;
+    let rf64 = &header[0..4] == b"RF64";
+    if !(rf64 || &header[0..4] == b"RIFF") || &header[8..12] != b"WAVE" {
+        bail!("Not a RIFF/WAVE file");
+    }
+
+    let mut data_size = None;
+    let mut chunks = Vec::new();
+    let mut pos = 12u64;
+    while pos + 8 <= len {
+        let mut head = [0u8; 8];
+        reader.seek(SeekFrom::Start(pos))?;
+        if reader.read_exact(&mut head).is_err() {
+            break;
+        }
+        let id = [head[0], head[1], head[2], head[3]];
+        let raw_size = u32::from_le_bytes([head[4], head[5], head[6], head[7]]);
+        let size = match data_size {
+            Some(real) if rf64 && &id == b"data" && raw_size == RF64_SIZE => real,
+            _ => u64::from(raw_size),
+        };
+        if rf64 && &id == b"ds64" && size >= 16 {
+            let mut sizes = [0u8; 16];
+            reader.read_exact(&mut sizes)?;
+            data_size = Some(u64::from_le_bytes(sizes[8..16].try_into().unwrap()));
+        }
+        chunks.push(Chunk {
+            id,
+            offset: pos + 8,
+            size,
+        });
+        // Chunks are padded to an even length
+        pos += 8 + size + (size & 1);
+    }
+    Ok(chunks)
+}
+
+/// Reads all `LIST/INFO` entries of a WAV file as (tag, value) pairs.
+pub fn read_info(path: &Path) -> Result<Vec<([u8; 4], String)>> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
+    let mut entries = Vec::new();
+    for chunk in chunks(&mut file)? {
+        if &chunk.id != b"LIST" || chunk.size < 4 {
+            continue;
+        }
+        let mut data = Vec::new();
+        file.seek(SeekFrom::Start(chunk.offset))?;
+        (&mut file).take(chunk.size).read_to_end(&mut data)?;
+        // The declared size may run past the end of a damaged file
+        if data.len() < 4 || &data[..4] != b"INFO" {
+            continue;
+        }
+        let mut rest = &data[4..];
+        while rest.len() >= 8 {
+            let tag = [rest[0], rest[1], rest[2], rest[3]];
+            let size = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+            let value = &rest[8..(8 + size).min(rest.len())];
+            let value = value.split(|&b| b == 0).next().unwrap_or_default();
+            entries.push((tag, String::from_utf8_lossy(value).into_owned()));
+            rest = &rest[(8 + size + (size & 1)).min(rest.len())..];
+        }
+    }
+    Ok(entries)
+}
+
+/// What the `fmt ` and `data` chunks of a WAV or RF64 file say about its audio.
+#[derive(Debug, Clone, Copy)]
+pub struct Format {
+    /// `WAVE_FORMAT_*` tag; for extensible files the tag of the sub-format.
+    pub tag: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    pub rf64: bool,
+    pub extensible: bool,
+    /// Length of the audio according to the `data` chunk.
+    pub duration_secs: f64,
+    /// Whether the `data` chunk ends before its declared size.
+    pub truncated: bool,
+}
+
+/// Reads the format of a WAV or RF64 file from its chunks, without decoding the samples.
+pub fn format(path: &Path) -> Result<Format> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
+    let len = file.metadata()?.len();
+    let chunks = chunks(&mut file)?;
+    let fmt = chunks
+        .iter()
+        .find(|c| &c.id == b"fmt ")
+        .context("No fmt chunk")?;
+    let data = chunks
+        .iter()
+        .find(|c| &c.id == b"data")
+        .context("No data chunk")?;
+    if fmt.size < 16 {
+        bail!("The fmt chunk is too short ({} bytes)", fmt.size);
+    }
+    let mut head = vec![0u8; fmt.size.min(40) as usize];
+    file.seek(SeekFrom::Start(fmt.offset))?;
+    file.read_exact(&mut head).context("Truncated fmt chunk")?;
+    let u16_at = |i: usize| u16::from_le_bytes([head[i], head[i + 1]]);
+    let mut tag = u16_at(0);
+    let extensible = tag == WAVE_FORMAT_EXTENSIBLE;
+    // The sub-format GUID starts with the plain format tag
+    if extensible && head.len() >= 26 {
+        tag = u16_at(24);
+    }
+    let sample_rate = u32::from_le_bytes(head[4..8].try_into().unwrap());
+    let block_align = u64::from(u16_at(12));
+    let available = len.saturating_sub(data.offset).min(data.size);
+    Ok(Format {
+        tag,
+        channels: u16_at(2),
+        sample_rate,
+        bits_per_sample: u16_at(14),
+        rf64: is_rf64(path),
+        extensible,
+        duration_secs: if block_align == 0 || sample_rate == 0 {
+            0.0
+        } else {
+            (available / block_align) as f64 / f64::from(sample_rate)
+        },
+        truncated: available < data.size,
+    })
+}
+
+/// Reads the payload of the first top-level chunk called `id`, if any.
+pub fn read_chunk(path: &Path, id: &[u8; 4]) -> Result<Option<Vec<u8>>> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
+    let Some(chunk) = chunks(&mut file)?.into_iter().find(|c| &c.id == id) else {
+        return Ok(None);
+    };
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(chunk.offset))?;
+    (&mut file).take(chunk.size).read_to_end(&mut data)?;
+    Ok(Some(data))
+}
+
+/// Turns every top-level chunk called `id` into a `JUNK` chunk, which readers skip.
+pub fn junk_chunks(path: &Path, id: &[u8; 4]) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open file: {}", path.display()))?;
+    for chunk in chunks(&mut file)?.into_iter().filter(|c| &c.id == id) {
+        file.seek(SeekFrom::Start(chunk.offset - 8))?;
+        file.write_all(b"JUNK")?;
+    }
+    Ok(())
+}
+
+/// Appends a `LIST/INFO` chunk with the given entries to the end of a WAV
+/// file and fixes up the RIFF size.
+pub fn append_info(path: &Path, entries: &[([u8; 4], String)]) -> Result<()> {
+    let mut payload = b"INFO".to_vec();
+    for (tag, value) in entries {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+        payload.extend_from_slice(tag);
+        payload.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&bytes);
+        if bytes.len() % 2 == 1 {
+            payload.push(0);
+        }
+    }
+    append_chunk(path, b"LIST", &payload)
+}
+
+/// Appends a raw chunk to the end of a WAV file and fixes up the RIFF size
+/// (in the `ds64` chunk for RF64 files).
+pub fn append_chunk(path: &Path, id: &[u8; 4], payload: &[u8]) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open file: {}", path.display()))?;
+    let ds64 = chunks(&mut file)
+        .with_context(|| format!("Not a WAV file: {}", path.display()))?
+        .into_iter()
+        .find(|c| &c.id == b"ds64");
+
+    let mut len = file.seek(SeekFrom::End(0))?;
+    if len % 2 == 1 {
+        file.write_all(&[0])?;
+        len += 1;
+    }
+    let new_len = len + 8 + payload.len() as u64 + (payload.len() as u64 & 1);
+    let riff_size = match ds64 {
+        Some(_) => None,
+        None => Some(
+            u32::try_from(new_len - 8)
+                .with_context(|| format!("File too large for a RIFF chunk: {}", path.display()))?,
+        ),
+    };
+
+    file.write_all(id)?;
+    file.write_all(&(payload.len() as u32).to_le_bytes())?;
+    file.write_all(payload)?;
+    if payload.len() % 2 == 1 {
+        file.write_all(&[0])?;
+    }
+    match (riff_size, ds64) {
+        (Some(size), _) => {
+            file.seek(SeekFrom::Start(4))?;
+            file.write_all(&size.to_le_bytes())?;
+        }
+        (None, Some(ds64)) => {
+            file.seek(SeekFrom::Start(ds64.offset))?;
+            file.write_all(&(new_len - 8).to_le_bytes())?;
+        }
+        (None, None) => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Whether `path` starts with an RF64 header.
+pub fn is_rf64(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .is_ok()
+        && &magic == b"RF64"
+}
+
+/// Whether the file at `path` starts like a RIFF or RF64 WAV file.
+pub fn is_wav(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .is_ok()
+        && (&magic == b"RIFF" || &magic == b"RF64")
+}
+
+/// Format tag of `fmt ` chunks that carry a WAVE_FORMAT_EXTENSIBLE header.
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// Copies a WAVE_FORMAT_EXTENSIBLE file to `dest` with the plain PCM or float
+/// header its sub-format stands for; returns `false`, writing nothing, for
+/// files that are not extensible.
+///
+/// Only the basic fields are kept, so extensions other readers reject in
+/// files from the wild (an odd `cbSize`, zero valid bits) are dropped.
+pub fn unwrap_extensible(path: &Path, dest: &Path) -> Result<bool> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
+    let (format, data) = format_and_data(&mut file, path)?;
+    if format.len() < 16 || u16::from_le_bytes([format[0], format[1]]) != WAVE_FORMAT_EXTENSIBLE {
+        return Ok(false);
+    }
+    // The sub-format GUID starts with the plain format tag it extends
+    let tag = match format.get(24..26) {
+        Some(&[1, 0]) => 1u16,
+        Some(&[3, 0]) => 3,
+        _ => {
+            return Err(anyhow!(
+                "Unsupported WAVE_FORMAT_EXTENSIBLE sub-format in {}",
+                path.display()
+            )
+            .context(DenoiseError::InvalidFormat));
+        }
+    };
+    let mut plain = format[..16].to_vec();
+    plain[0..2].copy_from_slice(&tag.to_le_bytes());
+
+    let mut out = std::io::BufWriter::new(
+        File::create(dest).with_context(|| format!("Failed to create file: {}", dest.display()))?,
+    );
+    write_header(&mut out, &plain, data.size)?;
+    file.seek(SeekFrom::Start(data.offset))?;
+    std::io::copy(&mut file.take(data.size), &mut out)?;
+    if data.size % 2 == 1 {
+        out.write_all(&[0])?;
+    }
+    out.flush()
+        .with_context(|| format!("Failed to write file: {}", dest.display()))?;
+    Ok(true)
+}
+
+/// The raw `fmt ` payload and the `data` chunk of a WAV or RF64 file.
+fn format_and_data(file: &mut File, path: &Path) -> Result<(Vec<u8>, Chunk)> {
+    let chunks = chunks(file)
+        .with_context(|| format!("Not a WAV file: {}", path.display()))
+        .context(DenoiseError::InvalidFormat)?;
+    let fmt = chunks
+        .iter()
+        .find(|c| &c.id == b"fmt ")
+        .with_context(|| format!("No fmt chunk in {}", path.display()))?;
+    let data = *chunks
+        .iter()
+        .find(|c| &c.id == b"data")
+        .with_context(|| format!("No data chunk in {}", path.display()))?;
+    let mut format = vec![0u8; fmt.size as usize];
+    file.seek(SeekFrom::Start(fmt.offset))?;
+    file.read_exact(&mut format)?;
+    Ok((format, data))
+}
+
+/// Writes a RIFF (or, when `data_len` needs it, RF64) header for a file whose
+/// data chunk of `data_len` bytes follows immediately.
+fn write_header(out: &mut impl Write, format: &[u8], data_len: u64) -> Result<()> {
+    let fmt_len = 8 + format.len() as u64 + (format.len() as u64 & 1);
+    let riff_len = 4 + fmt_len + 8 + data_len + (data_len & 1);
+    match u32::try_from(riff_len) {
+        Ok(riff_len) if riff_len != RF64_SIZE => {
+            out.write_all(b"RIFF")?;
+            out.write_all(&riff_len.to_le_bytes())?;
+            out.write_all(b"WAVE")?;
+        }
+        _ => {
+            let riff_len = riff_len + 8 + 28;
+            out.write_all(b"RF64")?;
+            out.write_all(&RF64_SIZE.to_le_bytes())?;
+            out.write_all(b"WAVE")?;
+            out.write_all(b"ds64")?;
+            out.write_all(&28u32.to_le_bytes())?;
+            out.write_all(&riff_len.to_le_bytes())?;
+            out.write_all(&data_len.to_le_bytes())?;
+            // Sample count is optional; readers derive it from the data size
+            out.write_all(&0u64.to_le_bytes())?;
+            out.write_all(&0u32.to_le_bytes())?;
+        }
+    }
+    out.write_all(b"fmt ")?;
+    out.write_all(&(format.len() as u32).to_le_bytes())?;
+    out.write_all(format)?;
+    if format.len() % 2 == 1 {
+        out.write_all(&[0])?;
+    }
+    out.write_all(b"data")?;
+    let size = u32::try_from(data_len).unwrap_or(RF64_SIZE);
+    out.write_all(&size.to_le_bytes())?;
+    Ok(())
+}
+
+/// Copies a WAV file whose `data` chunk does not match the file to `dest`
+/// with only its `fmt ` chunk and the audio that is really there, for
+/// `--tolerant-parse`; returns what was fixed, writing nothing when the
+/// file is sound.
+///
+/// A data length past the end of the file is cut to the end, an unset (zero)
+/// one followed by bytes that are no chunks takes the rest of the file, a
+/// partial last frame is dropped, and so are bytes after the audio that are
+/// no chunks.
+pub fn repair(path: &Path, dest: &Path) -> Result<Vec<String>> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
+    let len = file.metadata()?.len();
+    let (format, data) = format_and_data(&mut file, path)?;
+    if format.len() < 16 {
+        bail!("The fmt chunk is too short ({} bytes)", format.len());
+    }
+    let mut fixes = Vec::new();
+    let available = len.saturating_sub(data.offset);
+    let mut size = data.size;
+    if size == 0 && available > 0 && garbage_from(&mut file, data.offset, len)? == Some(data.offset)
+    {
+        size = available;
+        fixes.push(format!(
+            "the data chunk length is unset; took the {available} bytes after it"
+        ));
+    } else if size > available {
+        fixes.push(format!(
+            "the data chunk declares {size} bytes but only {available} are there"
+        ));
+        size = available;
+    }
+    let block_align = match u64::from(u16::from_le_bytes([format[12], format[13]])) {
+        0 => {
+            let channels = u64::from(u16::from_le_bytes([format[2], format[3]]));
+            let bits = u64::from(u16::from_le_bytes([format[14], format[15]]));
+            (channels * bits.div_ceil(8)).max(1)
+        }
+        align => align,
+    };
+    if size % block_align != 0 {
+        fixes.push(format!(
+            "dropped a partial frame of {} bytes",
+            size % block_align
+        ));
+        size -= size % block_align;
+    }
+    let end = data.offset + data.size + (data.size & 1);
+    if fixes.is_empty()
+        && let Some(from) = garbage_from(&mut file, end, len)?
+    {
+        fixes.push(format!("dropped {} bytes of trailing garbage", len - from));
+    }
+    if fixes.is_empty() {
+        return Ok(fixes);
+    }
+
+    let mut out = std::io::BufWriter::new(
+        File::create(dest).with_context(|| format!("Failed to create file: {}", dest.display()))?,
+    );
+    write_header(&mut out, &format, size)?;
+    file.seek(SeekFrom::Start(data.offset))?;
+    std::io::copy(&mut file.take(size), &mut out)?;
+    if size % 2 == 1 {
+        out.write_all(&[0])?;
+    }
+    out.flush()
+        .with_context(|| format!("Failed to write file: {}", dest.display()))?;
+    Ok(fixes)
+}
+
+/// Where the bytes from `pos` to `len` stop being well-formed chunks (an
+/// ASCII id and a size that fits the file), if they do.
+fn garbage_from(file: &mut File, mut pos: u64, len: u64) -> Result<Option<u64>> {
+    while pos < len {
+        let mut head = [0u8; 8];
+        file.seek(SeekFrom::Start(pos))?;
+        if pos + 8 > len || file.read_exact(&mut head).is_err() {
+            return Ok(Some(pos));
+        }
+        let size = u64::from(u32::from_le_bytes([head[4], head[5], head[6], head[7]]));
+        let next = pos + 8 + size + (size & 1);
+        // The pad byte of a last chunk is often left out
+        if !head[..4].iter().all(|b| (0x20..0x7f).contains(b)) || pos + 8 + size > len {
+            return Ok(Some(pos));
+        }
+        pos = next;
+    }
+    Ok(None)
+}
+
+/// Splits the audio of a WAV or RF64 file into plain WAV files of at most
+/// `max_bytes` of sample data each, written to the paths `part` returns.
+pub fn split(
+    path: &Path,
+    max_bytes: u64,
+    mut part: impl FnMut(usize) -> PathBuf,
+) -> Result<Vec<PathBuf>> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
+    let (format, data) = format_and_data(&mut file, path)?;
+    // Block align (bytes per frame) lives at offset 12 of the fmt payload
+    let align = format
+        .get(12..14)
+        .map_or(1, |b| u64::from(u16::from_le_bytes([b[0], b[1]])).max(1));
+    let step = (max_bytes / align).max(1) * align;
+
+    let mut parts = Vec::new();
+    let mut offset = 0;
+    while offset < data.size {
+        let len = step.min(data.size - offset);
+        let dest = part(parts.len());
+        let mut out = std::io::BufWriter::new(
+            File::create(&dest)
+                .with_context(|| format!("Failed to create file: {}", dest.display()))?,
+        );
+        write_header(&mut out, &format, len)?;
+        file.seek(SeekFrom::Start(data.offset + offset))?;
+        std::io::copy(&mut (&mut file).take(len), &mut out)?;
+        if len % 2 == 1 {
+            out.write_all(&[0])?;
+        }
+        out.flush()?;
+        parts.push(dest);
+        offset += len;
+    }
+    Ok(parts)
+}
+
+/// Concatenates the audio of `parts` (which must share one format) into
+/// `dest`, writing RF64 when the result exceeds the 4 GB RIFF limit.
+pub fn join(parts: &[PathBuf], dest: &Path) -> Result<()> {
+    let mut sources = Vec::new();
+    for path in parts {
+        let mut file =
+            File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
+        let (format, data) = format_and_data(&mut file, path)?;
+        sources.push((file, format, data));
+    }
+    let Some((_, format, _)) = sources.first() else {
+        bail!("Nothing to join into {}", dest.display());
+    };
+    let format = format.clone();
+    if sources.iter().any(|(_, f, _)| *f != format) {
+        bail!("Segments of {} differ in format", dest.display());
+    }
+    let total: u64 = sources.iter().map(|(_, _, data)| data.size).sum();
+
+    let mut out = std::io::BufWriter::new(
+        File::create(dest).with_context(|| format!("Failed to create file: {}", dest.display()))?,
+    );
+    write_header(&mut out, &format, total)?;
+    for (mut file, _, data) in sources {
+        file.seek(SeekFrom::Start(data.offset))?;
+        std::io::copy(&mut file.take(data.size), &mut out)?;
+    }
+    if total % 2 == 1 {
+        out.write_all(&[0])?;
+    }
+    out.flush()
+        .with_context(|| format!("Failed to write file: {}", dest.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A `fmt ` payload for PCM with `channels` and `bits`, at 16kHz.
+    fn pcm(channels: u16, bits: u16) -> Vec<u8> {
+        let align = channels * bits.div_ceil(8);
+        let mut format = Vec::new();
+        format.extend_from_slice(&1u16.to_le_bytes());
+        format.extend_from_slice(&channels.to_le_bytes());
+        format.extend_from_slice(&16_000u32.to_le_bytes());
+        format.extend_from_slice(&(16_000 * u32::from(align)).to_le_bytes());
+        format.extend_from_slice(&align.to_le_bytes());
+        format.extend_from_slice(&bits.to_le_bytes());
+        format
+    }
+
+    /// A RIFF file of `format` and a `data` chunk declaring `declared` bytes
+    /// holding `data`, followed by `after`.
+    fn wav(format: &[u8], declared: u32, data: &[u8], after: &[u8]) -> Vec<u8> {
+        let mut bytes = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        bytes.extend_from_slice(&(format.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(format);
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&declared.to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes.extend_from_slice(after);
+        let riff = (bytes.len() - 8) as u32;
+        bytes[4..8].copy_from_slice(&riff.to_le_bytes());
+        bytes
+    }
+
+    /// An RF64 file of `format` whose `data` chunk holds `data`.
+    fn rf64(format: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut bytes = b"RF64".to_vec();
+        bytes.extend_from_slice(&RF64_SIZE.to_le_bytes());
+        bytes.extend_from_slice(b"WAVEds64");
+        bytes.extend_from_slice(&28u32.to_le_bytes());
+        let riff = 4 + 36 + 8 + format.len() + 8 + data.len();
+        bytes.extend_from_slice(&(riff as u64).to_le_bytes());
+        bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&[0; 12]);
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&(format.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(format);
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&RF64_SIZE.to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    /// A fresh directory for one test's files.
+    fn scratch(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "{}-riff-{}-{test}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes `bytes` to `name` in `dir`.
+    fn file(dir: &Path, name: &str, bytes: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    /// The payload of the `data` chunk of the file at `path`.
+    fn audio(path: &Path) -> Vec<u8> {
+        read_chunk(path, b"data").unwrap().unwrap()
+    }
+
+    /// Repairs the file `bytes`, returning the fixes and the repaired file's bytes.
+    fn repaired(test: &str, bytes: &[u8]) -> (Vec<String>, Vec<u8>) {
+        let dir = scratch(test);
+        let input = file(&dir, "in.wav", bytes);
+        let dest = dir.join("out.wav");
+        let fixes = repair(&input, &dest).unwrap();
+        let out = std::fs::read(&dest).unwrap_or_default();
+        std::fs::remove_dir_all(&dir).unwrap();
+        (fixes, out)
+    }
+
+    /// Parses repaired bytes back into (chunk ids, data payload).
+    fn parsed(bytes: &[u8]) -> (Vec<[u8; 4]>, Vec<u8>) {
+        let mut cursor = Cursor::new(bytes);
+        let chunks = chunks(&mut cursor).unwrap();
+        let data = chunks.iter().find(|c| &c.id == b"data").unwrap();
+        let payload = bytes[data.offset as usize..][..data.size as usize].to_vec();
+        (chunks.iter().map(|c| c.id).collect(), payload)
+    }
+
+    #[test]
+    fn repair_leaves_sound_files_alone() {
+        let (fixes, out) = repaired("sound", &wav(&pcm(1, 16), 8, &[1; 8], b""));
+        assert!(fixes.is_empty());
+        assert!(out.is_empty(), "nothing is written for a sound file");
+    }
+
+    #[test]
+    fn repair_cuts_truncated_data_to_the_file() {
+        let samples: Vec<u8> = (0..40).collect();
+        let (fixes, out) = repaired("truncated", &wav(&pcm(1, 16), 100, &samples, b""));
+        assert_eq!(
+            fixes,
+            ["the data chunk declares 100 bytes but only 40 are there"]
+        );
+        let (ids, data) = parsed(&out);
+        assert_eq!(ids, [*b"fmt ", *b"data"]);
+        assert_eq!(data, samples);
+        assert_eq!(out.len(), 44 + 40);
+        assert_eq!(u32::from_le_bytes(out[4..8].try_into().unwrap()), 36 + 40);
+    }
+
+    #[test]
+    fn repair_takes_the_rest_for_an_unset_length() {
+        let samples = [7u8; 12];
+        let (fixes, out) = repaired("unset", &wav(&pcm(1, 16), 0, &samples, b""));
+        assert_eq!(
+            fixes,
+            ["the data chunk length is unset; took the 12 bytes after it"]
+        );
+        assert_eq!(parsed(&out).1, samples);
+    }
+
+    #[test]
+    fn repair_drops_trailing_garbage() {
+        let samples = [3u8; 16];
+        let (fixes, out) = repaired(
+            "garbage",
+            &wav(&pcm(2, 16), 16, &samples, &[0xff, 0x00, 0x13, 0x80, 0x01]),
+        );
+        assert_eq!(fixes, ["dropped 5 bytes of trailing garbage"]);
+        assert_eq!(parsed(&out).1, samples);
+        assert_eq!(out.len(), 44 + 16);
+    }
+
+    #[test]
+    fn repair_keeps_chunks_after_the_audio() {
+        let mut after = b"LIST".to_vec();
+        after.extend_from_slice(&4u32.to_le_bytes());
+        after.extend_from_slice(b"INFO");
+        let (fixes, out) = repaired("chunks", &wav(&pcm(1, 16), 4, &[1; 4], &after));
+        assert!(fixes.is_empty(), "{fixes:?}");
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn repair_drops_a_partial_frame() {
+        let samples: Vec<u8> = (0..13).collect();
+        let (fixes, out) = repaired("partial", &wav(&pcm(2, 16), 13, &samples, b""));
+        assert_eq!(fixes, ["dropped a partial frame of 1 bytes"]);
+        assert_eq!(parsed(&out).1, samples[..12]);
+    }
+
+    #[test]
+    fn repair_pads_odd_sized_data() {
+        let samples = [9u8, 8, 7];
+        let (fixes, out) = repaired("odd", &wav(&pcm(1, 8), 5, &samples, b""));
+        assert_eq!(
+            fixes,
+            ["the data chunk declares 5 bytes but only 3 are there"]
+        );
+        // 3 bytes of audio and the pad byte that keeps chunks even
+        assert_eq!(out.len(), 44 + 3 + 1);
+        assert_eq!(out[out.len() - 1], 0);
+        assert_eq!(u32::from_le_bytes(out[4..8].try_into().unwrap()), 36 + 4);
+        let (ids, data) = parsed(&out);
+        assert_eq!(ids, [*b"fmt ", *b"data"]);
+        assert_eq!(data, samples);
+    }
+
+    #[test]
+    fn chunks_reads_the_data_size_of_rf64_from_ds64() {
+        let samples = [5u8; 10];
+        let bytes = rf64(&pcm(1, 16), &samples);
+        let chunks = chunks(&mut Cursor::new(&bytes)).unwrap();
+        let data = chunks.iter().find(|c| &c.id == b"data").unwrap();
+        assert_eq!(data.size, 10);
+    }
+
+    #[test]
+    fn write_header_switches_to_rf64_past_4_gb() {
+        let format = pcm(1, 16);
+        let data_len = 5u64 << 30;
+        let mut header = Cursor::new(Vec::new());
+        write_header(&mut header, &format, data_len).unwrap();
+        let header = header.into_inner();
+        assert_eq!(&header[..4], b"RF64");
+        let chunks = chunks(&mut Cursor::new(&header)).unwrap();
+        let ids: Vec<[u8; 4]> = chunks.iter().map(|c| c.id).collect();
+        assert_eq!(ids, [*b"ds64", *b"fmt ", *b"data"]);
+        assert_eq!(chunks[2].size, data_len);
+        assert_eq!(chunks[2].offset, header.len() as u64);
+
+        let mut small = Cursor::new(Vec::new());
+        write_header(&mut small, &format, 1000).unwrap();
+        assert_eq!(&small.into_inner()[..4], b"RIFF");
+    }
+
+    #[test]
+    fn split_and_join_rf64_round_trip() {
+        let dir = scratch("rf64");
+        let samples: Vec<u8> = (0..100).collect();
+        let input = file(&dir, "in.wav", &rf64(&pcm(1, 16), &samples));
+        assert!(is_rf64(&input));
+
+        let parts = split(&input, 31, |i| dir.join(format!("part{i}.wav"))).unwrap();
+        // Parts are cut on whole frames: 30 bytes, not 31
+        let sizes: Vec<usize> = parts.iter().map(|p| audio(p).len()).collect();
+        assert_eq!(sizes, [30, 30, 30, 10]);
+        assert!(parts.iter().all(|p| !is_rf64(p)));
+
+        let joined = dir.join("joined.wav");
+        join(&parts, &joined).unwrap();
+        assert_eq!(audio(&joined), samples);
+        let format = format(&joined).unwrap();
+        assert_eq!((format.channels, format.bits_per_sample), (1, 16));
+        assert!(!format.truncated);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn join_refuses_parts_of_different_formats() {
+        let dir = scratch("formats");
+        let mono = file(&dir, "mono.wav", &wav(&pcm(1, 16), 4, &[0; 4], b""));
+        let stereo = file(&dir, "stereo.wav", &wav(&pcm(2, 16), 4, &[0; 4], b""));
+        let err = join(&[mono, stereo], &dir.join("out.wav")).unwrap_err();
+        assert!(err.to_string().contains("differ in format"), "{err}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_info_round_trips_append_info() {
+        let dir = scratch("info");
+        let path = file(&dir, "in.wav", &wav(&pcm(1, 16), 3, &[1, 2, 3], b"\0"));
+        let entries = vec![
+            (*b"INAM", "Take 1".to_string()),
+            (*b"ICMT", "odd".to_string()),
+        ];
+        append_info(&path, &entries).unwrap();
+        assert_eq!(read_info(&path).unwrap(), entries);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_info_reads_what_is_left_of_a_truncated_chunk() {
+        let dir = scratch("info-truncated");
+        let mut list = b"LIST".to_vec();
+        list.extend_from_slice(&200u32.to_le_bytes());
+        list.extend_from_slice(b"INFOINAM");
+        list.extend_from_slice(&6u32.to_le_bytes());
+        list.extend_from_slice(b"Title\0");
+        // An entry declaring 50 bytes, cut off after 3
+        list.extend_from_slice(b"IART");
+        list.extend_from_slice(&50u32.to_le_bytes());
+        list.extend_from_slice(b"Ann");
+        let path = file(&dir, "in.wav", &wav(&pcm(1, 16), 2, &[0; 2], &list));
+        assert_eq!(
+            read_info(&path).unwrap(),
+            [
+                (*b"INAM", "Title".to_string()),
+                (*b"IART", "Ann".to_string())
+            ]
+        );
+
+        // A LIST chunk cut off inside its INFO tag is skipped
+        let path = file(
+            &dir,
+            "cut.wav",
+            &wav(&pcm(1, 16), 2, &[0; 2], b"LIST\x10\0\0\0IN"),
+        );
+        assert!(read_info(&path).unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
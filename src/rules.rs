@@ -0,0 +1,174 @@
+//! `--model-rules`: picks each input's model from the attributes in a JSON
+//! sidecar beside it, so a corpus of mixed languages or recording chains is
+//! denoised in one pass. The rules are a TOML file of `[[rule]]` tables, tried
+//! in order; a rule applies when every attribute it names has one of the
+//! values it lists, and the first that applies gives the model:
+//!
+//! ```toml
+//! [[rule]]
+//! language = "de"
+//! channel_type = ["telephone", "voip"]
+//! model = "de-telephony"
+//!
+//! [[rule]]
+//! language = "de"
+//! model = "/models/de.rnnn"
+//! ```
+//!
+//! Inputs without a sidecar, or that no rule matches, get `--model`.
+
+use anyhow::{Context, Result, bail};
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use walkdir::DirEntry;
+
+#[derive(Deserialize)]
+struct RulesFile {
+    #[serde(default)]
+    rule: Vec<RuleTable>,
+}
+
+#[derive(Deserialize)]
+struct RuleTable {
+    model: String,
+    #[serde(flatten)]
+    when: BTreeMap<String, toml::Value>,
+}
+
+struct Rule {
+    model: String,
+    /// Each attribute with the values that match it.
+    when: BTreeMap<String, Vec<String>>,
+}
+
+pub struct ModelRules {
+    rules: Vec<Rule>,
+}
+
+impl ModelRules {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read model rules: {}", path.display()))?;
+        let file: RulesFile = toml::from_str(&text)
+            .with_context(|| format!("Malformed model rules: {}", path.display()))?;
+        let mut rules = Vec::new();
+        for (i, table) in file.rule.into_iter().enumerate() {
+            let mut when = BTreeMap::new();
+            for (attribute, value) in table.when {
+                let values = match value {
+                    toml::Value::Array(items) => items.iter().map(scalar).collect(),
+                    value => scalar(&value).map(|value| vec![value]),
+                };
+                match values {
+                    Some(values) => when.insert(attribute, values),
+                    None => bail!(
+                        "Rule {} of {}: '{attribute}' must be a string, number or boolean, or a list of them",
+                        i + 1,
+                        path.display()
+                    ),
+                };
+            }
+            rules.push(Rule {
+                model: table.model,
+                when,
+            });
+        }
+        if rules.is_empty() {
+            bail!("No [[rule]] tables in {}", path.display());
+        }
+        Ok(ModelRules { rules })
+    }
+
+    /// Every model a rule names, once each.
+    pub fn models(&self) -> Vec<&str> {
+        let mut models: Vec<&str> = self.rules.iter().map(|r| r.model.as_str()).collect();
+        models.sort_unstable();
+        models.dedup();
+        models
+    }
+
+    /// The model of the first rule the sidecar of `input` matches, if any.
+    pub fn model_for(&self, input: &Path) -> Option<&str> {
+        let attributes = attributes(input)?;
+        self.rules
+            .iter()
+            .find(|rule| {
+                rule.when.iter().all(|(attribute, values)| {
+                    attributes
+                        .get(attribute)
+                        .is_some_and(|value| values.contains(value))
+                })
+            })
+            .map(|rule| rule.model.as_str())
+    }
+
+    /// The model a rule picks for each of `files` a rule matches, by its
+    /// path relative to `input_dir`.
+    pub fn choose(&self, input_dir: &Path, files: &[DirEntry]) -> HashMap<String, &str> {
+        files
+            .par_iter()
+            .filter_map(|e| {
+                let model = self.model_for(e.path())?;
+                let key = e.path().strip_prefix(input_dir).unwrap_or(e.path());
+                Some((key.to_string_lossy().to_string(), model))
+            })
+            .collect()
+    }
+}
+
+/// A rule value as the text sidecar values are compared with.
+fn scalar(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(s) => Some(s.clone()),
+        toml::Value::Integer(n) => Some(n.to_string()),
+        toml::Value::Float(n) => Some(n.to_string()),
+        toml::Value::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// The top-level attributes of the sidecar beside `input`, `NAME.wav.json`
+/// or else `NAME.json`, as text.
+fn attributes(input: &Path) -> Option<BTreeMap<String, String>> {
+    let mut beside = input.as_os_str().to_owned();
+    beside.push(".json");
+    let path = [PathBuf::from(beside), input.with_extension("json")]
+        .into_iter()
+        .find(|p| p.is_file())?;
+    let parsed = std::fs::read(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(|bytes| Ok(serde_json::from_slice::<serde_json::Value>(&bytes)?));
+    let object = match parsed {
+        Ok(serde_json::Value::Object(object)) => object,
+        Ok(_) => {
+            eprintln!(
+                "Warning: {} is not a JSON object; --model-rules ignores it",
+                path.display()
+            );
+            return None;
+        }
+        Err(e) => {
+            eprintln!(
+                "Warning: cannot read {}: {e:#}; --model-rules ignores it",
+                path.display()
+            );
+            return None;
+        }
+    };
+    Some(
+        object
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let text = match value {
+                    serde_json::Value::String(s) => s,
+                    serde_json::Value::Number(n) => n.to_string(),
+                    serde_json::Value::Bool(b) => b.to_string(),
+                    _ => return None,
+                };
+                Some((key, text))
+            })
+            .collect(),
+    )
+}
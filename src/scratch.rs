@@ -0,0 +1,69 @@
+//! `--temp-dir`: intermediate files (converted inputs, chunks, plugin and
+//! backend outputs) are staged in a scratch directory, e.g. on fast local
+//! disk, instead of beside outputs that may live on a network mount. Each
+//! thread stages into a subdirectory of its own, and subprocesses get the
+//! scratch directory as `TMPDIR`.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// This run's directory under `--temp-dir`, once [`init`] has run.
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+static NEXT_WORKER: AtomicUsize = AtomicUsize::new(0);
+static NEXT_FILE: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static WORKER: usize = NEXT_WORKER.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Removes the run's scratch directory when dropped.
+pub struct Scratch(PathBuf);
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Creates this run's scratch directory under `dir` and points `TMPDIR` at
+/// it. Must be called before any other thread starts.
+pub fn init(dir: &Path) -> Result<Scratch> {
+    let root = dir.join(format!("{}-{}", env!("CARGO_PKG_NAME"), std::process::id()));
+    std::fs::create_dir_all(&root)
+        .with_context(|| format!("Failed to create temp directory: {}", root.display()))?;
+    // SAFETY: main calls this before spawning any thread, so nothing reads the environment concurrently
+    unsafe { std::env::set_var("TMPDIR", &root) };
+    let _ = ROOT.set(root.clone());
+    Ok(Scratch(root))
+}
+
+/// A fresh path in the calling thread's scratch directory for a file called
+/// `name`, or `None` without `--temp-dir`.
+pub fn path(name: &str) -> Option<PathBuf> {
+    let root = ROOT.get()?;
+    let dir = root.join(format!("worker-{}", WORKER.with(|w| *w)));
+    // A failure here surfaces as soon as the file is created
+    let _ = std::fs::create_dir_all(&dir);
+    // Threads may stage for several files with the same name at once
+    let n = NEXT_FILE.fetch_add(1, Ordering::Relaxed);
+    // Files staged from files staged here keep a single number
+    let name = name
+        .strip_prefix('.')
+        .and_then(|rest| rest.split_once('.'))
+        .filter(|(n, _)| n.bytes().all(|b| b.is_ascii_digit()))
+        .map_or(name, |(_, rest)| rest);
+    Some(dir.join(format!(".{n}.{name}")))
+}
+
+/// Moves `from` to `to`, copying when they are on different filesystems.
+pub fn persist(from: &Path, to: &Path) -> Result<()> {
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    std::fs::copy(from, to).with_context(|| format!("Failed to write output: {}", to.display()))?;
+    let _ = std::fs::remove_file(from);
+    Ok(())
+}
@@ -0,0 +1,886 @@
+//! HTTP server mode with an asynchronous job queue.
+//!
+//! Uploads are spooled to disk and denoised by a bounded worker pool, so large
+//! files never hold an HTTP request open while they are processed:
+//!
+//! - `POST /jobs` with a WAV body queues a job and answers `202 {"id": ...}`,
+//!   or `503` when the queue is full; `?priority=high|normal|low` picks its
+//!   line in the queue (see [`crate::queue`])
+//! - `GET /jobs/{id}` reports `{"id", "status", "priority", "code", "error"}`, where status is
+//!   `queued`, `running`, `done` or `failed`
+//! - `GET /jobs/{id}/result` downloads the denoised file once the job is done
+//! - `DELETE /jobs/{id}` forgets a finished job and removes its files
+//! - `GET /metrics` exposes counters, queue depth and latencies for Prometheus
+//! - `GET /healthz` answers `200` while the process serves requests, and
+//!   `GET /readyz` `200` once the pipeline is loaded and while the queue has
+//!   room, else `503` with the reason, for orchestrator probes and load balancers
+//! - `GET /stream` upgrades to a WebSocket that denoises live audio (below)
+//! - `GET /openapi.json` describes these endpoints (see [`crate::openapi`]),
+//!   and `GET /docs` renders that as Swagger UI with `--swagger-ui`
+//!
+//! The server listens while the pipeline and its model load, so probes get
+//! answers from the start; jobs submitted meanwhile wait in the queue.
+//!
+//! When tokens are configured (see [`crate::auth`]) every request but the
+//! documentation, probe and metrics routes needs an `Authorization: Bearer`
+//! header; others get `401`, and `429` once a token's rate limit is used up.
+//!
+//! So one client cannot take over a shared server, uploads over
+//! `--max-upload-size` get `413`, and a client (token, or address without
+//! tokens) with `--max-jobs-per-client` jobs queued or running gets `429`
+//! until one finishes.
+//!
+//! With `--job-ttl`, finished jobs and their files are removed that long
+//! after they finish, as are files in the spool directory that belong to no
+//! job (left by a previous run). With `--max-spool-size`, uploads get `507`
+//! while the spool directory holds that much.
+//!
+//! A `/stream` client sends binary messages of 48kHz 16-bit little-endian mono
+//! PCM and gets the denoised samples back in binary messages as each 10ms
+//! frame completes, about one frame behind. An empty message ends the audio:
+//! the rest of the output follows, and a new stream may start. Streams use
+//! the built-in model (with `--model` weights under `--backend nnnoiseless`),
+//! not the pipeline, and run on threads of their own, `--max-streams` at once.
+
+use crate::auth::{Auth, AuthArgs, Denied};
+use crate::backend::{BackendKind, Nnnoiseless};
+use crate::error::DenoiseError;
+use crate::metrics::Metrics;
+use crate::openapi;
+use crate::pipeline::{Outcome, Pipeline, PipelineArgs};
+use crate::queue::{JobQueue, Priority};
+use crate::rng::Rng;
+use crate::space::ByteSize;
+use crate::websocket::{self, Message, Violation};
+use anyhow::{Context, Result, anyhow};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread::Scope;
+use std::time::{Duration, Instant};
+use tiny_http::{Header, Method, Request, Response, ResponseBox};
+
+/// Threads accepting HTTP requests; uploads are copied to the spool on these.
+const HTTP_THREADS: usize = 4;
+
+/// Longest time between sweeps for expired jobs.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Largest `/stream` message accepted: about 10 seconds of audio.
+const MAX_STREAM_MESSAGE: usize = 1 << 20;
+
+/// Sample rate of `/stream` audio.
+const STREAM_RATE: usize = 48000;
+
+/// An endpoint of the API, found by [`Route::of`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Route<'a> {
+    Health,
+    Readiness,
+    Metrics,
+    OpenApi,
+    Docs,
+    Stream,
+    Submit,
+    Status(&'a str),
+    Result(&'a str),
+    Delete(&'a str),
+}
+
+impl<'a> Route<'a> {
+    /// The route of a request for `url`; `/docs` is only served with `--swagger-ui`.
+    fn of(method: &Method, url: &'a str, swagger_ui: bool) -> Option<Self> {
+        let path = url.split('?').next().unwrap_or_default();
+        let segments: Vec<&'a str> = path.trim_matches('/').split('/').collect();
+        Some(match (method, segments.as_slice()) {
+            (Method::Get, ["healthz"]) => Route::Health,
+            (Method::Get, ["readyz"]) => Route::Readiness,
+            (Method::Get, ["metrics"]) => Route::Metrics,
+            (Method::Get, ["openapi.json"]) => Route::OpenApi,
+            (Method::Get, ["docs"]) if swagger_ui => Route::Docs,
+            (Method::Get, ["stream"]) => Route::Stream,
+            (Method::Post, ["jobs"]) => Route::Submit,
+            (Method::Get, ["jobs", id]) => Route::Status(id),
+            (Method::Get, ["jobs", id, "result"]) => Route::Result(id),
+            (Method::Delete, ["jobs", id]) => Route::Delete(id),
+            _ => return None,
+        })
+    }
+
+    /// Whether the route needs no token: client generators, browsers, probes
+    /// and Prometheus get by without one.
+    fn public(self) -> bool {
+        matches!(
+            self,
+            Route::Health | Route::Readiness | Route::Metrics | Route::OpenApi | Route::Docs
+        )
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+
+    /// Jobs denoised concurrently [default: one per API address]
+    #[arg(long)]
+    workers: Option<usize>,
+
+    /// Jobs that may wait in the queue before uploads are rejected
+    #[arg(long, default_value_t = 64)]
+    queue_size: usize,
+
+    /// Shares of the workers that high, normal and low priority jobs get while all are queued
+    #[arg(long, value_delimiter = ',', value_name = "HIGH,NORMAL,LOW", default_values_t = [8, 2, 1])]
+    priority_weights: Vec<u32>,
+
+    /// Directory for uploaded and denoised files [default: system temp directory]
+    #[arg(long)]
+    spool_dir: Option<PathBuf>,
+
+    /// Remove finished jobs and their files this many seconds after they finish
+    #[arg(long, value_name = "SECS")]
+    job_ttl: Option<f64>,
+
+    /// Reject uploads while the spool directory holds this much, e.g. 20G
+    #[arg(long, value_name = "SIZE")]
+    max_spool_size: Option<ByteSize>,
+
+    /// Reject uploads larger than this, e.g. 500M
+    #[arg(long, value_name = "SIZE")]
+    max_upload_size: Option<ByteSize>,
+
+    /// Jobs one client may have queued or running at once (0 = unlimited)
+    #[arg(long, default_value_t = 0, value_name = "N")]
+    max_jobs_per_client: usize,
+
+    /// WebSocket sessions on /stream served at once
+    #[arg(long, default_value_t = 8, value_name = "N")]
+    max_streams: usize,
+
+    /// Denoised audio collected before /stream sends it, a multiple of 10 ms; more means fewer, later messages
+    #[arg(long, default_value_t = 10, value_name = "MS")]
+    frame_size: usize,
+
+    /// Serve a Swagger UI page for the API at /docs
+    #[arg(long)]
+    swagger_ui: bool,
+
+    #[command(flatten)]
+    pipeline: PipelineArgs,
+
+    #[command(flatten)]
+    auth: AuthArgs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Serialize)]
+struct Job {
+    id: String,
+    status: Status,
+    priority: Priority,
+    /// The class of the failure of a failed job.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<DenoiseError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Who submitted the job, as returned by [`Auth::check`].
+    #[serde(skip)]
+    client: String,
+    /// When the job was done or failed.
+    #[serde(skip)]
+    finished: Option<Instant>,
+}
+
+struct Server {
+    /// Set once loaded, which the workers wait for.
+    pipeline: OnceLock<Pipeline>,
+    spool: PathBuf,
+    jobs: Mutex<HashMap<String, Job>>,
+    queue: JobQueue,
+    ids: Mutex<Rng>,
+    metrics: Metrics,
+    auth: Auth,
+    /// The OpenAPI document, rendered once.
+    openapi: Vec<u8>,
+    swagger_ui: bool,
+    max_upload_size: Option<ByteSize>,
+    max_spool_size: Option<ByteSize>,
+    max_jobs_per_client: usize,
+    /// Denoiser of the `/stream` sessions, loaded with the pipeline.
+    live: OnceLock<Nnnoiseless>,
+    /// Samples `/stream` sends per message, `--frame-size` long.
+    frame: usize,
+    streams: AtomicUsize,
+    max_streams: usize,
+}
+
+/// Runs the server until the process is stopped.
+pub fn serve(args: &ServeArgs, models_dir: Option<&Path>) -> Result<()> {
+    let job_ttl = args
+        .job_ttl
+        .map(|secs| {
+            if !secs.is_finite() || secs < 0.0 {
+                anyhow::bail!("--job-ttl must not be negative");
+            }
+            Ok(Duration::from_secs_f64(secs))
+        })
+        .transpose()?;
+    if args.frame_size == 0 || !args.frame_size.is_multiple_of(10) {
+        anyhow::bail!("--frame-size must be a positive multiple of 10 ms");
+    }
+    let weights: [u32; 3] = match args.priority_weights[..] {
+        [high, normal, low] if high > 0 && normal > 0 && low > 0 => [high, normal, low],
+        _ => anyhow::bail!("--priority-weights takes three positive weights, e.g. 8,2,1"),
+    };
+    let spool = args.spool_dir.clone().unwrap_or_else(|| {
+        std::env::temp_dir().join(format!(
+            "{}-serve-{}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ))
+    });
+    std::fs::create_dir_all(&spool)
+        .with_context(|| format!("Failed to create spool directory: {}", spool.display()))?;
+
+    let http = tiny_http::Server::http(&args.listen)
+        .map_err(|e| anyhow!("Failed to listen on {}: {e}", args.listen))?;
+
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+        ^ (u64::from(std::process::id()) << 32);
+    let auth = Auth::new(&args.auth)?;
+    let server = Server {
+        pipeline: OnceLock::new(),
+        spool,
+        jobs: Mutex::new(HashMap::new()),
+        queue: JobQueue::new(args.queue_size, weights),
+        ids: Mutex::new(Rng::new(seed)),
+        metrics: Metrics::default(),
+        openapi: serde_json::to_vec_pretty(&openapi::document(auth.enabled(), args.swagger_ui))?,
+        auth,
+        swagger_ui: args.swagger_ui,
+        max_upload_size: args.max_upload_size,
+        max_spool_size: args.max_spool_size,
+        max_jobs_per_client: args.max_jobs_per_client,
+        live: OnceLock::new(),
+        streams: AtomicUsize::new(0),
+        max_streams: args.max_streams,
+        frame: args.frame_size * STREAM_RATE / 1000,
+    };
+    eprintln!(
+        "Listening on http://{}, loading the pipeline (spool: {})",
+        args.listen,
+        server.spool.display()
+    );
+    if !server.auth.enabled() {
+        eprintln!("Warning: no tokens configured; the API accepts unauthenticated requests");
+    }
+
+    let server = &server;
+    let http = &http;
+    std::thread::scope(|scope| {
+        for _ in 0..HTTP_THREADS {
+            scope.spawn(|| {
+                while let Ok(mut request) = http.recv() {
+                    // Handed the connection rather than answered
+                    if Route::of(request.method(), request.url(), server.swagger_ui)
+                        == Some(Route::Stream)
+                    {
+                        server.stream(scope, request);
+                        continue;
+                    }
+                    let response = server.route(&mut request);
+                    if let Err(e) = request.respond(response) {
+                        eprintln!("Warning: failed to send response: {e}");
+                    }
+                }
+            });
+        }
+
+        let loaded = Pipeline::new(&args.pipeline, models_dir).and_then(|pipeline| {
+            let live = Nnnoiseless::new(
+                match pipeline.backend_kind() {
+                    BackendKind::Nnnoiseless => pipeline.model(),
+                    _ => None,
+                },
+                args.pipeline.overlap,
+            )?;
+            Ok((pipeline, live))
+        });
+        let (pipeline, live) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                // Lets every HTTP thread return, so the scope can end
+                for _ in 0..HTTP_THREADS {
+                    http.unblock();
+                }
+                return Err(e);
+            }
+        };
+        let workers = args
+            .workers
+            .unwrap_or_else(|| pipeline.default_jobs())
+            .max(1);
+        let _ = server.live.set(live);
+        let _ = server.pipeline.set(pipeline);
+        eprintln!("Ready with {workers} workers");
+        for _ in 0..workers {
+            scope.spawn(|| server.work());
+        }
+        if let Some(ttl) = job_ttl {
+            scope.spawn(move || {
+                loop {
+                    server.sweep(ttl);
+                    std::thread::sleep(ttl.clamp(Duration::from_secs(1), SWEEP_INTERVAL));
+                }
+            });
+        }
+        Ok(())
+    })
+}
+
+impl Server {
+    fn input_path(&self, id: &str) -> PathBuf {
+        self.spool.join(format!("{id}.in.wav"))
+    }
+
+    fn result_path(&self, id: &str) -> PathBuf {
+        self.spool.join(format!("{id}.wav"))
+    }
+
+    fn set_status(
+        &self,
+        id: &str,
+        status: Status,
+        code: Option<DenoiseError>,
+        error: Option<String>,
+    ) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            job.status = status;
+            job.code = code;
+            job.error = error;
+            if matches!(status, Status::Done | Status::Failed) {
+                job.finished = Some(Instant::now());
+            }
+        }
+    }
+
+    /// Bytes held in the spool directory.
+    fn spool_bytes(&self) -> u64 {
+        std::fs::read_dir(&self.spool)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok()?.metadata().ok())
+            .filter(|metadata| metadata.is_file())
+            .map(|metadata| metadata.len())
+            .sum()
+    }
+
+    /// Removes jobs finished more than `ttl` ago, and spool files as old that
+    /// belong to no job.
+    fn sweep(&self, ttl: Duration) {
+        let mut doomed: Vec<PathBuf> = Vec::new();
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            jobs.retain(|id, job| {
+                let expired = job.finished.is_some_and(|at| at.elapsed() >= ttl);
+                if expired {
+                    doomed.extend([self.input_path(id), self.result_path(id)]);
+                }
+                !expired
+            });
+            let entries = std::fs::read_dir(&self.spool).into_iter().flatten();
+            for path in entries.filter_map(|entry| Some(entry.ok()?.path())) {
+                let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                    continue;
+                };
+                // Only names starting with a job ID are the server's own files
+                let id = name.split('.').next().unwrap_or_default();
+                let ours = id.len() == 16 && id.bytes().all(|b| b.is_ascii_hexdigit());
+                let old = std::fs::metadata(&path)
+                    .and_then(|m| m.modified())
+                    .is_ok_and(|at| at.elapsed().is_ok_and(|age| age >= ttl));
+                if ours && old && !jobs.contains_key(id) {
+                    doomed.push(path);
+                }
+            }
+        }
+        for path in doomed {
+            match std::fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => eprintln!("Warning: failed to remove {}: {e}", path.display()),
+            }
+        }
+    }
+
+    /// Takes queued jobs one at a time, for as long as the server runs.
+    fn work(&self) {
+        let pipeline = self
+            .pipeline
+            .get()
+            .expect("workers start once it is loaded");
+        loop {
+            let id = self.queue.pop();
+            // Jobs deleted while queued are dropped here, under the same lock
+            // as the move to running so a deletion cannot slip in between
+            match self.jobs.lock().unwrap().get_mut(&id) {
+                Some(job) => job.status = Status::Running,
+                None => continue,
+            }
+
+            let input = self.input_path(&id);
+            let output = self.result_path(&id);
+            let started = Instant::now();
+            let outcome = pipeline.process(&input, &output).unwrap_or_else(|e| {
+                eprintln!("Error processing job {id}: {e:?}");
+                Outcome::failed(&e)
+            });
+            let (status, error) = match &outcome {
+                Outcome::Processed => (Status::Done, None),
+                Outcome::Skipped(reason)
+                | Outcome::Invalid(reason)
+                | Outcome::Failed(_, reason) => (Status::Failed, Some(reason.clone())),
+            };
+            let _ = std::fs::remove_file(&input);
+            match status {
+                Status::Done => {
+                    let bytes = std::fs::metadata(&output).map_or(0, |m| m.len());
+                    self.metrics.record_done(bytes, started.elapsed());
+                }
+                _ => self.metrics.record_failed(started.elapsed()),
+            }
+            self.set_status(&id, status, outcome.code(), error);
+        }
+    }
+
+    fn route(&self, request: &mut Request) -> ResponseBox {
+        let url = request.url().to_string();
+        let route = Route::of(request.method(), &url, self.swagger_ui);
+        let client = match route {
+            Some(route) if route.public() => String::new(),
+            _ => match self.authorize(request) {
+                Ok(client) => client,
+                Err(refusal) => return refusal,
+            },
+        };
+
+        match route {
+            Some(Route::Health) => json(200, &serde_json::json!({ "status": "ok" })),
+            Some(Route::Readiness) => self.readiness(),
+            Some(Route::Metrics) => self.metrics(),
+            Some(Route::OpenApi) => Response::from_data(self.openapi.clone())
+                .with_header(header("Content-Type", "application/json"))
+                .boxed(),
+            Some(Route::Docs) => Response::from_string(openapi::SWAGGER_UI)
+                .with_header(header("Content-Type", "text/html; charset=utf-8"))
+                .boxed(),
+            Some(Route::Submit) => self.submit(request, client),
+            Some(Route::Status(id)) => self.status(id),
+            Some(Route::Result(id)) => self.result(id),
+            Some(Route::Delete(id)) => self.delete(id),
+            // WebSocket upgrades are handed to `stream` before routing
+            Some(Route::Stream) | None => error(404, "not found"),
+        }
+    }
+
+    /// Who is asking, or the response refusing them.
+    fn authorize(&self, request: &Request) -> Result<String, ResponseBox> {
+        let authorization = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Authorization"))
+            .map(|h| h.value.as_str());
+        let remote = request
+            .remote_addr()
+            .map_or_else(String::new, |addr| addr.ip().to_string());
+        self.auth
+            .check(authorization, &remote)
+            .map_err(|denied| match denied {
+                Denied::Unauthorized => error(401, "missing or invalid bearer token")
+                    .with_header(header("WWW-Authenticate", "Bearer")),
+                Denied::RateLimited(retry) => error(429, "rate limit exceeded")
+                    .with_header(header("Retry-After", &retry.to_string())),
+            })
+    }
+
+    /// Completes a WebSocket handshake on `/stream` and serves the session on a thread of its own.
+    fn stream<'scope>(&'scope self, scope: &'scope Scope<'scope, '_>, request: Request) {
+        let value = |name: &'static str| {
+            request
+                .headers()
+                .iter()
+                .find(|h| h.field.equiv(name))
+                .map(|h| h.value.as_str().to_string())
+        };
+        let key = value("Sec-WebSocket-Key");
+        let live = self.live.get();
+        let refusal = match self.authorize(&request) {
+            Err(refusal) => Some(refusal),
+            Ok(_) if live.is_none() => Some(error(503, "the model is still loading")),
+            Ok(_) if !value("Upgrade").is_some_and(|v| v.eq_ignore_ascii_case("websocket")) => {
+                Some(error(400, "expected a WebSocket upgrade"))
+            }
+            Ok(_) if key.is_none() || value("Sec-WebSocket-Version").as_deref() != Some("13") => {
+                Some(
+                    error(426, "WebSocket version 13 is required")
+                        .with_header(header("Sec-WebSocket-Version", "13")),
+                )
+            }
+            Ok(_) => {
+                let claimed = self
+                    .streams
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                        (n < self.max_streams).then_some(n + 1)
+                    });
+                claimed
+                    .is_err()
+                    .then(|| error(503, "too many streams are open"))
+            }
+        };
+        if let Some(refusal) = refusal {
+            if let Err(e) = request.respond(refusal) {
+                eprintln!("Warning: failed to send response: {e}");
+            }
+            return;
+        }
+
+        let accept = websocket::accept_key(key.as_deref().unwrap_or_default());
+        let response = Response::empty(101).with_header(header("Sec-WebSocket-Accept", &accept));
+        let mut connection = request.upgrade("websocket", response);
+        let Some(live) = live else { return };
+        scope.spawn(move || {
+            self.session(live, &mut connection);
+            self.streams.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+
+    /// Whether the server should be sent work: `503` while the pipeline
+    /// loads or the queue is full.
+    fn readiness(&self) -> ResponseBox {
+        let reason = if self.pipeline.get().is_none() {
+            Some("the pipeline is loading")
+        } else if self.queue.is_full() {
+            Some("the job queue is full")
+        } else {
+            None
+        };
+        match reason {
+            Some(reason) => json(
+                503,
+                &serde_json::json!({ "ready": false, "reason": reason }),
+            ),
+            None => json(200, &serde_json::json!({ "ready": true })),
+        }
+    }
+
+    /// Denoises the PCM a client streams until it closes the connection.
+    fn session(&self, live: &Nnnoiseless, connection: &mut (impl std::io::Read + std::io::Write)) {
+        let mut stream = live.live();
+        let mut outgoing: Vec<i16> = Vec::new();
+        let violation = loop {
+            let message = match websocket::read_message(connection, MAX_STREAM_MESSAGE) {
+                Ok(Ok(message)) => message,
+                Ok(Err(violation)) => break Some(violation),
+                // The client went away
+                Err(_) => return,
+            };
+            let denoised = match message {
+                Message::Close => break None,
+                Message::Ping => continue,
+                Message::Text => {
+                    break Some(Violation {
+                        code: websocket::UNSUPPORTED_DATA,
+                        reason: "expected binary 16-bit PCM",
+                    });
+                }
+                Message::Binary(data) if data.len() % 2 != 0 => {
+                    break Some(Violation {
+                        code: websocket::INVALID_DATA,
+                        reason: "PCM messages hold whole 16-bit samples",
+                    });
+                }
+                Message::Binary(data) if data.is_empty() => {
+                    // The end of a stream sends the rest, however short
+                    outgoing.extend(std::mem::replace(&mut stream, live.live()).finish());
+                    std::mem::take(&mut outgoing)
+                }
+                Message::Binary(data) => {
+                    outgoing.extend(
+                        stream.push(
+                            &data
+                                .chunks_exact(2)
+                                .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                                .collect::<Vec<_>>(),
+                        ),
+                    );
+                    let whole = outgoing.len() - outgoing.len() % self.frame;
+                    outgoing.drain(..whole).collect()
+                }
+            };
+            if !denoised.is_empty() {
+                let pcm: Vec<u8> = denoised.iter().flat_map(|s| s.to_le_bytes()).collect();
+                if websocket::send_binary(connection, &pcm).is_err() {
+                    return;
+                }
+            }
+        };
+        let (code, reason) = violation.map_or((websocket::NORMAL, ""), |v| (v.code, v.reason));
+        let _ = websocket::send_close(connection, code, reason);
+    }
+
+    fn submit(&self, request: &mut Request, client: String) -> ResponseBox {
+        let query = request.url().split_once('?').map_or("", |(_, q)| q);
+        let priority = match query.split('&').find_map(|p| p.strip_prefix("priority=")) {
+            None => Priority::default(),
+            Some(name) => match Priority::from_str(name, true) {
+                Ok(priority) => priority,
+                Err(_) => return error(400, "priority must be high, normal or low"),
+            },
+        };
+        let too_large = |bytes: u64| self.max_upload_size.is_some_and(|max| bytes > max.0);
+        if request.body_length().is_some_and(|n| too_large(n as u64)) {
+            return error(413, "upload is too large");
+        }
+        if let Some(max) = self.max_spool_size {
+            let incoming = request.body_length().map_or(0, |n| n as u64);
+            if self.spool_bytes().saturating_add(incoming) > max.0 {
+                return error(507, "spool directory is full");
+            }
+        }
+        let id = format!("{:016x}", self.ids.lock().unwrap().next_u64());
+        let input = self.input_path(&id);
+
+        // Counted and added under one lock, so parallel uploads cannot overshoot the limit
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            let in_flight = jobs
+                .values()
+                .filter(|job| job.client == client)
+                .filter(|job| matches!(job.status, Status::Queued | Status::Running))
+                .count();
+            if self.max_jobs_per_client > 0 && in_flight >= self.max_jobs_per_client {
+                return error(429, "too many jobs queued or running for this client");
+            }
+            jobs.insert(
+                id.clone(),
+                Job {
+                    id: id.clone(),
+                    status: Status::Queued,
+                    priority,
+                    code: None,
+                    error: None,
+                    client,
+                    finished: None,
+                },
+            );
+        }
+
+        // One byte over the limit is read to tell a body without Content-Length is too large
+        let limit = self
+            .max_upload_size
+            .map_or(u64::MAX, |max| max.0.saturating_add(1));
+        let spooled = File::create(&input)
+            .and_then(|mut file| std::io::copy(&mut request.as_reader().take(limit), &mut file));
+        let refused = match spooled {
+            Ok(bytes) if too_large(bytes) => Some(error(413, "upload is too large")),
+            Ok(bytes) => {
+                self.metrics.record_upload(bytes);
+                None
+            }
+            Err(e) => {
+                eprintln!("Error spooling upload: {e}");
+                Some(error(500, "failed to store upload"))
+            }
+        };
+        if let Some(response) = refused {
+            self.jobs.lock().unwrap().remove(&id);
+            let _ = std::fs::remove_file(&input);
+            return response;
+        }
+
+        match self.queue.push(id.clone(), priority) {
+            Ok(()) => json(202, &serde_json::json!({ "id": id })),
+            Err(_) => {
+                self.jobs.lock().unwrap().remove(&id);
+                let _ = std::fs::remove_file(&input);
+                error(503, "job queue is full")
+            }
+        }
+    }
+
+    fn status(&self, id: &str) -> ResponseBox {
+        match self.jobs.lock().unwrap().get(id) {
+            Some(job) => json(200, job),
+            None => error(404, "no such job"),
+        }
+    }
+
+    fn result(&self, id: &str) -> ResponseBox {
+        let status = self.jobs.lock().unwrap().get(id).map(|job| job.status);
+        match status {
+            None => error(404, "no such job"),
+            Some(Status::Done) => match File::open(self.result_path(id)) {
+                Ok(file) => Response::from_file(file)
+                    .with_header(header("Content-Type", "audio/wav"))
+                    .boxed(),
+                Err(_) => error(410, "result is no longer available"),
+            },
+            Some(Status::Failed) => error(409, "job failed"),
+            Some(_) => error(409, "job is not finished"),
+        }
+    }
+
+    fn delete(&self, id: &str) -> ResponseBox {
+        let mut jobs = self.jobs.lock().unwrap();
+        match jobs.get(id).map(|job| job.status) {
+            None => error(404, "no such job"),
+            Some(Status::Running) => error(409, "job is running"),
+            Some(_) => {
+                jobs.remove(id);
+                let _ = std::fs::remove_file(self.input_path(id));
+                let _ = std::fs::remove_file(self.result_path(id));
+                Response::empty(204).boxed()
+            }
+        }
+    }
+
+    fn metrics(&self) -> ResponseBox {
+        let (queued, running) =
+            self.jobs
+                .lock()
+                .unwrap()
+                .values()
+                .fold((0, 0), |(queued, running), job| match job.status {
+                    Status::Queued => (queued + 1, running),
+                    Status::Running => (queued, running + 1),
+                    _ => (queued, running),
+                });
+        Response::from_string(self.metrics.render(queued, running))
+            .with_header(header("Content-Type", "text/plain; version=0.0.4"))
+            .boxed()
+    }
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("valid header")
+}
+
+fn json(status: u16, body: &impl Serialize) -> ResponseBox {
+    let body = serde_json::to_vec(body).unwrap_or_default();
+    Response::from_data(body)
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"))
+        .boxed()
+}
+
+fn error(status: u16, message: &str) -> ResponseBox {
+    json(status, &serde_json::json!({ "error": message }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    /// One request of each route, with `{id}` for the job ID.
+    const ROUTES: [Route<'static>; 10] = [
+        Route::Health,
+        Route::Readiness,
+        Route::Metrics,
+        Route::OpenApi,
+        Route::Docs,
+        Route::Stream,
+        Route::Submit,
+        Route::Status("{id}"),
+        Route::Result("{id}"),
+        Route::Delete("{id}"),
+    ];
+
+    /// The method and path of a route, as the OpenAPI document writes them.
+    fn operation(route: Route) -> (Method, String) {
+        match route {
+            Route::Health => (Method::Get, "/healthz".to_string()),
+            Route::Readiness => (Method::Get, "/readyz".to_string()),
+            Route::Metrics => (Method::Get, "/metrics".to_string()),
+            Route::OpenApi => (Method::Get, "/openapi.json".to_string()),
+            Route::Docs => (Method::Get, "/docs".to_string()),
+            Route::Stream => (Method::Get, "/stream".to_string()),
+            Route::Submit => (Method::Post, "/jobs".to_string()),
+            Route::Status(id) => (Method::Get, format!("/jobs/{id}")),
+            Route::Result(id) => (Method::Get, format!("/jobs/{id}/result")),
+            Route::Delete(id) => (Method::Delete, format!("/jobs/{id}")),
+        }
+    }
+
+    /// The operations of the document, with whether they need a token.
+    fn documented(document: &serde_json::Value) -> BTreeSet<(String, String, bool)> {
+        let mut operations = BTreeSet::new();
+        for (path, item) in document["paths"].as_object().unwrap() {
+            for (method, operation) in item.as_object().unwrap() {
+                if method == "parameters" {
+                    continue;
+                }
+                let public = operation.get("security") == Some(&serde_json::json!([]));
+                operations.insert((method.to_uppercase(), path.clone(), !public));
+            }
+        }
+        operations
+    }
+
+    #[test]
+    fn the_openapi_document_covers_every_route() {
+        let document = openapi::document(true, true);
+        let routed: BTreeSet<(String, String, bool)> = ROUTES
+            .into_iter()
+            .map(|route| {
+                let (method, path) = operation(route);
+                assert_eq!(Route::of(&method, &path, true), Some(route), "{path}");
+                (method.to_string(), path, !route.public())
+            })
+            .collect();
+        assert_eq!(documented(&document), routed);
+    }
+
+    #[test]
+    fn docs_are_only_served_with_swagger_ui() {
+        assert_eq!(Route::of(&Method::Get, "/docs", false), None);
+        let documented = documented(&openapi::document(false, false));
+        assert!(!documented.iter().any(|(_, path, _)| path == "/docs"));
+        assert!(
+            documented
+                .iter()
+                .any(|(_, path, _)| path == "/openapi.json")
+        );
+    }
+
+    #[test]
+    fn routes_ignore_queries_and_unknown_paths() {
+        assert_eq!(
+            Route::of(&Method::Post, "/jobs?priority=high", false),
+            Some(Route::Submit)
+        );
+        assert_eq!(
+            Route::of(&Method::Get, "/jobs/abc/result", false),
+            Some(Route::Result("abc"))
+        );
+        assert_eq!(Route::of(&Method::Put, "/jobs/abc", false), None);
+        assert_eq!(Route::of(&Method::Get, "/jobs/abc/logs", false), None);
+    }
+}
@@ -0,0 +1,91 @@
+//! Byte sizes given on the command line and the free space of the output filesystem.
+
+use crate::riff;
+use anyhow::{Context, Result};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use walkdir::DirEntry;
+
+/// How much larger than the input a decoded (compressed) input's PCM output is
+/// assumed to be: 16kHz mono PCM is 256 kbit/s, four times a 64 kbit/s voice MP3.
+const DECODED_GROWTH: u64 = 4;
+
+/// A size such as `512M`, `2G` or `1.5GB` (decimal units); plain numbers are bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+            "" | "B" => 1,
+            "K" | "KB" => 1_000,
+            "M" | "MB" => 1_000_000,
+            "G" | "GB" => 1_000_000_000,
+            "T" | "TB" => 1_000_000_000_000,
+            other => return Err(format!("unknown size unit '{other}' (B, K, M, G, T)")),
+        };
+        match number.parse::<f64>() {
+            Ok(n) if n >= 0.0 => Ok(ByteSize((n * multiplier as f64).round() as u64)),
+            _ => Err(format!("expected a size such as 500M or 2G, got '{s}'")),
+        }
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let units = [("TB", 1e12), ("GB", 1e9), ("MB", 1e6), ("KB", 1e3)];
+        let bytes = self.0 as f64;
+        match units.iter().find(|(_, size)| bytes >= *size) {
+            Some((unit, size)) => write!(f, "{:.1} {unit}", bytes / size),
+            None => write!(f, "{} B", self.0),
+        }
+    }
+}
+
+/// Bytes available to this user on the filesystem holding `dir`, where that can be asked.
+pub fn free_space(dir: &Path) -> Result<Option<u64>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let path = std::ffi::CString::new(dir.as_os_str().as_bytes())
+            .context("Path contains a NUL byte")?;
+        // SAFETY: statvfs fills in the struct passed to it and reads the NUL-terminated path
+        let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to query free space: {}", dir.display()));
+        }
+        #[allow(clippy::unnecessary_cast)]
+        Ok(Some(stats.f_bavail as u64 * stats.f_frsize as u64))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = dir;
+        Ok(None)
+    }
+}
+
+/// Rough size of the outputs of `files`: about as large as the inputs, more for
+/// inputs that are decoded to PCM.
+pub fn estimate_outputs(files: &[DirEntry]) -> ByteSize {
+    let bytes = files
+        .iter()
+        .map(|e| {
+            let bytes = e.metadata().map_or(0, |m| m.len());
+            if riff::is_wav(e.path()) {
+                bytes
+            } else {
+                bytes * DECODED_GROWTH
+            }
+        })
+        .sum();
+    ByteSize(bytes)
+}
@@ -0,0 +1,101 @@
+//! `--split-on-silence`: cutting each output into utterance-sized clips at its
+//! pauses, as numbered files beside it.
+
+use crate::audio;
+use anyhow::Result;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Length of the frames whose level decides what is silence.
+const FRAME_SECS: f64 = 0.01;
+
+#[derive(clap::Args, Debug, Clone, Copy, Serialize)]
+pub struct SplitArgs {
+    /// Also cut each output into clips at its pauses, STEM_001.wav, STEM_002.wav, ... beside it
+    #[arg(long)]
+    pub split_on_silence: bool,
+
+    /// Shortest pause that --split-on-silence cuts at
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 700,
+        requires = "split_on_silence"
+    )]
+    pub min_silence_ms: u64,
+
+    /// Level below which --split-on-silence counts audio as silence
+    #[arg(long, value_name = "DBFS", default_value_t = -40.0, allow_hyphen_values = true,
+          requires = "split_on_silence")]
+    pub silence_threshold: f64,
+}
+
+impl SplitArgs {
+    /// The splitter, when `--split-on-silence` is given.
+    pub fn splitter(&self) -> Option<Splitter> {
+        self.split_on_silence.then_some(Splitter {
+            min_silence_secs: self.min_silence_ms as f64 / 1000.0,
+            threshold_db: self.silence_threshold,
+        })
+    }
+}
+
+pub struct Splitter {
+    min_silence_secs: f64,
+    threshold_db: f64,
+}
+
+impl Splitter {
+    /// Cuts `path` in the middle of every pause of at least the minimum
+    /// length and writes the parts with sound in them beside it. Returns
+    /// the clips written.
+    pub fn split(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        let audio = audio::read_wav(path)?;
+        let channels = usize::from(audio.spec.channels.max(1));
+        let frames = audio.samples.len() / channels;
+        let frame = ((f64::from(audio.spec.sample_rate) * FRAME_SECS) as usize).max(1);
+        let threshold = 10f64.powf(self.threshold_db / 10.0);
+        let silent: Vec<bool> = audio.samples[..frames * channels]
+            .chunks(frame * channels)
+            .map(|block| {
+                let power = block.iter().map(|&s| f64::from(s).powi(2)).sum::<f64>();
+                power / block.len() as f64 <= threshold
+            })
+            .collect();
+
+        // Each long enough pause becomes a cut at its middle
+        let min_frames = ((self.min_silence_secs / FRAME_SECS).ceil() as usize).max(1);
+        let mut cuts = vec![0];
+        let mut run_start = None;
+        for (i, &quiet) in silent.iter().chain([&false]).enumerate() {
+            match (quiet, run_start) {
+                (true, None) => run_start = Some(i),
+                (false, Some(start)) => {
+                    if i - start >= min_frames {
+                        cuts.push(((start + i) / 2 * frame).min(frames));
+                    }
+                    run_start = None;
+                }
+                _ => {}
+            }
+        }
+        cuts.push(frames);
+
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let mut clips = Vec::new();
+        for pair in cuts.windows(2) {
+            let (start, end) = (pair[0], pair[1]);
+            let has_sound = silent[start / frame..end.div_ceil(frame).min(silent.len())]
+                .iter()
+                .any(|&quiet| !quiet);
+            if start >= end || !has_sound {
+                continue;
+            }
+            let clip = path.with_file_name(format!("{stem}_{:03}.wav", clips.len() + 1));
+            let samples = &audio.samples[start * channels..end * channels];
+            audio::write_spec(&clip, samples, audio.spec, false)?;
+            clips.push(clip);
+        }
+        Ok(clips)
+    }
+}
@@ -0,0 +1,134 @@
+//! The stages of a batch run. Readers decode, repair and convert inputs into
+//! the denoiser's format ahead of the denoise workers, which only run the
+//! backend, and writers finish each output behind them: converting it back
+//! to the input's format, measuring it, writing sidecars and manifests,
+//! running hooks. The stages hand files on
+//! through bounded queues, so a stage only waits when the queue after it is
+//! full, and a slow disk does not leave the workers idle or the other way
+//! round.
+
+use crate::concurrency::Throttle;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::SyncSender;
+use std::time::Duration;
+use walkdir::DirEntry;
+
+/// How many files are in each stage and in the queues between them.
+pub struct Gauges {
+    /// Room in each queue.
+    pub depth: usize,
+    pub reading: AtomicUsize,
+    pub to_denoise: Queue,
+    pub denoising: AtomicUsize,
+    pub to_write: Queue,
+    pub writing: AtomicUsize,
+}
+
+/// Counts the files put into and taken out of a queue.
+#[derive(Default)]
+pub struct Queue {
+    sent: AtomicUsize,
+    taken: AtomicUsize,
+}
+
+impl Queue {
+    pub fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn taken(&self) {
+        self.taken.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Files waiting in the queue; a file taken just as it was sent is not counted.
+    pub fn len(&self) -> usize {
+        let taken = self.taken.load(Ordering::SeqCst);
+        self.sent.load(Ordering::SeqCst).saturating_sub(taken)
+    }
+}
+
+impl Gauges {
+    pub fn new(depth: usize) -> Self {
+        Gauges {
+            depth,
+            reading: AtomicUsize::new(0),
+            to_denoise: Queue::default(),
+            denoising: AtomicUsize::new(0),
+            to_write: Queue::default(),
+            writing: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let get = |gauge: &AtomicUsize| gauge.load(Ordering::SeqCst);
+        format!(
+            "Stages: {} reading, {}/{} waiting to denoise, {} denoising, {}/{} waiting to write, {} writing",
+            get(&self.reading),
+            self.to_denoise.len(),
+            self.depth,
+            get(&self.denoising),
+            self.to_write.len(),
+            self.depth,
+            get(&self.writing)
+        )
+    }
+}
+
+/// Counts a file as in a stage while held.
+pub struct Busy<'a>(&'a AtomicUsize);
+
+pub fn busy(gauge: &AtomicUsize) -> Busy<'_> {
+    gauge.fetch_add(1, Ordering::SeqCst);
+    Busy(gauge)
+}
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Takes the next files of `files` in turn, decoding each with `read` ahead
+/// of the workers, and hands the results to `ready`. Returns once every file
+/// is taken, `stop` is set or the workers stop listening.
+pub fn read_ahead<T>(
+    files: &[DirEntry],
+    next: &AtomicUsize,
+    throttle: Option<&Throttle>,
+    gauges: &Gauges,
+    stop: &AtomicBool,
+    read: impl Fn(usize) -> T,
+    ready: SyncSender<T>,
+) {
+    while !stop.load(Ordering::SeqCst) {
+        let i = next.fetch_add(1, Ordering::SeqCst);
+        let Some(entry) = files.get(i) else { return };
+        if let Some(throttle) = throttle {
+            throttle.consume(entry.metadata().map_or(0, |m| m.len()));
+        }
+        let decoded = {
+            let _reading = busy(&gauges.reading);
+            read(i)
+        };
+        if ready.send(decoded).is_err() {
+            return;
+        }
+        gauges.to_denoise.sent();
+    }
+}
+
+/// Prints the gauges every few seconds until `stop` is set.
+pub fn report(gauges: &Gauges, stop: &AtomicBool) {
+    const INTERVAL: Duration = Duration::from_secs(5);
+
+    while !stop.load(Ordering::SeqCst) {
+        // Sleep in short steps so the run is not held up when it finishes
+        for _ in 0..20 {
+            if stop.load(Ordering::SeqCst) {
+                return;
+            }
+            std::thread::sleep(INTERVAL / 20);
+        }
+        eprintln!("{}", gauges.describe());
+    }
+}
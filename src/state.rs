@@ -0,0 +1,243 @@
+//! Resumable run state.
+//!
+//! Long batch runs periodically persist which files finished and which failed
+//! to a small JSON file. A restarted run with the same state file skips the
+//! finished files and retries the failed ones; the `retry` subcommand
+//! re-processes just the failed ones.
+
+use crate::loudness::Loudness;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Serialize, Deserialize, Default)]
+struct StateData {
+    /// Relative paths of inputs whose outputs are complete.
+    done: BTreeSet<String>,
+    /// Relative paths of inputs that failed, with the last error.
+    failed: BTreeMap<String, String>,
+    /// Relative output paths of the failed inputs.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    outputs: BTreeMap<String, String>,
+    /// Directories of the run, for `retry`; absent when they were staged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    input_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output_dir: Option<PathBuf>,
+    /// Loudness of the finished outputs, with `--measure-loudness`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    loudness: BTreeMap<String, Loudness>,
+}
+
+struct Inner {
+    data: StateData,
+    unsaved: usize,
+}
+
+/// Checkpointed progress of a run, shared between workers.
+pub struct RunState {
+    path: PathBuf,
+    every: usize,
+    inner: Mutex<Inner>,
+}
+
+impl RunState {
+    /// Loads `path` if it exists; progress is written back every `every` updates.
+    pub fn load(path: &Path, every: usize) -> Result<Self> {
+        let data = if path.exists() {
+            let bytes = std::fs::read(path)
+                .with_context(|| format!("Failed to read state file: {}", path.display()))?;
+            serde_json::from_slice(&bytes)
+                .with_context(|| format!("Malformed state file: {}", path.display()))?
+        } else {
+            StateData::default()
+        };
+        Ok(RunState {
+            path: path.to_path_buf(),
+            every: every.max(1),
+            inner: Mutex::new(Inner { data, unsaved: 0 }),
+        })
+    }
+
+    pub fn is_done(&self, relative: &str) -> bool {
+        self.inner.lock().unwrap().data.done.contains(relative)
+    }
+
+    /// Number of failures carried over from the previous run.
+    pub fn failed_count(&self) -> usize {
+        self.inner.lock().unwrap().data.failed.len()
+    }
+
+    /// The input and output directories of the run, or `None` when they are not `retry`-able.
+    pub fn dirs(&self) -> Option<(PathBuf, PathBuf)> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .data
+            .input_dir
+            .clone()
+            .zip(inner.data.output_dir.clone())
+    }
+
+    pub fn set_dirs(&self, input_dir: &Path, output_dir: &Path) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.data.input_dir = Some(input_dir.to_path_buf());
+        inner.data.output_dir = Some(output_dir.to_path_buf());
+    }
+
+    /// Failed inputs with their relative output paths, where recorded.
+    pub fn failed_files(&self) -> Vec<(String, Option<String>)> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .data
+            .failed
+            .keys()
+            .map(|key| (key.clone(), inner.data.outputs.get(key).cloned()))
+            .collect()
+    }
+
+    pub fn record_done(&self, relative: &str, loudness: Option<Loudness>) -> Result<()> {
+        self.update(|data| {
+            data.failed.remove(relative);
+            data.outputs.remove(relative);
+            match loudness {
+                Some(loudness) => data.loudness.insert(relative.to_string(), loudness),
+                None => data.loudness.remove(relative),
+            };
+            data.done.insert(relative.to_string());
+        })
+    }
+
+    pub fn record_failed(&self, relative: &str, output: &str, reason: &str) -> Result<()> {
+        self.update(|data| {
+            data.failed.insert(relative.to_string(), reason.to_string());
+            data.outputs
+                .insert(relative.to_string(), output.to_string());
+        })
+    }
+
+    fn update(&self, f: impl FnOnce(&mut StateData)) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        f(&mut inner.data);
+        inner.unsaved += 1;
+        if inner.unsaved >= self.every {
+            self.write(&inner.data)?;
+            inner.unsaved = 0;
+        }
+        Ok(())
+    }
+
+    /// Writes the current state unconditionally.
+    pub fn save(&self) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        self.write(&inner.data)?;
+        inner.unsaved = 0;
+        Ok(())
+    }
+
+    fn write(&self, data: &StateData) -> Result<()> {
+        // Write-then-rename so a crash mid-write never leaves a corrupt state file
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(data)?)
+            .with_context(|| format!("Failed to write state file: {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("Failed to write state file: {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for one test's files.
+    fn scratch(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "{}-state-{}-{test}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn a_resumed_run_sees_what_the_last_one_saved() {
+        let dir = scratch("resume");
+        let path = dir.join("run.json");
+        let state = RunState::load(&path, 100).unwrap();
+        assert!(!state.is_done("a.wav"));
+        state.set_dirs(Path::new("/in"), Path::new("/out"));
+        state.record_done("a.wav", None).unwrap();
+        state
+            .record_failed("b.wav", "b-clean.wav", "API returned status 500")
+            .unwrap();
+        state.save().unwrap();
+
+        let resumed = RunState::load(&path, 100).unwrap();
+        assert!(resumed.is_done("a.wav"));
+        assert!(!resumed.is_done("b.wav"));
+        assert_eq!(resumed.failed_count(), 1);
+        assert_eq!(
+            resumed.failed_files(),
+            [("b.wav".to_string(), Some("b-clean.wav".to_string()))]
+        );
+        assert_eq!(
+            resumed.dirs(),
+            Some((PathBuf::from("/in"), PathBuf::from("/out")))
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn checkpoints_every_few_updates() {
+        let dir = scratch("checkpoint");
+        let path = dir.join("run.json");
+        let state = RunState::load(&path, 2).unwrap();
+        state.record_done("a.wav", None).unwrap();
+        assert!(!path.exists(), "written before the checkpoint");
+        state.record_done("b.wav", None).unwrap();
+        assert!(RunState::load(&path, 1).unwrap().is_done("b.wav"));
+        state.record_done("c.wav", None).unwrap();
+        assert!(!RunState::load(&path, 1).unwrap().is_done("c.wav"));
+        assert!(!dir.join("run.tmp").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_retried_file_that_succeeds_is_no_longer_failed() {
+        let dir = scratch("retried");
+        let state = RunState::load(&dir.join("run.json"), 1).unwrap();
+        state.record_failed("a.wav", "a.wav", "timed out").unwrap();
+        state.record_done("a.wav", None).unwrap();
+        assert!(state.is_done("a.wav"));
+        assert!(state.failed_files().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn state_files_without_directories_are_not_retryable() {
+        let dir = scratch("old");
+        let path = dir.join("run.json");
+        std::fs::write(&path, r#"{"done": ["a.wav"], "failed": {"b.wav": "boom"}}"#).unwrap();
+        let state = RunState::load(&path, 1).unwrap();
+        assert!(state.is_done("a.wav"));
+        assert_eq!(state.failed_files(), [("b.wav".to_string(), None)]);
+        assert_eq!(state.dirs(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_malformed_state_file() {
+        let dir = scratch("malformed");
+        let path = dir.join("run.json");
+        std::fs::write(&path, "{\"done\": [").unwrap();
+        let err = RunState::load(&path, 1).err().unwrap();
+        assert!(
+            format!("{err:#}").contains("Malformed state file"),
+            "{err:#}"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
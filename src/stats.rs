@@ -0,0 +1,187 @@
+//! The `stats` subcommand: how the WAV files under a directory are spread
+//! over sample rates, sample formats, channel counts and lengths, to size up
+//! an incoming corpus before choosing conversion settings.
+
+use crate::riff::{self, Format};
+use crate::space::ByteSize;
+use crate::walk::{self, SymlinkPolicy, WalkOptions};
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Args, Debug)]
+pub struct StatsArgs {
+    /// Directory to scan
+    dir: PathBuf,
+
+    /// Write the statistics as JSON to this file ('-' for stdout) instead of tables
+    #[arg(long, value_name = "FILE")]
+    json: Option<PathBuf>,
+
+    /// Comma-separated file extensions to count, matched case-sensitively
+    #[arg(long, value_delimiter = ',', default_value = "wav")]
+    extensions: Vec<String>,
+}
+
+/// Upper bounds of the length classes, in seconds, with their labels.
+const LENGTHS: [(f64, &str); 6] = [
+    (1.0, "under 1 s"),
+    (10.0, "1-10 s"),
+    (60.0, "10 s-1 min"),
+    (600.0, "1-10 min"),
+    (3600.0, "10 min-1 h"),
+    (f64::INFINITY, "1 h and over"),
+];
+
+#[derive(Serialize)]
+struct Stats {
+    files: usize,
+    /// Files whose header cannot be read, left out of everything below.
+    unreadable: usize,
+    bytes: u64,
+    hours: f64,
+    sample_rates: Vec<Share>,
+    sample_formats: Vec<Share>,
+    channels: Vec<Share>,
+    lengths: Vec<Share>,
+    shortest_secs: f64,
+    median_secs: f64,
+    longest_secs: f64,
+}
+
+/// The files that have one value, e.g. a sample rate of 16000.
+#[derive(Serialize)]
+struct Share {
+    value: String,
+    files: usize,
+    hours: f64,
+}
+
+/// Scans the directory and prints the statistics.
+pub fn run(args: &StatsArgs) -> Result<()> {
+    let discovered = walk::discover(
+        &args.dir,
+        &WalkOptions {
+            follow_symlinks: false,
+            symlink_files: SymlinkPolicy::Process,
+            max_depth: None,
+            extensions: args.extensions.clone(),
+        },
+    );
+    let scanned: Vec<(u64, Option<Format>)> = discovered
+        .files
+        .par_iter()
+        .map(|e| {
+            (
+                e.metadata().map_or(0, |m| m.len()),
+                riff::format(e.path()).ok(),
+            )
+        })
+        .collect();
+    let formats: Vec<&Format> = scanned.iter().filter_map(|(_, f)| f.as_ref()).collect();
+    let mut secs: Vec<f64> = formats.iter().map(|f| f.duration_secs).collect();
+    secs.sort_by(f64::total_cmp);
+    let stats = Stats {
+        files: scanned.len() + discovered.unreadable.len(),
+        unreadable: scanned.len() - formats.len() + discovered.unreadable.len(),
+        bytes: scanned.iter().map(|(bytes, _)| bytes).sum(),
+        hours: secs.iter().sum::<f64>() / 3600.0,
+        sample_rates: shares(&formats, |f| {
+            (u64::from(f.sample_rate), format!("{} Hz", f.sample_rate))
+        }),
+        sample_formats: shares(&formats, |f| {
+            let kind = match f.tag {
+                1 => "PCM".to_string(),
+                3 => "float".to_string(),
+                tag => format!("tag {tag:#06x}"),
+            };
+            (
+                u64::from(f.tag) << 16 | u64::from(f.bits_per_sample),
+                format!("{}-bit {kind}", f.bits_per_sample),
+            )
+        }),
+        channels: shares(&formats, |f| {
+            (u64::from(f.channels), f.channels.to_string())
+        }),
+        lengths: shares(&formats, |f| {
+            let class = LENGTHS
+                .iter()
+                .position(|(bound, _)| f.duration_secs < *bound)
+                .unwrap_or(LENGTHS.len() - 1);
+            (class as u64, LENGTHS[class].1.to_string())
+        }),
+        shortest_secs: secs.first().copied().unwrap_or_default(),
+        median_secs: secs.get(secs.len() / 2).copied().unwrap_or_default(),
+        longest_secs: secs.last().copied().unwrap_or_default(),
+    };
+
+    match &args.json {
+        Some(path) => {
+            let json = serde_json::to_string_pretty(&stats)?;
+            if path == Path::new("-") {
+                println!("{json}");
+            } else {
+                std::fs::write(path, json + "\n")
+                    .with_context(|| format!("Failed to write file: {}", path.display()))?;
+            }
+        }
+        None => print_tables(&stats),
+    }
+    Ok(())
+}
+
+/// The files grouped by the value `key` gives, in the order of its sort key.
+fn shares(formats: &[&Format], key: impl Fn(&Format) -> (u64, String)) -> Vec<Share> {
+    let mut groups: BTreeMap<u64, Share> = BTreeMap::new();
+    for format in formats {
+        let (order, value) = key(format);
+        let share = groups.entry(order).or_insert(Share {
+            value,
+            files: 0,
+            hours: 0.0,
+        });
+        share.files += 1;
+        share.hours += format.duration_secs / 3600.0;
+    }
+    groups.into_values().collect()
+}
+
+fn print_tables(stats: &Stats) {
+    println!(
+        "{} files, {}, {:.2} hours of audio",
+        stats.files,
+        ByteSize(stats.bytes),
+        stats.hours
+    );
+    if stats.unreadable > 0 {
+        println!(
+            "{} files have no readable WAV header and are left out",
+            stats.unreadable
+        );
+    }
+    println!(
+        "Lengths: shortest {:.2} s, median {:.2} s, longest {:.2} s",
+        stats.shortest_secs, stats.median_secs, stats.longest_secs
+    );
+    let counted = (stats.files - stats.unreadable).max(1) as f64;
+    for (title, shares) in [
+        ("SAMPLE RATE", &stats.sample_rates),
+        ("SAMPLE FORMAT", &stats.sample_formats),
+        ("CHANNELS", &stats.channels),
+        ("LENGTH", &stats.lengths),
+    ] {
+        println!();
+        println!("{title:<14} {:>8} {:>6} {:>10}", "FILES", "SHARE", "HOURS");
+        for share in shares {
+            println!(
+                "{:<14} {:>8} {:>5.1}% {:>10.2}",
+                share.value,
+                share.files,
+                share.files as f64 * 100.0 / counted,
+                share.hours
+            );
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! The `validate` subcommand: reports the format of every WAV file under a
+//! directory and why the denoiser would turn it down, without denoising anything.
+
+use crate::pipeline::TargetArgs;
+use crate::provenance;
+use crate::riff;
+use crate::walk::{self, SymlinkPolicy, WalkOptions};
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[derive(clap::Args, Debug)]
+pub struct ValidateArgs {
+    /// Directory to check
+    dir: PathBuf,
+
+    /// Write the diagnostics as a JSON array to this file ('-' for stdout) instead of a table
+    #[arg(long, value_name = "FILE")]
+    json: Option<PathBuf>,
+
+    /// Comma-separated file extensions to check, matched case-sensitively
+    #[arg(long, value_delimiter = ',', default_value = "wav")]
+    extensions: Vec<String>,
+
+    /// List only the files that would not be denoised
+    #[arg(long)]
+    problems_only: bool,
+
+    #[command(flatten)]
+    target: TargetArgs,
+}
+
+/// What a batch run would do with a file.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    /// Denoised.
+    Ok,
+    /// Skipped as invalid.
+    Rejected,
+    /// The header cannot be read; the file would fail.
+    Unreadable,
+    /// Skipped as already denoised (with the default --reprocess-policy).
+    Denoised,
+}
+
+#[derive(Serialize)]
+struct Diagnosis {
+    file: String,
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    channels: Option<u16>,
+    sample_rate: Option<u32>,
+    bits_per_sample: Option<u16>,
+    /// `pcm`, `float` or the hexadecimal format tag.
+    format: Option<String>,
+    duration_secs: Option<f64>,
+    rf64: bool,
+    /// Whether the header is WAVE_FORMAT_EXTENSIBLE (rewritten as plain PCM before denoising).
+    extensible: bool,
+}
+
+/// Checks every file and prints the diagnostics; exits with 1 when any file would not be denoised.
+pub fn run(args: &ValidateArgs) -> Result<ExitCode> {
+    let discovered = walk::discover(
+        &args.dir,
+        &WalkOptions {
+            follow_symlinks: false,
+            symlink_files: SymlinkPolicy::Process,
+            max_depth: None,
+            extensions: args.extensions.clone(),
+        },
+    );
+    let mut files: Vec<&Path> = discovered.files.iter().map(|e| e.path()).collect();
+    files.extend(discovered.unreadable.iter().map(|(path, _)| path.as_path()));
+    files.sort();
+    let mut diagnoses: Vec<Diagnosis> = files
+        .par_iter()
+        .map(
+            |path| match discovered.unreadable.iter().find(|(p, _)| p == path) {
+                Some((_, reason)) => unreadable(&args.dir, path, reason.clone()),
+                None => diagnose(&args.dir, path, &args.target),
+            },
+        )
+        .collect();
+    let problems = diagnoses.iter().filter(|d| d.status != Status::Ok).count();
+    if args.problems_only {
+        diagnoses.retain(|d| d.status != Status::Ok);
+    }
+
+    match &args.json {
+        Some(path) => {
+            let json = serde_json::to_string_pretty(&diagnoses)?;
+            if path == Path::new("-") {
+                println!("{json}");
+            } else {
+                std::fs::write(path, json + "\n")
+                    .with_context(|| format!("Failed to write file: {}", path.display()))?;
+            }
+        }
+        None => print_table(&diagnoses),
+    }
+    eprintln!(
+        "{} files checked, {} would be denoised, {problems} would not.",
+        files.len(),
+        files.len() - problems
+    );
+    Ok(if problems > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+fn relative(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string()
+}
+
+fn unreadable(root: &Path, path: &Path, reason: String) -> Diagnosis {
+    Diagnosis {
+        file: relative(root, path),
+        status: Status::Unreadable,
+        reason: Some(reason),
+        channels: None,
+        sample_rate: None,
+        bits_per_sample: None,
+        format: None,
+        duration_secs: None,
+        rf64: false,
+        extensible: false,
+    }
+}
+
+fn diagnose(root: &Path, path: &Path, target: &TargetArgs) -> Diagnosis {
+    let format = match riff::format(path) {
+        Ok(format) => format,
+        Err(e) => return unreadable(root, path, format!("{e:#}")),
+    };
+
+    let readable = match (format.tag, format.bits_per_sample) {
+        (1, 8 | 16 | 24 | 32) | (3, 32) => None,
+        (1 | 3, bits) => Some(format!("{bits}-bit samples cannot be read")),
+        (tag, _) => Some(format!(
+            "format tag {tag:#06x} is compressed; only PCM and float WAV files can be read (see --decoder ffmpeg)"
+        )),
+    };
+    let (status, reason) = if let Some(reason) = readable {
+        (Status::Unreadable, Some(reason))
+    } else if format.truncated {
+        (
+            Status::Unreadable,
+            Some("the data chunk is cut short of its declared size".to_string()),
+        )
+    } else if let Some(reason) = target.rejection(format.channels, format.sample_rate) {
+        (Status::Rejected, Some(reason))
+    } else if provenance::is_denoised(path) {
+        (Status::Denoised, Some("already denoised".to_string()))
+    } else {
+        (Status::Ok, None)
+    };
+    Diagnosis {
+        file: relative(root, path),
+        status,
+        reason,
+        channels: Some(format.channels),
+        sample_rate: Some(format.sample_rate),
+        bits_per_sample: Some(format.bits_per_sample),
+        format: Some(match format.tag {
+            1 => "pcm".to_string(),
+            3 => "float".to_string(),
+            tag => format!("{tag:#06x}"),
+        }),
+        duration_secs: Some(format.duration_secs),
+        rf64: format.rf64,
+        extensible: format.extensible,
+    }
+}
+
+fn print_table(diagnoses: &[Diagnosis]) {
+    let cell = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    println!(
+        "{:<10} {:>2} {:>6} {:>4} {:>6} {:>10}  FILE",
+        "STATUS", "CH", "RATE", "BITS", "FORMAT", "DURATION"
+    );
+    for d in diagnoses {
+        let status = match d.status {
+            Status::Ok => "ok",
+            Status::Rejected => "rejected",
+            Status::Unreadable => "unreadable",
+            Status::Denoised => "denoised",
+        };
+        print!(
+            "{status:<10} {:>2} {:>6} {:>4} {:>6} {:>10}  {}",
+            cell(d.channels.map(|c| c.to_string())),
+            cell(d.sample_rate.map(|r| r.to_string())),
+            cell(d.bits_per_sample.map(|b| b.to_string())),
+            cell(d.format.clone()),
+            cell(d.duration_secs.map(|s| format!("{s:.2}s"))),
+            d.file
+        );
+        match &d.reason {
+            Some(reason) => println!(": {reason}"),
+            None => println!(),
+        }
+    }
+}
@@ -0,0 +1,297 @@
+//! Input discovery: walking the input tree and deciding which entries to process.
+
+use crate::rng::Rng;
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use std::path::{Path, PathBuf};
+use walkdir::{DirEntry, WalkDir};
+
+/// What to do with input files that are symbolic links.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Denoise the file the link points to
+    Process,
+    /// Ignore symlinked files
+    Skip,
+    /// Re-create the link in the output tree
+    Mirror,
+}
+
+/// How `--passthrough` and `--copy-sidecars` put input files into the output tree.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Passthrough {
+    Copy,
+    /// A hard link, or a copy where linking is not possible (e.g. across filesystems)
+    Hardlink,
+    /// A symlink to the input's absolute path
+    Symlink,
+}
+
+/// Order in which files are handed to the workers.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// Biggest files first, so no large file is left running alone at the end
+    LargestFirst,
+    SmallestFirst,
+    /// Shuffled, spreading directories of similar files across the run
+    Random,
+    /// Sorted by path
+    Path,
+}
+
+/// Sorts `files` into `order`; `seed` drives [`Order::Random`].
+pub fn sort(files: &mut [DirEntry], order: Order, seed: u64) {
+    let size = |e: &DirEntry| e.metadata().map_or(0, |m| m.len());
+    match order {
+        Order::LargestFirst => files.sort_by_cached_key(|e| std::cmp::Reverse(size(e))),
+        Order::SmallestFirst => files.sort_by_cached_key(size),
+        Order::Path => files.sort_by(|a, b| a.path().cmp(b.path())),
+        Order::Random => {
+            // Fisher-Yates
+            let mut rng = Rng::new(seed);
+            for i in (1..files.len()).rev() {
+                files.swap(i, (rng.next_u64() % (i as u64 + 1)) as usize);
+            }
+        }
+    }
+}
+
+/// Keeps a random `count` of `files`, in their original order.
+pub fn sample(files: &mut Vec<DirEntry>, count: usize, seed: u64) {
+    if count >= files.len() {
+        return;
+    }
+    // Partial Fisher-Yates over the indices
+    let mut rng = Rng::new(seed);
+    let mut indices: Vec<usize> = (0..files.len()).collect();
+    for i in 0..count {
+        let j = i + (rng.next_u64() % (indices.len() - i) as u64) as usize;
+        indices.swap(i, j);
+    }
+    let mut keep = vec![false; files.len()];
+    for &i in &indices[..count] {
+        keep[i] = true;
+    }
+    let mut keep = keep.into_iter();
+    files.retain(|_| keep.next().unwrap());
+}
+
+/// Options controlling which files are discovered.
+pub struct WalkOptions {
+    pub follow_symlinks: bool,
+    pub symlink_files: SymlinkPolicy,
+    /// Maximum directory depth; files directly in the root are at depth 1.
+    pub max_depth: Option<usize>,
+    /// Accepted file extensions, matched exactly.
+    pub extensions: Vec<String>,
+}
+
+/// Files found under the input directory.
+#[derive(Default)]
+pub struct Discovered {
+    /// WAV files to process.
+    pub files: Vec<DirEntry>,
+    /// Symlinked WAV files to re-create as links in the output tree.
+    pub links: Vec<DirEntry>,
+    /// Entries that could not be read (e.g. permission denied), with the error.
+    pub unreadable: Vec<(PathBuf, String)>,
+    /// Files without an accepted extension, for `--passthrough`.
+    pub others: Vec<PathBuf>,
+}
+
+impl Discovered {
+    /// The symlinks and other files of the tree, and the WAV files not yet
+    /// taken out of `files`.
+    pub fn sources(&self) -> Vec<&Path> {
+        self.files
+            .iter()
+            .chain(&self.links)
+            .map(DirEntry::path)
+            .chain(self.others.iter().map(PathBuf::as_path))
+            .collect()
+    }
+}
+
+/// Walks `root` and collects matching WAV files.
+pub fn discover(root: &Path, opts: &WalkOptions) -> Discovered {
+    let mut found = Discovered::default();
+    let mut walker = WalkDir::new(root).follow_links(opts.follow_symlinks);
+    if let Some(depth) = opts.max_depth {
+        walker = walker.max_depth(depth);
+    }
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let path = e.path().unwrap_or(root).to_path_buf();
+                match e.loop_ancestor() {
+                    Some(ancestor) => eprintln!(
+                        "Skipping symlink loop: {} points back to {}",
+                        path.display(),
+                        ancestor.display()
+                    ),
+                    // Files of other types would not have been processed anyway
+                    None if path.extension().and_then(|s| s.to_str()).is_some()
+                        && !has_extension(&path, &opts.extensions) => {}
+                    None => {
+                        let reason = e
+                            .io_error()
+                            .map_or_else(|| e.to_string(), ToString::to_string);
+                        eprintln!("Error reading {}: {reason}", path.display());
+                        found.unreadable.push((path, reason));
+                    }
+                }
+                continue;
+            }
+        };
+
+        let is_link = entry.path_is_symlink();
+        // Without following links a symlink's own file type is "symlink", so check its target
+        let is_file = entry.file_type().is_file() || (is_link && entry.path().is_file());
+        if !is_file {
+            continue;
+        }
+        if !has_extension(entry.path(), &opts.extensions) {
+            found.others.push(entry.into_path());
+            continue;
+        }
+
+        match (is_link, opts.symlink_files) {
+            (false, _) | (true, SymlinkPolicy::Process) => found.files.push(entry),
+            (true, SymlinkPolicy::Mirror) => found.links.push(entry),
+            (true, SymlinkPolicy::Skip) => {}
+        }
+    }
+    found
+}
+
+/// Whether `path` has one of `extensions`, compared case-sensitively: with
+/// `wav`, `a.WAV` is not an input. Inputs, archive entries, S3 keys and
+/// single files all go through this.
+pub fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| extensions.iter().any(|e| e == ext))
+}
+
+/// Length of a WAV file in seconds according to its header, if it can be read.
+pub fn wav_duration(path: &Path) -> Option<f64> {
+    let reader = hound::WavReader::open(path).ok()?;
+    let spec = reader.spec();
+    Some(f64::from(reader.duration()) / f64::from(spec.sample_rate.max(1)))
+}
+
+/// Re-creates the symlink `link` (under `input_dir`) at the matching place in `output_dir`.
+///
+/// Relative targets are kept as they are, so links within the tree point at the
+/// denoised counterpart; absolute targets inside the input tree are remapped.
+pub fn mirror_link(input_dir: &Path, output_dir: &Path, link: &Path) -> Result<()> {
+    let relative = link.strip_prefix(input_dir)?;
+    let output = output_dir.join(relative);
+    let target = std::fs::read_link(link)
+        .with_context(|| format!("Failed to read symlink: {}", link.display()))?;
+    let target = match target.strip_prefix(input_dir) {
+        Ok(inner) if target.is_absolute() => output_dir.join(inner),
+        _ => target,
+    };
+
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent).with_context(|| {
+            format!(
+                "Failed to create output directory for: {}",
+                output.display()
+            )
+        })?;
+    }
+    if output.symlink_metadata().is_ok() {
+        std::fs::remove_file(&output)
+            .with_context(|| format!("Failed to replace: {}", output.display()))?;
+    }
+
+    #[cfg(unix)]
+    let created = std::os::unix::fs::symlink(&target, &output);
+    #[cfg(windows)]
+    let created = std::os::windows::fs::symlink_file(&target, &output);
+    created.with_context(|| format!("Failed to create symlink: {}", output.display()))
+}
+
+/// Hard-links `src` to `dest`, replacing any existing file, or copies it when
+/// linking fails (e.g. across filesystems).
+pub fn link_or_copy(src: &Path, dest: &Path) -> Result<()> {
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent).with_context(|| {
+            format!("Failed to create output directory for: {}", dest.display())
+        })?;
+    }
+    if dest.symlink_metadata().is_ok() {
+        std::fs::remove_file(dest)
+            .with_context(|| format!("Failed to replace: {}", dest.display()))?;
+    }
+    if std::fs::hard_link(src, dest).is_err() {
+        std::fs::copy(src, dest)
+            .with_context(|| format!("Failed to copy {} to {}", src.display(), dest.display()))?;
+    }
+    Ok(())
+}
+
+/// Puts the input file `src` at `dest` in the output tree, replacing any
+/// existing file.
+pub fn pass_through(src: &Path, dest: &Path, mode: Passthrough) -> Result<()> {
+    match mode {
+        Passthrough::Hardlink => return link_or_copy(src, dest),
+        Passthrough::Copy | Passthrough::Symlink => {}
+    }
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent).with_context(|| {
+            format!("Failed to create output directory for: {}", dest.display())
+        })?;
+    }
+    if dest.symlink_metadata().is_ok() {
+        std::fs::remove_file(dest)
+            .with_context(|| format!("Failed to replace: {}", dest.display()))?;
+    }
+    if mode == Passthrough::Copy {
+        std::fs::copy(src, dest)
+            .with_context(|| format!("Failed to copy {} to {}", src.display(), dest.display()))?;
+        return Ok(());
+    }
+    let target = std::fs::canonicalize(src)
+        .with_context(|| format!("Failed to resolve {}", src.display()))?;
+    #[cfg(unix)]
+    let created = std::os::unix::fs::symlink(&target, dest);
+    #[cfg(windows)]
+    let created = std::os::windows::fs::symlink_file(&target, dest);
+    created.with_context(|| format!("Failed to create symlink: {}", dest.display()))
+}
+
+/// Passes through the files beside `input` that share its stem and have one
+/// of `extensions`, such as transcripts, to the same names beside `output`.
+pub fn copy_companions(
+    input: &Path,
+    output: &Path,
+    extensions: &[String],
+    mode: Passthrough,
+) -> Result<()> {
+    for ext in extensions {
+        let companion = input.with_extension(ext);
+        let dest = output.with_extension(ext);
+        if companion.is_file() && companion != dest {
+            pass_through(&companion, &dest, mode)?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves `replacement` over `path`, first keeping the current contents of
+/// `path` at `backup` (which must not exist yet).
+///
+/// The backup is a hard link where possible and `path` is replaced by a
+/// rename, so it always holds either the original or the complete replacement.
+pub fn replace_with_backup(path: &Path, replacement: &Path, backup: &Path) -> Result<()> {
+    if backup.symlink_metadata().is_ok() {
+        bail!("Backup already exists: {}", backup.display());
+    }
+    link_or_copy(path, backup)?;
+    std::fs::rename(replacement, path)
+        .with_context(|| format!("Failed to replace: {}", path.display()))
+}
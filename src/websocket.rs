@@ -0,0 +1,359 @@
+//! Just enough of WebSocket (RFC 6455) for the server's `/stream` endpoint:
+//! the handshake's accept key, reading a client's messages and writing frames.
+
+use anyhow::{Result, bail};
+use base64::Engine;
+use std::io::{Read, Write};
+
+/// Appended to the client's key to prove the server speaks WebSocket.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Close codes sent to the client.
+pub const NORMAL: u16 = 1000;
+pub const PROTOCOL_ERROR: u16 = 1002;
+pub const UNSUPPORTED_DATA: u16 = 1003;
+pub const INVALID_DATA: u16 = 1007;
+pub const TOO_BIG: u16 = 1009;
+
+/// A complete message from the client.
+pub enum Message {
+    /// Nothing here takes text, so it is not kept.
+    Text,
+    Binary(Vec<u8>),
+    /// Already answered with a pong.
+    Ping,
+    /// The client's close frame; the session ends.
+    Close,
+}
+
+/// Why reading a message failed, with the close code to send.
+pub struct Violation {
+    pub code: u16,
+    pub reason: &'static str,
+}
+
+/// The `Sec-WebSocket-Accept` value for a client's `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let digest = sha1(format!("{}{GUID}", key.trim()).as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(digest)
+}
+
+/// Reads frames until a whole message arrives, answering pings on the way.
+/// `Ok(Err(..))` is a client that broke the protocol; `Err` a broken connection.
+pub fn read_message(
+    stream: &mut (impl Read + Write),
+    max_len: usize,
+) -> Result<std::result::Result<Message, Violation>> {
+    let mut message: Option<(u8, Vec<u8>)> = None;
+    loop {
+        let mut head = [0; 2];
+        stream.read_exact(&mut head)?;
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0f;
+        if head[1] & 0x80 == 0 {
+            return Ok(Err(Violation {
+                code: PROTOCOL_ERROR,
+                reason: "client frames must be masked",
+            }));
+        }
+        let len = match head[1] & 0x7f {
+            126 => {
+                let mut len = [0; 2];
+                stream.read_exact(&mut len)?;
+                u64::from(u16::from_be_bytes(len))
+            }
+            127 => {
+                let mut len = [0; 8];
+                stream.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => u64::from(len),
+        };
+        let buffered = message.as_ref().map_or(0, |(_, data)| data.len());
+        if (buffered as u64).saturating_add(len) > max_len as u64 {
+            return Ok(Err(Violation {
+                code: TOO_BIG,
+                reason: "message is too large",
+            }));
+        }
+        let mut mask = [0; 4];
+        stream.read_exact(&mut mask)?;
+        let mut payload = vec![0; len as usize];
+        stream.read_exact(&mut payload)?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+
+        match opcode {
+            // Control frames may come between the fragments of a message
+            0x8 => return Ok(Ok(Message::Close)),
+            0x9 => {
+                write_frame(stream, 0xa, &payload)?;
+                if message.is_none() {
+                    return Ok(Ok(Message::Ping));
+                }
+            }
+            0xa => {}
+            0x0 => match &mut message {
+                Some((_, data)) => data.extend(payload),
+                None => {
+                    return Ok(Err(Violation {
+                        code: PROTOCOL_ERROR,
+                        reason: "continuation without a message",
+                    }));
+                }
+            },
+            0x1 | 0x2 if message.is_none() => message = Some((opcode, payload)),
+            _ => {
+                return Ok(Err(Violation {
+                    code: PROTOCOL_ERROR,
+                    reason: "unexpected opcode",
+                }));
+            }
+        }
+        if fin
+            && opcode <= 0x2
+            && let Some((first, data)) = message.take()
+        {
+            return Ok(Ok(match first {
+                0x1 => Message::Text,
+                _ => Message::Binary(data),
+            }));
+        }
+    }
+}
+
+pub fn send_binary(stream: &mut impl Write, data: &[u8]) -> Result<()> {
+    write_frame(stream, 0x2, data)
+}
+
+/// Sends a close frame; the connection is done with afterwards.
+pub fn send_close(stream: &mut impl Write, code: u16, reason: &str) -> Result<()> {
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend(reason.as_bytes());
+    write_frame(stream, 0x8, &payload)
+}
+
+/// Writes one unmasked, final frame.
+fn write_frame(stream: &mut impl Write, opcode: u8, payload: &[u8]) -> Result<()> {
+    if opcode >= 0x8 && payload.len() > 125 {
+        bail!("control frame payload is too long");
+    }
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend((len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend((len as u64).to_be_bytes());
+        }
+    }
+    frame.extend(payload);
+    stream.write_all(&frame)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// SHA-1 (FIPS 180-4), which the handshake requires; nothing else here uses it.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend((data.len() as u64 * 8).to_be_bytes());
+
+    for block in padded.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..20 => ((b & c) | (!b & d), 0x5a827999),
+                20..40 => (b ^ c ^ d, 0x6ed9eba1),
+                40..60 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(h) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A connection with the client's bytes to read and what the server wrote.
+    struct Connection {
+        incoming: Cursor<Vec<u8>>,
+        outgoing: Vec<u8>,
+    }
+
+    impl Connection {
+        fn new(frames: &[Vec<u8>]) -> Self {
+            Connection {
+                incoming: Cursor::new(frames.concat()),
+                outgoing: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for Connection {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.incoming.read(buf)
+        }
+    }
+
+    impl Write for Connection {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.outgoing.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A client frame, masked as clients must.
+    fn masked(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![if fin { 0x80 } else { 0 } | opcode];
+        match payload.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len => {
+                frame.push(0x80 | 126);
+                frame.extend((len as u16).to_be_bytes());
+            }
+        }
+        frame.extend(mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    fn read(frames: &[Vec<u8>]) -> (std::result::Result<Message, Violation>, Vec<u8>) {
+        let mut connection = Connection::new(frames);
+        let message = read_message(&mut connection, 1024).unwrap();
+        (message, connection.outgoing)
+    }
+
+    fn violation(frames: &[Vec<u8>]) -> u16 {
+        match read(frames).0 {
+            Err(violation) => violation.code,
+            Ok(_) => panic!("frames were accepted"),
+        }
+    }
+
+    #[test]
+    fn computes_the_rfc_6455_accept_key() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn hashes_the_fips_180_sha1_example() {
+        let hex: String = sha1(b"abc").iter().map(|b| format!("{b:02x}")).collect();
+        assert_eq!(hex, "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[test]
+    fn reads_the_rfc_6455_masked_text_example() {
+        let frame = vec![
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        assert_eq!(frame, masked(true, 0x1, b"Hello"));
+        assert!(matches!(read(&[frame]).0, Ok(Message::Text)));
+    }
+
+    #[test]
+    fn joins_fragments_and_answers_pings_between_them() {
+        let (message, outgoing) = read(&[
+            masked(false, 0x2, b"ab"),
+            masked(true, 0x9, b"p"),
+            masked(true, 0x0, b"cd"),
+        ]);
+        assert!(matches!(message, Ok(Message::Binary(data)) if data == b"abcd"));
+        assert_eq!(outgoing, [0x8a, 0x01, b'p']);
+    }
+
+    #[test]
+    fn reads_16_bit_lengths() {
+        let payload = vec![7; 300];
+        let (message, _) = read(&[masked(true, 0x2, &payload)]);
+        assert!(matches!(message, Ok(Message::Binary(data)) if data == payload));
+    }
+
+    #[test]
+    fn a_lone_ping_and_a_close_are_messages() {
+        let (message, outgoing) = read(&[masked(true, 0x9, b"")]);
+        assert!(matches!(message, Ok(Message::Ping)));
+        assert_eq!(outgoing, [0x8a, 0x00]);
+        assert!(matches!(
+            read(&[masked(true, 0x8, b"")]).0,
+            Ok(Message::Close)
+        ));
+    }
+
+    #[test]
+    fn rejects_frames_that_break_the_protocol() {
+        // Unmasked
+        assert_eq!(violation(&[vec![0x82, 0x01, 0x00]]), PROTOCOL_ERROR);
+        assert_eq!(violation(&[masked(true, 0x0, b"x")]), PROTOCOL_ERROR);
+        assert_eq!(violation(&[masked(true, 0x3, b"x")]), PROTOCOL_ERROR);
+        assert_eq!(
+            violation(&[masked(false, 0x2, b"x"), masked(true, 0x2, b"y")]),
+            PROTOCOL_ERROR
+        );
+        assert_eq!(
+            violation(&[masked(false, 0x2, &[0; 1000]), masked(true, 0x0, &[0; 100])]),
+            TOO_BIG
+        );
+    }
+
+    #[test]
+    fn writes_each_length_encoding() {
+        let header = |len: usize| {
+            let mut out = Vec::new();
+            send_binary(&mut out, &vec![0; len]).unwrap();
+            out[..out.len() - len].to_vec()
+        };
+        assert_eq!(header(125), [0x82, 125]);
+        assert_eq!(header(200), [0x82, 126, 0, 200]);
+        assert_eq!(header(70000), [0x82, 127, 0, 0, 0, 0, 0, 1, 0x11, 0x70]);
+
+        let mut out = Vec::new();
+        send_close(&mut out, NORMAL, "bye").unwrap();
+        assert_eq!(out, [0x88, 5, 0x03, 0xe8, b'b', b'y', b'e']);
+        assert!(send_close(&mut Vec::new(), NORMAL, &"x".repeat(124)).is_err());
+    }
+}